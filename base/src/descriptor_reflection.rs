@@ -187,6 +187,8 @@ impl<'a, T: Serialize> Serialize for SerializeDescriptors<'a, T> {
 
 thread_local! {
     static DESCRIPTOR_SRC: RefCell<Option<Vec<Option<SafeDescriptor>>>> = Default::default();
+    // Set when deserialization refers to a descriptor index that was not in the source.
+    static DESCRIPTOR_SRC_OUT_OF_BOUNDS: Cell<bool> = Cell::new(false);
 }
 
 /// Sets the thread local storage of descriptors for deserialization. Fails if this was already
@@ -200,6 +202,7 @@ fn set_descriptor_src(descriptors: Vec<Option<SafeDescriptor>>) -> Result<(), &'
             return Err("attempt to set descriptor source that was already set");
         }
         *src = Some(descriptors);
+        DESCRIPTOR_SRC_OUT_OF_BOUNDS.with(|o| o.set(false));
         Ok(())
     })
 }
@@ -225,7 +228,10 @@ fn take_descriptor(index: usize) -> Result<SafeDescriptor, &'static str> {
             .as_mut()
             .ok_or("attempt to deserialize descriptor without descriptor source")?
             .get_mut(index)
-            .ok_or("attempt to deserialize out of bounds descriptor")?
+            .ok_or_else(|| {
+                DESCRIPTOR_SRC_OUT_OF_BOUNDS.with(|o| o.set(true));
+                "attempt to deserialize out of bounds descriptor"
+            })?
             .take()
             .ok_or("attempt to deserialize descriptor that was already taken")
     })
@@ -306,6 +312,68 @@ pub fn deserialize_with_descriptors<F, T, E>(
     f: F,
     descriptors: impl IntoIterator<Item = SafeDescriptor>,
 ) -> Result<T, E>
+where
+    F: FnOnce() -> Result<T, E>,
+    E: de::Error,
+{
+    let (res, usage) = deserialize_with_descriptor_src(f, descriptors)?;
+
+    // The deserializer should have consumed every descriptor.
+    debug_assert_eq!(usage.unused, 0);
+
+    res
+}
+
+/// Error returned by `deserialize_with_exact_descriptors`.
+#[derive(Debug)]
+pub(crate) enum ExactDescriptorsError<E> {
+    /// The closure failed to deserialize the data.
+    Deserialize(E),
+    /// The data referred to more descriptors than were provided.
+    Missing,
+    /// The data was deserialized but did not refer to the given number of provided descriptors.
+    Unused(usize),
+}
+
+/// Like `deserialize_with_descriptors`, but fails if the deserialized data does not refer to
+/// exactly the provided descriptors.
+///
+/// This is meant for data received from another process, where a mismatch between the message and
+/// the descriptors sent with it indicates a misbehaving peer. Unused descriptors are closed.
+pub(crate) fn deserialize_with_exact_descriptors<F, T, E>(
+    f: F,
+    descriptors: impl IntoIterator<Item = SafeDescriptor>,
+) -> Result<T, ExactDescriptorsError<E>>
+where
+    F: FnOnce() -> Result<T, E>,
+    E: de::Error,
+{
+    let (res, usage) = deserialize_with_descriptor_src(f, descriptors)
+        .map_err(ExactDescriptorsError::Deserialize)?;
+    if usage.out_of_bounds {
+        return Err(ExactDescriptorsError::Missing);
+    }
+    let value = res.map_err(ExactDescriptorsError::Deserialize)?;
+    if usage.unused != 0 {
+        return Err(ExactDescriptorsError::Unused(usage.unused));
+    }
+    Ok(value)
+}
+
+/// How the descriptors given to `deserialize_with_descriptor_src` were used.
+struct DescriptorUsage {
+    /// Number of descriptors that were not taken during deserialization.
+    unused: usize,
+    /// Whether deserialization referred to a descriptor beyond the ones given.
+    out_of_bounds: bool,
+}
+
+/// Runs `f` with `descriptors` as the thread local descriptor source. The outer result is an error
+/// only if the descriptor source could not be set.
+fn deserialize_with_descriptor_src<F, T, E>(
+    f: F,
+    descriptors: impl IntoIterator<Item = SafeDescriptor>,
+) -> Result<(Result<T, E>, DescriptorUsage), E>
 where
     F: FnOnce() -> Result<T, E>,
     E: de::Error,
@@ -319,13 +387,14 @@ where
 
     // unwrap is used because set_descriptor_src is always called before this, so it should never
     // panic.
-    let empty_descriptors = take_descriptor_src().unwrap();
-
-    // The deserializer should have consumed every descriptor.
-    debug_assert!(empty_descriptors.into_iter().all(|d| d.is_none()));
+    let remaining_descriptors = take_descriptor_src().unwrap();
+    let usage = DescriptorUsage {
+        unused: remaining_descriptors.iter().filter(|d| d.is_some()).count(),
+        out_of_bounds: DESCRIPTOR_SRC_OUT_OF_BOUNDS.with(|o| o.get()),
+    };
 
     match res {
-        Ok(r) => r,
+        Ok(r) => Ok((r, usage)),
         Err(e) => resume_unwind(e),
     }
 }
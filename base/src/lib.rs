@@ -98,24 +98,37 @@ cfg_if::cfg_if! {
         pub use linux::{
             block_signal, clear_signal, get_blocked_signals, new_pipe_full,
             register_rt_signal_handler, signal, unblock_signal, Killable, SIGRTMIN,
-            AcpiNotifyEvent, NetlinkGenericSocket, SignalFd, Terminal,
+            AcpiEventFilter, AcpiNotifyEvent, NetlinkGenericSocket, SignalFd, Terminal,
         };
+        pub use linux::{AddrChange, LinkChange, RouteChange, RouteMonitor};
 
         pub use linux::{
             drop_capabilities, pipe, read_raw_stdin
         };
+        pub use linux::dup_descriptor_with_flags;
         pub use linux::{enable_core_scheduling, set_rt_prio_limit, set_rt_round_robin};
+        pub use linux::RealtimeGuard;
+        pub use linux::vsock;
         pub use linux::{flock, FlockOperation};
         pub use linux::{getegid, geteuid};
         pub use linux::{gettid, kill_process_group, reap_child};
         pub use linux::logical_core_capacity;
         pub use linux::logical_core_cluster_id;
         pub use linux::logical_core_frequencies_khz;
+        pub use linux::{PsiMemoryMonitor, PsiStallType};
+        pub use linux::{process_memory_summary, MemorySummary};
+        pub use linux::process_vm_read;
+        pub use linux::process_vm_write;
+        pub use linux::set_mempolicy_for_range;
+        pub use linux::RemoteIoVec;
         pub use linux::sched_attr;
+        pub use linux::sched_getattr;
         pub use linux::sched_setattr;
+        pub use linux::set_current_thread_name;
         pub use linux::UnlinkUnixListener;
         pub use linux::EventExt;
         pub use linux::Gid;
+        pub use linux::HugePageSize;
     }
 }
 
@@ -62,6 +62,8 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub struct Protection {
     pub(crate) read: bool,
     pub(crate) write: bool,
+    #[serde(default)]
+    pub(crate) exec: bool,
 }
 
 impl Protection {
@@ -71,6 +73,17 @@ impl Protection {
         Protection {
             read: true,
             write: true,
+            ..Default::default()
+        }
+    }
+
+    /// Returns Protection allowing read/write/execute access.
+    #[inline(always)]
+    pub fn read_write_exec() -> Protection {
+        Protection {
+            read: true,
+            write: true,
+            exec: true,
         }
     }
 
@@ -107,10 +120,22 @@ impl Protection {
         }
     }
 
+    /// Set execute events.
+    #[inline(always)]
+    pub fn set_exec(self) -> Protection {
+        Protection { exec: true, ..self }
+    }
+
     /// Returns true if all access allowed by |other| is also allowed by |self|.
     #[inline(always)]
     pub fn allows(&self, other: &Protection) -> bool {
-        self.read >= other.read && self.write >= other.write
+        self.read >= other.read && self.write >= other.write && self.exec >= other.exec
+    }
+
+    /// Returns true if memory with this protection can be read but not written.
+    #[inline(always)]
+    pub fn is_read_only(&self) -> bool {
+        self.read && !self.write
     }
 }
 
@@ -2,6 +2,7 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::collections::BTreeSet;
 use std::str;
 
 use thiserror::Error;
@@ -9,17 +10,39 @@ use zerocopy::FromBytes;
 use zerocopy::FromZeroes;
 
 use super::netlink::*;
+use crate::descriptor::AsRawDescriptor;
 
 const ACPI_EVENT_SIZE: usize = std::mem::size_of::<AcpiGenlEvent>();
+const NLMSG_HDRLEN: usize = std::mem::size_of::<libc::nlmsghdr>();
 const GENL_HDRLEN: usize = std::mem::size_of::<GenlMsgHdr>();
 const NLA_HDRLEN: usize = std::mem::size_of::<NlAttr>();
 
+/// Maximum length of a device class, which is stored nul-terminated in `AcpiGenlEvent`.
+const DEVICE_CLASS_MAX_LEN: usize = 19;
+
+// Offset of `AcpiGenlEvent::device_class` in the netlink messages seen by socket filters, which
+// start with the netlink header.
+const DEVICE_CLASS_OFFSET: usize = NLMSG_HDRLEN + GENL_HDRLEN + NLA_HDRLEN;
+
+// Classic BPF opcodes, see include/uapi/linux/bpf_common.h.
+const BPF_LD: u16 = 0x00;
+const BPF_JMP: u16 = 0x05;
+const BPF_RET: u16 = 0x06;
+const BPF_B: u16 = 0x10;
+const BPF_ABS: u16 = 0x20;
+const BPF_JEQ: u16 = 0x10;
+const BPF_K: u16 = 0x00;
+
 #[derive(Error, Debug)]
 pub enum AcpiEventError {
     #[error("GenmsghdrCmd or NlAttrType inappropriate for acpi event")]
     TypeAttrMissmatch,
     #[error("Something goes wrong: msg_len {0} is not correct")]
     InvalidMsgLen(usize),
+    #[error("invalid acpi device class {0:?}")]
+    InvalidDeviceClass(String),
+    #[error("failed to attach acpi event filter: {0}")]
+    AttachFilter(super::Error),
 }
 type Result<T> = std::result::Result<T, AcpiEventError>;
 
@@ -109,3 +132,236 @@ fn strip_padding(b: &[u8]) -> &str {
 
     str::from_utf8(&b[..pos]).unwrap()
 }
+
+/// Selects the ACPI events delivered to a listener by device class, e.g. `ac_adapter` or
+/// `battery`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AcpiEventFilter {
+    // `None` delivers all events.
+    device_classes: Option<BTreeSet<String>>,
+}
+
+impl AcpiEventFilter {
+    /// Creates a filter that delivers every event.
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Creates a filter that only delivers the events of one of `device_classes`.
+    pub fn device_classes<I, S>(device_classes: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let device_classes: BTreeSet<String> = device_classes.into_iter().map(Into::into).collect();
+        if let Some(class) = device_classes.iter().find(|class| {
+            class.is_empty() || class.len() > DEVICE_CLASS_MAX_LEN || class.contains('\0')
+        }) {
+            return Err(AcpiEventError::InvalidDeviceClass(class.clone()));
+        }
+        Ok(AcpiEventFilter {
+            device_classes: Some(device_classes),
+        })
+    }
+
+    /// Returns true if the filter delivers events of `device_class`.
+    pub fn matches(&self, device_class: &str) -> bool {
+        match &self.device_classes {
+            Some(device_classes) => device_classes.contains(device_class),
+            None => true,
+        }
+    }
+
+    /// Decapsulates an acpi event from `netlink_message` like `AcpiNotifyEvent::new`, but returns
+    /// `None` if the filter doesn't deliver it.
+    pub fn parse(&self, netlink_message: NetlinkMessage) -> Result<Option<AcpiNotifyEvent>> {
+        let event = AcpiNotifyEvent::new(netlink_message)?;
+        Ok(Some(event).filter(|event| self.matches(&event.device_class)))
+    }
+
+    /// Installs the filter on `sock`, a socket receiving acpi events, so that the kernel drops the
+    /// events it doesn't deliver instead of waking up the reader for them. Any filter previously
+    /// attached to `sock` is replaced.
+    ///
+    /// Messages that are not acpi events are dropped as well, unless the filter delivers all
+    /// events.
+    pub fn attach(&self, sock: &dyn AsRawDescriptor) -> Result<()> {
+        let mut program = self.bpf_program();
+        let prog = libc::sock_fprog {
+            len: program.len() as u16,
+            filter: program.as_mut_ptr(),
+        };
+        // SAFETY:
+        // Safe because `prog` points to a valid program that the kernel copies, and we check the
+        // return value.
+        let ret = unsafe {
+            libc::setsockopt(
+                sock.as_raw_descriptor(),
+                libc::SOL_SOCKET,
+                libc::SO_ATTACH_FILTER,
+                &prog as *const libc::sock_fprog as *const libc::c_void,
+                std::mem::size_of::<libc::sock_fprog>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(AcpiEventError::AttachFilter(super::Error::last()));
+        }
+        Ok(())
+    }
+
+    /// Builds a socket filter program accepting the messages whose device class, including its
+    /// nul terminator, matches one of the filter's classes.
+    fn bpf_program(&self) -> Vec<libc::sock_filter> {
+        let stmt = |code, k| libc::sock_filter {
+            code,
+            jt: 0,
+            jf: 0,
+            k,
+        };
+        let accept = stmt(BPF_RET | BPF_K, u32::MAX);
+        let drop = stmt(BPF_RET | BPF_K, 0);
+
+        let Some(device_classes) = &self.device_classes else {
+            return vec![accept];
+        };
+        let mut program = Vec::new();
+        for class in device_classes {
+            let bytes: Vec<u8> = class.bytes().chain(std::iter::once(0)).collect();
+            for (i, &byte) in bytes.iter().enumerate() {
+                program.push(stmt(
+                    BPF_LD | BPF_B | BPF_ABS,
+                    (DEVICE_CLASS_OFFSET + i) as u32,
+                ));
+                // On mismatch, skip the remaining comparisons and the accept of this class.
+                let remaining = (bytes.len() - i - 1) * 2 + 1;
+                program.push(libc::sock_filter {
+                    code: BPF_JMP | BPF_JEQ | BPF_K,
+                    jt: 0,
+                    jf: remaining as u8,
+                    k: byte as u32,
+                });
+            }
+            program.push(accept);
+        }
+        program.push(drop);
+        program
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixDatagram;
+
+    use zerocopy::AsBytes;
+
+    use super::*;
+
+    /// Returns an acpi event netlink message, including the netlink header.
+    fn acpi_event_message(device_class: &str, data: u32) -> Vec<u8> {
+        let mut event = AcpiGenlEvent::new_zeroed();
+        for (dst, src) in event.device_class.iter_mut().zip(device_class.bytes()) {
+            *dst = src as _;
+        }
+        event._type = 0x80;
+        event.data = data;
+
+        let len = NLMSG_HDRLEN + GENL_HDRLEN + NLA_HDRLEN + ACPI_EVENT_SIZE;
+        let mut msg = Vec::with_capacity(len);
+        msg.extend_from_slice(&(len as u32).to_ne_bytes());
+        msg.resize(NLMSG_HDRLEN, 0);
+        msg.extend_from_slice(
+            GenlMsgHdr {
+                cmd: GenmsghdrCmd::AcpiGenlCmdEvent as u8,
+                version: 1,
+                reserved: 0,
+            }
+            .as_bytes(),
+        );
+        msg.extend_from_slice(
+            NlAttr {
+                len: (NLA_HDRLEN + ACPI_EVENT_SIZE) as u16,
+                _type: NlAttrType::AcpiGenlAttrEvent as u16,
+            }
+            .as_bytes(),
+        );
+        // SAFETY:
+        // Safe because `AcpiGenlEvent` is a plain C struct without padding.
+        msg.extend_from_slice(unsafe {
+            std::slice::from_raw_parts(&event as *const AcpiGenlEvent as *const u8, ACPI_EVENT_SIZE)
+        });
+        msg
+    }
+
+    fn netlink_message(msg: &[u8]) -> NetlinkMessage<'_> {
+        NetlinkMessage {
+            _type: 0,
+            flags: 0,
+            seq: 0,
+            pid: 0,
+            data: &msg[NLMSG_HDRLEN..],
+        }
+    }
+
+    #[test]
+    fn invalid_device_class() {
+        for class in ["", "a_very_long_device_class", "ac\0"] {
+            assert!(matches!(
+                AcpiEventFilter::device_classes([class]),
+                Err(AcpiEventError::InvalidDeviceClass(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn parse_subscribed_classes() {
+        let filter = AcpiEventFilter::device_classes(["ac_adapter", "battery"]).unwrap();
+        let delivered: Vec<(String, u32)> = [("ac_adapter", 1), ("gpe", 2), ("battery", 3)]
+            .iter()
+            .map(|&(class, data)| acpi_event_message(class, data))
+            .filter_map(|msg| filter.parse(netlink_message(&msg)).unwrap())
+            .map(|event| (event.device_class, event.data))
+            .collect();
+        assert_eq!(
+            delivered,
+            vec![("ac_adapter".to_owned(), 1), ("battery".to_owned(), 3)]
+        );
+
+        let msg = acpi_event_message("gpe", 2);
+        assert!(AcpiEventFilter::all()
+            .parse(netlink_message(&msg))
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn socket_filter() {
+        let (sender, receiver) = UnixDatagram::pair().unwrap();
+        receiver.set_nonblocking(true).unwrap();
+        AcpiEventFilter::device_classes(["ac_adapter", "battery"])
+            .unwrap()
+            .attach(&receiver)
+            .unwrap();
+
+        // "battery_x" shares a prefix with a subscribed class but isn't one.
+        for (class, data) in [
+            ("ac_adapter", 1),
+            ("gpe", 2),
+            ("battery_x", 3),
+            ("battery", 4),
+        ] {
+            sender.send(&acpi_event_message(class, data)).unwrap();
+        }
+        sender.send(b"not an acpi event").unwrap();
+
+        let mut received = Vec::new();
+        let mut buf = [0u8; 256];
+        while let Ok(len) = receiver.recv(&mut buf) {
+            let event = AcpiNotifyEvent::new(netlink_message(&buf[..len])).unwrap();
+            received.push((event.device_class, event.data));
+        }
+        assert_eq!(
+            received,
+            vec![("ac_adapter".to_owned(), 1), ("battery".to_owned(), 4)]
+        );
+    }
+}
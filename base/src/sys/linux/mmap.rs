@@ -8,6 +8,7 @@
 use std::ptr::null_mut;
 
 use libc::c_int;
+use libc::PROT_EXEC;
 use libc::PROT_READ;
 use libc::PROT_WRITE;
 use log::warn;
@@ -35,6 +36,9 @@ impl From<Protection> for c_int {
         if p.write {
             value |= PROT_WRITE;
         }
+        if p.exec {
+            value |= PROT_EXEC;
+        }
         value
     }
 }
@@ -77,6 +81,32 @@ impl dyn MappedRegion {
             Err(Error::SystemCallFailed(ErrnoError::last()))
         }
     }
+
+    /// Populates the pages of a mapping of `size` bytes starting at `offset` from the start of the
+    /// region, faulting them in writable as if they had been written to so later accesses don't
+    /// fault. `offset`..`offset+size` must be contained within the `MappedRegion`.
+    ///
+    /// This uses `MADV_POPULATE_WRITE`, which requires Linux 5.14 or later.
+    pub fn populate(&self, offset: usize, size: usize) -> Result<()> {
+        validate_includes_range(self.size(), offset, size)?;
+
+        // SAFETY:
+        // Safe because the MemoryMapping/MemoryMappingArena interface ensures our pointer and size
+        // are correct, we've validated that `offset`..`offset+size` is in the range owned by this
+        // `MappedRegion`, and populating pages doesn't change their contents.
+        let ret = unsafe {
+            libc::madvise(
+                (self.as_ptr() as usize + offset) as *mut libc::c_void,
+                size,
+                libc::MADV_POPULATE_WRITE,
+            )
+        };
+        if ret != -1 {
+            Ok(())
+        } else {
+            Err(Error::SystemCallFailed(ErrnoError::last()))
+        }
+    }
 }
 
 /// Wraps an anonymous shared memory mapping in the current process. Provides
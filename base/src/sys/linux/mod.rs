@@ -29,11 +29,15 @@ mod mmap;
 mod net;
 mod netlink;
 mod notifiers;
+mod numa;
 pub mod panic_handler;
 pub mod platform_timer_resolution;
 mod poll;
 mod priority;
 pub mod process;
+mod process_memory;
+mod process_vm;
+mod psi;
 mod sched;
 mod shm;
 pub mod signal;
@@ -89,11 +93,18 @@ pub(in crate::sys) use net::sockaddr_un;
 pub(in crate::sys) use net::sockaddrv4_to_lib_c;
 pub(in crate::sys) use net::sockaddrv6_to_lib_c;
 pub use netlink::*;
+pub use numa::set_mempolicy_for_range;
 use once_cell::sync::OnceCell;
 pub use poll::EventContext;
 pub use priority::*;
+pub use process_memory::*;
+pub use process_vm::*;
+pub use psi::PsiMemoryMonitor;
+pub use psi::PsiStallType;
 pub use sched::*;
+pub use shm::HugePageSize;
 pub use shm::MemfdSeals;
+pub use shm::MemfdSealsBuilder;
 pub use shm::SharedMemoryLinux;
 pub use signal::*;
 pub use signalfd::Error as SignalFdError;
@@ -108,10 +119,13 @@ use crate::descriptor::SafeDescriptor;
 pub use crate::errno::Error;
 pub use crate::errno::Result;
 pub use crate::errno::*;
+use crate::handle_eintr_errno;
 use crate::number_of_logical_cores;
 use crate::round_up_to_page_size;
 pub use crate::sys::unix::descriptor::*;
 use crate::syscall;
+use crate::unix::add_fd_flags;
+use crate::unix::clear_fd_flags;
 use crate::AsRawDescriptor;
 use crate::Pid;
 
@@ -144,6 +158,36 @@ pub fn gettid() -> Pid {
     unsafe { syscall(SYS_gettid as c_long) as Pid }
 }
 
+/// Maximum length of a thread name in bytes, not counting the terminating nul.
+const MAX_THREAD_NAME_LEN: usize = 15;
+
+/// Sets the name of the calling thread, as shown in `/proc/self/task/<tid>/comm`.
+///
+/// Linux limits thread names to 15 bytes, so longer names are truncated at the last character
+/// boundary that fits and a warning is logged. Returns `EINVAL` if `name` contains a nul byte.
+pub fn set_current_thread_name(name: &str) -> Result<()> {
+    if name.contains('\0') {
+        return Err(Error::new(EINVAL));
+    }
+    let mut len = name.len().min(MAX_THREAD_NAME_LEN);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    if len < name.len() {
+        warn!(
+            "thread name {:?} is too long, truncating to {:?}",
+            name,
+            &name[..len]
+        );
+    }
+    let mut buf = [0u8; MAX_THREAD_NAME_LEN + 1];
+    buf[..len].copy_from_slice(&name.as_bytes()[..len]);
+    // SAFETY:
+    // Safe because `buf` is a nul-terminated string that outlives the call, and the kernel only
+    // reads from it.
+    syscall!(unsafe { libc::prctl(libc::PR_SET_NAME, buf.as_ptr(), 0, 0, 0) }).map(|_| ())
+}
+
 /// Safe wrapper for `geteuid(2)`.
 #[inline(always)]
 pub fn geteuid() -> Uid {
@@ -256,6 +300,7 @@ pub fn is_block_file<F: AsRawDescriptor>(file: &F) -> Result<bool> {
 }
 
 const BLOCK_IO_TYPE: u32 = 0x12;
+ioctl_io_nr!(BLKSSZGET, BLOCK_IO_TYPE, 104);
 ioctl_io_nr!(BLKDISCARD, BLOCK_IO_TYPE, 119);
 
 /// Discards the given range of a block file.
@@ -270,6 +315,78 @@ pub fn discard_block<F: AsRawDescriptor>(file: &F, offset: u64, len: u64) -> Res
     syscall!(unsafe { libc::ioctl(file.as_raw_descriptor(), BLKDISCARD(), &range) }).map(|_| ())
 }
 
+/// Discards the given `(offset, len)` ranges of a block file.
+///
+/// Overlapping and adjacent ranges are merged first so that as few `BLKDISCARD` ioctls as possible
+/// are issued. Returns `EINVAL` if `file` is not a block device.
+pub fn discard_block_ranges<F: AsRawDescriptor>(file: &F, ranges: &[(u64, u64)]) -> Result<()> {
+    if !is_block_file(file)? {
+        return Err(Error::new(libc::EINVAL));
+    }
+    for (offset, len) in coalesce_ranges(ranges)? {
+        discard_block(file, offset, len)?;
+    }
+    Ok(())
+}
+
+/// Returns the discard granularity of a block device in bytes, or 0 if the device doesn't support
+/// discard.
+///
+/// Ranges passed to `discard_block` should be aligned to this granularity, or the kernel may
+/// reject them. Returns `EINVAL` if `file` is not a block device.
+pub fn block_discard_granularity<F: AsRawDescriptor>(file: &F) -> Result<u64> {
+    let stat = fstat(file)?;
+    if (stat.st_mode & libc::S_IFMT) != libc::S_IFBLK {
+        return Err(Error::new(libc::EINVAL));
+    }
+    discard_granularity_from_sysfs(Path::new("/sys/dev/block"), stat.st_rdev)
+}
+
+/// Reads the discard granularity of the block device `rdev` from `sysfs_dev_block`, which is
+/// normally `/sys/dev/block`.
+fn discard_granularity_from_sysfs(sysfs_dev_block: &Path, rdev: libc::dev_t) -> Result<u64> {
+    // Same encoding as glibc's `major()` and `minor()`.
+    let major = ((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff);
+    let minor = (rdev & 0xff) | ((rdev >> 12) & !0xff);
+    let dev_dir = sysfs_dev_block.join(format!("{}:{}", major, minor));
+    // Partitions don't have a queue of their own, the one of their parent disk applies.
+    for queue_dir in [dev_dir.join("queue"), dev_dir.join("../queue")] {
+        match std::fs::read_to_string(queue_dir.join("discard_granularity")) {
+            Ok(granularity) => {
+                return granularity
+                    .trim()
+                    .parse()
+                    .map_err(|_| Error::new(libc::EINVAL))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    // Devices without a request queue can't be discarded.
+    Ok(0)
+}
+
+/// Sorts `(offset, len)` ranges and merges the ones that overlap or are adjacent. Empty ranges are
+/// dropped. Returns `EINVAL` if a range extends past `u64::MAX`.
+fn coalesce_ranges(ranges: &[(u64, u64)]) -> Result<Vec<(u64, u64)>> {
+    let mut sorted: Vec<(u64, u64)> = ranges.iter().copied().filter(|&(_, len)| len > 0).collect();
+    sorted.sort_unstable();
+
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(sorted.len());
+    for (offset, len) in sorted {
+        let end = offset
+            .checked_add(len)
+            .ok_or_else(|| Error::new(libc::EINVAL))?;
+        match merged.last_mut() {
+            Some((last_offset, last_len)) if offset <= *last_offset + *last_len => {
+                *last_len = std::cmp::max(*last_offset + *last_len, end) - *last_offset;
+            }
+            _ => merged.push((offset, len)),
+        }
+    }
+    Ok(merged)
+}
+
 /// A trait used to abstract types that provide a process id that can be operated on.
 pub trait AsRawPid {
     fn as_raw_pid(&self) -> Pid;
@@ -292,13 +409,13 @@ impl AsRawPid for std::process::Child {
 /// On success if a process was reaped, it will be returned as the first value.
 /// The second returned value is the ExitStatus from the libc::waitpid() call.
 ///
-/// Note: this can block if libc::WNOHANG is not set and EINTR is not handled internally.
+/// Note: this can block if libc::WNOHANG is not set. Waits interrupted by a signal are retried.
 pub fn wait_for_pid<A: AsRawPid>(pid: A, options: c_int) -> Result<(Option<Pid>, ExitStatus)> {
     let pid = pid.as_raw_pid();
     let mut status: c_int = 1;
     // SAFETY:
     // Safe because status is owned and the error is checked.
-    let ret = unsafe { libc::waitpid(pid, &mut status, options) };
+    let ret = handle_eintr_errno!(unsafe { libc::waitpid(pid, &mut status, options) });
     if ret < 0 {
         return errno_result();
     }
@@ -483,6 +600,45 @@ pub fn validate_raw_fd(raw_fd: RawFd) -> Result<RawFd> {
     Ok(dup_fd as RawFd)
 }
 
+/// Verifies that |raw_descriptor| is actually owned by this process and duplicates it, with the
+/// new descriptor's close-on-exec and non-blocking flags set as requested.
+///
+/// Note that the non-blocking flag belongs to the open file description, so changing it also
+/// affects |raw_descriptor| and any other duplicate of it.
+pub fn dup_descriptor_with_flags(
+    raw_descriptor: RawDescriptor,
+    cloexec: bool,
+    nonblocking: bool,
+) -> Result<SafeDescriptor> {
+    // SAFETY:
+    // Safe because this doesn't modify any memory and we check the return value.
+    if unsafe { libc::fcntl(raw_descriptor, libc::F_GETFD) } < 0 {
+        return Err(Error::new(libc::EBADF));
+    }
+
+    let dup_cmd = if cloexec {
+        libc::F_DUPFD_CLOEXEC
+    } else {
+        libc::F_DUPFD
+    };
+    // SAFETY:
+    // Safe because this doesn't modify any memory and we check the return value.
+    let dup_fd = unsafe { libc::fcntl(raw_descriptor, dup_cmd, 0) };
+    if dup_fd < 0 {
+        return errno_result();
+    }
+    // SAFETY:
+    // Safe because we own the newly duplicated descriptor.
+    let descriptor = unsafe { SafeDescriptor::from_raw_descriptor(dup_fd) };
+
+    if nonblocking {
+        add_fd_flags(dup_fd, libc::O_NONBLOCK)?;
+    } else {
+        clear_fd_flags(dup_fd, libc::O_NONBLOCK)?;
+    }
+    Ok(descriptor)
+}
+
 /// Utility function that returns true if the given FD is readable without blocking.
 ///
 /// On an error, such as an invalid or incompatible FD, this will return false, which can not be
@@ -495,7 +651,7 @@ pub fn poll_in<F: AsRawDescriptor>(fd: &F) -> bool {
     };
     // SAFETY:
     // Safe because we give a valid pointer to a list (of 1) FD and check the return value.
-    let ret = unsafe { libc::poll(&mut fds, 1, 0) };
+    let ret = handle_eintr_errno!(unsafe { libc::poll(&mut fds, 1, 0) });
     // An error probably indicates an invalid FD, or an FD that can't be polled. Returning false in
     // that case is probably correct as such an FD is unlikely to be readable, although there are
     // probably corner cases in which that is wrong.
@@ -558,6 +714,88 @@ pub fn open_file_or_duplicate<P: AsRef<Path>>(path: P, options: &OpenOptions) ->
     })
 }
 
+/// Open the file with the given path like `open_file_or_duplicate`, then try to enable `O_DIRECT`
+/// on it.
+///
+/// If the underlying filesystem does not support direct I/O (e.g. tmpfs), a warning is logged and
+/// the file is returned with buffered I/O instead. Use `direct_io_alignment` to find out how
+/// buffers, offsets and lengths must be aligned when direct I/O is enabled.
+pub fn open_direct<P: AsRef<Path>>(path: P, options: &OpenOptions) -> Result<File> {
+    let path = path.as_ref();
+    let file = open_file_or_duplicate(path, options)?;
+    match add_fd_flags(file.as_raw_descriptor(), libc::O_DIRECT) {
+        Ok(()) => {}
+        Err(e) if e.errno() == libc::EINVAL => {
+            warn!(
+                "{} does not support O_DIRECT, falling back to buffered I/O",
+                path.display()
+            );
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(file)
+}
+
+/// Returns the alignment in bytes required for buffers, offsets and lengths when doing direct I/O
+/// on `file`.
+///
+/// Block devices report their logical block size. For other files the alignment reported by
+/// `statx(STATX_DIOALIGN)` is used when the kernel supports it, otherwise the filesystem's
+/// preferred block size is used as a conservative value.
+pub fn direct_io_alignment<F: AsRawDescriptor>(file: &F) -> Result<usize> {
+    let stat = fstat(file)?;
+    if (stat.st_mode & libc::S_IFMT) == libc::S_IFBLK {
+        let mut block_size: c_int = 0;
+        // SAFETY:
+        // Safe because the kernel only writes an int to `block_size` and we check the return
+        // value.
+        syscall!(unsafe {
+            libc::ioctl(
+                file.as_raw_descriptor(),
+                BLKSSZGET(),
+                &mut block_size as *mut c_int,
+            )
+        })?;
+        return Ok(block_size as usize);
+    }
+
+    #[cfg(target_env = "gnu")]
+    if let Some(alignment) = statx_dio_alignment(file) {
+        return Ok(alignment);
+    }
+
+    Ok(stat.st_blksize as usize)
+}
+
+/// Queries the direct I/O alignment of `file` with `statx`, returning `None` if the kernel or
+/// filesystem doesn't report it.
+#[cfg(target_env = "gnu")]
+fn statx_dio_alignment<F: AsRawDescriptor>(file: &F) -> Option<usize> {
+    let mut stx = MaybeUninit::<libc::statx>::zeroed();
+    // SAFETY:
+    // Safe because the kernel will only write data in `stx`, the path is a valid empty C string
+    // and we check the return value.
+    let ret = unsafe {
+        libc::statx(
+            file.as_raw_descriptor(),
+            b"\0".as_ptr() as *const libc::c_char,
+            libc::AT_EMPTY_PATH,
+            libc::STATX_DIOALIGN,
+            stx.as_mut_ptr(),
+        )
+    };
+    if ret < 0 {
+        return None;
+    }
+    // SAFETY:
+    // Safe because the struct was zero-initialized and the kernel only fills in fields.
+    let stx = unsafe { stx.assume_init() };
+    if stx.stx_mask & libc::STATX_DIOALIGN == 0 || stx.stx_dio_offset_align == 0 {
+        return None;
+    }
+    Some(stx.stx_dio_mem_align.max(stx.stx_dio_offset_align) as usize)
+}
+
 /// Get the max number of open files allowed by the environment.
 pub fn max_open_files() -> Result<u64> {
     let mut buf = mem::MaybeUninit::<libc::rlimit64>::zeroed();
@@ -706,6 +944,28 @@ pub fn sched_setattr(pid: Pid, attr: &mut sched_attr, flags: u32) -> Result<()>
     Ok(())
 }
 
+/// Reads the scheduling policy and attributes of thread `pid` (0 for the calling thread) into
+/// `attr`.
+pub fn sched_getattr(pid: Pid, attr: &mut sched_attr, flags: u32) -> Result<()> {
+    // SAFETY:
+    // Safe because the kernel writes at most `size_of::<sched_attr>()` bytes to `attr`, which is
+    // valid for that size, and we check the return value.
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_sched_getattr,
+            pid as usize,
+            attr as *mut sched_attr as usize,
+            std::mem::size_of::<sched_attr>(),
+            flags as usize,
+        )
+    };
+
+    if ret < 0 {
+        return Err(Error::last());
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Write;
@@ -724,4 +984,222 @@ mod tests {
         tx.write(&[0u8; 8])
             .expect_err("Write after fill didn't fail");
     }
+
+    #[test]
+    fn coalesce_discard_ranges() {
+        assert_eq!(coalesce_ranges(&[]).unwrap(), Vec::<(u64, u64)>::new());
+        // Adjacent, overlapping and contained ranges are merged regardless of their order.
+        assert_eq!(
+            coalesce_ranges(&[
+                (0x3000, 0x1000),
+                (0, 0x1000),
+                (0x1000, 0x1000),
+                (0x3800, 0x100)
+            ])
+            .unwrap(),
+            vec![(0, 0x2000), (0x3000, 0x1000)]
+        );
+        assert_eq!(
+            coalesce_ranges(&[(0, 0x2000), (0x1000, 0x2000)]).unwrap(),
+            vec![(0, 0x3000)]
+        );
+        // Empty ranges don't issue a discard and don't bridge gaps.
+        assert_eq!(
+            coalesce_ranges(&[(0, 0x1000), (0x1800, 0), (0x2000, 0x1000)]).unwrap(),
+            vec![(0, 0x1000), (0x2000, 0x1000)]
+        );
+        assert_eq!(
+            coalesce_ranges(&[(u64::MAX, 2)]).err(),
+            Some(Error::new(libc::EINVAL))
+        );
+    }
+
+    #[test]
+    fn discard_granularity() {
+        let sysfs = tempfile::tempdir().unwrap();
+        let disk = sysfs.path().join("devices/sda");
+        std::fs::create_dir_all(disk.join("queue")).unwrap();
+        std::fs::create_dir_all(disk.join("sda1")).unwrap();
+        std::fs::write(disk.join("queue/discard_granularity"), "4096\n").unwrap();
+        let nvme = sysfs.path().join("devices/nvme0n1");
+        std::fs::create_dir_all(nvme.join("queue")).unwrap();
+        std::fs::write(nvme.join("queue/discard_granularity"), "0\n").unwrap();
+
+        let dev_block = sysfs.path().join("dev/block");
+        std::fs::create_dir_all(&dev_block).unwrap();
+        std::os::unix::fs::symlink(&disk, dev_block.join("8:0")).unwrap();
+        std::os::unix::fs::symlink(disk.join("sda1"), dev_block.join("8:1")).unwrap();
+        std::os::unix::fs::symlink(&nvme, dev_block.join("259:0")).unwrap();
+
+        // Disk, and partition inheriting the granularity of its disk.
+        assert_eq!(
+            discard_granularity_from_sysfs(&dev_block, 0x800).unwrap(),
+            4096
+        );
+        assert_eq!(
+            discard_granularity_from_sysfs(&dev_block, 0x801).unwrap(),
+            4096
+        );
+        // Device without discard support.
+        assert_eq!(
+            discard_granularity_from_sysfs(&dev_block, 0x10300).unwrap(),
+            0
+        );
+        // Unknown device.
+        assert_eq!(
+            discard_granularity_from_sysfs(&dev_block, 0x1000).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn discard_block_ranges_not_block_file() {
+        let file = tempfile::tempfile().unwrap();
+        assert_eq!(
+            discard_block_ranges(&file, &[(0, 0x1000)]).err(),
+            Some(Error::new(libc::EINVAL))
+        );
+    }
+
+    #[test]
+    fn open_direct_temp_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("direct");
+        let file = open_direct(
+            &path,
+            OpenOptions::new().read(true).write(true).create(true),
+        )
+        .unwrap();
+
+        // Whether or not the filesystem accepted O_DIRECT, the file must be usable and the
+        // reported alignment must be something an aligned buffer can satisfy.
+        let alignment = direct_io_alignment(&file).unwrap();
+        assert!(alignment.is_power_of_two());
+
+        let layout = std::alloc::Layout::from_size_align(alignment, alignment).unwrap();
+        // SAFETY:
+        // Safe because the layout has a non-zero size.
+        let buf = unsafe { std::alloc::alloc_zeroed(layout) };
+        // SAFETY:
+        // Safe because `buf` was allocated above with `alignment` bytes.
+        let slice = unsafe { std::slice::from_raw_parts(buf, alignment) };
+        std::os::unix::fs::FileExt::write_all_at(&file, slice, 0).unwrap();
+        // SAFETY:
+        // Safe because `buf` was allocated with `layout` and is no longer used.
+        unsafe { std::alloc::dealloc(buf, layout) };
+        assert_eq!(file.metadata().unwrap().len(), alignment as u64);
+    }
+
+    #[test]
+    fn dup_descriptor_flags() {
+        let file = tempfile::tempfile().unwrap();
+        let fd_flags = |d: &SafeDescriptor| {
+            // SAFETY:
+            // Safe because this doesn't modify any memory and we check the return value.
+            let fd_flags = unsafe { libc::fcntl(d.as_raw_descriptor(), libc::F_GETFD) };
+            // SAFETY:
+            // Safe because this doesn't modify any memory and we check the return value.
+            let fl_flags = unsafe { libc::fcntl(d.as_raw_descriptor(), libc::F_GETFL) };
+            assert!(fd_flags >= 0 && fl_flags >= 0);
+            (
+                fd_flags & libc::FD_CLOEXEC != 0,
+                fl_flags & libc::O_NONBLOCK != 0,
+            )
+        };
+
+        let dup = dup_descriptor_with_flags(file.as_raw_descriptor(), true, true).unwrap();
+        assert_ne!(dup.as_raw_descriptor(), file.as_raw_descriptor());
+        assert_eq!(fd_flags(&dup), (true, true));
+
+        let dup = dup_descriptor_with_flags(file.as_raw_descriptor(), false, false).unwrap();
+        assert_eq!(fd_flags(&dup), (false, false));
+
+        assert_eq!(
+            dup_descriptor_with_flags(-1, true, false).err(),
+            Some(Error::new(libc::EBADF))
+        );
+    }
+
+    #[test]
+    fn thread_name() {
+        fn current_thread_name() -> String {
+            std::fs::read_to_string("/proc/thread-self/comm")
+                .unwrap()
+                .trim_end_matches('\n')
+                .to_owned()
+        }
+
+        std::thread::spawn(|| {
+            set_current_thread_name("v_balloon").unwrap();
+            assert_eq!(current_thread_name(), "v_balloon");
+
+            // Long names are truncated to 15 bytes without splitting a character.
+            set_current_thread_name("a_very_long_thread_name").unwrap();
+            assert_eq!(current_thread_name(), "a_very_long_thr");
+            set_current_thread_name("virtio_sound\u{e9}\u{e9}").unwrap();
+            assert_eq!(current_thread_name(), "virtio_sound\u{e9}");
+
+            assert_eq!(
+                set_current_thread_name("bad\0name").err(),
+                Some(Error::new(EINVAL))
+            );
+            assert_eq!(current_thread_name(), "virtio_sound\u{e9}");
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn wait_for_pid_retries_eintr() {
+        use std::io::Read;
+        use std::os::unix::thread::JoinHandleExt;
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::atomic::Ordering;
+
+        static SIGNALS: AtomicUsize = AtomicUsize::new(0);
+        extern "C" fn handle_signal(_: c_int) {
+            SIGNALS.fetch_add(1, Ordering::SeqCst);
+        }
+
+        // Install the handler without SA_RESTART so that the kernel reports EINTR from waitpid
+        // instead of restarting it.
+        let signum = signal::SIGRTMIN() + 4;
+        // SAFETY:
+        // Safe because the handler only does an atomic increment, which is async-signal-safe.
+        unsafe {
+            let mut sigact: libc::sigaction = mem::zeroed();
+            sigact.sa_sigaction = handle_signal as *const () as usize;
+            assert_eq!(libc::sigaction(signum, &sigact, ptr::null_mut()), 0);
+        }
+
+        let (mut rx, tx) = pipe(true).unwrap();
+        // SAFETY:
+        // Safe because the child only reads from a pipe before exiting.
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0, "fork failed");
+        if pid == 0 {
+            drop(tx);
+            // Exit once the parent closes its end of the pipe.
+            let _ = rx.read(&mut [0]);
+            // SAFETY:
+            // Safe because _exit never returns and skips the test harness's exit handlers.
+            unsafe { libc::_exit(7) };
+        }
+
+        let waiter = std::thread::spawn(move || wait_for_pid(pid, 0));
+        for _ in 0..3 {
+            std::thread::sleep(Duration::from_millis(50));
+            // SAFETY:
+            // Safe because the waiter thread can't exit before the child does, and the signal has
+            // a handler installed.
+            let ret = unsafe { libc::pthread_kill(waiter.as_pthread_t(), signum) };
+            assert_eq!(ret, 0);
+        }
+        drop(tx);
+
+        let (reaped, status) = waiter.join().unwrap().unwrap();
+        assert_eq!(reaped, Some(pid));
+        assert_eq!(status.code(), Some(7));
+        assert!(SIGNALS.load(Ordering::SeqCst) >= 3);
+    }
 }
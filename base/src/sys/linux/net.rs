@@ -2,7 +2,9 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::fs::read_to_string;
 use std::io;
+use std::mem::size_of;
 use std::net::SocketAddrV4;
 use std::net::SocketAddrV6;
 use std::os::fd::RawFd;
@@ -41,6 +43,9 @@ use crate::StreamChannel;
 use crate::UnixSeqpacket;
 use crate::UnixSeqpacketListener;
 
+const RMEM_MAX_PATH: &str = "/proc/sys/net/core/rmem_max";
+const WMEM_MAX_PATH: &str = "/proc/sys/net/core/wmem_max";
+
 pub(in crate::sys) unsafe fn sendmsg_nosignal(
     fd: RawFd,
     msg: *const msghdr,
@@ -135,6 +140,60 @@ impl UnixSeqpacket {
         socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC, 0)
             .map(|(s0, s1)| (UnixSeqpacket::from(s0), UnixSeqpacket::from(s1)))
     }
+
+    fn set_buffer_size(&self, size: usize, kind: c_int, max_path: &str) -> io::Result<()> {
+        let max: usize = read_to_string(max_path)?.trim().parse().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("invalid {}", max_path))
+        })?;
+        if size == 0 || size > max {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "buffer size {} is not within 1..={} ({})",
+                    size, max, max_path
+                ),
+            ));
+        }
+        // `size` fits in c_int because the kernel limits are c_int as well.
+        let size = size as c_int;
+        // SAFETY:
+        // Safe because we own the fd, the length of the pointer's data is the same as the passed in
+        // length parameter, and the return value is checked.
+        let ret = unsafe {
+            libc::setsockopt(
+                self.as_raw_descriptor(),
+                libc::SOL_SOCKET,
+                kind,
+                &size as *const c_int as *const libc::c_void,
+                size_of::<c_int>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sets the size of the socket's receive buffer (`SO_RCVBUF`).
+    ///
+    /// `size` must be non-zero and no larger than `/proc/sys/net/core/rmem_max`, otherwise
+    /// `ErrorKind::InvalidInput` is returned.
+    ///
+    /// Note that unix sockets account queued packets against the sender's send buffer, so the
+    /// peer also needs a large enough send buffer (see [`Self::set_send_buffer_size`]).
+    pub fn set_recv_buffer_size(&self, size: usize) -> io::Result<()> {
+        self.set_buffer_size(size, libc::SO_RCVBUF, RMEM_MAX_PATH)
+    }
+
+    /// Sets the size of the socket's send buffer (`SO_SNDBUF`).
+    ///
+    /// A unix socket can't send a packet larger than its send buffer, so this has to be raised to
+    /// send large messages. `size` must be non-zero and no larger than
+    /// `/proc/sys/net/core/wmem_max`, otherwise `ErrorKind::InvalidInput` is returned.
+    pub fn set_send_buffer_size(&self, size: usize) -> io::Result<()> {
+        self.set_buffer_size(size, libc::SO_SNDBUF, WMEM_MAX_PATH)
+    }
 }
 
 impl UnixSeqpacketListener {
@@ -4,6 +4,7 @@
 
 use std::alloc::Layout;
 use std::mem::MaybeUninit;
+use std::net::Ipv4Addr;
 use std::os::unix::io::AsRawFd;
 use std::str;
 
@@ -34,6 +35,8 @@ const NLMSGHDR_SIZE: usize = std::mem::size_of::<NlMsgHdr>();
 const GENL_HDRLEN: usize = std::mem::size_of::<GenlMsgHdr>();
 const NLA_HDRLEN: usize = std::mem::size_of::<NlAttr>();
 const NLATTR_ALIGN_TO: usize = 4;
+const IFINFOMSG_SIZE: usize = std::mem::size_of::<IfInfoMsg>();
+const IFADDRMSG_SIZE: usize = std::mem::size_of::<IfAddrMsg>();
 
 #[repr(C)]
 #[derive(Copy, Clone, FromZeroes, FromBytes, AsBytes)]
@@ -61,6 +64,29 @@ pub struct GenlMsgHdr {
     pub version: u8,
     pub reserved: u16,
 }
+/// `struct ifinfomsg`, the header of rtnetlink link messages.
+#[repr(C)]
+#[derive(Copy, Clone, FromZeroes, FromBytes, AsBytes)]
+struct IfInfoMsg {
+    family: u8,
+    pad: u8,
+    _type: u16,
+    index: i32,
+    flags: u32,
+    change: u32,
+}
+
+/// `struct ifaddrmsg`, the header of rtnetlink address messages.
+#[repr(C)]
+#[derive(Copy, Clone, FromZeroes, FromBytes, AsBytes)]
+struct IfAddrMsg {
+    family: u8,
+    prefixlen: u8,
+    flags: u8,
+    scope: u8,
+    index: u32,
+}
+
 /// A single netlink message, including its header and data.
 pub struct NetlinkMessage<'a> {
     pub _type: u16,
@@ -509,3 +535,322 @@ impl NetlinkGenericRead {
         None
     }
 }
+
+/// A network interface was added, removed, or had its state changed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LinkChange {
+    /// Index of the interface.
+    pub index: u32,
+    /// Name of the interface, if the kernel included it.
+    pub name: Option<String>,
+    /// The interface is administratively up (`IFF_UP`).
+    pub up: bool,
+    /// The interface is operationally up (`IFF_RUNNING`).
+    pub running: bool,
+    /// The interface was removed.
+    pub removed: bool,
+}
+
+/// An IPv4 address was added to or removed from a network interface.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AddrChange {
+    /// Index of the interface.
+    pub index: u32,
+    /// The address, if the kernel included it.
+    pub address: Option<Ipv4Addr>,
+    /// Length of the network prefix of the address.
+    pub prefix_len: u8,
+    /// The address was removed.
+    pub removed: bool,
+}
+
+/// A change reported by a [`RouteMonitor`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RouteChange {
+    Link(LinkChange),
+    Addr(AddrChange),
+}
+
+/// Watches for network interface and IPv4 address changes using a `NETLINK_ROUTE` socket
+/// subscribed to `RTMGRP_LINK` and `RTMGRP_IPV4_IFADDR`.
+///
+/// The monitor's descriptor becomes readable when changes are pending, so it can be added to a
+/// `WaitContext`. Call [`read_changes`](Self::read_changes) to get them.
+pub struct RouteMonitor {
+    sock: SafeDescriptor,
+}
+
+impl AsRawDescriptor for RouteMonitor {
+    fn as_raw_descriptor(&self) -> RawDescriptor {
+        self.sock.as_raw_descriptor()
+    }
+}
+
+impl RouteMonitor {
+    pub fn new() -> Result<Self> {
+        // SAFETY:
+        // Safe because we check the return value and convert the raw fd into a SafeDescriptor.
+        let sock = unsafe {
+            let fd = libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+                libc::NETLINK_ROUTE,
+            );
+            if fd < 0 {
+                return errno_result();
+            }
+
+            SafeDescriptor::from_raw_descriptor(fd)
+        };
+
+        // SAFETY:
+        // Safe because all 0s is valid data for sockaddr_nl.
+        let mut sa = unsafe { MaybeUninit::<libc::sockaddr_nl>::zeroed().assume_init() };
+        sa.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        sa.nl_groups = (libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR) as u32;
+
+        // SAFETY:
+        // Safe because we pass a descriptor that we own and valid pointer/size for sockaddr.
+        let res = unsafe {
+            libc::bind(
+                sock.as_raw_fd(),
+                &sa as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of_val(&sa) as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return errno_result();
+        }
+
+        Ok(RouteMonitor { sock })
+    }
+
+    /// Returns the changes that are pending on the monitor, without blocking.
+    ///
+    /// Returns `ENOBUFS` if the kernel dropped changes because they were not read fast enough, in
+    /// which case the caller should query the current state of the interfaces it cares about.
+    pub fn read_changes(&self) -> Result<Vec<RouteChange>> {
+        let buf_size = 8192;
+        let layout = Layout::from_size_align(buf_size, std::mem::align_of::<NlMsgHdr>())
+            .map_err(|_| Error::new(EINVAL))?;
+        let allocation = LayoutAllocation::uninitialized(layout);
+
+        let mut changes = Vec::new();
+        loop {
+            // SAFETY:
+            // Safe because we pass a valid, owned socket fd and a valid pointer/size for the
+            // buffer.
+            let res =
+                unsafe { libc::recv(self.sock.as_raw_fd(), allocation.as_ptr(), buf_size, 0) };
+            if res < 0 {
+                let e = Error::last();
+                if e.errno() == libc::EAGAIN {
+                    return Ok(changes);
+                }
+                return Err(e);
+            }
+
+            // SAFETY:
+            // Safe because the data in allocation was initialized up to `res` by `recv()` and is
+            // sufficiently aligned.
+            let data = unsafe { allocation.as_slice(res as usize) };
+            changes.extend(NetlinkMessageIter { data }.filter_map(|msg| parse_route_change(&msg)));
+        }
+    }
+}
+
+/// Parses a rtnetlink message into a `RouteChange`, ignoring messages of other types.
+fn parse_route_change(msg: &NetlinkMessage) -> Option<RouteChange> {
+    match msg._type {
+        libc::RTM_NEWLINK | libc::RTM_DELLINK => {
+            let info = IfInfoMsg::read_from(msg.data.get(..IFINFOMSG_SIZE)?)?;
+            let attrs = NetlinkGenericDataIter {
+                data: msg.data.get(nlattr_align(IFINFOMSG_SIZE)..).unwrap_or(&[]),
+            };
+            let name = attrs
+                .filter(|attr| attr._type == libc::IFLA_IFNAME)
+                .find_map(|attr| {
+                    let end = attr.data.iter().position(|&c| c == 0)?;
+                    str::from_utf8(&attr.data[..end]).ok().map(str::to_owned)
+                });
+            Some(RouteChange::Link(LinkChange {
+                index: info.index as u32,
+                name,
+                up: info.flags & libc::IFF_UP as u32 != 0,
+                running: info.flags & libc::IFF_RUNNING as u32 != 0,
+                removed: msg._type == libc::RTM_DELLINK,
+            }))
+        }
+        libc::RTM_NEWADDR | libc::RTM_DELADDR => {
+            let addr = IfAddrMsg::read_from(msg.data.get(..IFADDRMSG_SIZE)?)?;
+            if addr.family != libc::AF_INET as u8 {
+                return None;
+            }
+            let attrs = NetlinkGenericDataIter {
+                data: msg.data.get(nlattr_align(IFADDRMSG_SIZE)..).unwrap_or(&[]),
+            };
+            let mut address = None;
+            for attr in attrs {
+                let Ok(octets) = <[u8; 4]>::try_from(attr.data) else {
+                    continue;
+                };
+                // `IFA_LOCAL` is the address of the interface. `IFA_ADDRESS` is the same except
+                // on point-to-point links, where it is the address of the peer.
+                if attr._type == libc::IFA_LOCAL
+                    || (attr._type == libc::IFA_ADDRESS && address.is_none())
+                {
+                    address = Some(Ipv4Addr::from(octets));
+                }
+            }
+            Some(RouteChange::Addr(AddrChange {
+                index: addr.index,
+                address,
+                prefix_len: addr.prefixlen,
+                removed: msg._type == libc::RTM_DELADDR,
+            }))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::test_utils::call_test_with_sudo;
+
+    /// Appends an attribute with `data` to `msg`.
+    fn push_attr(msg: &mut Vec<u8>, _type: u16, data: &[u8]) {
+        let attr = NlAttr {
+            len: (NLA_HDRLEN + data.len()) as u16,
+            _type,
+        };
+        msg.extend_from_slice(attr.as_bytes());
+        msg.extend_from_slice(data);
+        msg.resize(nlattr_align(msg.len()), 0);
+    }
+
+    #[test]
+    fn parse_link_change() {
+        let mut data = IfInfoMsg {
+            family: 0,
+            pad: 0,
+            _type: 1,
+            index: 7,
+            flags: (libc::IFF_UP | libc::IFF_BROADCAST) as u32,
+            change: 0,
+        }
+        .as_bytes()
+        .to_vec();
+        push_attr(&mut data, libc::IFLA_MTU, &1500u32.to_ne_bytes());
+        push_attr(&mut data, libc::IFLA_IFNAME, b"eth0\0");
+        let msg = NetlinkMessage {
+            _type: libc::RTM_NEWLINK,
+            flags: 0,
+            seq: 0,
+            pid: 0,
+            data: &data,
+        };
+        assert_eq!(
+            parse_route_change(&msg),
+            Some(RouteChange::Link(LinkChange {
+                index: 7,
+                name: Some("eth0".to_owned()),
+                up: true,
+                running: false,
+                removed: false,
+            }))
+        );
+    }
+
+    #[test]
+    fn parse_addr_change() {
+        let mut data = IfAddrMsg {
+            family: libc::AF_INET as u8,
+            prefixlen: 24,
+            flags: 0,
+            scope: 0,
+            index: 3,
+        }
+        .as_bytes()
+        .to_vec();
+        push_attr(&mut data, libc::IFA_ADDRESS, &[10, 0, 0, 2]);
+        push_attr(&mut data, libc::IFA_LOCAL, &[10, 0, 0, 1]);
+        let msg = NetlinkMessage {
+            _type: libc::RTM_DELADDR,
+            flags: 0,
+            seq: 0,
+            pid: 0,
+            data: &data,
+        };
+        assert_eq!(
+            parse_route_change(&msg),
+            Some(RouteChange::Addr(AddrChange {
+                index: 3,
+                address: Some(Ipv4Addr::new(10, 0, 0, 1)),
+                prefix_len: 24,
+                removed: true,
+            }))
+        );
+
+        // Truncated messages and other message types are ignored.
+        let msg = NetlinkMessage {
+            _type: libc::RTM_NEWADDR,
+            flags: 0,
+            seq: 0,
+            pid: 0,
+            data: &data[..4],
+        };
+        assert_eq!(parse_route_change(&msg), None);
+        let msg = NetlinkMessage {
+            _type: libc::RTM_NEWROUTE,
+            flags: 0,
+            seq: 0,
+            pid: 0,
+            data: &data,
+        };
+        assert_eq!(parse_route_change(&msg), None);
+    }
+
+    fn ip(args: &[&str]) -> bool {
+        matches!(Command::new("ip").args(args).output(), Ok(output) if output.status.success())
+    }
+
+    #[test]
+    fn link_up_event() {
+        call_test_with_sudo("sys::linux::netlink::tests::link_up_event_impl")
+    }
+
+    #[ignore = "Only to be called by link_up_event"]
+    #[test]
+    fn link_up_event_impl() {
+        const NAME: &str = "crosvm-rtmon0";
+
+        let monitor = RouteMonitor::new().unwrap();
+        // Fall back to a tap device on kernels without the dummy driver.
+        assert!(
+            ip(&["link", "add", NAME, "type", "dummy"])
+                || ip(&["tuntap", "add", NAME, "mode", "tap"]),
+            "failed to create {}",
+            NAME
+        );
+        let up = ip(&["link", "set", NAME, "up"]);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut saw_up = false;
+        while up && !saw_up && Instant::now() < deadline {
+            saw_up = monitor.read_changes().unwrap().iter().any(|change| {
+                matches!(change, RouteChange::Link(link)
+                    if link.name.as_deref() == Some(NAME) && link.up && !link.removed)
+            });
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        ip(&["link", "del", NAME]);
+
+        assert!(up, "failed to bring {} up", NAME);
+        assert!(saw_up, "no LinkChange for {} going up", NAME);
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright 2023 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Wrappers for NUMA memory policy functions.
+
+use std::fs::read_to_string;
+
+use libc::c_ulong;
+use libc::syscall;
+use libc::SYS_mbind;
+use libc::EINVAL;
+use libc::MPOL_BIND;
+
+use super::errno_result;
+use super::Error;
+use super::Result;
+
+const NODE_ONLINE_PATH: &str = "/sys/devices/system/node/online";
+
+/// Returns true if `node` is in a node list formatted like `0-3,5`.
+fn node_list_contains(list: &str, node: u32) -> bool {
+    list.trim().split(',').any(|range| {
+        let mut bounds = range.splitn(2, '-').map(|n| n.trim().parse::<u32>());
+        match (bounds.next(), bounds.next()) {
+            (Some(Ok(start)), None) => node == start,
+            (Some(Ok(start)), Some(Ok(end))) => (start..=end).contains(&node),
+            _ => false,
+        }
+    })
+}
+
+/// Binds the memory policy of the host pages backing `addr..addr + len` to NUMA `node`.
+///
+/// This is meant to be applied to guest memory regions after they are mapped (e.g. in response to
+/// a `VmMemoryRequest`) so that pages faulted in later are allocated from `node`. It is only a
+/// best-effort placement hint: pages that are already populated are not migrated, and the kernel
+/// may still fall back to other nodes if `node` runs out of memory.
+///
+/// `addr` must be page aligned. Returns `EINVAL` if `node` is not online, and `ENOSYS` or
+/// `ENOENT` if the host kernel doesn't support NUMA.
+///
+/// # Safety
+///
+/// `addr..addr + len` must be a memory mapping owned by the caller. The policy applies to whatever
+/// is mapped in that range, so it must not overlap mappings that other code expects to keep their
+/// own memory policy.
+pub unsafe fn set_mempolicy_for_range(addr: *mut u8, len: usize, node: u32) -> Result<()> {
+    if !node_list_contains(&read_to_string(NODE_ONLINE_PATH)?, node) {
+        return Err(Error::new(EINVAL));
+    }
+
+    let bits_per_long = c_ulong::BITS as usize;
+    let node = node as usize;
+    let mut nodemask = vec![0 as c_ulong; node / bits_per_long + 1];
+    nodemask[node / bits_per_long] |= 1 << (node % bits_per_long);
+    // The kernel only looks at the first `maxnode - 1` bits of the mask.
+    let maxnode = nodemask.len() * bits_per_long + 1;
+
+    // SAFETY:
+    // Safe because the caller owns the given range, mbind only changes its memory policy without
+    // affecting the memory's contents, the node mask is valid for `maxnode - 1` bits, and we check
+    // the return value.
+    let ret = unsafe {
+        syscall(
+            SYS_mbind,
+            addr,
+            len,
+            MPOL_BIND,
+            nodemask.as_ptr(),
+            maxnode,
+            0,
+        )
+    };
+    if ret < 0 {
+        return errno_result();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MappedRegion;
+    use crate::MemoryMappingBuilder;
+
+    #[test]
+    fn node_list() {
+        assert!(node_list_contains("0\n", 0));
+        assert!(!node_list_contains("0\n", 1));
+        assert!(node_list_contains("0-3,5", 2));
+        assert!(node_list_contains("0-3,5", 5));
+        assert!(!node_list_contains("0-3,5", 4));
+        assert!(!node_list_contains("", 0));
+    }
+
+    #[test]
+    fn bind_range() {
+        // Skip the test on hosts without NUMA support.
+        if read_to_string(NODE_ONLINE_PATH).is_err() {
+            return;
+        }
+
+        let mmap = MemoryMappingBuilder::new(0x4000).build().unwrap();
+        // SAFETY:
+        // Safe because the range is exactly the mapping owned by `mmap`.
+        match unsafe { set_mempolicy_for_range(mmap.as_ptr(), mmap.size(), 0) } {
+            // mbind may be unavailable in a sandbox even though the node list is readable.
+            Err(e) if e.errno() == libc::ENOSYS || e.errno() == libc::EPERM => return,
+            r => r.expect("failed to bind range to node 0"),
+        }
+
+        assert_eq!(
+            // SAFETY:
+            // Safe because the range is exactly the mapping owned by `mmap`.
+            unsafe { set_mempolicy_for_range(mmap.as_ptr(), mmap.size(), 4095) },
+            Err(Error::new(EINVAL))
+        );
+    }
+}
@@ -15,6 +15,7 @@ use libc::epoll_ctl;
 use libc::epoll_event;
 use libc::epoll_wait;
 use libc::ENOENT;
+use libc::EPOLLET;
 use libc::EPOLLHUP;
 use libc::EPOLLIN;
 use libc::EPOLLOUT;
@@ -120,9 +121,34 @@ impl<T: EventToken> EventContext<T> {
         descriptor: &dyn AsRawDescriptor,
         event_type: EventType,
         token: T,
+    ) -> Result<()> {
+        self.add_with_epoll_events(descriptor, event_type.into(), token)
+    }
+
+    /// Like `add_for_event` except the `descriptor` is registered edge-triggered (`EPOLLET`).
+    ///
+    /// An edge-triggered descriptor is only reported by `wait` when its state changes (e.g. new
+    /// data arrives), not for as long as it stays ready. Callers must fully drain the descriptor
+    /// (e.g. read until `EAGAIN`) every time it is reported, otherwise any data left behind will
+    /// not cause another wakeup and may never be handled. The descriptor should usually be
+    /// non-blocking so that draining it cannot block.
+    pub fn add_for_event_edge_triggered(
+        &self,
+        descriptor: &dyn AsRawDescriptor,
+        event_type: EventType,
+        token: T,
+    ) -> Result<()> {
+        self.add_with_epoll_events(descriptor, u32::from(event_type) | EPOLLET as u32, token)
+    }
+
+    fn add_with_epoll_events(
+        &self,
+        descriptor: &dyn AsRawDescriptor,
+        events: u32,
+        token: T,
     ) -> Result<()> {
         let mut evt = epoll_event {
-            events: event_type.into(),
+            events,
             u64: token.as_raw_token(),
         };
         // SAFETY:
@@ -269,6 +295,39 @@ impl<T: EventToken> EventContext<T> {
             .collect();
         Ok(events)
     }
+
+    /// Like `wait_timeout` except events that share a token are merged into a single
+    /// `TriggeredEvent` whose readable, writable and hangup flags are the union of the merged
+    /// events.
+    ///
+    /// This is useful when several descriptors (e.g. a descriptor and its duplicate watched for
+    /// different event types) are registered with the same token, so that the handler for that
+    /// token is only dispatched once per wait. Events are returned in the order their token was
+    /// first seen.
+    pub fn wait_coalesced(&self, timeout: Duration) -> Result<SmallVec<[TriggeredEvent<T>; 16]>> {
+        Ok(coalesce_events(self.wait_timeout(timeout)?))
+    }
+}
+
+fn coalesce_events<T: EventToken>(
+    events: SmallVec<[TriggeredEvent<T>; 16]>,
+) -> SmallVec<[TriggeredEvent<T>; 16]> {
+    let mut coalesced: SmallVec<[TriggeredEvent<T>; 16]> = SmallVec::new();
+    for event in events {
+        let raw_token = event.token.as_raw_token();
+        match coalesced
+            .iter_mut()
+            .find(|e| e.token.as_raw_token() == raw_token)
+        {
+            Some(existing) => {
+                existing.is_readable |= event.is_readable;
+                existing.is_writable |= event.is_writable;
+                existing.is_hungup |= event.is_hungup;
+            }
+            None => coalesced.push(event),
+        }
+    }
+    coalesced
 }
 
 impl<T: EventToken> AsRawDescriptor for EventContext<T> {
@@ -279,6 +338,8 @@ impl<T: EventToken> AsRawDescriptor for EventContext<T> {
 
 #[cfg(test)]
 mod tests {
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
     use std::time::Instant;
 
     use base_event_token_derive::EventToken;
@@ -334,6 +395,81 @@ mod tests {
         }
     }
 
+    #[test]
+    fn event_context_wait_coalesced() {
+        let (sock, mut peer) = UnixStream::pair().unwrap();
+        let sock_dup = sock.try_clone().unwrap();
+        peer.write_all(&[1]).unwrap();
+
+        // The same socket is watched through two descriptors sharing one token, so a plain wait
+        // reports it twice.
+        let ctx: EventContext<u32> = EventContext::new().unwrap();
+        ctx.add_for_event(&sock, EventType::Read, 1).unwrap();
+        ctx.add_for_event(&sock_dup, EventType::Write, 1).unwrap();
+        assert_eq!(ctx.wait_timeout(Duration::ZERO).unwrap().len(), 2);
+
+        let events = ctx.wait_coalesced(Duration::ZERO).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].token, 1);
+        assert!(events[0].is_readable);
+        assert!(events[0].is_writable);
+        assert!(!events[0].is_hungup);
+
+        // Hangups are preserved through coalescing.
+        drop(peer);
+        let events = ctx.wait_coalesced(Duration::ZERO).unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(events[0].is_readable);
+        assert!(events[0].is_writable);
+        assert!(events[0].is_hungup);
+    }
+
+    #[test]
+    fn event_context_edge_triggered() {
+        let level_evt = Event::new().unwrap();
+        let edge_evt = Event::new().unwrap();
+        let ctx: EventContext<u32> = EventContext::new().unwrap();
+        ctx.add_for_event(&level_evt, EventType::Read, 1).unwrap();
+        ctx.add_for_event_edge_triggered(&edge_evt, EventType::Read, 2)
+            .unwrap();
+
+        for _ in 0..3 {
+            level_evt.signal().unwrap();
+            edge_evt.signal().unwrap();
+        }
+
+        // Both fire after the burst of writes.
+        let mut tokens: Vec<u32> = ctx
+            .wait_timeout(Duration::ZERO)
+            .unwrap()
+            .iter()
+            .map(|e| e.token)
+            .collect();
+        tokens.sort_unstable();
+        assert_eq!(tokens, vec![1, 2]);
+
+        // Without draining, only the level-triggered event keeps firing.
+        let events = ctx.wait_timeout(Duration::ZERO).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].token, 1);
+
+        // A new write re-arms the edge-triggered event.
+        edge_evt.signal().unwrap();
+        let mut tokens: Vec<u32> = ctx
+            .wait_timeout(Duration::ZERO)
+            .unwrap()
+            .iter()
+            .map(|e| e.token)
+            .collect();
+        tokens.sort_unstable();
+        assert_eq!(tokens, vec![1, 2]);
+
+        // Once drained, neither fires.
+        level_evt.wait().unwrap();
+        edge_evt.wait().unwrap();
+        assert!(ctx.wait_timeout(Duration::ZERO).unwrap().is_empty());
+    }
+
     #[test]
     fn event_context_timeout() {
         let ctx: EventContext<u32> = EventContext::new().unwrap();
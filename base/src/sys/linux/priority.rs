@@ -2,9 +2,17 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::marker::PhantomData;
 use std::mem::MaybeUninit;
 
+use libc::EINVAL;
+use log::error;
+
 use super::errno_result;
+use super::sched_attr;
+use super::sched_getattr;
+use super::sched_setattr;
+use super::Error;
 use super::Result;
 
 /// Enables real time thread priorities in the current thread up to `limit`.
@@ -43,3 +51,103 @@ pub fn set_rt_round_robin(priority: i32) -> Result<()> {
         Ok(())
     }
 }
+
+/// Scheduling policy of the current thread that is set to `SCHED_FIFO` while the guard is alive.
+///
+/// Dropping the guard restores the policy and priority the thread had before
+/// [`RealtimeGuard::enable`]. Scheduling attributes apply to a single thread, so the guard must be
+/// dropped on the thread that created it.
+pub struct RealtimeGuard {
+    prev_attr: sched_attr,
+    // Keeps the guard on the thread whose policy it changed.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl RealtimeGuard {
+    /// Schedules the current thread with the `SCHED_FIFO` real time policy at `priority`.
+    ///
+    /// Returns `EINVAL` if `priority` is outside the range supported by `SCHED_FIFO` (1 to 99 on
+    /// Linux). Changing the policy requires `CAP_SYS_NICE`, or an `RLIMIT_RTPRIO` limit of at
+    /// least `priority` (see [`set_rt_prio_limit`]), otherwise `EPERM` is returned.
+    pub fn enable(priority: u32) -> Result<RealtimeGuard> {
+        // SAFETY: trivially safe
+        let min = unsafe { libc::sched_get_priority_min(libc::SCHED_FIFO) };
+        // SAFETY: trivially safe
+        let max = unsafe { libc::sched_get_priority_max(libc::SCHED_FIFO) };
+        if min < 0 || max < 0 {
+            return errno_result();
+        }
+        if !(min as u32..=max as u32).contains(&priority) {
+            return Err(Error::new(EINVAL));
+        }
+
+        let mut prev_attr = sched_attr::default();
+        sched_getattr(0, &mut prev_attr, 0)?;
+
+        let mut attr = sched_attr::default();
+        attr.sched_policy = libc::SCHED_FIFO as u32;
+        attr.sched_priority = priority;
+        sched_setattr(0, &mut attr, 0)?;
+
+        Ok(RealtimeGuard {
+            prev_attr,
+            _not_send: PhantomData,
+        })
+    }
+}
+
+impl Drop for RealtimeGuard {
+    fn drop(&mut self) {
+        if let Err(e) = sched_setattr(0, &mut self.prev_attr, 0) {
+            error!("failed to restore the thread scheduling policy: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use super::*;
+
+    const CAP_SYS_NICE: u32 = 23;
+
+    fn has_cap_sys_nice() -> bool {
+        let caps = read_to_string("/proc/self/status")
+            .unwrap()
+            .lines()
+            .find_map(|line| line.strip_prefix("CapEff:"))
+            .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok());
+        matches!(caps, Some(caps) if caps & (1 << CAP_SYS_NICE) != 0)
+    }
+
+    fn current_policy() -> (u32, u32) {
+        let mut attr = sched_attr::default();
+        sched_getattr(0, &mut attr, 0).unwrap();
+        (attr.sched_policy, attr.sched_priority)
+    }
+
+    #[test]
+    fn realtime_guard_invalid_priority() {
+        assert_eq!(RealtimeGuard::enable(0).err(), Some(Error::new(EINVAL)));
+        assert_eq!(RealtimeGuard::enable(100).err(), Some(Error::new(EINVAL)));
+    }
+
+    #[test]
+    fn realtime_guard_restores_policy() {
+        if !has_cap_sys_nice() {
+            return;
+        }
+
+        // Run on a separate thread so the test harness thread keeps its policy if the test fails.
+        std::thread::spawn(|| {
+            let prev = current_policy();
+            let guard = RealtimeGuard::enable(10).unwrap();
+            assert_eq!(current_policy(), (libc::SCHED_FIFO as u32, 10));
+            drop(guard);
+            assert_eq!(current_policy(), prev);
+        })
+        .join()
+        .unwrap();
+    }
+}
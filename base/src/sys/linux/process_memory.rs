@@ -0,0 +1,175 @@
+// Copyright 2023 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Memory usage accounting for processes, based on `/proc/<pid>/smaps_rollup`.
+
+use std::fs::read_to_string;
+
+use libc::EINVAL;
+use libc::ENOENT;
+
+use super::Error;
+use super::Result;
+use crate::pagesize;
+use crate::Pid;
+
+/// Summary of the memory used by a process, in bytes.
+///
+/// The fields other than `rss` are only available from `smaps_rollup`. On kernels without it
+/// (before 4.14), they are `None`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemorySummary {
+    /// Resident set size: memory mapped by the process that is currently in RAM.
+    pub rss: u64,
+    /// Proportional set size: `rss` with each shared page divided by the number of processes
+    /// mapping it.
+    pub pss: Option<u64>,
+    /// Anonymous memory of the process that is swapped out.
+    pub swap: Option<u64>,
+    /// Resident memory shared with other processes and not modified since it was read.
+    pub shared_clean: Option<u64>,
+    /// Resident memory only mapped by this process and modified since it was read.
+    pub private_dirty: Option<u64>,
+}
+
+/// Parses the contents of a `smaps_rollup` file.
+fn parse_smaps_rollup(contents: &str) -> Result<MemorySummary> {
+    let mut rss = None;
+    let mut summary = MemorySummary::default();
+    // The first line is the `[rollup]` pseudo-mapping header, which doesn't contain a ':'.
+    for line in contents.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let field = match key {
+            "Rss" => &mut rss,
+            "Pss" => &mut summary.pss,
+            "Swap" => &mut summary.swap,
+            "Shared_Clean" => &mut summary.shared_clean,
+            "Private_Dirty" => &mut summary.private_dirty,
+            _ => continue,
+        };
+        let kb = value
+            .trim()
+            .strip_suffix("kB")
+            .and_then(|kb| kb.trim().parse::<u64>().ok())
+            .ok_or_else(|| Error::new(EINVAL))?;
+        *field = Some(kb * 1024);
+    }
+
+    summary.rss = rss.ok_or_else(|| Error::new(EINVAL))?;
+    if summary.pss.is_none()
+        || summary.swap.is_none()
+        || summary.shared_clean.is_none()
+        || summary.private_dirty.is_none()
+    {
+        return Err(Error::new(EINVAL));
+    }
+    Ok(summary)
+}
+
+/// Parses the contents of a `statm` file, whose sizes are counted in pages of `page_size` bytes.
+fn parse_statm(contents: &str, page_size: u64) -> Result<MemorySummary> {
+    // The fields are size, resident, shared, text, lib, data and dt.
+    let resident = contents
+        .split_whitespace()
+        .nth(1)
+        .and_then(|pages| pages.parse::<u64>().ok())
+        .ok_or_else(|| Error::new(EINVAL))?;
+    Ok(MemorySummary {
+        rss: resident * page_size,
+        ..Default::default()
+    })
+}
+
+/// Returns a summary of the memory used by process `pid`.
+///
+/// The summary is read from `/proc/<pid>/smaps_rollup`. On kernels where it is unavailable, only
+/// the resident set size is reported, read from `/proc/<pid>/statm`. Reading another process's
+/// `smaps_rollup` requires ptrace read access to it, otherwise `EACCES` is returned.
+pub fn process_memory_summary(pid: Pid) -> Result<MemorySummary> {
+    match read_to_string(format!("/proc/{}/smaps_rollup", pid)) {
+        Ok(contents) => parse_smaps_rollup(&contents),
+        Err(e) if e.raw_os_error() == Some(ENOENT) => {
+            let contents = read_to_string(format!("/proc/{}/statm", pid))?;
+            parse_statm(&contents, pagesize() as u64)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::getpid;
+
+    const SMAPS_ROLLUP: &str = "\
+55d1e4a2c000-7ffc8f5fd000 ---p 00000000 00:00 0                          [rollup]
+Rss:               14628 kB
+Pss:                5031 kB
+Pss_Anon:           3268 kB
+Pss_File:           1763 kB
+Pss_Shmem:             0 kB
+Shared_Clean:       9592 kB
+Shared_Dirty:          0 kB
+Private_Clean:       768 kB
+Private_Dirty:      4268 kB
+Referenced:        14628 kB
+Anonymous:          3268 kB
+LazyFree:              0 kB
+AnonHugePages:         0 kB
+ShmemPmdMapped:        0 kB
+FilePmdMapped:         0 kB
+Shared_Hugetlb:        0 kB
+Private_Hugetlb:       0 kB
+Swap:                 12 kB
+SwapPss:              12 kB
+Locked:                0 kB
+";
+
+    #[test]
+    fn parse_smaps_rollup_fixture() {
+        assert_eq!(
+            parse_smaps_rollup(SMAPS_ROLLUP).unwrap(),
+            MemorySummary {
+                rss: 14628 * 1024,
+                pss: Some(5031 * 1024),
+                swap: Some(12 * 1024),
+                shared_clean: Some(9592 * 1024),
+                private_dirty: Some(4268 * 1024),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(parse_smaps_rollup(""), Err(Error::new(EINVAL)));
+        assert_eq!(
+            parse_smaps_rollup("Rss: 12 kB\nPss: 4 kB\n"),
+            Err(Error::new(EINVAL))
+        );
+        assert_eq!(
+            parse_smaps_rollup(&SMAPS_ROLLUP.replace("14628 kB", "14628 pages")),
+            Err(Error::new(EINVAL))
+        );
+        assert_eq!(parse_statm("1234", 4096), Err(Error::new(EINVAL)));
+    }
+
+    #[test]
+    fn parse_statm_fallback() {
+        assert_eq!(
+            parse_statm("5840 3657 2398 200 0 1441 0\n", 4096).unwrap(),
+            MemorySummary {
+                rss: 3657 * 4096,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn current_process() {
+        let summary = process_memory_summary(getpid()).unwrap();
+        assert!(summary.rss > 0);
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright 2023 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Wrappers for `process_vm_readv` and `process_vm_writev` to access another process's memory.
+
+use libc::c_void;
+use libc::iovec;
+use libc::EINVAL;
+
+use super::errno_result;
+use super::Error;
+use super::Result;
+use crate::Pid;
+
+// The kernel rejects more iovecs than this with `EINVAL`.
+const IOV_MAX: usize = 1024;
+
+/// A range of memory in another process's address space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RemoteIoVec {
+    /// Start address of the range in the remote process.
+    pub addr: usize,
+    /// Length of the range in bytes.
+    pub len: usize,
+}
+
+/// Converts `remote_iov` to `iovec`s, checking that it fits the kernel limit and that the total
+/// length doesn't overflow or exceed `local_len`.
+fn remote_iovecs(remote_iov: &[RemoteIoVec], local_len: usize) -> Result<Vec<iovec>> {
+    if remote_iov.len() > IOV_MAX {
+        return Err(Error::new(EINVAL));
+    }
+    let total = remote_iov
+        .iter()
+        .try_fold(0usize, |total, iov| total.checked_add(iov.len))
+        .ok_or_else(|| Error::new(EINVAL))?;
+    if total > local_len || total > isize::MAX as usize {
+        return Err(Error::new(EINVAL));
+    }
+    Ok(remote_iov
+        .iter()
+        .map(|iov| iovec {
+            iov_base: iov.addr as *mut c_void,
+            iov_len: iov.len,
+        })
+        .collect())
+}
+
+/// Reads the memory ranges `remote_iov` of process `pid` into consecutive bytes of `local_buf`.
+///
+/// Returns the number of bytes read, which may be less than requested if one of the remote ranges
+/// is only partially mapped. The total length of `remote_iov` must not exceed `local_buf`, and at
+/// most 1024 ranges may be given, otherwise `EINVAL` is returned.
+///
+/// The caller needs ptrace access to `pid` (`PTRACE_MODE_ATTACH_REALCREDS`): the target must run
+/// with the same credentials as the caller or the caller must have `CAP_SYS_PTRACE`, and Yama
+/// (`/proc/sys/kernel/yama/ptrace_scope`) may further restrict access to descendant processes.
+/// Missing permission is reported as `EPERM`, and a target that doesn't exist as `ESRCH`.
+pub fn process_vm_read(
+    pid: Pid,
+    remote_iov: &[RemoteIoVec],
+    local_buf: &mut [u8],
+) -> Result<usize> {
+    let remote = remote_iovecs(remote_iov, local_buf.len())?;
+    let local = iovec {
+        iov_base: local_buf.as_mut_ptr() as *mut c_void,
+        iov_len: local_buf.len(),
+    };
+    // SAFETY:
+    // Safe because the kernel only writes to `local_buf`, which we own and which is valid for
+    // `local.iov_len` bytes, and we check the return value.
+    let ret = unsafe {
+        libc::process_vm_readv(
+            pid,
+            &local,
+            1,
+            remote.as_ptr(),
+            remote.len() as libc::c_ulong,
+            0,
+        )
+    };
+    if ret < 0 {
+        return errno_result();
+    }
+    Ok(ret as usize)
+}
+
+/// Writes consecutive bytes of `local_buf` to the memory ranges `remote_iov` of process `pid`.
+///
+/// Returns the number of bytes written, which may be less than requested if one of the remote
+/// ranges is only partially mapped. Validation and permission requirements are the same as for
+/// [`process_vm_read`].
+///
+/// # Safety
+///
+/// If `pid` shares its address space with the calling process (e.g. it is the calling process),
+/// the remote ranges must not overlap any memory that Rust code in this process expects to remain
+/// unchanged.
+pub unsafe fn process_vm_write(
+    pid: Pid,
+    remote_iov: &[RemoteIoVec],
+    local_buf: &[u8],
+) -> Result<usize> {
+    let remote = remote_iovecs(remote_iov, local_buf.len())?;
+    let local = iovec {
+        iov_base: local_buf.as_ptr() as *mut c_void,
+        iov_len: local_buf.len(),
+    };
+    // SAFETY:
+    // Safe because the kernel only reads from `local_buf`, the caller guarantees that the remote
+    // ranges are safe to modify, and we check the return value.
+    let ret = unsafe {
+        libc::process_vm_writev(
+            pid,
+            &local,
+            1,
+            remote.as_ptr(),
+            remote.len() as libc::c_ulong,
+            0,
+        )
+    };
+    if ret < 0 {
+        return errno_result();
+    }
+    Ok(ret as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::io::Write;
+
+    use super::*;
+    use crate::linux::wait_for_pid;
+    use crate::pipe;
+
+    const SENTINEL: &[u8; 8] = b"crosvm!\0";
+
+    #[test]
+    fn invalid_iovecs() {
+        let mut buf = [0u8; 4];
+        let too_long = [RemoteIoVec { addr: 0, len: 8 }];
+        assert_eq!(
+            process_vm_read(0, &too_long, &mut buf),
+            Err(Error::new(EINVAL))
+        );
+        let overflow = [
+            RemoteIoVec {
+                addr: 0,
+                len: usize::MAX,
+            },
+            RemoteIoVec { addr: 0, len: 1 },
+        ];
+        assert_eq!(
+            process_vm_read(0, &overflow, &mut buf),
+            Err(Error::new(EINVAL))
+        );
+        let too_many = vec![RemoteIoVec { addr: 0, len: 0 }; IOV_MAX + 1];
+        assert_eq!(
+            process_vm_read(0, &too_many, &mut buf),
+            Err(Error::new(EINVAL))
+        );
+    }
+
+    #[test]
+    fn read_child_memory() {
+        let mut buf = [0u8; 8];
+        let (mut ready_rx, mut ready_tx) = pipe(true).unwrap();
+        let (mut exit_rx, mut exit_tx) = pipe(true).unwrap();
+
+        // SAFETY:
+        // Safe because the child only writes to its own copy of `buf` and to pipes before exiting.
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0, "fork failed");
+        if pid == 0 {
+            buf.copy_from_slice(SENTINEL);
+            let _ = ready_tx.write_all(&[1]);
+            // Stay alive until the parent has read the sentinel.
+            let _ = exit_rx.read(&mut [0]);
+            // SAFETY:
+            // Safe because _exit never returns and skips the test harness's exit handlers.
+            unsafe { libc::_exit(0) };
+        }
+
+        ready_rx.read_exact(&mut [0]).unwrap();
+        let remote = [RemoteIoVec {
+            addr: buf.as_ptr() as usize,
+            len: buf.len(),
+        }];
+        let mut local = [0u8; 8];
+        let result = process_vm_read(pid, &remote, &mut local);
+        exit_tx.write_all(&[1]).unwrap();
+        wait_for_pid(pid, 0).unwrap();
+
+        match result {
+            // Cross-process access may be forbidden in a sandbox.
+            Err(e) if e.errno() == libc::EPERM || e.errno() == libc::ENOSYS => {}
+            r => {
+                assert_eq!(r.unwrap(), SENTINEL.len());
+                assert_eq!(&local, SENTINEL);
+                // The parent's copy of the buffer was never written.
+                assert_eq!(buf, [0u8; 8]);
+            }
+        }
+    }
+}
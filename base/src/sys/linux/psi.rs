@@ -0,0 +1,196 @@
+// Copyright 2023 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Host memory pressure notifications using the Linux PSI (pressure stall information) interface.
+
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::time::Duration;
+
+use libc::epoll_create1;
+use libc::epoll_ctl;
+use libc::epoll_event;
+use libc::epoll_wait;
+use libc::EPOLLPRI;
+use libc::EPOLL_CLOEXEC;
+use libc::EPOLL_CTL_ADD;
+use libc::O_NONBLOCK;
+
+use super::errno_result;
+use super::Error;
+use super::Result;
+use crate::handle_eintr_errno;
+use crate::AsRawDescriptor;
+use crate::FromRawDescriptor;
+use crate::RawDescriptor;
+
+const PSI_MEMORY_PATH: &str = "/proc/pressure/memory";
+
+/// Which tasks must be stalled on memory for the time to count towards a PSI trigger.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PsiStallType {
+    /// At least one task is stalled on memory.
+    Some,
+    /// All non-idle tasks are stalled on memory at the same time.
+    Full,
+}
+
+impl PsiStallType {
+    fn as_str(self) -> &'static str {
+        match self {
+            PsiStallType::Some => "some",
+            PsiStallType::Full => "full",
+        }
+    }
+}
+
+/// Watches host memory pressure through a PSI trigger on `/proc/pressure/memory`.
+///
+/// The monitor's descriptor becomes readable once tasks have been stalled on memory for more than
+/// `stall` time within a `window`, so it can be added to a `WaitContext` like an `Event`. After it
+/// fires, call [`clear`](Self::clear) before waiting on it again.
+pub struct PsiMemoryMonitor {
+    // The PSI trigger only reports `EPOLLPRI`, so it is wrapped in an epoll instance that reports
+    // `EPOLLIN` when the trigger fires.
+    epoll_ctx: File,
+    // Closing the PSI file removes the trigger, so it has to be kept open.
+    _psi_file: File,
+}
+
+impl PsiMemoryMonitor {
+    /// Creates a monitor that fires when `stall_type` memory stalls exceed `stall` within any
+    /// `window` of time.
+    ///
+    /// The kernel requires `stall` to be non-zero and no larger than `window`, and `window` to be
+    /// between 500ms and 10s. Without `CAP_SYS_RESOURCE`, `window` must also be a multiple of 2s.
+    /// Returns `ENOENT` if the kernel doesn't support PSI.
+    pub fn new(stall_type: PsiStallType, stall: Duration, window: Duration) -> Result<Self> {
+        let mut psi_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(O_NONBLOCK)
+            .open(PSI_MEMORY_PATH)?;
+        // The kernel expects the trigger to be nul-terminated.
+        let trigger = format!(
+            "{} {} {}\0",
+            stall_type.as_str(),
+            stall.as_micros(),
+            window.as_micros()
+        );
+        psi_file.write_all(trigger.as_bytes())?;
+
+        // SAFETY:
+        // Safe because we check the return value.
+        let epoll_fd = unsafe { epoll_create1(EPOLL_CLOEXEC) };
+        if epoll_fd < 0 {
+            return errno_result();
+        }
+        // SAFETY:
+        // Safe because epoll_fd is valid and nothing else owns it.
+        let epoll_ctx = unsafe { File::from_raw_descriptor(epoll_fd) };
+
+        let mut evt = epoll_event {
+            events: EPOLLPRI as u32,
+            u64: 0,
+        };
+        // SAFETY:
+        // Safe because we give a valid epoll FD and FD to watch, as well as a valid epoll_event
+        // structure. Then we check the return value.
+        let ret = unsafe {
+            epoll_ctl(
+                epoll_ctx.as_raw_descriptor(),
+                EPOLL_CTL_ADD,
+                psi_file.as_raw_descriptor(),
+                &mut evt,
+            )
+        };
+        if ret < 0 {
+            return errno_result();
+        }
+
+        Ok(PsiMemoryMonitor {
+            epoll_ctx,
+            _psi_file: psi_file,
+        })
+    }
+
+    /// Consumes a pending pressure notification, if any, so the descriptor stops being readable.
+    ///
+    /// Returns `true` if a notification was pending. Note that polling the monitor's descriptor
+    /// from another epoll instance may already consume the notification, in which case this
+    /// returns `false` even though the descriptor was reported readable.
+    pub fn clear(&self) -> Result<bool> {
+        let mut evt = epoll_event { events: 0, u64: 0 };
+        // SAFETY:
+        // Safe because we give a valid epoll FD and a buffer for a single event, and we check the
+        // return value. A zero timeout makes this non-blocking.
+        let ret = handle_eintr_errno!(unsafe {
+            epoll_wait(self.epoll_ctx.as_raw_descriptor(), &mut evt, 1, 0)
+        });
+        if ret < 0 {
+            return Err(Error::last());
+        }
+        Ok(ret > 0)
+    }
+}
+
+impl AsRawDescriptor for PsiMemoryMonitor {
+    fn as_raw_descriptor(&self) -> RawDescriptor {
+        self.epoll_ctx.as_raw_descriptor()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::EventToken;
+    use crate::WaitContext;
+
+    #[derive(EventToken)]
+    enum Token {
+        Pressure,
+    }
+
+    // PSI may be compiled out or restricted to privileged users.
+    fn psi_unsupported(e: &Error) -> bool {
+        [libc::ENOENT, libc::EPERM, libc::EACCES, libc::EOPNOTSUPP].contains(&e.errno())
+    }
+
+    #[test]
+    fn memory_monitor_pollable() {
+        let monitor = match PsiMemoryMonitor::new(
+            PsiStallType::Some,
+            Duration::from_millis(150),
+            Duration::from_secs(2),
+        ) {
+            Ok(m) => m,
+            Err(e) if psi_unsupported(&e) => return,
+            Err(e) => panic!("failed to create PSI monitor: {}", e),
+        };
+
+        // Whether or not the host is under pressure, the monitor must be usable with a
+        // `WaitContext` and clearing it must not fail.
+        let wait_ctx = WaitContext::build_with(&[(&monitor, Token::Pressure)]).unwrap();
+        wait_ctx.wait_timeout(Duration::ZERO).unwrap();
+        monitor.clear().unwrap();
+    }
+
+    #[test]
+    fn memory_monitor_invalid_trigger() {
+        // A stall time longer than the window is rejected by the kernel.
+        match PsiMemoryMonitor::new(
+            PsiStallType::Full,
+            Duration::from_secs(4),
+            Duration::from_secs(2),
+        ) {
+            Ok(_) => panic!("invalid PSI trigger was accepted"),
+            Err(e) if psi_unsupported(&e) => {}
+            Err(e) => assert_eq!(e.errno(), libc::EINVAL),
+        }
+    }
+}
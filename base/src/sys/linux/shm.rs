@@ -3,6 +3,7 @@
 // found in the LICENSE file.
 
 use std::ffi::CStr;
+use std::ffi::CString;
 use std::fs::File;
 use std::io::Seek;
 use std::io::SeekFrom;
@@ -17,6 +18,7 @@ use libc::ftruncate64;
 use libc::off64_t;
 use libc::syscall;
 use libc::SYS_memfd_create;
+use libc::EINVAL;
 use libc::F_ADD_SEALS;
 use libc::F_GET_SEALS;
 use libc::F_SEAL_FUTURE_WRITE;
@@ -25,12 +27,16 @@ use libc::F_SEAL_SEAL;
 use libc::F_SEAL_SHRINK;
 use libc::F_SEAL_WRITE;
 use libc::MFD_ALLOW_SEALING;
+use libc::MFD_HUGETLB;
+use libc::MFD_HUGE_1GB;
+use libc::MFD_HUGE_2MB;
 use once_cell::sync::Lazy;
 
 use crate::errno_result;
 use crate::shm::PlatformSharedMemory;
 use crate::trace;
 use crate::AsRawDescriptor;
+use crate::Error;
 use crate::FromRawDescriptor;
 use crate::Result;
 use crate::SafeDescriptor;
@@ -59,6 +65,24 @@ impl MemfdSeals {
         MemfdSeals(0)
     }
 
+    /// Returns a builder for constructing a set of memfd seals, starting with no seals.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use base::linux::MemfdSeals;
+    /// # use base::SharedMemory;
+    /// # fn test() -> base::Result<()> {
+    /// let shm = SharedMemory::new("example", 4096)?;
+    /// MemfdSeals::builder().shrink().grow().seal().apply_to(&shm)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[inline]
+    pub fn builder() -> MemfdSealsBuilder {
+        MemfdSealsBuilder(MemfdSeals::new())
+    }
+
     /// Gets the raw bitmask of seals enumerated in `fcntl(2)`.
     #[inline]
     pub fn bitmask(self) -> i32 {
@@ -124,6 +148,74 @@ impl MemfdSeals {
     pub fn set_seal_seal(&mut self) {
         self.0 |= F_SEAL_SEAL;
     }
+
+    /// Adds this set of seals to `shm` in a single `F_ADD_SEALS` call.
+    ///
+    /// Fails with `EINVAL` if `shm` is not backed by a memfd, and with `EPERM` if the memfd was
+    /// created without sealing allowed or already has the seal seal (`F_SEAL_SEAL`) bit. Adding
+    /// the write seal also fails with `EBUSY` while `shm` has writable shared mappings.
+    pub fn apply_to(self, shm: &SharedMemory) -> Result<()> {
+        // SAFETY:
+        // Safe because we check the return value to fcntl and all the args to the function are
+        // valid.
+        let ret = unsafe { fcntl(shm.descriptor.as_raw_descriptor(), F_ADD_SEALS, self.0) };
+        if ret < 0 {
+            return errno_result();
+        }
+        Ok(())
+    }
+}
+
+/// Builds a `MemfdSeals` by chaining the seals to add.
+#[derive(Copy, Clone, Default)]
+pub struct MemfdSealsBuilder(MemfdSeals);
+
+impl MemfdSealsBuilder {
+    /// Adds the shrink seal.
+    #[inline]
+    pub fn shrink(mut self) -> Self {
+        self.0.set_shrink_seal();
+        self
+    }
+
+    /// Adds the grow seal.
+    #[inline]
+    pub fn grow(mut self) -> Self {
+        self.0.set_grow_seal();
+        self
+    }
+
+    /// Adds the write seal.
+    #[inline]
+    pub fn write(mut self) -> Self {
+        self.0.set_write_seal();
+        self
+    }
+
+    /// Adds the future write seal.
+    #[inline]
+    pub fn future_write(mut self) -> Self {
+        self.0.set_future_write_seal();
+        self
+    }
+
+    /// Adds the seal seal, which prevents any further seals from being added.
+    #[inline]
+    pub fn seal(mut self) -> Self {
+        self.0.set_seal_seal();
+        self
+    }
+
+    /// Returns the set of seals built so far.
+    #[inline]
+    pub fn build(self) -> MemfdSeals {
+        self.0
+    }
+
+    /// Adds the seals built so far to `shm`. See `MemfdSeals::apply_to`.
+    pub fn apply_to(self, shm: &SharedMemory) -> Result<()> {
+        self.0.apply_to(shm)
+    }
 }
 
 static MFD_NOEXEC_SEAL_SUPPORTED: Lazy<bool> = Lazy::new(|| {
@@ -149,6 +241,83 @@ static MFD_NOEXEC_SEAL_SUPPORTED: Lazy<bool> = Lazy::new(|| {
     }
 });
 
+/// Size of the huge pages backing a `SharedMemory` created with `SharedMemory::new_huge`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HugePageSize {
+    /// 2 MiB pages.
+    Size2MiB,
+    /// 1 GiB pages.
+    Size1GiB,
+}
+
+impl HugePageSize {
+    /// Returns the page size in bytes.
+    pub fn bytes(self) -> u64 {
+        match self {
+            HugePageSize::Size2MiB => 2 << 20,
+            HugePageSize::Size1GiB => 1 << 30,
+        }
+    }
+
+    fn memfd_flags(self) -> c_uint {
+        MFD_HUGETLB
+            | match self {
+                HugePageSize::Size2MiB => MFD_HUGE_2MB,
+                HugePageSize::Size1GiB => MFD_HUGE_1GB,
+            }
+    }
+}
+
+/// Creates a memfd of `size` bytes with the close on exec flag, sealing allowed and, if supported,
+/// `MFD_NOEXEC_SEAL`, plus any `extra_flags`.
+fn memfd_new(debug_name: &CStr, size: u64, extra_flags: c_uint) -> Result<SharedMemory> {
+    let mut flags = MFD_CLOEXEC | MFD_ALLOW_SEALING | extra_flags;
+    if *MFD_NOEXEC_SEAL_SUPPORTED {
+        flags |= MFD_NOEXEC_SEAL;
+    }
+
+    let shm_name = debug_name.as_ptr() as *const c_char;
+    // SAFETY:
+    // The following are safe because we give a valid C string and check the
+    // results of the memfd_create call.
+    let fd = unsafe { memfd_create(shm_name, flags) };
+    if fd < 0 {
+        return errno_result();
+    }
+    // SAFETY: Safe because fd is valid.
+    let descriptor = unsafe { SafeDescriptor::from_raw_descriptor(fd) };
+
+    // Set the size of the memfd.
+    // SAFETY: Safe because we check the return value to ftruncate64 and all the args to the
+    // function are valid.
+    let ret = unsafe { ftruncate64(descriptor.as_raw_descriptor(), size as off64_t) };
+    if ret < 0 {
+        return errno_result();
+    }
+
+    Ok(SharedMemory { descriptor, size })
+}
+
+impl SharedMemory {
+    /// Creates a new shared memory object of the given size backed by huge pages of
+    /// `huge_page_size`.
+    ///
+    /// `size` must be a multiple of `huge_page_size`, otherwise `EINVAL` is returned. There is no
+    /// fallback to regular pages: creation fails if the kernel doesn't support hugetlb memfds of
+    /// that size, and mapping the memory fails if the hugetlb pool doesn't have enough free pages.
+    pub fn new_huge<T: Into<Vec<u8>>>(
+        debug_name: T,
+        size: u64,
+        huge_page_size: HugePageSize,
+    ) -> Result<SharedMemory> {
+        if size % huge_page_size.bytes() != 0 {
+            return Err(Error::new(EINVAL));
+        }
+        let debug_name = CString::new(debug_name).map_err(|_| Error::new(EINVAL))?;
+        memfd_new(&debug_name, size, huge_page_size.memfd_flags())
+    }
+}
+
 impl PlatformSharedMemory for SharedMemory {
     /// Creates a new shared memory file descriptor with the specified `size` in bytes.
     ///
@@ -161,31 +330,7 @@ impl PlatformSharedMemory for SharedMemory {
     /// non-executable file mode (in other words, it cannot be passed to the `exec` family of system
     /// calls).
     fn new(debug_name: &CStr, size: u64) -> Result<SharedMemory> {
-        let mut flags = MFD_CLOEXEC | MFD_ALLOW_SEALING;
-        if *MFD_NOEXEC_SEAL_SUPPORTED {
-            flags |= MFD_NOEXEC_SEAL;
-        }
-
-        let shm_name = debug_name.as_ptr() as *const c_char;
-        // SAFETY:
-        // The following are safe because we give a valid C string and check the
-        // results of the memfd_create call.
-        let fd = unsafe { memfd_create(shm_name, flags) };
-        if fd < 0 {
-            return errno_result();
-        }
-        // SAFETY: Safe because fd is valid.
-        let descriptor = unsafe { SafeDescriptor::from_raw_descriptor(fd) };
-
-        // Set the size of the memfd.
-        // SAFETY: Safe because we check the return value to ftruncate64 and all the args to the
-        // function are valid.
-        let ret = unsafe { ftruncate64(descriptor.as_raw_descriptor(), size as off64_t) };
-        if ret < 0 {
-            return errno_result();
-        }
-
-        Ok(SharedMemory { descriptor, size })
+        memfd_new(debug_name, size, 0)
     }
 
     /// Creates a SharedMemory instance from a SafeDescriptor owning a reference to a
@@ -235,22 +380,25 @@ impl SharedMemoryLinux for SharedMemory {
     }
 
     fn add_seals(&mut self, seals: MemfdSeals) -> Result<()> {
-        // SAFETY: Safe because we check the return value to fcntl and all the args to the
-        // function are valid.
-        let ret = unsafe { fcntl(self.descriptor.as_raw_descriptor(), F_ADD_SEALS, seals) };
-        if ret < 0 {
-            return errno_result();
-        }
-        Ok(())
+        seals.apply_to(self)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::fs::read_link;
+    use std::fs::File;
+    use std::os::unix::fs::MetadataExt;
 
     use libc::EINVAL;
-
+    use libc::F_GET_SEALS;
+    use libc::F_SEAL_GROW;
+    use libc::F_SEAL_SEAL;
+    use libc::F_SEAL_SHRINK;
+    use libc::F_SEAL_WRITE;
+
+    use crate::linux::HugePageSize;
+    use crate::linux::MemfdSeals;
     use crate::linux::SharedMemoryLinux;
     use crate::pagesize;
     use crate::AsRawDescriptor;
@@ -292,6 +440,40 @@ mod tests {
         assert_eq!(shm.size(), 0x7fff_ffff_ffff_ffff);
     }
 
+    #[test]
+    fn new_huge_unaligned_size() {
+        assert_eq!(
+            SharedMemory::new_huge("test", 4096, HugePageSize::Size2MiB).err(),
+            Some(Error::new(EINVAL))
+        );
+    }
+
+    #[test]
+    fn new_huge_pages() {
+        // Only run if the host has a free 2 MiB huge page to back the mapping.
+        let free_pages =
+            std::fs::read_to_string("/sys/kernel/mm/hugepages/hugepages-2048kB/free_hugepages")
+                .ok()
+                .and_then(|s| s.trim().parse::<u64>().ok())
+                .unwrap_or(0);
+        if free_pages == 0 {
+            return;
+        }
+
+        let page_size = HugePageSize::Size2MiB.bytes();
+        let shm = SharedMemory::new_huge("test", page_size, HugePageSize::Size2MiB)
+            .expect("failed to create huge page shared memory");
+        let mmap = MemoryMappingBuilder::new(shm.size() as usize)
+            .from_shared_memory(&shm)
+            .build()
+            .expect("failed to map huge page shared memory");
+        mmap.write_obj(0x45u8, page_size as usize - 1).unwrap();
+
+        // hugetlbfs reports the huge page size as the block size of its files.
+        let file = File::from(shm.descriptor);
+        assert_eq!(file.metadata().unwrap().blksize(), page_size);
+    }
+
     #[test]
     fn new_sealed() {
         let mut shm = SharedMemory::new("test", 0).expect("failed to create shared memory");
@@ -305,6 +487,37 @@ mod tests {
         shm.add_seals(seals).unwrap_err();
     }
 
+    #[test]
+    fn seals_builder() {
+        let shm = SharedMemory::new("test", 4096).expect("failed to create shared memory");
+        MemfdSeals::builder()
+            .shrink()
+            .grow()
+            .write()
+            .seal()
+            .apply_to(&shm)
+            .expect("failed to apply seals");
+
+        // SAFETY:
+        // Safe because this doesn't modify any memory and we check the return value.
+        let seals = unsafe { libc::fcntl(shm.as_raw_descriptor(), F_GET_SEALS) };
+        assert!(seals >= 0);
+        let expected = F_SEAL_SHRINK | F_SEAL_GROW | F_SEAL_WRITE | F_SEAL_SEAL;
+        assert_eq!(seals & expected, expected);
+
+        // The seal seal prevents adding any more seals.
+        MemfdSeals::builder()
+            .future_write()
+            .apply_to(&shm)
+            .unwrap_err();
+    }
+
+    #[test]
+    fn seals_not_sealable() {
+        let shm = SharedMemory::from_file(tempfile::tempfile().unwrap()).unwrap();
+        MemfdSeals::builder().grow().apply_to(&shm).unwrap_err();
+    }
+
     #[test]
     fn mmap_page() {
         let shm = SharedMemory::new("test", 4096).expect("failed to create shared memory");
@@ -3,6 +3,7 @@
 // found in the LICENSE file.
 
 /// Support for virtual sockets.
+use std::collections::BTreeSet;
 use std::fmt;
 use std::io;
 use std::mem;
@@ -22,12 +23,15 @@ use libc::sa_family_t;
 use libc::size_t;
 use libc::sockaddr;
 use libc::socklen_t;
+use libc::ENOSPC;
 use libc::F_GETFL;
 use libc::F_SETFL;
 use libc::O_NONBLOCK;
 use libc::VMADDR_CID_ANY;
 use libc::VMADDR_CID_HOST;
 use libc::VMADDR_CID_HYPERVISOR;
+use once_cell::sync::Lazy;
+use sync::Mutex;
 use thiserror::Error;
 
 // The domain for vsock sockets.
@@ -46,6 +50,13 @@ const PADDING: usize = size_of::<sockaddr>()
     - size_of::<c_ushort>()
     - (2 * size_of::<c_uint>());
 
+// The first CID that can be assigned to a guest. 0 to 2 are reserved for the hypervisor, the
+// loopback address and the host.
+const FIRST_GUEST_CID: c_uint = VMADDR_CID_HOST + 1;
+
+// CIDs currently allocated by `allocate_cid` in this process.
+static ALLOCATED_CIDS: Lazy<Mutex<BTreeSet<c_uint>>> = Lazy::new(|| Mutex::new(BTreeSet::new()));
+
 #[repr(C)]
 #[derive(Default)]
 struct sockaddr_vm {
@@ -512,3 +523,63 @@ impl AsRawFd for VsockListener {
         self.sock.as_raw_fd()
     }
 }
+
+/// A guest CID reserved by [`allocate_cid`]. The CID is released when this is dropped.
+#[derive(Debug, Eq, PartialEq)]
+pub struct AllocatedCid {
+    cid: c_uint,
+}
+
+impl AllocatedCid {
+    /// Returns the reserved CID.
+    pub fn cid(&self) -> c_uint {
+        self.cid
+    }
+}
+
+impl Drop for AllocatedCid {
+    fn drop(&mut self) {
+        ALLOCATED_CIDS.lock().remove(&self.cid);
+    }
+}
+
+/// Reserves a guest CID that isn't used by any other VM launched by this process.
+///
+/// The lowest free CID is returned, skipping the well-known CIDs 0 to 2 and `VMADDR_CID_ANY`. The
+/// allocations are only tracked in this process, so CIDs used by other VMMs on the host are not
+/// taken into account.
+pub fn allocate_cid() -> io::Result<AllocatedCid> {
+    let mut allocated = ALLOCATED_CIDS.lock();
+    let mut cid = FIRST_GUEST_CID;
+    // The set is ordered, so the first gap in it is the lowest free CID.
+    for &used in allocated.range(FIRST_GUEST_CID..) {
+        if used != cid {
+            break;
+        }
+        cid += 1;
+    }
+    if cid == VMADDR_CID_ANY {
+        return Err(io::Error::from_raw_os_error(ENOSPC));
+    }
+    allocated.insert(cid);
+    Ok(AllocatedCid { cid })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocate_cid_reuse() {
+        let first = allocate_cid().unwrap();
+        let second = allocate_cid().unwrap();
+        assert!(first.cid() >= FIRST_GUEST_CID);
+        assert!(second.cid() >= FIRST_GUEST_CID);
+        assert_ne!(first.cid(), second.cid());
+
+        let first_cid = first.cid();
+        drop(first);
+        let third = allocate_cid().unwrap();
+        assert_eq!(third.cid(), first_cid);
+    }
+}
@@ -11,7 +11,9 @@ use serde::Deserialize;
 use serde::Serialize;
 
 use crate::descriptor::AsRawDescriptor;
+use crate::descriptor::SafeDescriptor;
 use crate::descriptor_reflection::deserialize_with_descriptors;
+use crate::descriptor_reflection::deserialize_with_exact_descriptors;
 use crate::descriptor_reflection::SerializeDescriptors;
 use crate::handle_eintr;
 use crate::tube::Error;
@@ -94,6 +96,22 @@ impl Tube {
     }
 
     pub fn recv<T: DeserializeOwned>(&self) -> Result<T> {
+        let (msg_json, msg_descriptors) = self.recv_with_descriptors()?;
+        deserialize_with_descriptors(|| serde_json::from_slice(&msg_json), msg_descriptors)
+            .map_err(Error::Json)
+    }
+
+    /// Like `recv`, but fails if the message doesn't refer to exactly the descriptors sent with it.
+    ///
+    /// This is meant for messages from an untrusted peer, which could otherwise send descriptors
+    /// that don't belong to the message.
+    pub fn recv_with_exact_descriptors<T: DeserializeOwned>(&self) -> Result<T> {
+        let (msg_json, msg_descriptors) = self.recv_with_descriptors()?;
+        deserialize_with_exact_descriptors(|| serde_json::from_slice(&msg_json), msg_descriptors)
+            .map_err(Error::from_exact_descriptors_error)
+    }
+
+    fn recv_with_descriptors(&self) -> Result<(Vec<u8>, Vec<SafeDescriptor>)> {
         let msg_size = handle_eintr!(self.socket.inner().peek_size()).map_err(Error::Recv)?;
         // This buffer is the right size, as the size received in peek_size() represents the size
         // of only the message itself and not the file descriptors. The descriptors are stored
@@ -108,11 +126,8 @@ impl Tube {
             return Err(Error::Disconnected);
         }
 
-        deserialize_with_descriptors(
-            || serde_json::from_slice(&msg_json[0..msg_json_size]),
-            msg_descriptors,
-        )
-        .map_err(Error::Json)
+        msg_json.truncate(msg_json_size);
+        Ok((msg_json, msg_descriptors))
     }
 
     pub fn set_send_timeout(&self, timeout: Option<Duration>) -> Result<()> {
@@ -13,6 +13,7 @@ use winapi::um::memoryapi::FlushViewOfFile;
 use winapi::um::memoryapi::MapViewOfFile;
 use winapi::um::memoryapi::MapViewOfFileEx;
 use winapi::um::memoryapi::UnmapViewOfFile;
+use winapi::um::memoryapi::FILE_MAP_EXECUTE;
 use winapi::um::memoryapi::FILE_MAP_READ;
 use winapi::um::memoryapi::FILE_MAP_WRITE;
 
@@ -35,6 +36,9 @@ impl From<Protection> for DWORD {
         if p.write {
             value |= FILE_MAP_WRITE;
         }
+        if p.exec {
+            value |= FILE_MAP_EXECUTE;
+        }
         value
     }
 }
@@ -26,6 +26,7 @@ use crate::descriptor::AsRawDescriptor;
 use crate::descriptor::FromRawDescriptor;
 use crate::descriptor::SafeDescriptor;
 use crate::descriptor_reflection::deserialize_with_descriptors;
+use crate::descriptor_reflection::deserialize_with_exact_descriptors;
 use crate::descriptor_reflection::SerializeDescriptors;
 use crate::tube::Error;
 use crate::tube::RecvTube;
@@ -82,11 +83,24 @@ where
     }
 }
 
+/// Sizes of the parts of a Tube packet that follow this header. The fields are little-endian in
+/// the packet and have the same width on every host, so that processes of different architectures
+/// agree on the layout.
 #[derive(Copy, Clone, Debug, Default, AsBytes, FromZeroes, FromBytes)]
 #[repr(C)]
 struct MsgHeader {
-    msg_json_size: usize,
-    descriptor_json_size: usize,
+    msg_json_size: u64,
+    descriptor_json_size: u64,
+}
+
+/// Converts a size read from a packet header to `usize`.
+fn packet_part_size(size: u64) -> Result<usize> {
+    size.try_into().map_err(|_| {
+        Error::Recv(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("packet size {} is too large", size),
+        ))
+    })
 }
 
 static DH_TUBE: Lazy<sync::Mutex<Option<DuplicateHandleTube>>> =
@@ -148,10 +162,9 @@ impl Tube {
         let bytes = msg.write_to_bytes().map_err(Error::Proto)?;
         let size_header = bytes.len();
 
-        let mut data_packet =
-            Cursor::new(Vec::with_capacity(mem::size_of::<usize>() + size_header));
+        let mut data_packet = Cursor::new(Vec::with_capacity(mem::size_of::<u64>() + size_header));
         data_packet
-            .write(&size_header.to_le_bytes())
+            .write(&(size_header as u64).to_le_bytes())
             .map_err(Error::from_send_io_buf_error)?;
         data_packet.write(&bytes).map_err(Error::SendIoBuf)?;
         self.socket
@@ -162,10 +175,10 @@ impl Tube {
     }
 
     fn recv_proto<M: protobuf::Message>(&self) -> Result<M> {
-        let mut header_bytes = [0u8; mem::size_of::<usize>()];
+        let mut header_bytes = [0u8; mem::size_of::<u64>()];
         perform_read(&mut |buf| (&self.socket).read(buf), &mut header_bytes)
             .map_err(Error::from_recv_io_error)?;
-        let size_header = usize::from_le_bytes(header_bytes);
+        let size_header = packet_part_size(u64::from_le_bytes(header_bytes))?;
 
         let mut proto_bytes = vec![0u8; size_header];
         perform_read(&mut |buf| (&self.socket).read(buf), &mut proto_bytes)
@@ -181,6 +194,16 @@ impl Tube {
         deserialize_and_recv(|buf| (&self.socket).read(buf))
     }
 
+    /// Like `recv`, but fails if the message doesn't refer to exactly the descriptors sent with it.
+    ///
+    /// This is meant for messages from an untrusted peer, which could otherwise send descriptors
+    /// that don't belong to the message.
+    pub fn recv_with_exact_descriptors<T: DeserializeOwned>(&self) -> Result<T> {
+        let (msg_json, msg_descriptors) = recv_with_descriptors(|buf| (&self.socket).read(buf))?;
+        deserialize_with_exact_descriptors(|| serde_json::from_slice(&msg_json), msg_descriptors)
+            .map_err(Error::from_exact_descriptors_error)
+    }
+
     /// NOTE: On Windows this will only succeed if called on a server pipe. See #pair
     /// documentation to ensure you have a server pipe before calling.
     #[cfg(windows)]
@@ -235,13 +258,15 @@ pub fn serialize_and_send<T: Serialize, F: Fn(&[u8]) -> io::Result<usize>>(
         Some(serde_json::to_vec(&duped_descriptors).map_err(Error::Json)?)
     };
 
+    let msg_json_size = msg_json.len();
+    let descriptor_json_size = descriptor_json.as_ref().map_or(0, |json| json.len());
     let header = MsgHeader {
-        msg_json_size: msg_json.len(),
-        descriptor_json_size: descriptor_json.as_ref().map_or(0, |json| json.len()),
+        msg_json_size: (msg_json_size as u64).to_le(),
+        descriptor_json_size: (descriptor_json_size as u64).to_le(),
     };
 
     let mut data_packet = Cursor::new(Vec::with_capacity(
-        header.as_bytes().len() + header.msg_json_size + header.descriptor_json_size,
+        header.as_bytes().len() + msg_json_size + descriptor_json_size,
     ));
     data_packet
         .write(header.as_bytes())
@@ -311,12 +336,24 @@ fn perform_read<F: FnMut(&mut [u8]) -> io::Result<usize>>(
 /// Deserializes a Tube packet by calling the supplied read function. This function MUST
 /// assert that the buffer was filled.
 pub fn deserialize_and_recv<T: DeserializeOwned, F: FnMut(&mut [u8]) -> io::Result<usize>>(
-    mut read_fn: F,
+    read_fn: F,
 ) -> Result<T> {
+    let (msg_json, msg_descriptors) = recv_with_descriptors(read_fn)?;
+    deserialize_with_descriptors(|| serde_json::from_slice(&msg_json), msg_descriptors)
+        .map_err(Error::Json)
+}
+
+/// Reads a Tube packet by calling the supplied read function, and returns its message along with
+/// the descriptors sent with it.
+fn recv_with_descriptors<F: FnMut(&mut [u8]) -> io::Result<usize>>(
+    mut read_fn: F,
+) -> Result<(Vec<u8>, Vec<SafeDescriptor>)> {
     let mut header = MsgHeader::default();
     perform_read(&mut read_fn, header.as_bytes_mut()).map_err(Error::from_recv_io_error)?;
+    let msg_json_size = packet_part_size(u64::from_le(header.msg_json_size))?;
+    let descriptor_json_size = packet_part_size(u64::from_le(header.descriptor_json_size))?;
 
-    let mut msg_json = vec![0u8; header.msg_json_size];
+    let mut msg_json = vec![0u8; msg_json_size];
     perform_read(&mut read_fn, msg_json.as_mut_slice()).map_err(Error::from_recv_io_error)?;
 
     if msg_json.is_empty() {
@@ -326,8 +363,8 @@ pub fn deserialize_and_recv<T: DeserializeOwned, F: FnMut(&mut [u8]) -> io::Resu
         return Err(Error::RecvUnexpectedEmptyBody);
     }
 
-    let descriptor_usizes: Vec<usize> = if header.descriptor_json_size > 0 {
-        let mut msg_descriptors_json = vec![0u8; header.descriptor_json_size];
+    let descriptor_usizes: Vec<usize> = if descriptor_json_size > 0 {
+        let mut msg_descriptors_json = vec![0u8; descriptor_json_size];
         perform_read(&mut read_fn, msg_descriptors_json.as_mut_slice())
             .map_err(Error::from_recv_io_error)?;
         serde_json::from_slice(msg_descriptors_json.as_slice()).map_err(Error::Json)?
@@ -335,14 +372,16 @@ pub fn deserialize_and_recv<T: DeserializeOwned, F: FnMut(&mut [u8]) -> io::Resu
         Vec::new()
     };
 
-    let msg_descriptors = descriptor_usizes.into_iter().map(|item| {
-        // SAFETY: the usizes are RawDescriptors that were duplicated and converted to usize in the
-        // send method.
-        unsafe { SafeDescriptor::from_raw_descriptor(item as RawDescriptor) }
-    });
+    let msg_descriptors = descriptor_usizes
+        .into_iter()
+        .map(|item| {
+            // SAFETY: the usizes are RawDescriptors that were duplicated and converted to usize in
+            // the send method.
+            unsafe { SafeDescriptor::from_raw_descriptor(item as RawDescriptor) }
+        })
+        .collect();
 
-    deserialize_with_descriptors(|| serde_json::from_slice(&msg_json), msg_descriptors)
-        .map_err(Error::Json)
+    Ok((msg_json, msg_descriptors))
 }
 
 #[derive(EventToken, Eq, PartialEq, Copy, Clone)]
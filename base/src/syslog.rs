@@ -49,9 +49,14 @@
 //!
 //! [log-crate-url]: https://docs.rs/log/
 
+use std::collections::BTreeMap;
 use std::fmt::Display;
+use std::fs::File;
 use std::io;
 use std::io::Write;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::sync::MutexGuard;
 
 use chrono::Utc;
@@ -67,6 +72,7 @@ use sync::Mutex;
 use thiserror::Error as ThisError;
 
 use crate::descriptor::AsRawDescriptor;
+use crate::descriptor::FromRawDescriptor;
 use crate::platform::syslog::PlatformSyslog;
 use crate::platform::RawDescriptor;
 
@@ -157,6 +163,47 @@ pub enum Facility {
     Local7 = 23 << 3,
 }
 
+/// The most verbose level of messages that are logged, for changing it at runtime with
+/// `set_log_level`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => LevelFilter::Off,
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(LogLevel::Off),
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            "trace" => Ok(LogLevel::Trace),
+            _ => Err("log level must be one of off, error, warn, info, debug or trace"),
+        }
+    }
+}
+
 /// Errors returned by `syslog::init()`.
 #[sorted]
 #[derive(ThisError, Debug)]
@@ -164,6 +211,9 @@ pub enum Error {
     /// Error while attempting to connect socket.
     #[error("failed to connect socket: {0}")]
     Connect(io::Error),
+    /// The descriptor given to `set_fd_sink` can't be used to write logs.
+    #[error("invalid log sink descriptor: {0}")]
+    FdSink(io::Error),
     /// There was an error using `open` to get the lowest file descriptor.
     #[error("failed to get lowest file descriptor: {0}")]
     GetLowestFd(io::Error),
@@ -191,8 +241,20 @@ pub(crate) trait Syslog {
 pub struct State {
     /// Record filter
     filter: env_logger::filter::Filter,
+    /// Filter specification the record filter was built from, before any runtime changes.
+    filter_spec: String,
+    /// Global level set at runtime, overriding `filter_spec`.
+    log_level: Option<LogLevel>,
+    /// Per-module levels set at runtime, keyed by module path prefix.
+    module_filters: BTreeMap<String, LogLevel>,
     /// All the loggers we have
     loggers: Vec<Box<dyn Log + Send>>,
+    /// Descriptor that records are written to instead of `loggers`, which are only used if writing
+    /// to it fails.
+    fd_sink: Option<File>,
+    /// Whether records are formatted as JSON objects rather than plain text lines. Shared with the
+    /// formatters of `loggers`.
+    json_format: Arc<AtomicBool>,
     /// Raw Descriptors to preserve
     descriptors: Vec<RawDescriptor>,
     /// True if we have just been initialized with safe startup defaults (stderr logging), false
@@ -266,23 +328,17 @@ impl State {
     pub fn new(cfg: LogConfig) -> Result<Self, Error> {
         let mut loggers: Vec<Box<dyn Log + Send>> = vec![];
         let mut descriptors = vec![];
-        let mut builder = env_logger::filter::Builder::new();
-        builder.parse(&cfg.log_args.filter);
-        let filter = builder.build();
+        let filter_spec = cfg.log_args.filter;
+        let filter = env_logger::filter::Builder::new()
+            .parse(&filter_spec)
+            .build();
 
+        let json_format = Arc::new(AtomicBool::new(false));
         let create_formatted_builder = || {
             let mut builder = env_logger::Builder::new();
-
-            // Output log lines w/ local ISO 8601 timestamps.
-            builder.format(|buf, record| {
-                writeln!(
-                    buf,
-                    "[{} {:5} {}] {}",
-                    Utc::now().format("%Y-%m-%dT%H:%M:%S%.9f%:z"),
-                    record.level(),
-                    record.module_path().unwrap_or("<missing module path>"),
-                    record.args()
-                )
+            let json_format = json_format.clone();
+            builder.format(move |buf, record| {
+                format_record(buf, record, json_format.load(Ordering::Relaxed))
             });
             builder
         };
@@ -333,19 +389,146 @@ impl State {
 
         Ok(State {
             filter,
+            filter_spec,
+            log_level: None,
+            module_filters: BTreeMap::new(),
             loggers,
+            fd_sink: None,
+            json_format,
             descriptors,
             early_init: false,
         })
     }
 }
 
+impl State {
+    /// Replaces the record filter the state was created with by one that only allows messages at
+    /// `level` or more severe. Filters set with `set_module_filter` still apply.
+    pub fn set_log_level(&mut self, level: LogLevel) {
+        self.filter_spec.clear();
+        self.log_level = Some(level);
+        self.rebuild_filter();
+    }
+
+    /// Makes messages from modules whose path starts with `module_prefix` be filtered at `level`
+    /// instead of the global level. The longest matching prefix wins.
+    pub fn set_module_filter(&mut self, module_prefix: &str, level: LogLevel) {
+        self.module_filters.insert(module_prefix.to_owned(), level);
+        self.rebuild_filter();
+    }
+
+    /// Writes log records to `fd` instead of the sinks the state was created with. Those are still
+    /// used for records that can't be written to `fd`, e.g. because the reader of a pipe exited.
+    ///
+    /// `fd` is duplicated, so the caller keeps ownership of it. On unix, it must be open for
+    /// writing.
+    pub fn set_fd_sink(&mut self, fd: RawDescriptor) -> Result<(), Error> {
+        let sink = clone_writable_descriptor(fd).map_err(Error::FdSink)?;
+        if let Some(old_sink) = self.fd_sink.take() {
+            let old_fd = old_sink.as_raw_descriptor();
+            self.descriptors.retain(|&fd| fd != old_fd);
+        }
+        self.descriptors.push(sink.as_raw_descriptor());
+        self.fd_sink = Some(sink);
+        Ok(())
+    }
+
+    /// Formats records as single line JSON objects with `level`, `target`, `message`, `timestamp`
+    /// and `tid` fields if `enabled`, instead of plain text.
+    ///
+    /// This applies to stderr, the pipe and the descriptor sink, unless the pipe has a custom
+    /// `pipe_formatter`. Syslog has its own format.
+    pub fn set_json_format(&mut self, enabled: bool) {
+        self.json_format.store(enabled, Ordering::Relaxed);
+    }
+
+    fn rebuild_filter(&mut self) {
+        let mut builder = env_logger::filter::Builder::new();
+        builder.parse(&self.filter_spec);
+        if let Some(level) = self.log_level {
+            builder.filter_level(level.into());
+        }
+        for (module_prefix, level) in &self.module_filters {
+            builder.filter_module(module_prefix, (*level).into());
+        }
+        self.filter = builder.build();
+    }
+}
+
 impl Default for State {
     fn default() -> Self {
         Self::new(Default::default()).unwrap()
     }
 }
 
+/// Formats `record` as a log line, with a UTC ISO 8601 timestamp. The line is a JSON object if
+/// `json` is true.
+fn format_record(out: &mut dyn Write, record: &log::Record, json: bool) -> io::Result<()> {
+    let timestamp = Utc::now().format("%Y-%m-%dT%H:%M:%S%.9f%:z");
+    if json {
+        let line = serde_json::json!({
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "message": record.args().to_string(),
+            "timestamp": timestamp.to_string(),
+            "tid": current_tid(),
+        });
+        return writeln!(out, "{}", line);
+    }
+    writeln!(
+        out,
+        "[{} {:5} {}] {}",
+        timestamp,
+        record.level(),
+        record.module_path().unwrap_or("<missing module path>"),
+        record.args()
+    )
+}
+
+/// Returns the ID of the calling thread, as reported in JSON log records.
+fn current_tid() -> u64 {
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_os = "android", target_os = "linux"))] {
+            crate::linux::gettid() as u64
+        } else if #[cfg(windows)] {
+            // SAFETY: trivially safe
+            unsafe { winapi::um::processthreadsapi::GetCurrentThreadId() as u64 }
+        } else {
+            let mut tid = 0;
+            // SAFETY:
+            // Safe because `tid` is valid for writes and a null thread means the calling thread.
+            unsafe { libc::pthread_threadid_np(0, &mut tid) };
+            tid
+        }
+    }
+}
+
+/// Returns a duplicate of `fd` that records can be written to.
+#[cfg(unix)]
+fn clone_writable_descriptor(fd: RawDescriptor) -> io::Result<File> {
+    use crate::descriptor::Descriptor;
+    use crate::unix::clone_descriptor;
+    use crate::unix::FileFlags;
+
+    let descriptor = Descriptor(fd);
+    if FileFlags::from_file(&descriptor)? == FileFlags::Read {
+        return Err(io::Error::from_raw_os_error(libc::EBADF));
+    }
+    let fd = clone_descriptor(&descriptor)?;
+    // SAFETY:
+    // Safe because `fd` was just duplicated and nothing else owns it.
+    Ok(unsafe { File::from_raw_descriptor(fd) })
+}
+
+/// Returns a duplicate of `fd` that records can be written to.
+#[cfg(windows)]
+fn clone_writable_descriptor(fd: RawDescriptor) -> io::Result<File> {
+    let handle = win_util::duplicate_handle(fd)?;
+    // SAFETY:
+    // Safe because `handle` was just duplicated and nothing else owns it.
+    Ok(unsafe { File::from_raw_descriptor(handle) })
+}
+
 static STATE: Lazy<Mutex<State>> = Lazy::new(|| {
     let mut state = State::new(LogConfig::default()).expect("failed to configure minimal logging");
     state.early_init = true;
@@ -430,6 +613,31 @@ fn apply_logging_state(facade: &'static LoggingFacade) {
     log::set_max_level(log::LevelFilter::Trace);
 }
 
+/// Changes the level of messages logged by the global logger at runtime. See
+/// `State::set_log_level`.
+///
+/// Only the logger of the calling process is changed, so processes forked earlier keep their level.
+pub fn set_log_level(level: LogLevel) {
+    STATE.lock().set_log_level(level);
+}
+
+/// Overrides the level of messages logged by the global logger for modules under
+/// `module_prefix`, e.g. `devices::virtio::balloon`. See `State::set_module_filter`.
+pub fn set_module_filter(module_prefix: &str, level: LogLevel) {
+    STATE.lock().set_module_filter(module_prefix, level);
+}
+
+/// Makes the global logger write records to `fd`. See `State::set_fd_sink`.
+pub fn set_fd_sink(fd: RawDescriptor) -> Result<(), Error> {
+    STATE.lock().set_fd_sink(fd)
+}
+
+/// Enables or disables JSON formatting of the records of the global logger. See
+/// `State::set_json_format`.
+pub fn set_json_format(enabled: bool) {
+    STATE.lock().set_json_format(enabled);
+}
+
 /// Retrieves the file descriptors owned by the global syslogger.
 ///
 /// Does nothing if syslog was never initialized. If their are any file descriptors, they will be
@@ -448,6 +656,17 @@ impl Log for State {
 
     fn log(&self, record: &log::Record) {
         if self.filter.matches(record) {
+            if let Some(sink) = &self.fd_sink {
+                // Format the whole line before writing it so that lines written by other
+                // processes sharing the descriptor aren't interleaved with it.
+                let mut line = Vec::new();
+                let json = self.json_format.load(Ordering::Relaxed);
+                if format_record(&mut line, record, json).is_ok()
+                    && (&*sink).write_all(&line).is_ok()
+                {
+                    return;
+                }
+            }
             for logger in self.loggers.iter() {
                 logger.log(record)
             }
@@ -37,6 +37,7 @@ impl Tube {
     }
 }
 
+use crate::descriptor_reflection::ExactDescriptorsError;
 use crate::AsRawDescriptor;
 use crate::ReadNotifier;
 
@@ -125,6 +126,8 @@ pub enum Error {
     InvalidFramingMode,
     #[error("failed to serialize/deserialize json from packet: {0}")]
     Json(serde_json::Error),
+    #[error("received a message that refers to descriptors which were not sent with it")]
+    MissingDescriptors,
     #[error("cancelled a queued async operation")]
     OperationCancelled,
     #[error("failed to crate tube pair: {0}")]
@@ -146,6 +149,21 @@ pub enum Error {
     SetRecvTimeout(io::Error),
     #[error("failed to set send timeout: {0}")]
     SetSendTimeout(io::Error),
+    #[error("received {0} descriptors that were not used by the message")]
+    UnusedDescriptors(usize),
+}
+
+impl Error {
+    /// Converts a failure to deserialize a received message with exactly its descriptors.
+    pub(crate) fn from_exact_descriptors_error(
+        e: ExactDescriptorsError<serde_json::Error>,
+    ) -> Error {
+        match e {
+            ExactDescriptorsError::Deserialize(e) => Error::Json(e),
+            ExactDescriptorsError::Missing => Error::MissingDescriptors,
+            ExactDescriptorsError::Unused(count) => Error::UnusedDescriptors(count),
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
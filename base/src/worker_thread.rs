@@ -9,6 +9,9 @@ use std::thread;
 use std::thread::JoinHandle;
 use std::thread::Thread;
 
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use log::warn;
+
 use crate::Error;
 use crate::Event;
 
@@ -31,9 +34,16 @@ impl<T: Send + 'static> WorkerThread<T> {
         let stop_event = Event::new().expect("Event::new() failed");
         let thread_stop_event = stop_event.try_clone().expect("Event::try_clone() failed");
 
+        let thread_name = thread_name.into();
         let thread_handle = thread::Builder::new()
-            .name(thread_name.into())
-            .spawn(move || thread_func(thread_stop_event))
+            .name(thread_name.clone())
+            .spawn(move || {
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                if let Err(e) = crate::set_current_thread_name(&thread_name) {
+                    warn!("failed to set thread name {:?}: {}", thread_name, e);
+                }
+                thread_func(thread_stop_event)
+            })
             .expect("thread spawn failed");
 
         WorkerThread {
@@ -7,7 +7,9 @@ use std::io::Seek;
 use std::io::SeekFrom;
 use std::sync::Once;
 
+use base::pipe;
 use base::syslog::*;
+use base::AsRawDescriptor;
 
 static EARLY_INIT_ONCE: Once = Once::new();
 
@@ -56,6 +58,85 @@ fn syslog_file() {
     assert!(buf.contains(TEST_STR));
 }
 
+fn sink_test_config() -> LogConfig {
+    LogConfig {
+        log_args: LogArgs {
+            stderr: false,
+            syslog: false,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[test]
+fn fd_sink() {
+    setup();
+    let (mut read_end, write_end) = pipe(true).unwrap();
+    let mut state = State::new(sink_test_config()).unwrap();
+    state.set_fd_sink(write_end.as_raw_descriptor()).unwrap();
+    // The state keeps its own copy of the descriptor.
+    drop(write_end);
+
+    state.log(
+        &log::RecordBuilder::new()
+            .level(Level::Warn)
+            .module_path(Some("syslog_test"))
+            .args(format_args!("hello {}", "pipe"))
+            .build(),
+    );
+    // Close the write end so that the read below doesn't block.
+    drop(state);
+
+    let mut buf = String::new();
+    read_end.read_to_string(&mut buf).unwrap();
+    assert!(buf.starts_with('['), "unexpected log line: {:?}", buf);
+    assert!(
+        buf.ends_with(" WARN  syslog_test] hello pipe\n"),
+        "unexpected log line: {:?}",
+        buf
+    );
+    assert_eq!(buf.lines().count(), 1);
+}
+
+#[test]
+fn fd_sink_read_only() {
+    setup();
+    let (read_end, _write_end) = pipe(true).unwrap();
+    let mut state = State::new(sink_test_config()).unwrap();
+    assert!(matches!(
+        state.set_fd_sink(read_end.as_raw_descriptor()),
+        Err(Error::FdSink(_))
+    ));
+}
+
+#[test]
+fn fd_sink_fallback() {
+    setup();
+    let mut file = tempfile::tempfile().expect("failed to create tempfile");
+    let (read_end, write_end) = pipe(true).unwrap();
+    let mut state = State::new(LogConfig {
+        pipe: Some(Box::new(file.try_clone().unwrap())),
+        ..sink_test_config()
+    })
+    .unwrap();
+    state.set_fd_sink(write_end.as_raw_descriptor()).unwrap();
+    // Writing to a pipe without a reader fails, so the record goes to the original sink.
+    drop(read_end);
+
+    state.log(
+        &log::RecordBuilder::new()
+            .level(Level::Error)
+            .args(format_args!("reader is gone"))
+            .build(),
+    );
+
+    file.seek(SeekFrom::Start(0)).unwrap();
+    let mut buf = String::new();
+    file.read_to_string(&mut buf).unwrap();
+    assert!(buf.contains("reader is gone"));
+}
+
 #[test]
 fn macros() {
     setup();
@@ -11,6 +11,7 @@ use std::sync::Arc;
 use base::syslog::test_only_ensure_inited;
 use base::syslog::LogArgs;
 use base::syslog::LogConfig;
+use base::syslog::LogLevel;
 use base::syslog::Priority;
 use base::syslog::State;
 use base::syslog::Syslogger;
@@ -317,3 +318,105 @@ fn longest_path_prefix_match_should_apply_if_multiple_filters_match() {
             .metadata(),
     ));
 }
+
+#[test]
+fn set_log_level_filters_messages() {
+    let output = MockWrite::new();
+    let mut cfg = LogConfig::default();
+    cfg.log_args.filter = String::from("info");
+    cfg.pipe_formatter = Some(Box::new(pipe_formatter));
+    cfg.pipe = Some(Box::new(output.clone()));
+    let mut state = State::new(cfg).unwrap();
+
+    state.set_log_level(LogLevel::Warn);
+    for (level, msg) in [(Level::Info, "suppressed"), (Level::Error, "emitted")] {
+        state.log(
+            &log::RecordBuilder::new()
+                .level(level)
+                .args(format_args!("{}", msg))
+                .build(),
+        );
+    }
+
+    std::mem::drop(state);
+    assert_eq!(
+        "emitted\n",
+        String::from_utf8_lossy(&output.into_inner()[..])
+    );
+}
+
+#[test]
+fn log_level_from_str() {
+    assert_eq!("debug".parse(), Ok(LogLevel::Debug));
+    assert_eq!("WARN".parse(), Ok(LogLevel::Warn));
+    assert!("verbose".parse::<LogLevel>().is_err());
+}
+
+#[test]
+fn module_filter_overrides_global_level() {
+    let mut state = State::new(LogConfig {
+        log_args: LogArgs {
+            filter: String::from("info"),
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .unwrap();
+    state.set_module_filter("devices::virtio::balloon", LogLevel::Debug);
+
+    let enabled = |state: &State, level, target| {
+        state.enabled(
+            log::RecordBuilder::new()
+                .level(level)
+                .target(target)
+                .build()
+                .metadata(),
+        )
+    };
+    assert!(enabled(&state, Level::Debug, "devices::virtio::balloon"));
+    assert!(enabled(
+        &state,
+        Level::Debug,
+        "devices::virtio::balloon::stats"
+    ));
+    assert!(!enabled(&state, Level::Debug, "devices::virtio::block"));
+    assert!(enabled(&state, Level::Info, "devices::virtio::block"));
+
+    // Changing the global level keeps the module override.
+    state.set_log_level(LogLevel::Warn);
+    assert!(enabled(&state, Level::Debug, "devices::virtio::balloon"));
+    assert!(!enabled(&state, Level::Info, "devices::virtio::block"));
+}
+
+#[test]
+fn json_format() {
+    let output = MockWrite::new();
+    let mut cfg = LogConfig::default();
+    cfg.log_args.filter = String::from("info");
+    cfg.log_args.stderr = false;
+    cfg.pipe = Some(Box::new(output.clone()));
+    let mut state = State::new(cfg).unwrap();
+    state.set_json_format(true);
+    state.set_module_filter("json_test::quiet", LogLevel::Off);
+
+    for target in ["json_test", "json_test::quiet"] {
+        state.log(
+            &log::RecordBuilder::new()
+                .level(Level::Warn)
+                .target(target)
+                .args(format_args!("hello {}", "json"))
+                .build(),
+        );
+    }
+
+    std::mem::drop(state);
+    let output = String::from_utf8(output.into_inner()).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 1, "unexpected output: {:?}", output);
+    let record: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(record["level"], "WARN");
+    assert_eq!(record["target"], "json_test");
+    assert_eq!(record["message"], "hello json");
+    assert!(record["timestamp"].is_string());
+    assert!(record["tid"].is_u64());
+}
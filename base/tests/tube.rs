@@ -22,7 +22,7 @@ use base::WaitContext;
 use serde::Deserialize;
 use serde::Serialize;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 struct DataStruct {
     x: u32,
 }
@@ -202,3 +202,39 @@ fn send_recv_hash_map() {
         test_event_pair(test_event, recv_event);
     }
 }
+
+#[cfg(windows)]
+#[test]
+fn packet_header_little_endian() {
+    use std::cell::RefCell;
+    use std::io::Read;
+    use std::mem::size_of;
+
+    let packet = RefCell::new(Vec::new());
+    base::serialize_and_send(
+        |buf| {
+            packet.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        },
+        &DataStruct { x: 0x1234 },
+        None,
+    )
+    .unwrap();
+    let packet = packet.into_inner();
+
+    // The header holds the size of the message JSON and then of the descriptors JSON, both
+    // little-endian u64.
+    let size = size_of::<u64>();
+    let msg_json_size = u64::from_le_bytes(packet[..size].try_into().unwrap());
+    let descriptor_json_size = u64::from_le_bytes(packet[size..2 * size].try_into().unwrap());
+    assert_eq!(descriptor_json_size, 0);
+    assert_eq!(packet.len() as u64, 2 * size as u64 + msg_json_size);
+    assert_eq!(
+        serde_json::from_slice::<DataStruct>(&packet[2 * size..]).unwrap(),
+        DataStruct { x: 0x1234 }
+    );
+
+    let mut reader = &packet[..];
+    let msg: DataStruct = base::deserialize_and_recv(|buf| reader.read(buf)).unwrap();
+    assert_eq!(msg, DataStruct { x: 0x1234 });
+}
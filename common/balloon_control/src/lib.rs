@@ -31,6 +31,11 @@ pub enum BalloonTubeCommand {
         refresh_threshold: u32,
         report_threshold: u32,
     },
+    // Inflate the balloon by a small amount and deflate it back, checking that the guest reports
+    // the matching balloon size each time. The original target is restored afterwards, and the
+    // outcome is returned via a BalloonTubeResult::SelfTest message. Other commands are still
+    // handled during the test, but an Adjust ends it as failed.
+    SelfTest,
 }
 
 // BalloonStats holds stats returned from the stats_queue.
@@ -88,4 +93,9 @@ pub enum BalloonTubeResult {
         /// size of the balloon in bytes.
         balloon_actual: u64,
     },
+    SelfTest {
+        passed: bool,
+        /// Description of the outcome, e.g. the step that failed.
+        detail: String,
+    },
 }
@@ -7,12 +7,15 @@
 
 use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
+use std::collections::VecDeque;
 use std::convert::TryInto;
 use std::io;
 
 use remain::sorted;
 use thiserror::Error as ThisError;
 
+use crate::overlay::get_all_phandles;
+use crate::overlay::get_max_phandle;
 use crate::path::Path;
 use crate::propval::FromFdtPropval;
 use crate::propval::ToFdtPropval;
@@ -27,6 +30,8 @@ pub enum Error {
     ApplyOverlayError(String),
     #[error("Binary size must fit in 32 bits")]
     BinarySizeTooLarge,
+    #[error("Node {node} references missing phandle {value:#x}")]
+    DanglingPhandle { node: String, value: u32 },
     #[error("Duplicate node {}", .0)]
     DuplicateNode(String),
     #[error("I/O error dumping FDT to file code={} path={}", .0, .1.display())]
@@ -39,6 +44,8 @@ pub enum Error {
     FdtParseError(String),
     #[error("Error applying FDT tree filter: {}", .0)]
     FilterError(String),
+    #[error("Invalid memory reservation {:#x} with size {:#x}: {}", .0, .1, .2)]
+    InvalidMemReserve(u64, u64, &'static str),
     #[error("Invalid name string: {}", .0)]
     InvalidName(String),
     #[error("Invalid path: {}", .0)]
@@ -51,6 +58,10 @@ pub enum Error {
     PropertyValueInvalid,
     #[error("Property value size must fit in 32 bits")]
     PropertyValueTooLarge,
+    #[error("Invalid reserved memory region {:#x} with size {:#x}", .0, .1)]
+    ReservedMemoryInvalid(u64, u64),
+    #[error("Reserved memory region {:#x} with size {:#x} overlaps region {}", .0, .1, .2)]
+    ReservedMemoryOverlap(u64, u64, String),
     #[error("Total size must fit in 32 bits")]
     TotalSizeTooLarge,
 }
@@ -528,6 +539,16 @@ impl FdtNode {
         Ok(())
     }
 
+    /// Set the `phandle` property of the node, e.g. to a value returned by
+    /// [`Fdt::alloc_phandle`].
+    ///
+    /// # Arguments
+    ///
+    /// `phandle` - phandle value other nodes can use to reference this node.
+    pub fn set_phandle(&mut self, phandle: u32) -> Result<()> {
+        self.set_prop("phandle", phandle)
+    }
+
     /// Return a reference to an existing subnode with given name, or `None` if it doesn't exist.
     ///
     /// # Arguments
@@ -587,6 +608,8 @@ pub struct Fdt {
     pub(crate) root: FdtNode,
     strings: FdtStrings,
     boot_cpuid_phys: u32,
+    // Largest phandle value returned by `alloc_phandle`.
+    last_phandle: u32,
 }
 
 /// Reserved physical memory region.
@@ -604,6 +627,18 @@ pub struct FdtReserveEntry {
 // Last entry in the reserved memory section
 const RESVMEM_TERMINATOR: FdtReserveEntry = FdtReserveEntry::new(0, 0);
 
+// Properties whose values are lists of phandles, checked by `Fdt::validate_references`. Properties
+// named `*-parent` are checked too, but only their first cell is a phandle.
+const PHANDLE_LIST_PROPS: &[&str] = &[
+    "cpu",
+    "memory-region",
+    "next-level-cache",
+    "operating-points-v2",
+];
+
+// Alignment required for entries added with `Fdt::add_memreserve`.
+const MEMRESERVE_ALIGN: u64 = 0x1000;
+
 impl FdtReserveEntry {
     /// Create a new FdtReserveEntry
     ///
@@ -643,6 +678,7 @@ impl Fdt {
             root: FdtNode::empty("").unwrap(),
             strings: FdtStrings::default(),
             boot_cpuid_phys: 0u32,
+            last_phandle: 0u32,
         }
     }
 
@@ -655,6 +691,39 @@ impl Fdt {
         self.boot_cpuid_phys = boot_cpuid_phys;
     }
 
+    /// Append an entry to the memory reservation block of the FDT header.
+    ///
+    /// This is read by firmware and kernels that consult the header rather than the
+    /// `/reserved-memory` node. Entries must be page-aligned, non-empty, and added in increasing
+    /// address order without overlapping.
+    ///
+    /// # Arguments
+    ///
+    /// `addr` - physical address of the beginning of the reserved region.
+    /// `size` - size of the reserved region in bytes.
+    pub fn add_memreserve(&mut self, addr: u64, size: u64) -> Result<()> {
+        if size == 0 {
+            return Err(Error::InvalidMemReserve(addr, size, "empty region"));
+        }
+        if addr % MEMRESERVE_ALIGN != 0 || size % MEMRESERVE_ALIGN != 0 {
+            return Err(Error::InvalidMemReserve(addr, size, "not page-aligned"));
+        }
+        if addr.checked_add(size).is_none() {
+            return Err(Error::InvalidMemReserve(addr, size, "region overflows"));
+        }
+        if let Some(last) = self.reserved_memory.last() {
+            if addr < last.address.saturating_add(last.size) {
+                return Err(Error::InvalidMemReserve(
+                    addr,
+                    size,
+                    "not after the previous entry",
+                ));
+            }
+        }
+        self.reserved_memory.push(FdtReserveEntry::new(addr, size));
+        Ok(())
+    }
+
     // Parse the reserved memory block from a binary blob.
     fn parse_reserved_memory(mut input: Blob) -> Result<Vec<FdtReserveEntry>> {
         let mut entries = vec![];
@@ -706,6 +775,7 @@ impl Fdt {
             root,
             strings,
             boot_cpuid_phys: header.boot_cpuid_phys,
+            last_phandle: 0u32,
         })
     }
 
@@ -782,6 +852,61 @@ impl Fdt {
         Some(result_node)
     }
 
+    /// Allocate a phandle value that is not used by any node in the FDT.
+    ///
+    /// The value is larger than all phandles currently in the tree (including those added by
+    /// [`apply_overlay`](crate::apply_overlay)) and all previously allocated ones, so it is unique
+    /// even if it hasn't been assigned to a node with [`FdtNode::set_phandle`] yet.
+    ///
+    /// # Panics
+    ///
+    /// Panics if all valid phandle values have been used.
+    pub fn alloc_phandle(&mut self) -> u32 {
+        let phandle = self
+            .last_phandle
+            .max(get_max_phandle(&self.root))
+            .checked_add(1)
+            .filter(|&p| p != u32::MAX)
+            .expect("out of phandle values");
+        self.last_phandle = phandle;
+        phandle
+    }
+
+    /// Check that properties known to reference other nodes point to existing phandles.
+    ///
+    /// This covers `*-parent` properties (e.g. `interrupt-parent`) and properties that hold a
+    /// list of phandles such as `memory-region`. Properties whose phandles are interleaved with
+    /// specifier cells (e.g. `interrupts-extended`) are not checked.
+    pub fn validate_references(&self) -> Result<()> {
+        let phandles = get_all_phandles(self);
+        let mut nodes = VecDeque::new();
+        nodes.push_back((&self.root, "/".parse::<Path>()?));
+        while let Some((node, path)) = nodes.pop_front() {
+            for name in node.prop_names() {
+                let Some(cells) = node.get_prop::<Vec<u32>>(name) else {
+                    continue;
+                };
+                let refs = if name.ends_with("-parent") {
+                    &cells[..cells.len().min(1)]
+                } else if PHANDLE_LIST_PROPS.contains(&name) {
+                    &cells[..]
+                } else {
+                    continue;
+                };
+                if let Some(&value) = refs.iter().find(|p| !phandles.contains_key(p)) {
+                    return Err(Error::DanglingPhandle {
+                        node: path.to_string(),
+                        value,
+                    });
+                }
+            }
+            for subnode in node.iter_subnodes() {
+                nodes.push_back((subnode, path.push(&subnode.name)?));
+            }
+        }
+        Ok(())
+    }
+
     /// Find a device tree path to the symbol exported by the FDT. The symbol must be a node label.
     ///
     /// # Arguments
@@ -797,6 +922,66 @@ impl Fdt {
             .ok_or_else(|| Error::InvalidName(format!("filter symbol {symbol} does not exist")))?
             .parse()
     }
+
+    /// Add a region to the `/reserved-memory` node, creating the node if needed.
+    ///
+    /// The region is described by a `name@base` child node with 2-cell `reg` address and size.
+    /// Unlike the entries passed to [`Fdt::new`], these regions can be referenced by devices
+    /// through a phandle, e.g. as `memory-region` or `restricted-dma-pool`.
+    ///
+    /// # Arguments
+    ///
+    /// `name` - name of the region node, without the unit address.
+    /// `base` - physical address of the beginning of the region.
+    /// `size` - size of the region in bytes; must be non-zero.
+    /// `no_map` - set the `no-map` property so that the OS does not map the region at all.
+    pub fn add_reserved_memory(
+        &mut self,
+        name: &str,
+        base: u64,
+        size: u64,
+        no_map: bool,
+    ) -> Result<()> {
+        const RESERVED_MEMORY_NODE: &str = "reserved-memory";
+        let end = match base.checked_add(size) {
+            Some(end) if size > 0 => end,
+            _ => return Err(Error::ReservedMemoryInvalid(base, size)),
+        };
+
+        let resv_node = self.root.subnode_mut(RESERVED_MEMORY_NODE)?;
+        for (prop, cells) in [("#address-cells", 2u32), ("#size-cells", 2u32)] {
+            match resv_node.get_prop::<u32>(prop) {
+                None => resv_node.set_prop(prop, cells)?,
+                Some(c) if c == cells => (),
+                // Existing regions would be encoded differently than the one being added.
+                Some(_) => return Err(Error::PropertyValueInvalid),
+            }
+        }
+        resv_node.set_prop("ranges", ())?;
+
+        for region in resv_node.iter_subnodes() {
+            let Some(reg) = region.get_prop::<Vec<u64>>("reg") else {
+                continue;
+            };
+            for range in reg.chunks_exact(2) {
+                let (other_base, other_size) = (range[0], range[1]);
+                if base < other_base.saturating_add(other_size) && other_base < end {
+                    return Err(Error::ReservedMemoryOverlap(
+                        base,
+                        size,
+                        region.name.clone(),
+                    ));
+                }
+            }
+        }
+
+        let region = resv_node.subnode_mut(&format!("{name}@{base:x}"))?;
+        region.set_prop("reg", vec![base, size])?;
+        if no_map {
+            region.set_prop("no-map", ())?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -1291,6 +1476,161 @@ mod tests {
         );
     }
 
+    #[test]
+    fn reserved_memory() {
+        let mut fdt = Fdt::new(&[]);
+        fdt.add_reserved_memory("pkvm", 0x8000_0000, 0x10_0000, true)
+            .unwrap();
+        fdt.add_reserved_memory("dma", 0x1_0000_0000, 0x40_0000, false)
+            .unwrap();
+        let fdt = Fdt::from_blob(&fdt.finish().unwrap()).unwrap();
+
+        let resv_node = fdt.get_node("/reserved-memory").unwrap();
+        assert_eq!(resv_node.get_prop::<u32>("#address-cells"), Some(2));
+        assert_eq!(resv_node.get_prop::<u32>("#size-cells"), Some(2));
+        assert_eq!(resv_node.get_prop::<()>("ranges"), Some(()));
+        assert_eq!(resv_node.iter_subnodes().count(), 2);
+
+        let pkvm = fdt.get_node("/reserved-memory/pkvm@80000000").unwrap();
+        assert_eq!(
+            pkvm.get_prop::<Vec<u64>>("reg"),
+            Some(vec![0x8000_0000, 0x10_0000])
+        );
+        assert_eq!(pkvm.get_prop::<()>("no-map"), Some(()));
+
+        let dma = fdt.get_node("/reserved-memory/dma@100000000").unwrap();
+        assert_eq!(
+            dma.get_prop::<Vec<u64>>("reg"),
+            Some(vec![0x1_0000_0000, 0x40_0000])
+        );
+        assert_eq!(dma.get_prop::<()>("no-map"), None);
+    }
+
+    #[test]
+    fn reserved_memory_invalid() {
+        let mut fdt = Fdt::new(&[]);
+        fdt.add_reserved_memory("a", 0x1000, 0x2000, true).unwrap();
+        assert!(matches!(
+            fdt.add_reserved_memory("b", 0x2000, 0x1000, true),
+            Err(Error::ReservedMemoryOverlap(0x2000, 0x1000, _))
+        ));
+        assert!(matches!(
+            fdt.add_reserved_memory("c", 0, 0x1001, false),
+            Err(Error::ReservedMemoryOverlap(0, 0x1001, _))
+        ));
+        // Adjacent regions don't overlap.
+        fdt.add_reserved_memory("d", 0x3000, 0x1000, false).unwrap();
+        assert!(matches!(
+            fdt.add_reserved_memory("e", 0x10000, 0, false),
+            Err(Error::ReservedMemoryInvalid(0x10000, 0))
+        ));
+        assert!(matches!(
+            fdt.add_reserved_memory("f", u64::MAX, 2, false),
+            Err(Error::ReservedMemoryInvalid(u64::MAX, 2))
+        ));
+        assert_eq!(
+            fdt.get_node("/reserved-memory")
+                .unwrap()
+                .iter_subnodes()
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn memreserve() {
+        let mut fdt = Fdt::new(&[]);
+        fdt.add_memreserve(0x8000_0000, 0x1000).unwrap();
+        fdt.add_memreserve(0x8000_1000, 0x20_0000).unwrap();
+        fdt.add_memreserve(0x1_0000_0000, 0x1000).unwrap();
+        let fdt = Fdt::from_blob(&fdt.finish().unwrap()).unwrap();
+        assert_eq!(
+            fdt.reserved_memory,
+            [
+                FdtReserveEntry::new(0x8000_0000, 0x1000),
+                FdtReserveEntry::new(0x8000_1000, 0x20_0000),
+                FdtReserveEntry::new(0x1_0000_0000, 0x1000),
+            ]
+        );
+    }
+
+    #[test]
+    fn memreserve_invalid() {
+        let mut fdt = Fdt::new(&[FdtReserveEntry::new(0x10000, 0x2000)]);
+        // Overlapping and out of order.
+        fdt.add_memreserve(0x11000, 0x1000).unwrap_err();
+        fdt.add_memreserve(0x1000, 0x1000).unwrap_err();
+        // Unaligned or empty.
+        fdt.add_memreserve(0x20800, 0x1000).unwrap_err();
+        fdt.add_memreserve(0x20000, 0x800).unwrap_err();
+        fdt.add_memreserve(0x20000, 0).unwrap_err();
+        fdt.add_memreserve(0xffff_ffff_ffff_f000, 0x2000)
+            .unwrap_err();
+        assert_eq!(fdt.reserved_memory.len(), 1);
+    }
+
+    #[test]
+    fn alloc_phandles() {
+        let mut fdt = Fdt::new(&[]);
+        fdt.root_mut()
+            .subnode_mut("intc")
+            .unwrap()
+            .set_phandle(0x10)
+            .unwrap();
+
+        let phandles: Vec<u32> = (0..4).map(|_| fdt.alloc_phandle()).collect();
+        assert_eq!(phandles, [0x11, 0x12, 0x13, 0x14]);
+
+        let node = fdt.root_mut().subnode_mut("iommu").unwrap();
+        node.set_phandle(phandles[0]).unwrap();
+        assert_eq!(node.get_prop::<u32>("phandle"), Some(0x11));
+
+        // Phandles added to the tree by other means are not handed out again.
+        fdt.root_mut()
+            .subnode_mut("pci")
+            .unwrap()
+            .set_prop("linux,phandle", 0x20u32)
+            .unwrap();
+        assert_eq!(fdt.alloc_phandle(), 0x21);
+    }
+
+    #[test]
+    fn validate_references() {
+        let mut fdt = Fdt::new(&[]);
+        let gic = fdt.alloc_phandle();
+        let pool = fdt.alloc_phandle();
+        let root = fdt.root_mut();
+        root.set_prop("interrupt-parent", gic).unwrap();
+        root.subnode_mut("intc").unwrap().set_phandle(gic).unwrap();
+        root.subnode_mut("reserved-memory")
+            .unwrap()
+            .subnode_mut("pool")
+            .unwrap()
+            .set_prop("linux,phandle", pool)
+            .unwrap();
+        let pci = root.subnode_mut("pci").unwrap();
+        pci.set_prop("memory-region", vec![pool]).unwrap();
+        // Only the first cell of `*-parent` properties is a phandle.
+        pci.set_prop("msi-parent", vec![gic, 0x1234]).unwrap();
+        // Other properties are not checked.
+        pci.set_prop("bus-range", vec![0u32, 1]).unwrap();
+        fdt.validate_references().unwrap();
+
+        fdt.get_node_mut("/pci")
+            .unwrap()
+            .subnode_mut("dev")
+            .unwrap()
+            .set_prop("memory-region", vec![pool, 0x99])
+            .unwrap();
+        match fdt.validate_references() {
+            Err(Error::DanglingPhandle { node, value }) => {
+                assert_eq!(node, "/pci/dev");
+                assert_eq!(value, 0x99);
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
     #[test]
     fn prop_null() {
         let mut fdt = Fdt::new(&[]);
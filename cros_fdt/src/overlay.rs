@@ -110,7 +110,7 @@ fn collect_all_references_by_path(
 }
 
 // Collect locations of all phandles in the FDT.
-fn get_all_phandles(fdt: &Fdt) -> BTreeMap<u32, Path> {
+pub(crate) fn get_all_phandles(fdt: &Fdt) -> BTreeMap<u32, Path> {
     let mut phandles = BTreeMap::new();
     let mut nodes = VecDeque::<(&FdtNode, Path)>::new();
     nodes.push_back((&fdt.root, ROOT_NODE.parse().unwrap()));
@@ -196,7 +196,7 @@ fn get_node_phandle(node: &FdtNode) -> Option<u32> {
 }
 
 // Return the largest phandle value in a node tree.
-fn get_max_phandle(root_node: &FdtNode) -> u32 {
+pub(crate) fn get_max_phandle(root_node: &FdtNode) -> u32 {
     let mut max_phandle = 0u32;
     let mut nodes_to_visit = VecDeque::new();
     nodes_to_visit.push_back(root_node);
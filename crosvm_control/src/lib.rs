@@ -24,6 +24,7 @@ use std::time::Duration;
 
 use libc::c_char;
 use libc::ssize_t;
+pub use swap::SwapOutProgress;
 pub use swap::SwapStatus;
 use vm_control::client::*;
 use vm_control::BalloonControlCommand;
@@ -341,6 +342,45 @@ pub unsafe extern "C" fn crosvm_client_swap_status(
     .unwrap_or(false)
 }
 
+/// Returns the progress of the vmm-swap swap-out of the crosvm instance whose control socket is
+/// listening on `socket_path`.
+///
+/// The parameters `progress` is optional and will only be written to if they are non-null.
+///
+/// The function returns true on success or false if an error occurred.
+///
+/// # Safety
+///
+/// Function is unsafe due to raw pointer usage - a null pointer could be passed in. Usage of
+/// !raw_pointer.is_null() checks should prevent unsafe behavior but the caller should ensure no
+/// null pointers are passed.
+#[no_mangle]
+pub unsafe extern "C" fn crosvm_client_swap_out_progress(
+    socket_path: *const c_char,
+    progress: *mut SwapOutProgress,
+) -> bool {
+    catch_unwind(|| {
+        if let Some(socket_path) = validate_socket_path(socket_path) {
+            let request = &VmRequest::Swap(SwapCommand::SwapOutProgress);
+            if let Ok(VmResponse::SwapOutProgress(response)) = handle_request(request, socket_path)
+            {
+                if !progress.is_null() {
+                    // SAFETY: just checked that `progress` is not null.
+                    unsafe {
+                        *progress = response;
+                    }
+                }
+                true
+            } else {
+                false
+            }
+        } else {
+            false
+        }
+    })
+    .unwrap_or(false)
+}
+
 /// Represents an individual attached USB device.
 #[repr(C)]
 pub struct UsbDeviceEntry {
@@ -7,6 +7,7 @@ use std::str::FromStr;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
 
 use acpi_tables::aml;
 use acpi_tables::aml::Aml;
@@ -19,6 +20,8 @@ use base::Error as SysError;
 use base::Event;
 use base::EventToken;
 use base::SendTube;
+use base::Timer;
+use base::TimerTrait;
 use base::Tube;
 use base::VmEventType;
 use base::WaitContext;
@@ -109,6 +112,9 @@ pub struct ACPIPMResource {
     pci: Arc<Mutex<PciResource>>,
     #[serde(skip_serializing)]
     acdc: Option<Arc<Mutex<AcAdapter>>>,
+    // One-shot RTC wake alarm, serviced by the worker thread.
+    #[serde(skip_serializing)]
+    rtc_alarm: Timer,
 }
 
 #[derive(Deserialize)]
@@ -125,7 +131,7 @@ impl ACPIPMResource {
         suspend_evt: Event,
         exit_evt_wrtube: SendTube,
         acdc: Option<Arc<Mutex<AcAdapter>>>,
-    ) -> ACPIPMResource {
+    ) -> base::Result<ACPIPMResource> {
         let pm1 = Pm1Resource {
             status: 0,
             enable: 0,
@@ -140,7 +146,7 @@ impl ACPIPMResource {
             pme_notify: BTreeMap::new(),
         };
 
-        ACPIPMResource {
+        Ok(ACPIPMResource {
             sci_evt,
             worker_thread: None,
             suspend_evt,
@@ -149,7 +155,8 @@ impl ACPIPMResource {
             gpe0: Arc::new(Mutex::new(gpe0)),
             pci: Arc::new(Mutex::new(pci)),
             acdc,
-        }
+            rtc_alarm: Timer::new()?,
+        })
     }
 
     pub fn start(&mut self) {
@@ -157,11 +164,20 @@ impl ACPIPMResource {
         let pm1 = self.pm1.clone();
         let gpe0 = self.gpe0.clone();
         let acdc = self.acdc.clone();
+        let rtc_alarm = self.rtc_alarm.try_clone().expect("failed to clone timer");
 
         let acpi_event_ignored_gpe = Vec::new();
 
         self.worker_thread = Some(WorkerThread::start("ACPI PM worker", move |kill_evt| {
-            if let Err(e) = run_worker(sci_evt, kill_evt, pm1, gpe0, acpi_event_ignored_gpe, acdc) {
+            if let Err(e) = run_worker(
+                sci_evt,
+                kill_evt,
+                pm1,
+                gpe0,
+                acpi_event_ignored_gpe,
+                acdc,
+                rtc_alarm,
+            ) {
                 error!("{}", e);
             }
         }));
@@ -209,17 +225,20 @@ fn run_worker(
     gpe0: Arc<Mutex<GpeResource>>,
     acpi_event_ignored_gpe: Vec<u32>,
     arced_ac_adapter: Option<Arc<Mutex<AcAdapter>>>,
+    mut rtc_alarm: Timer,
 ) -> Result<(), ACPIPMError> {
     let acpi_event_sock = crate::sys::get_acpi_event_sock()?;
     #[derive(EventToken)]
     enum Token {
         AcpiEvent,
         InterruptResample,
+        RtcAlarm,
         Kill,
     }
 
     let wait_ctx: WaitContext<Token> = WaitContext::build_with(&[
         (sci_evt.get_resample(), Token::InterruptResample),
+        (&rtc_alarm, Token::RtcAlarm),
         (&kill_evt, Token::Kill),
     ])
     .map_err(ACPIPMError::CreateWaitContext)?;
@@ -249,6 +268,14 @@ fn run_worker(
                     pm1.lock().trigger_sci(&sci_evt);
                     gpe0.lock().trigger_sci(&sci_evt);
                 }
+                Token::RtcAlarm => {
+                    if let Err(e) = rtc_alarm.mark_waited() {
+                        error!("failed to ack rtc alarm: {}", e);
+                    }
+                    let mut pm1 = pm1.lock();
+                    pm1.status |= ACPIPMFixedEvent::RTC.bitmask();
+                    pm1.trigger_sci(&sci_evt);
+                }
                 Token::Kill => return Ok(()),
             }
         }
@@ -402,6 +429,29 @@ impl PmResource for ACPIPMResource {
         pm1.trigger_sci(&self.sci_evt);
     }
 
+    fn set_rtc_alarm(&mut self, seconds_from_now: u64) {
+        // A zero duration would disarm the timer instead of firing it.
+        if seconds_from_now == 0 {
+            if let Err(e) = self.rtc_alarm.clear() {
+                error!("failed to clear rtc alarm: {}", e);
+            }
+            self.rtc_evt();
+            return;
+        }
+        if let Err(e) = self
+            .rtc_alarm
+            .reset(Duration::from_secs(seconds_from_now), None)
+        {
+            error!("failed to set rtc alarm: {}", e);
+        }
+    }
+
+    fn clear_rtc_alarm(&mut self) {
+        if let Err(e) = self.rtc_alarm.clear() {
+            error!("failed to clear rtc alarm: {}", e);
+        }
+    }
+
     fn gpe_evt(&mut self, gpe: u32) {
         let mut gpe0 = self.gpe0.lock();
 
@@ -412,13 +462,26 @@ impl PmResource for ACPIPMResource {
     }
 
     fn pme_evt(&mut self, requester_id: u16) {
-        let bus = ((requester_id >> 8) & 0xFF) as u8;
+        self.pme_evt_batch(&[requester_id]);
+    }
+
+    fn pme_evt_batch(&mut self, requester_ids: &[u16]) -> Vec<base::Result<()>> {
         let mut pci = self.pci.lock();
-        if let Some(root_ports) = pci.pme_notify.get_mut(&bus) {
-            for root_port in root_ports {
-                root_port.lock().notify(requester_id);
-            }
-        }
+        requester_ids
+            .iter()
+            .map(|&requester_id| {
+                let bus = ((requester_id >> 8) & 0xFF) as u8;
+                // No root port to deliver the PME through.
+                let root_ports = pci
+                    .pme_notify
+                    .get_mut(&bus)
+                    .ok_or_else(|| SysError::new(libc::ENODEV))?;
+                for root_port in root_ports {
+                    root_port.lock().notify(requester_id);
+                }
+                Ok(())
+            })
+            .collect()
     }
 
     fn register_gpe_notify_dev(&mut self, gpe: u32, notify_dev: Arc<Mutex<dyn GpeNotify>>) {
@@ -730,7 +793,62 @@ mod tests {
 
     suspendable_tests!(
         acpi,
-        ACPIPMResource::new(get_irq_evt(), Event::new().unwrap(), get_evt_tube(), None,),
+        ACPIPMResource::new(get_irq_evt(), Event::new().unwrap(), get_evt_tube(), None).unwrap(),
         modify_device
     );
+
+    /// Returns a started `ACPIPMResource` with the RTC event enabled, and its SCI trigger event.
+    fn start_with_rtc_enabled() -> (ACPIPMResource, Event) {
+        let sci_evt = get_irq_evt();
+        let sci_trigger = sci_evt.get_trigger().try_clone().unwrap();
+        let mut acpi =
+            ACPIPMResource::new(sci_evt, Event::new().unwrap(), get_evt_tube(), None).unwrap();
+        acpi.pm1.lock().enable |= ACPIPMFixedEvent::RTC.bitmask();
+        acpi.start();
+        (acpi, sci_trigger)
+    }
+
+    fn rtc_status(acpi: &ACPIPMResource) -> bool {
+        acpi.pm1.lock().status & ACPIPMFixedEvent::RTC.bitmask() != 0
+    }
+
+    #[test]
+    fn rtc_alarm_raises_rtc_event() {
+        let (mut acpi, sci_trigger) = start_with_rtc_enabled();
+
+        acpi.set_rtc_alarm(1);
+        assert!(!rtc_status(&acpi));
+        // The worker thread raises the SCI when the timer expires.
+        assert_eq!(
+            sci_trigger.wait_timeout(Duration::from_secs(10)).unwrap(),
+            base::EventWaitResult::Signaled
+        );
+        assert!(rtc_status(&acpi));
+    }
+
+    #[test]
+    fn rtc_alarm_cleared() {
+        let (mut acpi, sci_trigger) = start_with_rtc_enabled();
+
+        acpi.set_rtc_alarm(1);
+        acpi.clear_rtc_alarm();
+        assert_eq!(
+            sci_trigger.wait_timeout(Duration::from_secs(2)).unwrap(),
+            base::EventWaitResult::TimedOut
+        );
+        assert!(!rtc_status(&acpi));
+    }
+
+    #[test]
+    fn rtc_alarm_zero_delay() {
+        let (mut acpi, sci_trigger) = start_with_rtc_enabled();
+
+        // Raised right away rather than disarming the timer.
+        acpi.set_rtc_alarm(0);
+        assert!(rtc_status(&acpi));
+        assert_eq!(
+            sci_trigger.wait_timeout(Duration::ZERO).unwrap(),
+            base::EventWaitResult::Signaled
+        );
+    }
 }
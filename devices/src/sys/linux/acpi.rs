@@ -7,6 +7,8 @@ use std::sync::Arc;
 use base::debug;
 use base::error;
 use base::info;
+use base::warn;
+use base::AcpiEventFilter;
 use base::AcpiNotifyEvent;
 use base::NetlinkGenericSocket;
 use sync::Mutex;
@@ -30,10 +32,18 @@ pub(crate) fn get_acpi_event_sock() -> Result<Option<NetlinkGenericSocket>, ACPI
         }
     }
 
-    match NetlinkGenericSocket::new(nl_groups) {
-        Ok(acpi_sock) => Ok(Some(acpi_sock)),
-        Err(e) => Err(ACPIPMError::AcpiEventSockError(e)),
+    let acpi_sock = match NetlinkGenericSocket::new(nl_groups) {
+        Ok(acpi_sock) => acpi_sock,
+        Err(e) => return Err(ACPIPMError::AcpiEventSockError(e)),
+    };
+
+    // Only wake up for the events handled by `acpi_event_run`.
+    if let Err(e) = AcpiEventFilter::device_classes(["gpe", "ac_adapter"])
+        .and_then(|filter| filter.attach(&acpi_sock))
+    {
+        warn!("failed to filter acpi events: {}", e);
     }
+    Ok(Some(acpi_sock))
 }
 
 fn get_acpi_event_group() -> Option<u32> {
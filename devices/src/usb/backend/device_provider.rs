@@ -253,6 +253,18 @@ impl ProviderInner {
         UsbControlResult::Devices(devices)
     }
 
+    fn handle_list_devices_v2(&self) -> UsbControlResult {
+        attached_device_list(self.usb_hub.ports().filter_map(|p| {
+            let device = p.backend_device().as_ref()?.clone();
+            let device = device.lock();
+            Some(UsbControlAttachedDevice {
+                port: p.port_id(),
+                vendor_id: device.get_vid(),
+                product_id: device.get_pid(),
+            })
+        }))
+    }
+
     fn on_event_helper(&self) -> Result<()> {
         let tube = self.control_tube.lock();
         let cmd = tube.recv().map_err(Error::ReadControlTube)?;
@@ -260,15 +272,48 @@ impl ProviderInner {
             UsbControlCommand::AttachDevice { file } => self.handle_attach_device(file),
             UsbControlCommand::DetachDevice { port } => self.handle_detach_device(port),
             UsbControlCommand::ListDevice { ports } => self.handle_list_devices(ports),
+            UsbControlCommand::ListDeviceV2 => self.handle_list_devices_v2(),
         };
         tube.send(&result).map_err(Error::WriteControlTube)?;
         Ok(())
     }
 }
 
+/// Builds the response to `UsbControlCommand::ListDeviceV2`, skipping invalid entries.
+fn attached_device_list(
+    devices: impl IntoIterator<Item = UsbControlAttachedDevice>,
+) -> UsbControlResult {
+    UsbControlResult::DeviceList(devices.into_iter().filter(|d| d.valid()).collect())
+}
+
 impl EventHandler for ProviderInner {
     fn on_event(&self) -> anyhow::Result<()> {
         self.on_event_helper()
             .context("host backend device provider failed")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_more_than_max_ports() {
+        let num_devices = USB_CONTROL_MAX_PORTS as u8 + 8;
+        let devices = (0..=num_devices).map(|port| UsbControlAttachedDevice {
+            port,
+            vendor_id: 0x18d1,
+            product_id: port.into(),
+        });
+
+        match attached_device_list(devices) {
+            UsbControlResult::DeviceList(list) => {
+                // Port 0 is not a valid port, so it is skipped.
+                assert_eq!(list.len(), num_devices as usize);
+                assert!(list.iter().all(|d| d.valid()));
+                assert_eq!(list.last().unwrap().port, num_devices);
+            }
+            r => panic!("unexpected result: {}", r),
+        }
+    }
+}
@@ -90,7 +90,7 @@ impl UsbPort {
         }
     }
 
-    fn port_id(&self) -> u8 {
+    pub fn port_id(&self) -> u8 {
         self.port_id
     }
 
@@ -223,6 +223,11 @@ impl UsbHub {
         Ok(())
     }
 
+    /// Get all the ports of the hub.
+    pub fn ports(&self) -> impl Iterator<Item = &Arc<UsbPort>> {
+        self.ports.iter()
+    }
+
     /// Get a specific port of the hub.
     pub fn get_port(&self, port_id: u8) -> Option<Arc<UsbPort>> {
         if port_id == 0 || port_id > MAX_PORTS {
@@ -6,8 +6,14 @@ mod sys;
 
 use std::collections::BTreeMap;
 use std::collections::VecDeque;
+use std::fs::File;
 use std::io::Write;
+use std::sync::mpsc as std_mpsc;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 use anyhow::anyhow;
 use anyhow::Context;
@@ -20,9 +26,14 @@ use balloon_control::VIRTIO_BALLOON_WS_MAX_NUM_BINS;
 use balloon_control::VIRTIO_BALLOON_WS_MIN_NUM_BINS;
 use base::debug;
 use base::error;
+use base::info;
 use base::warn;
 use base::AsRawDescriptor;
 use base::Event;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use base::PsiMemoryMonitor;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use base::PsiStallType;
 use base::RawDescriptor;
 #[cfg(feature = "registered_events")]
 use base::SendTube;
@@ -31,15 +42,19 @@ use base::WorkerThread;
 use cros_async::block_on;
 use cros_async::sync::RwLock as AsyncRwLock;
 use cros_async::AsyncTube;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use cros_async::AsyncWrapper;
 use cros_async::EventAsync;
 use cros_async::Executor;
 #[cfg(feature = "registered_events")]
 use cros_async::SendTubeAsync;
+use cros_async::TimerAsync;
 use data_model::Le16;
 use data_model::Le32;
 use data_model::Le64;
 use futures::channel::mpsc;
 use futures::channel::oneshot;
+use futures::future::Fuse;
 use futures::pin_mut;
 use futures::select;
 use futures::select_biased;
@@ -87,6 +102,12 @@ pub enum BalloonError {
     /// Failed to create async message receiver.
     #[error("failed to create async message receiver: {0}")]
     CreatingMessageReceiver(base::TubeError),
+    /// The auto mode watermarks are out of order or too large.
+    #[error("invalid balloon auto watermarks: low {low}, high {high}")]
+    InvalidAutoWatermarks { low: u64, high: u64 },
+    /// The OOM deflate step is too large.
+    #[error("invalid balloon OOM deflate step: {0}")]
+    InvalidOomDeflateStep(u64),
     /// Failed to receive command message.
     #[error("failed to receive command message: {0}")]
     ReceivingCommand(base::TubeError),
@@ -158,6 +179,84 @@ struct BalloonState {
     // Adjusted success/failure response is sent.
     failable_update: bool,
     pending_adjusted_responses: VecDeque<u32>,
+    // Time of the last explicit `Adjust` command, which suppresses automatic adjustments for
+    // `AUTO_BALLOON_OVERRIDE_DURATION`.
+    #[serde(skip)]
+    last_explicit_adjust: Option<Instant>,
+}
+
+// How long an explicit `Adjust` command overrides the automatic balloon target in
+// `BalloonMode::Auto`.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+const AUTO_BALLOON_OVERRIDE_DURATION: Duration = Duration::from_secs(60);
+
+// Number of pages the balloon is inflated by during `BalloonTubeCommand::SelfTest` (1 MiB).
+const SELF_TEST_PAGES: u32 = 256;
+// How long the guest has to reach each target during `BalloonTubeCommand::SelfTest`.
+const SELF_TEST_STEP_TIMEOUT: Duration = Duration::from_secs(10);
+// How often `actual_pages` is checked during `BalloonTubeCommand::SelfTest`.
+const SELF_TEST_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Maximum number of working set reports waiting to be written to the WS log. Reports arriving
+// while the log writer is this far behind are dropped.
+const WS_LOG_QUEUE_SIZE: usize = 64;
+
+// Host memory pressure state observed in `BalloonMode::Auto`.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum HostPressure {
+    High,
+    Low,
+}
+
+// Balloon targets in pages used in `BalloonMode::Auto`.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[derive(Clone, Copy, Debug)]
+struct AutoBalloonTargets {
+    low_pages: u32,
+    high_pages: u32,
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+impl AutoBalloonTargets {
+    // Converts the watermarks in bytes to pages. `low` must not exceed `high`.
+    fn new(low: u64, high: u64) -> Result<Self> {
+        let to_pages = |bytes: u64| u32::try_from(bytes >> VIRTIO_BALLOON_PFN_SHIFT).ok();
+        match (to_pages(low), to_pages(high)) {
+            (Some(low_pages), Some(high_pages)) if low <= high => Ok(AutoBalloonTargets {
+                low_pages,
+                high_pages,
+            }),
+            _ => Err(BalloonError::InvalidAutoWatermarks { low, high }),
+        }
+    }
+
+    // Moves the balloon target according to the host `pressure`, unless an explicit `Adjust`
+    // command is still in effect. Returns the new target if it changed.
+    fn update(
+        &self,
+        state: &mut BalloonState,
+        pressure: HostPressure,
+        now: Instant,
+    ) -> Option<u32> {
+        if state.failable_update {
+            return None;
+        }
+        if let Some(last) = state.last_explicit_adjust {
+            if now.saturating_duration_since(last) < AUTO_BALLOON_OVERRIDE_DURATION {
+                return None;
+            }
+        }
+        let target = match pressure {
+            HostPressure::High => self.high_pages,
+            HostPressure::Low => self.low_pages,
+        };
+        if state.num_pages == target {
+            return None;
+        }
+        state.num_pages = target;
+        Some(target)
+    }
 }
 
 // The constants defining stats types in virtio_baloon_stat
@@ -541,11 +640,37 @@ async fn handle_event(
     interrupt: Interrupt,
     r: &mut Reader,
     command_tube: &AsyncTube,
+    oom_deflate_pages: Option<u32>,
+    #[cfg(feature = "registered_events")] registered_evt_q: Option<&SendTubeAsync>,
 ) -> Result<()> {
     match r.read_obj::<virtio_balloon_event_header>() {
         Ok(hdr) => match hdr.evt_type.to_native() {
             VIRTIO_BALLOON_EVENT_PRESSURE => {
-                // TODO(b/213962590): See how this can be integrated this into memory rebalancing
+                // Give memory back to a guest under pressure if it can use balloon pages on OOM.
+                let Some(step) = oom_deflate_pages else {
+                    return Ok(());
+                };
+                let mut state = state.lock().await;
+                let num_pages = state.num_pages.saturating_sub(step);
+                if num_pages == state.num_pages {
+                    return Ok(());
+                }
+                info!(
+                    "guest memory pressure, deflating balloon from {} to {} pages",
+                    state.num_pages, num_pages
+                );
+                state.num_pages = num_pages;
+                interrupt.signal_config_changed();
+
+                #[cfg(feature = "registered_events")]
+                if let Some(registered_evt_q) = registered_evt_q {
+                    if let Err(e) = registered_evt_q
+                        .send(&RegisteredEventWithData::VirtioBalloonOOMDeflation)
+                        .await
+                    {
+                        error!("failed to send VirtioBalloonOOMDeflation event: {}", e);
+                    }
+                }
             }
             VIRTIO_BALLOON_EVENT_PUFF_FAILURE => {
                 let mut state = state.lock().await;
@@ -575,6 +700,8 @@ async fn handle_events_queue(
     state: Arc<AsyncRwLock<BalloonState>>,
     interrupt: Interrupt,
     command_tube: &AsyncTube,
+    oom_deflate_pages: Option<u32>,
+    #[cfg(feature = "registered_events")] registered_evt_q: Option<&SendTubeAsync>,
     mut stop_rx: oneshot::Receiver<()>,
 ) -> Result<Queue> {
     while let Some(mut avail_desc) = queue
@@ -587,6 +714,9 @@ async fn handle_events_queue(
             interrupt.clone(),
             &mut avail_desc.reader,
             command_tube,
+            oom_deflate_pages,
+            #[cfg(feature = "registered_events")]
+            registered_evt_q,
         )
         .await?;
 
@@ -697,6 +827,47 @@ fn parse_balloon_ws(reader: &mut Reader) -> BalloonWS {
     ws
 }
 
+/// A working set report as written to the WS log, one JSON object per line.
+#[derive(Serialize, Deserialize, Debug)]
+struct WSLogEntry {
+    /// Time the report was received, in milliseconds since the Unix epoch.
+    timestamp_ms: u64,
+    /// Size of the balloon in bytes when the report was received.
+    balloon_actual: u64,
+    /// Age and anon/file byte counts of each working set bucket.
+    ws: Vec<WSBucket>,
+}
+
+impl WSLogEntry {
+    fn new(ws: &BalloonWS, balloon_actual: u64) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        WSLogEntry {
+            timestamp_ms,
+            balloon_actual,
+            ws: ws.ws.clone(),
+        }
+    }
+}
+
+/// Appends each entry received on `entries` to `log` until the sending side is dropped.
+///
+/// This blocks on file I/O, so it must run on a blocking thread rather than the worker's executor.
+fn write_ws_log(entries: std_mpsc::Receiver<WSLogEntry>, mut log: impl Write) {
+    for entry in entries {
+        let res = serde_json::to_writer(&mut log, &entry)
+            .map_err(std::io::Error::from)
+            .and_then(|_| log.write_all(b"\n"))
+            .and_then(|_| log.flush());
+        if let Err(e) = res {
+            error!("failed to write WS log entry, disabling the WS log: {}", e);
+            return;
+        }
+    }
+}
+
 // Async task that handles the stats queue. Note that the arrival of events on
 // the WS vq may be the result of either a WS request (WS-R) command having
 // been sent to the guest, or an unprompted send due to memory pressue in the
@@ -709,6 +880,7 @@ async fn handle_ws_data_queue(
     #[cfg(feature = "registered_events")] registered_evt_q: Option<&SendTubeAsync>,
     state: Arc<AsyncRwLock<BalloonState>>,
     interrupt: Interrupt,
+    ws_log_tx: Option<&std_mpsc::SyncSender<WSLogEntry>>,
     mut stop_rx: oneshot::Receiver<()>,
 ) -> Result<Queue> {
     loop {
@@ -728,6 +900,15 @@ async fn handle_ws_data_queue(
         // update ws report with balloon pages now that we have a lock on state
         let balloon_actual = (state.actual_pages as u64) << VIRTIO_BALLOON_PFN_SHIFT;
 
+        if let Some(ws_log_tx) = ws_log_tx {
+            match ws_log_tx.try_send(WSLogEntry::new(&ws, balloon_actual)) {
+                Ok(()) | Err(std_mpsc::TrySendError::Disconnected(_)) => (),
+                Err(std_mpsc::TrySendError::Full(_)) => {
+                    warn!("WS log writer is falling behind, dropping report")
+                }
+            }
+        }
+
         if state.expecting_ws {
             let result = BalloonTubeResult::WorkingSet { ws, balloon_actual };
             let send_result = command_tube.send(result).await;
@@ -753,9 +934,93 @@ async fn handle_ws_data_queue(
     }
 }
 
+// Sets the balloon target to `num_pages` and waits for the guest to report it in `actual_pages`.
+// Returns a description of the failure if the guest doesn't get there in time.
+async fn self_test_step(
+    ex: &Executor,
+    interrupt: &Interrupt,
+    state: &AsyncRwLock<BalloonState>,
+    num_pages: u32,
+) -> std::result::Result<(), String> {
+    {
+        let mut state = state.lock().await;
+        state.num_pages = num_pages;
+        state.last_explicit_adjust = Some(Instant::now());
+    }
+    interrupt.signal_config_changed();
+
+    let deadline = Instant::now() + SELF_TEST_STEP_TIMEOUT;
+    loop {
+        let actual_pages = state.lock().await.actual_pages;
+        if actual_pages == num_pages {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(format!(
+                "guest reported {} pages instead of {} after {:?}",
+                actual_pages, num_pages, SELF_TEST_STEP_TIMEOUT
+            ));
+        }
+        TimerAsync::sleep(ex, SELF_TEST_POLL_INTERVAL)
+            .await
+            .map_err(|e| format!("failed to sleep: {}", e))?;
+    }
+}
+
+// Inflates the balloon by `SELF_TEST_PAGES` and deflates it back, checking that the guest follows
+// each time. Returns whether the round-trip succeeded along with a description of the outcome.
+// The caller is responsible for restoring the original target.
+async fn run_self_test(
+    ex: &Executor,
+    interrupt: &Interrupt,
+    state: &AsyncRwLock<BalloonState>,
+) -> (bool, String) {
+    let base_pages = {
+        let state = state.lock().await;
+        if state.failable_update {
+            return (false, "a balloon adjustment is in progress".to_string());
+        }
+        state.actual_pages
+    };
+    let inflated_pages = base_pages.saturating_add(SELF_TEST_PAGES);
+
+    if let Err(e) = self_test_step(ex, interrupt, state, inflated_pages).await {
+        return (false, format!("inflate failed: {}", e));
+    }
+    if let Err(e) = self_test_step(ex, interrupt, state, base_pages).await {
+        return (false, format!("deflate failed: {}", e));
+    }
+    (
+        true,
+        format!(
+            "inflated from {} to {} pages and back",
+            base_pages, inflated_pages
+        ),
+    )
+}
+
+// Logs the outcome of a `BalloonTubeCommand::SelfTest` and returns it to the host.
+async fn send_self_test_result(
+    tube: &AsyncTube,
+    passed: bool,
+    detail: String,
+) -> std::result::Result<(), base::TubeError> {
+    if passed {
+        info!("balloon self-test passed: {}", detail);
+    } else {
+        warn!("balloon self-test failed: {}", detail);
+    }
+    tube.send(BalloonTubeResult::SelfTest { passed, detail })
+        .await
+}
+
 // Async task that handles the command socket. The command socket handles messages from the host
 // requesting that the guest balloon be adjusted or to report guest memory statistics.
+//
+// A self-test runs alongside the other commands so that it doesn't hold them up while waiting for
+// the guest. An adjustment ends a running self-test, which then fails, as it changes the target.
 async fn handle_command_tube(
+    ex: &Executor,
     command_tube: &AsyncTube,
     interrupt: Interrupt,
     state: Arc<AsyncRwLock<BalloonState>>,
@@ -763,10 +1028,32 @@ async fn handle_command_tube(
     mut ws_op_tx: mpsc::Sender<WSOp>,
     mut stop_rx: oneshot::Receiver<()>,
 ) -> Result<()> {
+    // The balloon target to restore once the running self-test ends, if there is one.
+    let mut self_test_original_pages = None;
+    let self_test = Fuse::terminated();
+    pin_mut!(self_test);
     loop {
         let cmd_res = select_biased! {
             res = command_tube.next().fuse() => res,
-            _ = stop_rx => return Ok(())
+            result = self_test => {
+                let (passed, detail): (bool, String) = result;
+                if let Some(original_pages) = self_test_original_pages.take() {
+                    state.lock().await.num_pages = original_pages;
+                    interrupt.signal_config_changed();
+                }
+                send_self_test_result(command_tube, passed, detail)
+                    .await
+                    .map_err(BalloonError::SendResponse)?;
+                continue;
+            }
+            _ = stop_rx => {
+                // Restore the original target if a self-test is still running.
+                if let Some(original_pages) = self_test_original_pages.take() {
+                    state.lock().await.num_pages = original_pages;
+                    interrupt.signal_config_changed();
+                }
+                return Ok(());
+            }
         };
         match cmd_res {
             Ok(command) => match command {
@@ -774,10 +1061,21 @@ async fn handle_command_tube(
                     num_bytes,
                     allow_failure,
                 } => {
+                    if self_test_original_pages.take().is_some() {
+                        self_test.set(Fuse::terminated());
+                        send_self_test_result(
+                            command_tube,
+                            false,
+                            "interrupted by a balloon adjustment".to_string(),
+                        )
+                        .await
+                        .map_err(BalloonError::SendResponse)?;
+                    }
                     let num_pages = (num_bytes >> VIRTIO_BALLOON_PFN_SHIFT) as u32;
                     let mut state = state.lock().await;
 
                     state.num_pages = num_pages;
+                    state.last_explicit_adjust = Some(Instant::now());
                     interrupt.signal_config_changed();
 
                     if allow_failure {
@@ -813,6 +1111,20 @@ async fn handle_command_tube(
                         error!("failed to send report request to ws handler: {}", e);
                     }
                 }
+                BalloonTubeCommand::SelfTest => {
+                    if self_test_original_pages.is_some() {
+                        send_self_test_result(
+                            command_tube,
+                            false,
+                            "a self-test is already running".to_string(),
+                        )
+                        .await
+                        .map_err(BalloonError::SendResponse)?;
+                    } else {
+                        self_test_original_pages = Some(state.lock().await.num_pages);
+                        self_test.set(run_self_test(ex, &interrupt, &state).fuse());
+                    }
+                }
             },
             #[cfg(windows)]
             Err(base::TubeError::Recv(e)) if e.kind() == std::io::ErrorKind::TimedOut => {
@@ -850,6 +1162,74 @@ async fn handle_pending_adjusted_responses(
     }
 }
 
+// PSI trigger used to detect host memory pressure in `BalloonMode::Auto`: some task stalled on
+// memory for 150ms within a 2s window.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+const AUTO_BALLOON_PSI_STALL: Duration = Duration::from_millis(150);
+#[cfg(any(target_os = "android", target_os = "linux"))]
+const AUTO_BALLOON_PSI_WINDOW: Duration = Duration::from_secs(2);
+// How long the host must go without a pressure notification before the balloon is deflated again.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+const AUTO_BALLOON_RELIEF_PERIOD: Duration = Duration::from_secs(10);
+
+// Async task that adjusts the balloon target from host memory pressure in `BalloonMode::Auto`.
+// The balloon is inflated to the high watermark when the host is under pressure, and deflated to
+// the low watermark once no pressure has been seen for `AUTO_BALLOON_RELIEF_PERIOD`.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+async fn handle_host_pressure(
+    ex: &Executor,
+    targets: AutoBalloonTargets,
+    interrupt: Interrupt,
+    state: Arc<AsyncRwLock<BalloonState>>,
+) -> anyhow::Result<()> {
+    let monitor = PsiMemoryMonitor::new(
+        PsiStallType::Some,
+        AUTO_BALLOON_PSI_STALL,
+        AUTO_BALLOON_PSI_WINDOW,
+    )
+    .context("failed to create PSI memory monitor")?;
+    let monitor = ex
+        .async_from(AsyncWrapper::new(monitor))
+        .context("failed to create async PSI memory monitor")?;
+
+    let set_pressure = |pressure: HostPressure| {
+        let state = state.clone();
+        let interrupt = interrupt.clone();
+        async move {
+            let mut state = state.lock().await;
+            if let Some(num_pages) = targets.update(&mut state, pressure, Instant::now()) {
+                info!(
+                    "host memory pressure {:?}, setting balloon target to {} pages",
+                    pressure, num_pages
+                );
+                interrupt.signal_config_changed();
+            }
+        }
+    };
+
+    loop {
+        monitor
+            .wait_readable()
+            .await
+            .context("failed to wait for host memory pressure")?;
+        // Waiting may already have consumed the notification, so the result doesn't matter here.
+        monitor.as_source().clear()?;
+        set_pressure(HostPressure::High).await;
+
+        // Keep the balloon inflated until a whole relief period passes without pressure.
+        loop {
+            TimerAsync::sleep(ex, AUTO_BALLOON_RELIEF_PERIOD)
+                .await
+                .context("failed to sleep")?;
+            if !monitor.as_source().clear()? {
+                break;
+            }
+            set_pressure(HostPressure::High).await;
+        }
+        set_pressure(HostPressure::Low).await;
+    }
+}
+
 /// Represents queues & events for the balloon device.
 struct BalloonQueues {
     inflate: Queue,
@@ -952,9 +1332,27 @@ fn run_worker(
     mem: GuestMemory,
     state: Arc<AsyncRwLock<BalloonState>>,
     #[cfg(feature = "registered_events")] registered_evt_q: Option<SendTube>,
+    #[cfg(any(target_os = "android", target_os = "linux"))] auto_targets: Option<
+        AutoBalloonTargets,
+    >,
+    ws_log: Option<File>,
+    oom_deflate_pages: Option<u32>,
 ) -> WorkerReturn {
     let ex = Executor::new().unwrap();
     let command_tube = AsyncTube::new(&ex, command_tube).unwrap();
+
+    // Working set reports are written to the log from a blocking thread so that slow writes
+    // don't stall the WS data queue.
+    let (ws_log_tx, ws_log_writer) = match ws_log {
+        Some(log) => {
+            let (tx, rx) = std_mpsc::sync_channel(WS_LOG_QUEUE_SIZE);
+            (
+                Some(tx),
+                Some(ex.spawn_blocking(move || write_ws_log(rx, log))),
+            )
+        }
+        None => (None, None),
+    };
     #[cfg(feature = "registered_events")]
     let registered_evt_q_async = registered_evt_q
         .as_ref()
@@ -1090,6 +1488,7 @@ fn run_worker(
                 registered_evt_q_async.as_ref(),
                 state.clone(),
                 interrupt.clone(),
+                ws_log_tx.as_ref(),
                 stop_rx,
             )
             .left_future()
@@ -1125,6 +1524,7 @@ fn run_worker(
         // Future to handle command messages that resize the balloon.
         let stop_rx = create_stop_oneshot(&mut stop_queue_oneshots);
         let command = handle_command_tube(
+            &ex,
             &command_tube,
             interrupt.clone(),
             state.clone(),
@@ -1139,18 +1539,40 @@ fn run_worker(
         pin_mut!(resample);
 
         // Send a message if balloon target reached event is triggered.
-        let target_reached = handle_target_reached(
-            &ex,
-            target_reached_evt,
-            #[cfg(windows)]
-            &vm_memory_client,
-        );
+        let target_reached =
+            handle_target_reached(&ex, target_reached_evt, state.clone(), |size| {
+                sys::balloon_target_reached(
+                    size,
+                    #[cfg(windows)]
+                    &vm_memory_client,
+                )
+            });
         pin_mut!(target_reached);
 
         // Exit if the kill event is triggered.
         let kill = async_utils::await_and_exit(&ex, kill_evt);
         pin_mut!(kill);
 
+        // Adjust the balloon from host memory pressure in `BalloonMode::Auto`. If the host
+        // doesn't support PSI, the balloon only follows explicit commands.
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        let host_pressure = if let Some(targets) = auto_targets {
+            handle_host_pressure(&ex, targets, interrupt.clone(), state.clone())
+                .map(|r| {
+                    if let Err(e) = r {
+                        error!("balloon auto mode disabled: {:#}", e);
+                    }
+                })
+                .then(|_| std::future::pending::<()>())
+                .left_future()
+        } else {
+            std::future::pending().right_future()
+        };
+        #[cfg(not(any(target_os = "android", target_os = "linux")))]
+        let host_pressure = std::future::pending::<()>();
+        let host_pressure = host_pressure.fuse();
+        pin_mut!(host_pressure);
+
         // The next queue is used for events if VIRTIO_BALLOON_F_EVENTS_VQ is negotiated.
         let has_events_queue = events_queue.is_some();
         let events = if let Some(events_queue) = events_queue {
@@ -1165,6 +1587,9 @@ fn run_worker(
                 state.clone(),
                 interrupt,
                 &command_tube,
+                oom_deflate_pages,
+                #[cfg(feature = "registered_events")]
+                registered_evt_q_async.as_ref(),
                 stop_rx,
             )
             .left_future()
@@ -1196,6 +1621,7 @@ fn run_worker(
                 _ = pending_adjusted.fuse() => return Err(anyhow!("pending_adjusted stopped unexpectedly")),
                 _ = ws_data => return Err(anyhow!("ws_data stopped unexpectedly")),
                 _ = target_reached.fuse() => return Err(anyhow!("target_reached stopped unexpectedly")),
+                _ = host_pressure => return Err(anyhow!("host_pressure stopped unexpectedly")),
             }
 
             // Worker is shutting down. To recover the queues, we have to signal
@@ -1245,6 +1671,14 @@ fn run_worker(
         }
     };
 
+    // Let the log writer finish the reports that are already queued.
+    drop(ws_log_tx);
+    if let Some(ws_log_writer) = ws_log_writer {
+        if let Err(e) = ex.run_until(ws_log_writer) {
+            error!("failed to wait for the WS log writer: {}", e);
+        }
+    }
+
     WorkerReturn {
         command_tube: command_tube.into(),
         paused_queues,
@@ -1256,23 +1690,21 @@ fn run_worker(
     }
 }
 
+/// Calls `target_reached` with the actual balloon size in bytes each time `target_reached_evt` is
+/// signaled.
 async fn handle_target_reached(
     ex: &Executor,
     target_reached_evt: Event,
-    #[cfg(windows)] vm_memory_client: &VmMemoryClient,
+    state: Arc<AsyncRwLock<BalloonState>>,
+    target_reached: impl Fn(u64),
 ) -> anyhow::Result<()> {
     let event_async =
         EventAsync::new(target_reached_evt, ex).context("failed to create EventAsync")?;
     loop {
         // Wait for target reached trigger.
         let _ = event_async.next_val().await;
-        // Send the message to vm_control on the event. We don't have to read the current
-        // size yet.
-        sys::balloon_target_reached(
-            0,
-            #[cfg(windows)]
-            vm_memory_client,
-        );
+        let actual_pages = state.lock().await.actual_pages as u64;
+        target_reached(actual_pages << VIRTIO_BALLOON_PFN_SHIFT);
     }
     // The above loop will never terminate and there is no reason to terminate it either. However,
     // the function is used in an executor that expects a Result<> return. Make sure that clippy
@@ -1296,6 +1728,10 @@ pub struct Balloon {
     registered_evt_q: Option<SendTube>,
     ws_num_bins: u8,
     target_reached_evt: Option<Event>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    auto_targets: Option<AutoBalloonTargets>,
+    ws_log: Option<File>,
+    oom_deflate_pages: u32,
 }
 
 /// Snapshot of the [Balloon] state.
@@ -1314,6 +1750,15 @@ pub enum BalloonMode {
     Relaxed,
     /// The driver cannot access pages in the balloon. Implies F_RESPONSIVE_DEVICE.
     Strict,
+    /// Like `Relaxed`, but the balloon is also resized from host memory pressure (PSI): it is
+    /// inflated to `high_watermark` bytes while the host is under pressure and deflated to
+    /// `low_watermark` bytes once the pressure subsides. Explicit adjust commands temporarily
+    /// override the automatic target.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    Auto {
+        low_watermark: u64,
+        high_watermark: u64,
+    },
 }
 
 impl Balloon {
@@ -1322,6 +1767,10 @@ impl Balloon {
     /// by CoIOMMU to host, the release_memory_tube will be used to send the inflate
     /// ranges to CoIOMMU with UnpinRequest/UnpinResponse messages, so that The
     /// memory in the inflate range can be unpinned first.
+    /// If `ws_log` is given, each working set report from the guest is appended to it as a line of
+    /// JSON.
+    /// If the guest negotiates `VIRTIO_BALLOON_F_DEFLATE_ON_OOM`, each of its memory pressure
+    /// events lowers the balloon target by `oom_deflate_step` bytes. Zero disables this.
     pub fn new(
         base_features: u64,
         command_tube: Tube,
@@ -1332,7 +1781,11 @@ impl Balloon {
         enabled_features: u64,
         #[cfg(feature = "registered_events")] registered_evt_q: Option<SendTube>,
         ws_num_bins: u8,
+        ws_log: Option<File>,
+        oom_deflate_step: u64,
     ) -> Result<Balloon> {
+        let oom_deflate_pages = u32::try_from(oom_deflate_step >> VIRTIO_BALLOON_PFN_SHIFT)
+            .map_err(|_| BalloonError::InvalidOomDeflateStep(oom_deflate_step))?;
         let features = base_features
             | 1 << VIRTIO_BALLOON_F_MUST_TELL_HOST
             | 1 << VIRTIO_BALLOON_F_STATS_VQ
@@ -1344,6 +1797,15 @@ impl Balloon {
                 1 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM
             };
 
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        let auto_targets = match mode {
+            BalloonMode::Auto {
+                low_watermark,
+                high_watermark,
+            } => Some(AutoBalloonTargets::new(low_watermark, high_watermark)?),
+            _ => None,
+        };
+
         Ok(Balloon {
             command_tube: Some(command_tube),
             #[cfg(windows)]
@@ -1356,6 +1818,7 @@ impl Balloon {
                 failable_update: false,
                 pending_adjusted_responses: VecDeque::new(),
                 expecting_ws: false,
+                last_explicit_adjust: None,
             })),
             worker_thread: None,
             features,
@@ -1364,6 +1827,10 @@ impl Balloon {
             registered_evt_q,
             ws_num_bins,
             target_reached_evt: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            auto_targets,
+            ws_log,
+            oom_deflate_pages,
         })
     }
 
@@ -1494,6 +1961,18 @@ impl Balloon {
             .pending_adjusted_response_event
             .try_clone()
             .context("failed to clone Event")?;
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        let auto_targets = self.auto_targets;
+        let ws_log = self
+            .ws_log
+            .as_ref()
+            .map(|log| log.try_clone())
+            .transpose()
+            .context("failed to clone WS log")?;
+        // The guest only gets memory back on pressure events if it may use balloon pages on OOM.
+        let oom_deflate_pages = (self.acked_features & (1 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM) != 0
+            && self.oom_deflate_pages != 0)
+            .then_some(self.oom_deflate_pages);
 
         self.worker_thread = Some(WorkerThread::start("v_balloon", move |kill_evt| {
             run_worker(
@@ -1515,6 +1994,10 @@ impl Balloon {
                 state,
                 #[cfg(feature = "registered_events")]
                 registered_evt_q,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                auto_targets,
+                ws_log,
+                oom_deflate_pages,
             )
         }));
 
@@ -1535,6 +2018,9 @@ impl VirtioDevice for Balloon {
         if let Some(registered_evt_q) = &self.registered_evt_q {
             rds.push(registered_evt_q.as_raw_descriptor());
         }
+        if let Some(ws_log) = &self.ws_log {
+            rds.push(ws_log.as_raw_descriptor());
+        }
         rds.push(self.pending_adjusted_response_event.as_raw_descriptor());
         rds
     }
@@ -1647,9 +2133,17 @@ impl VirtioDevice for Balloon {
 
     fn virtio_restore(&mut self, data: serde_json::Value) -> anyhow::Result<()> {
         let snap: BalloonSnapshot = serde_json::from_value(data).context("error deserializing")?;
-        if snap.features != self.features {
+        // The offered features may change across versions, but the guest must not lose any
+        // feature it has already acked.
+        let missing_features = snap.acked_features & !self.features;
+        if missing_features != 0 {
+            let missing_bits: Vec<u32> = (0..u64::BITS)
+                .filter(|bit| missing_features & (1 << bit) != 0)
+                .collect();
             anyhow::bail!(
-                "balloon: expected features to match, but they did not. Live: {:?}, snapshot {:?}",
+                "balloon: snapshot acked feature bits {:?} which are not offered by this device \
+                 (features {:#x}, snapshot features {:#x})",
+                missing_bits,
                 self.features,
                 snap.features,
             );
@@ -1745,6 +2239,374 @@ mod tests {
         );
     }
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn auto_balloon_targets() {
+        let targets = AutoBalloonTargets {
+            low_pages: 0,
+            high_pages: 256,
+        };
+        let mut state = BalloonState::default();
+        let start = Instant::now();
+
+        // Host pressure inflates the balloon, relief deflates it again.
+        assert_eq!(
+            targets.update(&mut state, HostPressure::High, start),
+            Some(256)
+        );
+        assert_eq!(state.num_pages, 256);
+        assert_eq!(targets.update(&mut state, HostPressure::High, start), None);
+        assert_eq!(
+            targets.update(&mut state, HostPressure::Low, start),
+            Some(0)
+        );
+        assert_eq!(state.num_pages, 0);
+
+        // An explicit adjust overrides the automatic target for a while.
+        state.num_pages = 128;
+        state.last_explicit_adjust = Some(start);
+        assert_eq!(targets.update(&mut state, HostPressure::High, start), None);
+        assert_eq!(state.num_pages, 128);
+        let later = start + AUTO_BALLOON_OVERRIDE_DURATION;
+        assert_eq!(
+            targets.update(&mut state, HostPressure::High, later),
+            Some(256)
+        );
+
+        // Pending failable updates are never overridden.
+        state.failable_update = true;
+        assert_eq!(targets.update(&mut state, HostPressure::Low, later), None);
+        assert_eq!(state.num_pages, 256);
+    }
+
+    #[test]
+    fn oom_deflate_step_too_large() {
+        let (_ctrl_tube, ctrl_tube_device) = Tube::pair().unwrap();
+        #[cfg(windows)]
+        let (_mem_client_tube, mem_client_tube_device) = Tube::pair().unwrap();
+        let result = Balloon::new(
+            0,
+            ctrl_tube_device,
+            #[cfg(windows)]
+            VmMemoryClient::new(mem_client_tube_device),
+            None,
+            0,
+            BalloonMode::Relaxed,
+            0,
+            #[cfg(feature = "registered_events")]
+            None,
+            0,
+            None,
+            u64::MAX,
+        );
+        assert!(matches!(
+            result,
+            Err(BalloonError::InvalidOomDeflateStep(u64::MAX))
+        ));
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn auto_balloon_targets_invalid() {
+        let targets = AutoBalloonTargets::new(4 << 20, 8 << 20).unwrap();
+        assert_eq!(targets.low_pages, 1024);
+        assert_eq!(targets.high_pages, 2048);
+
+        assert!(matches!(
+            AutoBalloonTargets::new(8 << 20, 4 << 20),
+            Err(BalloonError::InvalidAutoWatermarks { .. })
+        ));
+        // Larger than the 32-bit page count of the balloon config.
+        assert!(matches!(
+            AutoBalloonTargets::new(0, u64::MAX),
+            Err(BalloonError::InvalidAutoWatermarks { .. })
+        ));
+    }
+
+    #[test]
+    fn target_reached_sends_actual_size() {
+        let ex = Executor::new().unwrap();
+        let target_reached_evt = Event::new().unwrap();
+        let state = Arc::new(AsyncRwLock::new(BalloonState {
+            actual_pages: 300,
+            ..Default::default()
+        }));
+        let (size_tx, mut size_rx) = mpsc::unbounded();
+
+        target_reached_evt.signal().unwrap();
+        let handler = handle_target_reached(
+            &ex,
+            target_reached_evt.try_clone().unwrap(),
+            state,
+            |size| size_tx.unbounded_send(size).unwrap(),
+        )
+        .fuse();
+        pin_mut!(handler);
+        let size = ex
+            .run_until(async {
+                select! {
+                    r = handler => panic!("target reached handler exited: {:?}", r),
+                    size = size_rx.next() => size,
+                }
+            })
+            .unwrap();
+        assert_eq!(size, Some(300 << VIRTIO_BALLOON_PFN_SHIFT));
+    }
+
+    #[test]
+    fn self_test_round_trip() {
+        let ex = Executor::new().unwrap();
+        let (host_tube, device_tube) = Tube::pair().unwrap();
+        let host_tube = AsyncTube::new(&ex, host_tube).unwrap();
+        let device_tube = AsyncTube::new(&ex, device_tube).unwrap();
+        let state = Arc::new(AsyncRwLock::new(BalloonState {
+            num_pages: 100,
+            actual_pages: 100,
+            ..Default::default()
+        }));
+        let (stats_tx, _stats_rx) = mpsc::channel(1);
+        let (ws_op_tx, _ws_op_rx) = mpsc::channel(1);
+        let (_stop_tx, stop_rx) = oneshot::channel();
+
+        let command = handle_command_tube(
+            &ex,
+            &device_tube,
+            Interrupt::new_for_test(),
+            state.clone(),
+            stats_tx,
+            ws_op_tx,
+            stop_rx,
+        )
+        .fuse();
+        pin_mut!(command);
+
+        // A guest that cooperatively follows the balloon target.
+        let guest = async {
+            loop {
+                {
+                    let mut state = state.lock().await;
+                    state.actual_pages = state.num_pages;
+                }
+                TimerAsync::sleep(&ex, Duration::from_millis(10))
+                    .await
+                    .unwrap();
+            }
+        }
+        .fuse();
+        pin_mut!(guest);
+
+        let result = ex
+            .run_until(async {
+                host_tube.send(BalloonTubeCommand::SelfTest).await.unwrap();
+                select! {
+                    r = command => panic!("command handler exited: {:?}", r),
+                    _ = guest => unreachable!(),
+                    r = host_tube.next::<BalloonTubeResult>().fuse() => r.unwrap(),
+                }
+            })
+            .unwrap();
+        match result {
+            BalloonTubeResult::SelfTest { passed, detail } => assert!(passed, "{}", detail),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert_eq!(block_on(state.lock()).num_pages, 100);
+    }
+
+    #[test]
+    fn self_test_interrupted_by_adjust() {
+        let ex = Executor::new().unwrap();
+        let (host_tube, device_tube) = Tube::pair().unwrap();
+        let host_tube = AsyncTube::new(&ex, host_tube).unwrap();
+        let device_tube = AsyncTube::new(&ex, device_tube).unwrap();
+        let state = Arc::new(AsyncRwLock::new(BalloonState {
+            num_pages: 100,
+            actual_pages: 100,
+            ..Default::default()
+        }));
+        let (stats_tx, _stats_rx) = mpsc::channel(1);
+        let (ws_op_tx, _ws_op_rx) = mpsc::channel(1);
+        let (_stop_tx, stop_rx) = oneshot::channel();
+
+        let command = handle_command_tube(
+            &ex,
+            &device_tube,
+            Interrupt::new_for_test(),
+            state.clone(),
+            stats_tx,
+            ws_op_tx,
+            stop_rx,
+        )
+        .fuse();
+        pin_mut!(command);
+
+        // The guest never follows the target, so the self-test would wait for the whole timeout.
+        let result = ex
+            .run_until(async {
+                host_tube.send(BalloonTubeCommand::SelfTest).await.unwrap();
+                host_tube
+                    .send(BalloonTubeCommand::Adjust {
+                        num_bytes: 200 << VIRTIO_BALLOON_PFN_SHIFT,
+                        allow_failure: false,
+                    })
+                    .await
+                    .unwrap();
+                select! {
+                    r = command => panic!("command handler exited: {:?}", r),
+                    r = host_tube.next::<BalloonTubeResult>().fuse() => r.unwrap(),
+                }
+            })
+            .unwrap();
+        match result {
+            BalloonTubeResult::SelfTest { passed, .. } => assert!(!passed),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        // The adjustment is applied instead of the original target being restored.
+        assert_eq!(block_on(state.lock()).num_pages, 200);
+    }
+
+    #[test]
+    fn pressure_event_deflates_balloon() {
+        let ex = Executor::new().unwrap();
+        let memory = GuestMemory::new(&[(GuestAddress(0x0), 0x10000)]).unwrap();
+        memory
+            .write_obj_at_addr(
+                virtio_balloon_event_header {
+                    evt_type: VIRTIO_BALLOON_EVENT_PRESSURE.into(),
+                },
+                GuestAddress(0x100),
+            )
+            .unwrap();
+        let (_host_tube, command_tube) = Tube::pair().unwrap();
+        let command_tube = AsyncTube::new(&ex, command_tube).unwrap();
+        #[cfg(feature = "registered_events")]
+        let (registered_evt_tx, registered_evt_rx) = Tube::directional_pair().unwrap();
+        #[cfg(feature = "registered_events")]
+        let registered_evt_tx = SendTubeAsync::new(registered_evt_tx, &ex).unwrap();
+        let state = Arc::new(AsyncRwLock::new(BalloonState {
+            num_pages: 1000,
+            actual_pages: 1000,
+            ..Default::default()
+        }));
+        let interrupt = Interrupt::new_for_test();
+
+        let send_pressure_event = |oom_deflate_pages| {
+            let mut chain = create_descriptor_chain(
+                &memory,
+                GuestAddress(0x0),
+                GuestAddress(0x100),
+                vec![(DescriptorType::Readable, 4)],
+                0,
+            )
+            .expect("create_descriptor_chain failed");
+            ex.run_until(handle_event(
+                state.clone(),
+                interrupt.clone(),
+                &mut chain.reader,
+                &command_tube,
+                oom_deflate_pages,
+                #[cfg(feature = "registered_events")]
+                Some(&registered_evt_tx),
+            ))
+            .unwrap()
+            .unwrap();
+            block_on(state.lock()).num_pages
+        };
+
+        // Without VIRTIO_BALLOON_F_DEFLATE_ON_OOM, the target is left alone.
+        assert_eq!(send_pressure_event(None), 1000);
+
+        assert_eq!(send_pressure_event(Some(300)), 700);
+        #[cfg(feature = "registered_events")]
+        assert!(matches!(
+            registered_evt_rx.recv::<RegisteredEventWithData>().unwrap(),
+            RegisteredEventWithData::VirtioBalloonOOMDeflation
+        ));
+
+        // The balloon can't be deflated below empty.
+        assert_eq!(send_pressure_event(Some(800)), 0);
+    }
+
+    #[test]
+    fn ws_log_entries_in_order() {
+        let (ws_log_tx, ws_log_rx) = std_mpsc::sync_channel(WS_LOG_QUEUE_SIZE);
+        for i in 0..3u64 {
+            let ws = BalloonWS {
+                ws: vec![
+                    WSBucket {
+                        age: i * 1000,
+                        bytes: [i, i + 1],
+                    },
+                    WSBucket {
+                        age: i * 1000 + 500,
+                        bytes: [i + 2, i + 3],
+                    },
+                ],
+            };
+            ws_log_tx
+                .send(WSLogEntry::new(&ws, i << VIRTIO_BALLOON_PFN_SHIFT))
+                .unwrap();
+        }
+        drop(ws_log_tx);
+
+        let mut log = Vec::new();
+        write_ws_log(ws_log_rx, &mut log);
+
+        let entries: Vec<WSLogEntry> = std::str::from_utf8(&log)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 3);
+        for (i, entry) in entries.iter().enumerate() {
+            let i = i as u64;
+            assert_eq!(entry.balloon_actual, i << VIRTIO_BALLOON_PFN_SHIFT);
+            assert_eq!(entry.ws.len(), 2);
+            assert_eq!(entry.ws[0].age, i * 1000);
+            assert_eq!(entry.ws[0].bytes, [i, i + 1]);
+            assert_eq!(entry.ws[1].age, i * 1000 + 500);
+            assert_eq!(entry.ws[1].bytes, [i + 2, i + 3]);
+        }
+        assert!(entries
+            .windows(2)
+            .all(|w| w[0].timestamp_ms <= w[1].timestamp_ms));
+    }
+
+    #[test]
+    fn restore_with_more_features() {
+        let (_ctx, mut device) = create_device();
+        device.ack_features(1 << VIRTIO_BALLOON_F_STATS_VQ);
+        let snapshot = device.virtio_snapshot().unwrap();
+
+        // A device offering more features than the snapshotted one can still be restored.
+        let (_ctx, mut restored) = create_device();
+        restored.features |= 1 << VIRTIO_BALLOON_F_PAGE_REPORTING;
+        restored.virtio_restore(snapshot).unwrap();
+        assert_eq!(restored.acked_features, 1 << VIRTIO_BALLOON_F_STATS_VQ);
+    }
+
+    #[test]
+    fn restore_with_missing_acked_feature() {
+        let (_ctx, mut device) = create_device();
+        device.ack_features(1 << VIRTIO_BALLOON_F_STATS_VQ | 1 << VIRTIO_BALLOON_F_EVENTS_VQ);
+        let snapshot = device.virtio_snapshot().unwrap();
+
+        // Features that were offered but not acked can go away.
+        let (_ctx, mut restored) = create_device();
+        restored.features &= !(1 << VIRTIO_BALLOON_F_MUST_TELL_HOST);
+        restored.virtio_restore(snapshot.clone()).unwrap();
+
+        // The guest can't lose a feature it acked.
+        let (_ctx, mut restored) = create_device();
+        restored.features &= !(1 << VIRTIO_BALLOON_F_EVENTS_VQ);
+        let err = restored.virtio_restore(snapshot).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains(&format!("[{}]", VIRTIO_BALLOON_F_EVENTS_VQ)),
+            "{}",
+            err
+        );
+    }
+
     struct BalloonContext {
         _ctrl_tube: Tube,
         #[cfg(windows)]
@@ -1777,6 +2639,8 @@ mod tests {
                 #[cfg(feature = "registered_events")]
                 None,
                 0,
+                None,
+                0,
             )
             .unwrap(),
         )
@@ -214,6 +214,7 @@ type BlockId = [u8; ID_LEN];
 /// Tracks the state of an anynchronous disk.
 struct DiskState {
     disk_image: Box<dyn AsyncDisk>,
+    /// Whether the disk image was opened read-only. Such a disk can never be made writable.
     read_only: bool,
     sparse: bool,
     id: Option<BlockId>,
@@ -225,6 +226,9 @@ struct DiskState {
 /// Disk state which can be modified by other worker threads
 struct WorkerSharedState {
     disk_size: Arc<AtomicU64>,
+    /// Whether the disk currently rejects writes. Starts out equal to `DiskState::read_only` and
+    /// can be changed at runtime by `DiskControlCommand::SetReadOnly`.
+    read_only: bool,
 }
 
 async fn process_one_request(
@@ -364,6 +368,10 @@ async fn handle_command_tube(
             Ok(command) => {
                 let resp = match command {
                     DiskControlCommand::Resize { new_size } => resize(&disk_state, new_size).await,
+                    DiskControlCommand::Flush => flush(&disk_state).await,
+                    DiskControlCommand::SetReadOnly { read_only } => {
+                        set_read_only(&disk_state, read_only).await
+                    }
                 };
 
                 let resp_clone = resp.clone();
@@ -371,7 +379,8 @@ async fn handle_command_tube(
                     .send(resp_clone)
                     .await
                     .map_err(ExecuteError::SendingResponse)?;
-                if let DiskControlResult::Ok = resp {
+                if let (DiskControlCommand::Resize { .. }, DiskControlResult::Ok) = (command, resp)
+                {
                     interrupt.signal_config_changed();
                 }
             }
@@ -388,7 +397,7 @@ async fn resize(disk_state: &AsyncRwLock<DiskState>, new_size: u64) -> DiskContr
     let worker_shared_state = Arc::clone(&disk_state.worker_shared_state);
     let worker_shared_state = worker_shared_state.lock().await;
 
-    if disk_state.read_only {
+    if worker_shared_state.read_only {
         error!("Attempted to resize read-only block device");
         return DiskControlResult::Err(SysError::new(libc::EROFS));
     }
@@ -416,6 +425,54 @@ async fn resize(disk_state: &AsyncRwLock<DiskState>, new_size: u64) -> DiskContr
     DiskControlResult::Ok
 }
 
+async fn flush(disk_state: &AsyncRwLock<DiskState>) -> DiskControlResult {
+    let disk_state = disk_state.read_lock().await;
+    if disk_state.worker_shared_state.read_lock().await.read_only {
+        return DiskControlResult::Ok;
+    }
+
+    if let Err(e) = disk_state.disk_image.fsync().await {
+        error!("Flushing disk failed! {:#}", e);
+        return DiskControlResult::Err(SysError::new(libc::EIO));
+    }
+    DiskControlResult::Ok
+}
+
+async fn set_read_only(disk_state: &AsyncRwLock<DiskState>, read_only: bool) -> DiskControlResult {
+    // Acquire exclusive access to the state so that in-flight requests on this worker and on any
+    // other worker threads have completed before the mode changes.
+    let disk_state = disk_state.lock().await;
+    let worker_shared_state = Arc::clone(&disk_state.worker_shared_state);
+    let mut worker_shared_state = worker_shared_state.lock().await;
+
+    if worker_shared_state.read_only == read_only {
+        return DiskControlResult::Ok;
+    }
+
+    if !read_only && disk_state.read_only {
+        error!("Attempted to make a block device opened read-only writable");
+        return DiskControlResult::Err(SysError::new(libc::ENOTSUP));
+    }
+
+    if read_only {
+        // Make sure completed writes have reached the backing storage before refusing new ones.
+        if let Err(e) = disk_state.disk_image.fsync().await {
+            error!(
+                "Draining writes before switching to read-only failed! {:#}",
+                e
+            );
+            return DiskControlResult::Err(SysError::new(libc::EIO));
+        }
+    }
+
+    info!(
+        "Switching block device to {}",
+        if read_only { "read-only" } else { "read-write" }
+    );
+    worker_shared_state.read_only = read_only;
+    DiskControlResult::Ok
+}
+
 /// Periodically flushes the disk when the given timer fires.
 async fn flush_disk(
     disk_state: Rc<AsyncRwLock<DiskState>>,
@@ -680,6 +737,7 @@ impl BlockAsync {
         let disk_size = Arc::new(AtomicU64::new(disk_size));
         let shared_state = Arc::new(AsyncRwLock::new(WorkerSharedState {
             disk_size: disk_size.clone(),
+            read_only,
         }));
 
         Ok(BlockAsync {
@@ -754,7 +812,10 @@ impl BlockAsync {
         let req_type = req_header.req_type.to_native();
         let sector = req_header.sector.to_native();
 
-        if disk_state.read_only && req_type != VIRTIO_BLK_T_IN && req_type != VIRTIO_BLK_T_GET_ID {
+        if worker_shared_state.read_only
+            && req_type != VIRTIO_BLK_T_IN
+            && req_type != VIRTIO_BLK_T_GET_ID
+        {
             return Err(ExecuteError::ReadOnly {
                 request_type: req_type,
             });
@@ -1199,12 +1260,19 @@ impl VirtioDevice for BlockAsync {
 
 #[cfg(test)]
 mod tests {
+    use std::cell::Cell;
     use std::fs::File;
     use std::mem::size_of_val;
     use std::sync::atomic::AtomicU64;
 
+    use async_trait::async_trait;
+    use base::FileAllocate;
+    use base::FileSetLen;
+    use cros_async::BackingMemory;
+    use cros_async::MemRegionIter;
     use data_model::Le32;
     use data_model::Le64;
+    use disk::DiskGetLen;
     use disk::SingleFileDisk;
     use hypervisor::ProtectionType;
     use tempfile::tempfile;
@@ -1387,6 +1455,7 @@ mod tests {
             id: None,
             worker_shared_state: Arc::new(AsyncRwLock::new(WorkerSharedState {
                 disk_size: Arc::new(AtomicU64::new(disk_size)),
+                read_only: false,
             })),
         }));
 
@@ -1455,6 +1524,7 @@ mod tests {
             id: None,
             worker_shared_state: Arc::new(AsyncRwLock::new(WorkerSharedState {
                 disk_size: Arc::new(AtomicU64::new(disk_size)),
+                read_only: false,
             })),
         }));
 
@@ -1525,6 +1595,7 @@ mod tests {
             id: Some(*id),
             worker_shared_state: Arc::new(AsyncRwLock::new(WorkerSharedState {
                 disk_size: Arc::new(AtomicU64::new(disk_size)),
+                read_only: false,
             })),
         }));
 
@@ -1872,6 +1943,165 @@ mod tests {
         assert_eq!(b.worker_threads.len(), 2, "2 threads should be spawned.");
     }
 
+    /// An `AsyncDisk` backend that only supports fsync, counting calls and optionally failing them.
+    struct MockDisk {
+        fsync_count: Rc<Cell<usize>>,
+        fail_fsync: bool,
+    }
+
+    impl DiskGetLen for MockDisk {
+        fn get_len(&self) -> io::Result<u64> {
+            Ok(0x1000)
+        }
+    }
+
+    impl FileSetLen for MockDisk {
+        fn set_len(&self, _len: u64) -> io::Result<()> {
+            unimplemented!()
+        }
+    }
+
+    impl FileAllocate for MockDisk {
+        fn allocate(&mut self, _offset: u64, _len: u64) -> io::Result<()> {
+            unimplemented!()
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl AsyncDisk for MockDisk {
+        fn into_inner(self: Box<Self>) -> Box<dyn DiskFile> {
+            unimplemented!()
+        }
+
+        async fn flush(&self) -> disk::Result<()> {
+            Ok(())
+        }
+
+        async fn fsync(&self) -> disk::Result<()> {
+            self.fsync_count.set(self.fsync_count.get() + 1);
+            if self.fail_fsync {
+                Err(disk::Error::IoFsync(io::Error::from_raw_os_error(
+                    libc::EIO,
+                )))
+            } else {
+                Ok(())
+            }
+        }
+
+        async fn fdatasync(&self) -> disk::Result<()> {
+            self.fsync().await
+        }
+
+        async fn read_to_mem<'a>(
+            &'a self,
+            _file_offset: u64,
+            _mem: Arc<dyn BackingMemory + Send + Sync>,
+            _mem_offsets: MemRegionIter<'a>,
+        ) -> disk::Result<usize> {
+            Err(disk::Error::UnsupportedOperation)
+        }
+
+        async fn write_from_mem<'a>(
+            &'a self,
+            _file_offset: u64,
+            _mem: Arc<dyn BackingMemory + Send + Sync>,
+            _mem_offsets: MemRegionIter<'a>,
+        ) -> disk::Result<usize> {
+            Err(disk::Error::UnsupportedOperation)
+        }
+
+        async fn punch_hole(&self, _file_offset: u64, _length: u64) -> disk::Result<()> {
+            Err(disk::Error::UnsupportedOperation)
+        }
+
+        async fn write_zeroes_at(&self, _file_offset: u64, _length: u64) -> disk::Result<()> {
+            Err(disk::Error::UnsupportedOperation)
+        }
+    }
+
+    fn mock_disk_state(
+        opened_read_only: bool,
+        fail_fsync: bool,
+    ) -> (Rc<AsyncRwLock<DiskState>>, Rc<Cell<usize>>) {
+        let fsync_count = Rc::new(Cell::new(0));
+        let disk_state = Rc::new(AsyncRwLock::new(DiskState {
+            disk_image: Box::new(MockDisk {
+                fsync_count: fsync_count.clone(),
+                fail_fsync,
+            }),
+            read_only: opened_read_only,
+            sparse: true,
+            id: None,
+            worker_shared_state: Arc::new(AsyncRwLock::new(WorkerSharedState {
+                disk_size: Arc::new(AtomicU64::new(0x1000)),
+                read_only: opened_read_only,
+            })),
+        }));
+        (disk_state, fsync_count)
+    }
+
+    async fn is_read_only(disk_state: &AsyncRwLock<DiskState>) -> bool {
+        let disk_state = disk_state.read_lock().await;
+        let worker_shared_state = disk_state.worker_shared_state.read_lock().await;
+        worker_shared_state.read_only
+    }
+
+    #[test]
+    fn set_read_only_both_directions() {
+        let ex = Executor::new().expect("creating an executor failed");
+        let (disk_state, fsync_count) = mock_disk_state(false, false);
+
+        ex.run_until(async {
+            assert_eq!(
+                set_read_only(&disk_state, true).await,
+                DiskControlResult::Ok
+            );
+            assert!(is_read_only(&disk_state).await);
+            // Pending writes are synced before the device stops accepting them.
+            assert_eq!(fsync_count.get(), 1);
+
+            assert_eq!(
+                set_read_only(&disk_state, false).await,
+                DiskControlResult::Ok
+            );
+            assert!(!is_read_only(&disk_state).await);
+            assert_eq!(fsync_count.get(), 1);
+        })
+        .expect("run_until failed");
+    }
+
+    #[test]
+    fn set_read_only_drain_failure() {
+        let ex = Executor::new().expect("creating an executor failed");
+        let (disk_state, fsync_count) = mock_disk_state(false, true);
+
+        ex.run_until(async {
+            assert_eq!(
+                set_read_only(&disk_state, true).await,
+                DiskControlResult::Err(SysError::new(libc::EIO))
+            );
+            assert_eq!(fsync_count.get(), 1);
+            // The disk stays writable if its writes couldn't be drained.
+            assert!(!is_read_only(&disk_state).await);
+        })
+        .expect("run_until failed");
+    }
+
+    #[test]
+    fn set_read_write_on_read_only_backend() {
+        let ex = Executor::new().expect("creating an executor failed");
+        let (disk_state, _) = mock_disk_state(true, false);
+
+        ex.run_until(async {
+            assert_eq!(
+                set_read_only(&disk_state, false).await,
+                DiskControlResult::Err(SysError::new(libc::ENOTSUP))
+            );
+            assert!(is_read_only(&disk_state).await);
+        })
+        .expect("run_until failed");
+    }
+
     struct BlockContext {}
 
     fn modify_device(_block_context: &mut BlockContext, b: &mut BlockAsync) {
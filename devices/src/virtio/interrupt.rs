@@ -5,6 +5,7 @@
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Instant;
 
 #[cfg(target_arch = "x86_64")]
 use base::error;
@@ -38,6 +39,8 @@ enum Transport {
     VhostUser {
         call_evt: Event,
         signal_config_changed_fn: Box<dyn Fn() + Send + Sync>,
+        // When the interrupt was last signaled, i.e. when the device last made progress.
+        last_signal: Mutex<Option<Instant>>,
     },
 }
 
@@ -109,7 +112,12 @@ impl Interrupt {
                     irq_evt_edge.trigger().unwrap();
                 }
             }
-            Transport::VhostUser { call_evt, .. } => {
+            Transport::VhostUser {
+                call_evt,
+                last_signal,
+                ..
+            } => {
+                *last_signal.lock() = Some(Instant::now());
                 // TODO(b/187487351): To avoid sending unnecessary events, we might want to support
                 // interrupt status. For this purpose, we need a mechanism to share interrupt status
                 // between the vmm and the device process.
@@ -234,6 +242,7 @@ impl Interrupt {
                 transport: Transport::VhostUser {
                     call_evt,
                     signal_config_changed_fn,
+                    last_signal: Mutex::new(None),
                 },
                 async_intr_status: false,
                 #[cfg(target_arch = "x86_64")]
@@ -253,6 +262,15 @@ impl Interrupt {
         )
     }
 
+    /// Returns when a vhost-user interrupt was last signaled, or `None` if it never was or the
+    /// interrupt isn't a vhost-user one.
+    pub fn last_signal(&self) -> Option<Instant> {
+        match &self.inner.as_ref().transport {
+            Transport::VhostUser { last_signal, .. } => *last_signal.lock(),
+            _ => None,
+        }
+    }
+
     /// Get a reference to the interrupt event.
     pub fn get_interrupt_evt(&self) -> &Event {
         match &self.inner.as_ref().transport {
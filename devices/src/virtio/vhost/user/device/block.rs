@@ -43,6 +43,18 @@ struct BlockBackendSnapshot {
     acked_protocol_features: u64,
 }
 
+impl BlockBackend {
+    fn new(inner: Box<BlockAsync>) -> Self {
+        let avail_features = inner.features() | 1 << VHOST_USER_F_PROTOCOL_FEATURES;
+        BlockBackend {
+            inner,
+            avail_features,
+            acked_features: 0,
+            acked_protocol_features: VhostUserProtocolFeatures::empty(),
+        }
+    }
+}
+
 impl VhostUserDevice for BlockAsync {
     fn max_queue_num(&self) -> usize {
         NUM_QUEUES as usize
@@ -52,14 +64,7 @@ impl VhostUserDevice for BlockAsync {
         self: Box<Self>,
         _ex: &Executor,
     ) -> anyhow::Result<Box<dyn VhostUserSlaveReqHandler>> {
-        let avail_features = self.features() | 1 << VHOST_USER_F_PROTOCOL_FEATURES;
-        let backend = BlockBackend {
-            inner: self,
-            avail_features,
-            acked_features: 0,
-            acked_protocol_features: VhostUserProtocolFeatures::empty(),
-        };
-        let handler = DeviceRequestHandler::new(Box::new(backend));
+        let handler = DeviceRequestHandler::new(Box::new(BlockBackend::new(self)));
         Ok(Box::new(handler))
     }
 }
@@ -5,13 +5,22 @@
 use anyhow::Context;
 use argh::FromArgs;
 use base::info;
+use base::warn;
+use base::Tube;
+use base::UnixSeqpacket;
+use cros_async::is_uring_stable;
 use cros_async::Executor;
+use cros_async::ExecutorKind;
 use hypervisor::ProtectionType;
 
 use crate::virtio::base_features;
 use crate::virtio::block::DiskOption;
+use crate::virtio::vhost::user::device::block::BlockBackend;
 use crate::virtio::vhost::user::device::listener::sys::VhostUserListener;
 use crate::virtio::vhost::user::device::listener::VhostUserListenerTrait;
+use crate::virtio::vhost::user::device::set_worker_cpu_affinity;
+use crate::virtio::vhost::user::device::signal::SigtermHandler;
+use crate::virtio::vhost::user::device::VhostUserDevice;
 use crate::virtio::BlockAsync;
 
 #[derive(FromArgs)]
@@ -24,12 +33,33 @@ pub struct Options {
     #[argh(option, arg_name = "PATH")]
     /// path to a vhost-user socket
     socket: String,
+    #[argh(option, arg_name = "EXECUTOR")]
+    /// async executor backend; "uring" or "epoll". Defaults to the executor preferred by the
+    /// device.
+    async_executor: Option<ExecutorKind>,
+    #[argh(option, arg_name = "CPU")]
+    /// host CPU the device worker thread may run on. Can be given multiple times.
+    cpu_affinity: Vec<usize>,
+    #[argh(option, arg_name = "PATH")]
+    /// path to a socket to connect to, on which the device answers control requests such as
+    /// health checks.
+    control_socket: Option<String>,
+}
+
+/// Creates an executor of the given `kind`, failing with a clear error if the host doesn't support
+/// it.
+fn create_executor(kind: ExecutorKind) -> anyhow::Result<Executor> {
+    if kind == ExecutorKind::Uring && !is_uring_stable() {
+        warn!("Enabling io_uring executor on the kernel version where io_uring is unstable");
+    }
+    Executor::with_executor_kind(kind)
+        .with_context(|| format!("{:?} executor is not available on this host", kind))
 }
 
 /// Starts a vhost-user block device.
 /// Returns an error if the given `args` is invalid or the device fails to run.
 pub fn start_device(opts: Options) -> anyhow::Result<()> {
-    let ex = Executor::new().context("failed to create executor")?;
+    let sigterm = SigtermHandler::new()?;
 
     let mut fileopts = opts.file.split(":").collect::<Vec<_>>();
     let filename = fileopts.remove(0);
@@ -38,6 +68,7 @@ pub fn start_device(opts: Options) -> anyhow::Result<()> {
         path: filename.into(),
         read_only: fileopts.contains(&"read-only"),
         sparse: false,
+        async_executor: opts.async_executor,
         ..DiskOption::default()
     };
 
@@ -50,8 +81,87 @@ pub fn start_device(opts: Options) -> anyhow::Result<()> {
         None,
     )?);
 
+    let executor_kind = opts
+        .async_executor
+        .or_else(|| block.executor_kind())
+        .unwrap_or_default();
+    let ex = create_executor(executor_kind)?;
+
     let listener = VhostUserListener::new_socket(&opts.socket, None)?;
     info!("vhost-user disk device ready, starting run loop...");
 
-    listener.run_device(ex, block)
+    if !opts.cpu_affinity.is_empty() {
+        set_worker_cpu_affinity(&opts.cpu_affinity)?;
+    }
+
+    match opts.control_socket {
+        Some(path) => {
+            let sock = UnixSeqpacket::connect(&path)
+                .with_context(|| format!("failed to connect to control socket {}", path))?;
+            let control_tube =
+                Tube::new_from_unix_seqpacket(sock).context("failed to create control tube")?;
+            let backend = Box::new(BlockBackend::new(block));
+            sigterm.run_until(
+                &ex,
+                listener.run_backend_with_control(backend, control_tube, &ex),
+            )
+        }
+        None => sigterm.run_device(listener, ex, block),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uring_executor_option() {
+        let opts = Options::from_args(
+            &["block"],
+            &[
+                "--file",
+                "disk.img",
+                "--socket",
+                "block.sock",
+                "--async-executor",
+                "uring",
+            ],
+        )
+        .unwrap();
+        assert_eq!(opts.async_executor, Some(ExecutorKind::Uring));
+
+        match create_executor(opts.async_executor.unwrap()) {
+            Ok(ex) => assert!(matches!(ex, Executor::Uring(_))),
+            // The host kernel may lack io_uring support.
+            Err(e) => assert!(e.to_string().contains("not available")),
+        }
+    }
+
+    #[test]
+    fn default_executor_option() {
+        let opts = Options::from_args(
+            &["block"],
+            &["--file", "disk.img", "--socket", "block.sock"],
+        )
+        .unwrap();
+        assert_eq!(opts.async_executor, None);
+        assert_eq!(opts.control_socket, None);
+    }
+
+    #[test]
+    fn control_socket_option() {
+        let opts = Options::from_args(
+            &["block"],
+            &[
+                "--file",
+                "disk.img",
+                "--socket",
+                "block.sock",
+                "--control-socket",
+                "control.sock",
+            ],
+        )
+        .unwrap();
+        assert_eq!(opts.control_socket.as_deref(), Some("control.sock"));
+    }
 }
@@ -32,7 +32,7 @@ use crate::virtio::vhost::user::device::handler::DeviceRequestHandler;
 use crate::virtio::vhost::user::device::handler::Error as DeviceError;
 use crate::virtio::vhost::user::device::handler::VhostUserBackend;
 use crate::virtio::vhost::user::device::listener::sys::VhostUserListener;
-use crate::virtio::vhost::user::device::listener::VhostUserListenerTrait;
+use crate::virtio::vhost::user::device::signal::SigtermHandler;
 use crate::virtio::vhost::user::device::VhostUserDevice;
 use crate::virtio::Interrupt;
 use crate::virtio::Queue;
@@ -256,6 +256,7 @@ fn run_multi_port_device(opts: Options) -> anyhow::Result<()> {
     if opts.port.is_empty() {
         bail!("console: must have at least one `--port`");
     }
+    let sigterm = SigtermHandler::new()?;
 
     // We won't jail the device and can simply ignore `keep_rds`.
     let device = Box::new(create_vu_multi_port_device(&opts.port, &mut Vec::new())?);
@@ -263,7 +264,7 @@ fn run_multi_port_device(opts: Options) -> anyhow::Result<()> {
 
     let listener = VhostUserListener::new_socket(&opts.socket, None)?;
 
-    listener.run_device(ex, device)
+    sigterm.run_device(listener, ex, device)
 }
 
 /// Return a new vhost-user console device. `params` are the device's configuration, and `keep_rds`
@@ -293,6 +294,7 @@ pub fn run_console_device(opts: Options) -> anyhow::Result<()> {
     if !opts.port.is_empty() {
         return run_multi_port_device(opts);
     }
+    let sigterm = SigtermHandler::new()?;
 
     // fall back to a multiport disabled console
     let type_ = match opts.output_file {
@@ -332,5 +334,5 @@ pub fn run_console_device(opts: Options) -> anyhow::Result<()> {
 
     let listener = VhostUserListener::new_socket(&opts.socket, None)?;
 
-    listener.run_device(ex, device)
+    sigterm.run_device(listener, ex, device)
 }
@@ -17,6 +17,7 @@ use crate::virtio::vhost::user::device::fs::FsBackend;
 use crate::virtio::vhost::user::device::fs::Options;
 use crate::virtio::vhost::user::device::listener::sys::VhostUserListener;
 use crate::virtio::vhost::user::device::listener::VhostUserListenerTrait;
+use crate::virtio::vhost::user::device::signal::SigtermHandler;
 
 fn default_uidmap() -> String {
     // SAFETY: trivially safe
@@ -128,6 +129,10 @@ pub fn start_device(opts: Options) -> anyhow::Result<()> {
         return Ok(());
     }
 
+    // Only the jailed child runs the device, so SIGTERM is only blocked and handled there. The
+    // signalfd is created after the fork so that it doesn't need to be kept in the jail.
+    let sigterm = SigtermHandler::new()?;
+
     // We need to set the no setuid fixup secure bit so that we don't drop capabilities when
     // changing the thread uid/gid. Without this, creating new entries can fail in some corner
     // cases.
@@ -151,6 +156,5 @@ pub fn start_device(opts: Options) -> anyhow::Result<()> {
         }
     }
 
-    // run_until() returns an Result<Result<..>> which the ? operator lets us flatten.
-    ex.run_until(listener.run_backend(fs_device, &ex))?
+    sigterm.run_until(&ex, listener.run_backend(fs_device, &ex))
 }
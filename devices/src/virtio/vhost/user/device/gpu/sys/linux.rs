@@ -29,6 +29,7 @@ use crate::virtio::gpu::ProcessDisplayResult;
 use crate::virtio::vhost::user::device::gpu::GpuBackend;
 use crate::virtio::vhost::user::device::listener::sys::VhostUserListener;
 use crate::virtio::vhost::user::device::listener::VhostUserListenerTrait;
+use crate::virtio::vhost::user::device::signal::SigtermHandler;
 use crate::virtio::vhost::user::device::wl::parse_wayland_sock;
 use crate::virtio::Gpu;
 use crate::virtio::GpuDisplayParameters;
@@ -151,6 +152,9 @@ pub struct Options {
 }
 
 pub fn run_gpu_device(opts: Options) -> anyhow::Result<()> {
+    // Block SIGTERM before the device starts any thread.
+    let sigterm = SigtermHandler::new()?;
+
     let Options {
         x_display,
         params: mut gpu_parameters,
@@ -256,8 +260,11 @@ pub fn run_gpu_device(opts: Options) -> anyhow::Result<()> {
         shmem_mapper: Arc::new(Mutex::new(None)),
     });
 
-    // Run until the backend is finished.
-    let _ = ex.run_until(listener.run_backend(backend, &ex))?;
+    // Run until the backend is finished or SIGTERM is received.
+    sigterm.run_until(&ex, async {
+        let _ = listener.run_backend(backend, &ex).await;
+        Ok(())
+    })?;
 
     // Process any tasks from the backend's destructor.
     Ok(ex.run_until(async {})?)
@@ -54,6 +54,8 @@ use std::num::Wrapping;
 #[cfg(any(target_os = "android", target_os = "linux"))]
 use std::os::unix::io::AsRawFd;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 
 use anyhow::bail;
 use anyhow::Context;
@@ -97,6 +99,8 @@ use vmm_vhost::VhostUserMasterReqHandler;
 use vmm_vhost::VhostUserSlaveReqHandler;
 use vmm_vhost::VHOST_USER_F_PROTOCOL_FEATURES;
 
+use crate::virtio::vhost::user::device::listener::VhostUserControlRequest;
+use crate::virtio::vhost::user::device::listener::VhostUserControlResponse;
 use crate::virtio::Interrupt;
 use crate::virtio::Queue;
 use crate::virtio::QueueConfig;
@@ -121,6 +125,23 @@ pub fn vmm_va_to_gpa(maps: &[MappingInfo], vmm_va: u64) -> VhostResult<GuestAddr
     Err(VhostError::InvalidMessage)
 }
 
+/// How long a device can go without signaling the guest while a queue has a pending kick before it
+/// is reported as stalled.
+const STALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Health of a vhost-user device, as reported to `VhostUserControlRequest::HealthCheck`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VhostUserHealth {
+    /// Whether the device is able to make progress, i.e. it didn't leave a kick of the guest
+    /// unanswered for too long.
+    pub alive: bool,
+    /// Number of queues the device is processing.
+    pub queues_active: usize,
+    /// Time since the device was last active, i.e. since it last signaled the guest or received a
+    /// request from the front-end.
+    pub last_activity: Duration,
+}
+
 /// Trait for vhost-user backend.
 pub trait VhostUserBackend {
     /// The maximum number of queues that this backend can manage.
@@ -206,6 +227,15 @@ pub trait VhostUserBackend {
         // TODO(rizhang): Return error once basic devices support this.
         Ok(())
     }
+
+    /// Returns the health of the backend, e.g. for a watchdog.
+    ///
+    /// Backends that don't implement this are reported by the request handler from the queues it
+    /// started: the device is stalled if a queue was kicked but it hasn't signaled the guest for a
+    /// while.
+    fn health(&self) -> Option<VhostUserHealth> {
+        None
+    }
 }
 
 /// A virtio ring entry.
@@ -213,6 +243,8 @@ struct Vring {
     // The queue config. This doesn't get mutated by the queue workers.
     queue: QueueConfig,
     doorbell: Option<Interrupt>,
+    // Clone of the kick event of the started queue, to check whether the guest's kicks are handled.
+    kick_evt: Option<Event>,
     enabled: bool,
     // Active queue that is only `Some` when the device is sleeping.
     paused_queue: Option<Queue>,
@@ -232,6 +264,7 @@ impl Vring {
         Self {
             queue: QueueConfig::new(max_size, features),
             doorbell: None,
+            kick_evt: None,
             enabled: false,
             paused_queue: None,
         }
@@ -240,6 +273,7 @@ impl Vring {
     fn reset(&mut self) {
         self.queue.reset();
         self.doorbell = None;
+        self.kick_evt = None;
         self.enabled = false;
         self.paused_queue = None;
     }
@@ -264,6 +298,11 @@ impl Vring {
     ) -> anyhow::Result<()> {
         self.queue.restore(vring_snapshot.queue)?;
         self.enabled = vring_snapshot.enabled;
+        self.kick_evt = event
+            .as_ref()
+            .map(Event::try_clone)
+            .transpose()
+            .context("failed to clone queue event")?;
         self.paused_queue = vring_snapshot
             .paused_queue
             .map(|value| {
@@ -390,6 +429,56 @@ impl DeviceRequestHandler {
     }
 }
 
+impl DeviceRequestHandler {
+    /// Returns the health of the device, given the time since the front-end last sent a request.
+    pub(crate) fn health(&self, last_request: Duration) -> VhostUserHealth {
+        if let Some(health) = self.backend.health() {
+            return health;
+        }
+
+        let now = Instant::now();
+        let active_vrings: Vec<&Vring> = self
+            .vrings
+            .iter()
+            .filter(|vring| vring.queue.ready() && vring.paused_queue.is_none())
+            .collect();
+        let last_activity = active_vrings
+            .iter()
+            .filter_map(|vring| vring.doorbell.as_ref()?.last_signal())
+            .map(|last_signal| now.saturating_duration_since(last_signal))
+            .fold(last_request, Duration::min);
+        let stalled = last_activity > STALL_TIMEOUT
+            && active_vrings
+                .iter()
+                .filter_map(|vring| vring.kick_evt.as_ref())
+                .any(sys::kick_pending);
+        VhostUserHealth {
+            alive: !stalled,
+            queues_active: active_vrings.len(),
+            last_activity,
+        }
+    }
+
+    /// Answers a control request received while the device runs.
+    pub(crate) fn handle_control_request(
+        &self,
+        request: VhostUserControlRequest,
+        last_request: Duration,
+    ) -> VhostUserControlResponse {
+        match request {
+            VhostUserControlRequest::HealthCheck => {
+                VhostUserControlResponse::Health(self.health(last_request))
+            }
+            // Once the device runs, its state is saved through the vhost-user protocol.
+            VhostUserControlRequest::Snapshot
+            | VhostUserControlRequest::Restore(_)
+            | VhostUserControlRequest::Start => {
+                VhostUserControlResponse::Err("device is already running".to_string())
+            }
+        }
+    }
+}
+
 impl VhostUserSlaveReqHandler for DeviceRequestHandler {
     fn set_owner(&mut self) -> VhostResult<()> {
         if self.owned {
@@ -550,6 +639,10 @@ impl VhostUserSlaveReqHandler for DeviceRequestHandler {
         }
 
         let kick_evt = VhostUserRegularOps::set_vring_kick(index, file)?;
+        vring.kick_evt = Some(kick_evt.try_clone().map_err(|e| {
+            error!("failed to clone kick event: {}", e);
+            VhostError::SlaveInternalError
+        })?);
 
         // Enable any virtqueue features that were negotiated (like VIRTIO_RING_F_EVENT_IDX).
         vring.queue.ack_features(self.backend.acked_features());
@@ -998,6 +1091,7 @@ mod tests {
         acked_features: u64,
         acked_protocol_features: VhostUserProtocolFeatures,
         active_queues: Vec<Option<Queue>>,
+        health: Option<VhostUserHealth>,
     }
 
     impl FakeBackend {
@@ -1011,6 +1105,7 @@ mod tests {
                 acked_features: 0,
                 acked_protocol_features: VhostUserProtocolFeatures::empty(),
                 active_queues,
+                health: None,
             }
         }
     }
@@ -1080,6 +1175,10 @@ mod tests {
                 .take()
                 .ok_or(Error::WorkerNotFound)?)
         }
+
+        fn health(&self) -> Option<VhostUserHealth> {
+            self.health
+        }
     }
 
     #[test]
@@ -1184,6 +1283,97 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_vhost_user_default_health() {
+        let handler = DeviceRequestHandler::new(Box::new(FakeBackend::new()));
+        assert_eq!(
+            handler.health(Duration::from_secs(3)),
+            VhostUserHealth {
+                alive: true,
+                queues_active: 0,
+                last_activity: Duration::from_secs(3),
+            }
+        );
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_vhost_user_stalled_health() {
+        let mut handler = DeviceRequestHandler::new(Box::new(FakeBackend::new()));
+        let kick_evt = Event::new().unwrap();
+        handler.vrings[0].queue.set_ready(true);
+        handler.vrings[0].kick_evt = Some(kick_evt.try_clone().unwrap());
+
+        // An idle queue is healthy whatever the time since the last request.
+        let idle = VhostUserHealth {
+            alive: true,
+            queues_active: 1,
+            last_activity: Duration::from_secs(60),
+        };
+        assert_eq!(handler.health(Duration::from_secs(60)), idle);
+
+        // A kick that was just received is still being processed.
+        kick_evt.signal().unwrap();
+        assert!(handler.health(Duration::from_secs(1)).alive);
+
+        // A kick left unanswered for too long means the device stalled.
+        assert_eq!(
+            handler.health(Duration::from_secs(60)),
+            VhostUserHealth {
+                alive: false,
+                ..idle
+            }
+        );
+
+        // Checking the queue didn't consume the kick, which is left for the queue's worker.
+        kick_evt.wait().unwrap();
+        assert!(handler.health(Duration::from_secs(60)).alive);
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_vhost_user_health_check() {
+        use base::Tube;
+        use cros_async::Executor;
+
+        use super::sys::linux::run_handler_with_control;
+
+        const HEALTH: VhostUserHealth = VhostUserHealth {
+            alive: false,
+            queues_active: 3,
+            last_activity: Duration::from_millis(1500),
+        };
+
+        let (dev, vmm) = test_helpers::setup();
+        let (control_tube, device_control_tube) = Tube::pair().unwrap();
+
+        let vmm_thread = std::thread::spawn(move || {
+            // Keep the connection open until the device answered.
+            let _connection = test_helpers::connect(vmm);
+            control_tube
+                .send(&VhostUserControlRequest::HealthCheck)
+                .unwrap();
+            match control_tube.recv().unwrap() {
+                VhostUserControlResponse::Health(health) => assert_eq!(health, HEALTH),
+                r => panic!("unexpected response: {:?}", r),
+            }
+        });
+
+        let mut backend = FakeBackend::new();
+        backend.health = Some(HEALTH);
+        let req_handler = test_helpers::listen(dev, DeviceRequestHandler::new(Box::new(backend)));
+        let ex = Executor::new().unwrap();
+        ex.run_until(run_handler_with_control(
+            req_handler,
+            device_control_tube,
+            &ex,
+        ))
+        .unwrap()
+        .unwrap();
+
+        vmm_thread.join().unwrap();
+    }
+
     fn handle_request<S: VhostUserSlaveReqHandler>(
         handler: &mut SlaveReqHandler<S>,
     ) -> Result<(), VhostError> {
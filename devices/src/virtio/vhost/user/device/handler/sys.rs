@@ -5,10 +5,12 @@
 cfg_if::cfg_if! {
     if #[cfg(any(target_os = "android", target_os = "linux"))] {
         pub mod linux;
+        pub(super) use linux::kick_pending;
         #[cfg(test)]
         pub use linux::test_helpers;
     } else if #[cfg(windows)] {
         pub mod windows;
+        pub(super) use windows::kick_pending;
         #[cfg(test)]
         pub use windows::test_helpers;
     }
@@ -2,53 +2,162 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::time::Duration;
+use std::time::Instant;
+
 use anyhow::Context;
 use anyhow::Result;
 use base::info;
+use base::warn;
 use base::AsRawDescriptor;
+use base::Event;
 use base::SafeDescriptor;
+use base::Tube;
+use base::WaitContext;
+use cros_async::AsyncTube;
 use cros_async::AsyncWrapper;
 use cros_async::Executor;
+use cros_async::IoSource;
+use futures::future;
+use futures::pin_mut;
+use futures::select;
+use futures::FutureExt;
 use vmm_vhost::Error as VhostError;
 use vmm_vhost::SlaveReqHandler;
 use vmm_vhost::VhostUserSlaveReqHandler;
 
-/// Performs the run loop for an already-constructor request handler.
-pub async fn run_handler<S>(mut req_handler: SlaveReqHandler<S>, ex: &Executor) -> Result<()>
+use crate::virtio::vhost::user::device::handler::DeviceRequestHandler;
+use crate::virtio::vhost::user::device::listener::VhostUserControlRequest;
+
+/// Returns whether the guest kicked the queue of `kick_evt` and the kick wasn't handled yet. The
+/// kick is left for the queue's worker.
+pub fn kick_pending(kick_evt: &Event) -> bool {
+    match WaitContext::build_with(&[(kick_evt, ())])
+        .and_then(|ctx| ctx.wait_timeout(Duration::ZERO))
+    {
+        Ok(events) => !events.is_empty(),
+        Err(e) => {
+            warn!("failed to poll kick event: {}", e);
+            false
+        }
+    }
+}
+
+/// Returns an async source that becomes readable when the front-end sends a request.
+fn async_handler_source<S>(
+    req_handler: &SlaveReqHandler<S>,
+    ex: &Executor,
+) -> Result<IoSource<AsyncWrapper<SafeDescriptor>>>
 where
     S: VhostUserSlaveReqHandler,
 {
-    let h = SafeDescriptor::try_from(&req_handler as &dyn AsRawDescriptor)
+    let h = SafeDescriptor::try_from(req_handler as &dyn AsRawDescriptor)
         .map(AsyncWrapper::new)
         .context("failed to get safe descriptor for handler")?;
-    let handler_source = ex
-        .async_from(h)
-        .context("failed to create an async source")?;
+    ex.async_from(h).context("failed to create an async source")
+}
 
-    loop {
+/// Waits for the next request of the front-end and processes it. Returns `false` once the
+/// front-end closed the connection.
+async fn handle_next_request<S>(
+    req_handler: &mut SlaveReqHandler<S>,
+    handler_source: &IoSource<AsyncWrapper<SafeDescriptor>>,
+) -> Result<bool>
+where
+    S: VhostUserSlaveReqHandler,
+{
+    handler_source
+        .wait_readable()
+        .await
+        .context("failed to wait for the handler to become readable")?;
+    let (hdr, files) = match req_handler.recv_header() {
+        Ok((hdr, files)) => (hdr, files),
+        Err(VhostError::ClientExit) => {
+            info!("vhost-user connection closed");
+            return Ok(false);
+        }
+        Err(e) => {
+            return Err(e.into());
+        }
+    };
+
+    if req_handler.needs_wait_for_payload(&hdr) {
         handler_source
             .wait_readable()
             .await
             .context("failed to wait for the handler to become readable")?;
-        let (hdr, files) = match req_handler.recv_header() {
-            Ok((hdr, files)) => (hdr, files),
-            Err(VhostError::ClientExit) => {
-                info!("vhost-user connection closed");
-                // Exit as the client closed the connection.
-                return Ok(());
+    }
+    req_handler.process_message(hdr, files)?;
+    Ok(true)
+}
+
+/// Performs the run loop for an already-constructor request handler.
+pub async fn run_handler<S>(mut req_handler: SlaveReqHandler<S>, ex: &Executor) -> Result<()>
+where
+    S: VhostUserSlaveReqHandler,
+{
+    let handler_source = async_handler_source(&req_handler, ex)?;
+
+    // Exit once the client closed the connection.
+    while handle_next_request(&mut req_handler, &handler_source).await? {}
+    Ok(())
+}
+
+/// Like `run_handler`, but also answers the control requests received on `control_tube` between
+/// the requests of the front-end.
+pub async fn run_handler_with_control(
+    mut req_handler: SlaveReqHandler<DeviceRequestHandler>,
+    control_tube: Tube,
+    ex: &Executor,
+) -> Result<()> {
+    let handler_source = async_handler_source(&req_handler, ex)?;
+    let mut control_tube =
+        Some(AsyncTube::new(ex, control_tube).context("failed to create async control tube")?);
+    let mut last_request = Instant::now();
+
+    loop {
+        // Only wait for the front-end here, so that its request is not lost if a control request
+        // comes first.
+        let control_request = {
+            let request_ready = handler_source.wait_readable().fuse();
+            let control_request = match &control_tube {
+                Some(tube) => tube.next::<VhostUserControlRequest>().left_future(),
+                None => future::pending().right_future(),
             }
-            Err(e) => {
-                return Err(e.into());
+            .fuse();
+            pin_mut!(request_ready, control_request);
+            select! {
+                r = request_ready => {
+                    r.context("failed to wait for the handler to become readable")?;
+                    None
+                }
+                r = control_request => Some(r),
             }
         };
 
-        if req_handler.needs_wait_for_payload(&hdr) {
-            handler_source
-                .wait_readable()
-                .await
-                .context("failed to wait for the handler to become readable")?;
+        match control_request {
+            Some(Ok(request)) => {
+                let response = req_handler
+                    .as_ref()
+                    .handle_control_request(request, last_request.elapsed());
+                if let Some(tube) = &control_tube {
+                    tube.send(response)
+                        .await
+                        .context("failed to send control response")?;
+                }
+            }
+            Some(Err(e)) => {
+                // Keep the device running without its control tube.
+                warn!("stopped processing control requests: {}", e);
+                control_tube = None;
+            }
+            None => {
+                if !handle_next_request(&mut req_handler, &handler_source).await? {
+                    return Ok(());
+                }
+                last_request = Instant::now();
+            }
         }
-        req_handler.process_message(hdr, files)?;
     }
 }
 
@@ -27,6 +27,13 @@ use vmm_vhost::VhostUserSlaveReqHandler;
 
 use crate::virtio::vhost::user::device::handler::DeviceRequestHandler;
 
+/// Returns whether the guest kicked the queue of `kick_evt` and the kick wasn't handled yet.
+///
+/// Waiting on an auto-reset event would consume the kick, so this always returns `false`.
+pub fn kick_pending(_kick_evt: &Event) -> bool {
+    false
+}
+
 pub fn read_from_tube_transporter(
     raw_transport_tube: RawDescriptor,
 ) -> anyhow::Result<TubeTransferDataList> {
@@ -5,17 +5,87 @@
 pub mod sys;
 use std::any::Any;
 use std::pin::Pin;
+use std::time::Instant;
 
+use anyhow::Context;
 use base::RawDescriptor;
+use base::Tube;
 use cros_async::Executor;
 use futures::Future;
+use serde::Deserialize;
+use serde::Serialize;
 pub use sys::VhostUserListener;
 use vmm_vhost::VhostUserSlaveReqHandler;
 
 use crate::virtio::vhost::user::device::handler::DeviceRequestHandler;
 use crate::virtio::vhost::user::device::handler::VhostUserBackend;
+use crate::virtio::vhost::user::device::handler::VhostUserHealth;
 use crate::virtio::vhost::user::VhostUserDevice;
 
+/// Requests that can be sent on the control tube of a vhost-user device. Only `HealthCheck` is
+/// accepted once the device started processing the requests of the front-end.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum VhostUserControlRequest {
+    /// Return the state of the device, as returned by `VhostUserBackend::snapshot`.
+    Snapshot,
+    /// Restore the state of the device from the result of a previous `Snapshot` request, with
+    /// `VhostUserBackend::restore`.
+    Restore(Vec<u8>),
+    /// Stop processing control requests and start running the device.
+    Start,
+    /// Return the health of the device.
+    HealthCheck,
+}
+
+/// Responses to `VhostUserControlRequest`s.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum VhostUserControlResponse {
+    Ok,
+    Snapshot(Vec<u8>),
+    Health(VhostUserHealth),
+    Err(String),
+}
+
+/// Answers the requests received on `control_tube` for `backend`, until a
+/// `VhostUserControlRequest::Start` is received.
+fn handle_control_requests(
+    backend: &mut dyn VhostUserBackend,
+    control_tube: &Tube,
+) -> anyhow::Result<()> {
+    let waiting_since = Instant::now();
+    loop {
+        let request = control_tube
+            .recv()
+            .context("failed to receive control request")?;
+        let (response, start) = match request {
+            VhostUserControlRequest::Snapshot => match backend.snapshot() {
+                Ok(data) => (VhostUserControlResponse::Snapshot(data), false),
+                Err(e) => (VhostUserControlResponse::Err(format!("{:#}", e)), false),
+            },
+            VhostUserControlRequest::Restore(data) => match backend.restore(data) {
+                Ok(()) => (VhostUserControlResponse::Ok, false),
+                Err(e) => (VhostUserControlResponse::Err(format!("{:#}", e)), false),
+            },
+            VhostUserControlRequest::Start => (VhostUserControlResponse::Ok, true),
+            // The device has no queues until it starts.
+            VhostUserControlRequest::HealthCheck => (
+                VhostUserControlResponse::Health(VhostUserHealth {
+                    alive: true,
+                    queues_active: 0,
+                    last_activity: waiting_since.elapsed(),
+                }),
+                false,
+            ),
+        };
+        control_tube
+            .send(&response)
+            .context("failed to send control response")?;
+        if start {
+            return Ok(());
+        }
+    }
+}
+
 /// Trait that the platform-specific type `VhostUserListener` needs to implement. It contains all
 /// the methods that are ok to call from non-platform specific code.
 pub trait VhostUserListenerTrait {
@@ -72,6 +142,15 @@ pub trait VhostUserListenerTrait {
         self.run_req_handler(Box::new(DeviceRequestHandler::new(backend)), ex)
     }
 
+    /// Like `run_backend`, but also answers the `VhostUserControlRequest::HealthCheck` requests
+    /// received on `control_tube` while the backend runs, e.g. for a watchdog.
+    fn run_backend_with_control<'e>(
+        self,
+        backend: Box<dyn VhostUserBackend>,
+        control_tube: Tube,
+        ex: &'e Executor,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + 'e>>;
+
     /// Start processing requests for a `VhostUserDevice` on `listener`. Returns when the front-end
     /// side disconnects or an error occurs.
     fn run_device(self, ex: Executor, device: Box<dyn VhostUserDevice>) -> anyhow::Result<()>
@@ -80,4 +159,165 @@ pub trait VhostUserListenerTrait {
     {
         ex.run_until(self.run_req_handler(device.into_req_handler(&ex).unwrap(), &ex))?
     }
+
+    /// Like `run_backend_with_control`, but first answers the requests received on
+    /// `control_tube` until a `VhostUserControlRequest::Start` is received. This lets the state of
+    /// `backend` be restored from a snapshot before the front-end starts using it. The state of
+    /// the queues is restored by the front-end through the vhost-user protocol.
+    fn restore_and_run_backend(
+        self,
+        ex: Executor,
+        mut backend: Box<dyn VhostUserBackend>,
+        control_tube: Tube,
+    ) -> anyhow::Result<()>
+    where
+        Self: Sized,
+    {
+        handle_control_requests(backend.as_mut(), &control_tube)?;
+        ex.run_until(self.run_backend_with_control(backend, control_tube, &ex))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use anyhow::bail;
+    use vm_memory::GuestMemory;
+    use vmm_vhost::message::VhostUserProtocolFeatures;
+
+    use super::*;
+    use crate::virtio::Interrupt;
+    use crate::virtio::Queue;
+
+    /// A backend whose whole state is a counter.
+    struct FakeBackend {
+        counter: u32,
+    }
+
+    impl VhostUserBackend for FakeBackend {
+        fn max_queue_num(&self) -> usize {
+            1
+        }
+
+        fn features(&self) -> u64 {
+            0
+        }
+
+        fn ack_features(&mut self, _value: u64) -> anyhow::Result<()> {
+            bail!("not used by the tests")
+        }
+
+        fn acked_features(&self) -> u64 {
+            0
+        }
+
+        fn protocol_features(&self) -> VhostUserProtocolFeatures {
+            VhostUserProtocolFeatures::empty()
+        }
+
+        fn ack_protocol_features(&mut self, _value: u64) -> anyhow::Result<()> {
+            bail!("not used by the tests")
+        }
+
+        fn acked_protocol_features(&self) -> u64 {
+            0
+        }
+
+        fn read_config(&self, _offset: u64, _dst: &mut [u8]) {}
+
+        fn start_queue(
+            &mut self,
+            _idx: usize,
+            _queue: Queue,
+            _mem: GuestMemory,
+            _doorbell: Interrupt,
+        ) -> anyhow::Result<()> {
+            bail!("not used by the tests")
+        }
+
+        fn stop_queue(&mut self, _idx: usize) -> anyhow::Result<Queue> {
+            bail!("not used by the tests")
+        }
+
+        fn reset(&mut self) {}
+
+        fn snapshot(&self) -> anyhow::Result<Vec<u8>> {
+            Ok(serde_json::to_vec(&self.counter)?)
+        }
+
+        fn restore(&mut self, data: Vec<u8>) -> anyhow::Result<()> {
+            self.counter = serde_json::from_slice(&data).context("invalid counter")?;
+            Ok(())
+        }
+    }
+
+    /// Runs `requests` against `backend` on its control tube and returns the responses.
+    fn control(
+        backend: &mut dyn VhostUserBackend,
+        requests: Vec<VhostUserControlRequest>,
+    ) -> Vec<VhostUserControlResponse> {
+        let (device_tube, control_tube) = Tube::pair().unwrap();
+        let client = thread::spawn(move || {
+            requests
+                .into_iter()
+                .map(|request| {
+                    control_tube.send(&request).unwrap();
+                    control_tube.recv().unwrap()
+                })
+                .collect()
+        });
+        handle_control_requests(backend, &device_tube).unwrap();
+        client.join().unwrap()
+    }
+
+    #[test]
+    fn snapshot_restore_over_control_tube() {
+        let mut backend = FakeBackend { counter: 42 };
+        let snapshot = match control(
+            &mut backend,
+            vec![
+                VhostUserControlRequest::Snapshot,
+                VhostUserControlRequest::Start,
+            ],
+        )
+        .as_slice()
+        {
+            [VhostUserControlResponse::Snapshot(data), VhostUserControlResponse::Ok] => {
+                data.clone()
+            }
+            r => panic!("unexpected responses: {:?}", r),
+        };
+
+        let mut restored = FakeBackend { counter: 0 };
+        let responses = control(
+            &mut restored,
+            vec![
+                VhostUserControlRequest::Restore(snapshot),
+                VhostUserControlRequest::Start,
+            ],
+        );
+        assert!(matches!(
+            responses.as_slice(),
+            [VhostUserControlResponse::Ok, VhostUserControlResponse::Ok]
+        ));
+        assert_eq!(restored.counter, 42);
+
+        // Restoring bad data is reported to the client without stopping the device.
+        let responses = control(
+            &mut restored,
+            vec![
+                VhostUserControlRequest::Restore(b"{}".to_vec()),
+                VhostUserControlRequest::Start,
+            ],
+        );
+        assert!(matches!(
+            responses.as_slice(),
+            [
+                VhostUserControlResponse::Err(_),
+                VhostUserControlResponse::Ok
+            ]
+        ));
+        assert_eq!(restored.counter, 42);
+    }
 }
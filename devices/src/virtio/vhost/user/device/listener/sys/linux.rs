@@ -7,6 +7,7 @@ use std::pin::Pin;
 use anyhow::Context;
 use base::AsRawDescriptor;
 use base::RawDescriptor;
+use base::Tube;
 use cros_async::AsyncWrapper;
 use cros_async::Executor;
 use futures::Future;
@@ -17,6 +18,9 @@ use vmm_vhost::SlaveReqHandler;
 use vmm_vhost::VhostUserSlaveReqHandler;
 
 use crate::virtio::vhost::user::device::handler::sys::linux::run_handler;
+use crate::virtio::vhost::user::device::handler::sys::linux::run_handler_with_control;
+use crate::virtio::vhost::user::device::handler::DeviceRequestHandler;
+use crate::virtio::vhost::user::device::handler::VhostUserBackend;
 use crate::virtio::vhost::user::device::listener::VhostUserListenerTrait;
 
 /// On Unix we can listen to a socket.
@@ -40,13 +44,13 @@ impl VhostUserListener {
     }
 }
 
-/// Attaches to an already bound socket via `listener` and handles incoming messages from the
-/// VMM, which are dispatched to the device backend via the `VhostUserBackend` trait methods.
-async fn run_with_handler(
+/// Waits for the VMM to connect to the already bound socket `listener`, and returns a request
+/// handler for the connection that dispatches its messages to `handler`.
+async fn accept_connection<S: VhostUserSlaveReqHandler>(
     mut listener: SocketListener,
-    handler: Box<dyn VhostUserSlaveReqHandler>,
+    handler: S,
     ex: &Executor,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<SlaveReqHandler<S>> {
     listener.set_nonblocking(true)?;
 
     loop {
@@ -57,10 +61,7 @@ async fn run_with_handler(
             .accept()
             .context("failed to accept an incoming connection")?
         {
-            Some(connection) => {
-                let req_handler = SlaveReqHandler::new(connection, handler);
-                return run_handler(req_handler, ex).await;
-            }
+            Some(connection) => return Ok(SlaveReqHandler::new(connection, handler)),
             None => {
                 // Nobody is on the other end yet, wait until we get a connection.
                 let async_waiter = ex
@@ -96,7 +97,27 @@ impl VhostUserListenerTrait for VhostUserListener {
         handler: Box<dyn VhostUserSlaveReqHandler>,
         ex: &'e Executor,
     ) -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + 'e>> {
-        async { run_with_handler(self.0, handler, ex).await }.boxed_local()
+        async {
+            let req_handler = accept_connection(self.0, handler, ex).await?;
+            run_handler(req_handler, ex).await
+        }
+        .boxed_local()
+    }
+
+    /// Returns a future that runs `backend` using this listener, and answers the control requests
+    /// received on `control_tube`.
+    fn run_backend_with_control<'e>(
+        self,
+        backend: Box<dyn VhostUserBackend>,
+        control_tube: Tube,
+        ex: &'e Executor,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + 'e>> {
+        async {
+            let handler = DeviceRequestHandler::new(backend);
+            let req_handler = accept_connection(self.0, handler, ex).await?;
+            run_handler_with_control(req_handler, control_tube, ex).await
+        }
+        .boxed_local()
     }
 
     fn take_parent_process_resources(&mut self) -> Option<Box<dyn std::any::Any>> {
@@ -5,6 +5,7 @@
 use std::pin::Pin;
 
 use base::RawDescriptor;
+use base::Tube;
 use cros_async::Executor;
 use futures::Future;
 use vmm_vhost::VhostUserSlaveReqHandler;
@@ -33,4 +34,13 @@ impl VhostUserListenerTrait for VhostUserListener {
     ) -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + 'e>> {
         todo!()
     }
+
+    fn run_backend_with_control<'e>(
+        self,
+        _backend: Box<dyn VhostUserBackend>,
+        _control_tube: Tube,
+        _ex: &'e Executor,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + 'e>> {
+        todo!()
+    }
 }
@@ -12,6 +12,7 @@ mod net;
 #[cfg(feature = "audio")]
 pub mod snd;
 
+use anyhow::Context;
 pub use block::run_block_device;
 pub use block::Options as BlockOptions;
 use cros_async::Executor;
@@ -22,7 +23,10 @@ pub use gpu::run_gpu_device;
 pub use gpu::Options as GpuOptions;
 pub use handler::VhostBackendReqConnectionState;
 pub use handler::VhostUserBackend;
+pub use handler::VhostUserHealth;
 pub use listener::sys::VhostUserListener;
+pub use listener::VhostUserControlRequest;
+pub use listener::VhostUserControlResponse;
 pub use listener::VhostUserListenerTrait;
 #[cfg(feature = "net")]
 pub use net::run_net_device;
@@ -40,6 +44,7 @@ cfg_if::cfg_if! {
     if #[cfg(any(target_os = "android", target_os = "linux"))] {
         mod console;
         mod fs;
+        mod signal;
         mod vsock;
         mod wl;
 
@@ -79,3 +84,49 @@ pub trait VhostUserDevice {
         None
     }
 }
+
+/// Pins the calling thread, i.e. the thread running a device's executor, to the CPUs in `cpus`.
+///
+/// Returns an error if `cpus` is empty or contains a CPU that doesn't exist on the host.
+pub fn set_worker_cpu_affinity(cpus: &[usize]) -> anyhow::Result<()> {
+    if cpus.is_empty() {
+        anyhow::bail!("CPU affinity mask is empty");
+    }
+    let num_cores = base::number_of_logical_cores().context("failed to get number of cores")?;
+    if let Some(cpu) = cpus.iter().find(|&&cpu| cpu >= num_cores) {
+        anyhow::bail!(
+            "CPU {} is out of range, the host has {} cores",
+            cpu,
+            num_cores
+        );
+    }
+    base::set_cpu_affinity(cpus.iter().copied()).context("failed to set CPU affinity")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn worker_cpu_affinity_invalid() {
+        assert!(set_worker_cpu_affinity(&[]).is_err());
+        let num_cores = base::number_of_logical_cores().unwrap();
+        assert!(set_worker_cpu_affinity(&[0, num_cores]).is_err());
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn worker_cpu_affinity() {
+        // Pick a CPU we are allowed to run on, in case the test itself is already pinned.
+        let cpu = base::get_cpu_affinity().unwrap()[0];
+        // Use a separate thread so the affinity of the test harness thread is left untouched.
+        let affinity = std::thread::spawn(move || {
+            set_worker_cpu_affinity(&[cpu]).unwrap();
+            base::get_cpu_affinity().unwrap()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(affinity, vec![cpu]);
+    }
+}
@@ -14,6 +14,7 @@ use base::error;
 use base::info;
 use base::validate_raw_descriptor;
 use base::warn;
+use base::Event;
 use base::RawDescriptor;
 use cros_async::EventAsync;
 use cros_async::Executor;
@@ -42,6 +43,9 @@ use crate::virtio::vhost::user::device::net::run_ctrl_queue;
 use crate::virtio::vhost::user::device::net::run_tx_queue;
 use crate::virtio::vhost::user::device::net::NetBackend;
 use crate::virtio::vhost::user::device::net::NET_EXECUTOR;
+use crate::virtio::vhost::user::device::set_worker_cpu_affinity;
+use crate::virtio::vhost::user::device::signal::run_until_stopped;
+use crate::virtio::vhost::user::device::signal::SigtermHandler;
 use crate::virtio::Interrupt;
 use crate::virtio::Queue;
 
@@ -255,6 +259,9 @@ pub struct Options {
     #[argh(option, arg_name = "SOCKET_PATH,TAP_FD")]
     /// TAP FD with a socket path"
     tap_fd: Vec<String>,
+    #[argh(option, arg_name = "CPU")]
+    /// host CPU the device worker threads may run on. Can be given multiple times.
+    cpu_affinity: Vec<usize>,
 }
 
 enum Connection {
@@ -302,6 +309,9 @@ pub fn start_device(opts: Options) -> anyhow::Result<()> {
         bail!("no device option was passed");
     }
 
+    // Block SIGTERM before spawning the loop threads.
+    let sigterm = SigtermHandler::new()?;
+
     let mut devices: Vec<(Connection, NetBackend<Tap>)> = Vec::with_capacity(num_devices);
 
     // vhost-user
@@ -318,26 +328,52 @@ pub fn start_device(opts: Options) -> anyhow::Result<()> {
     }
 
     let mut threads = Vec::with_capacity(num_devices);
+    let mut stop_evts = Vec::with_capacity(num_devices);
+    // Signaled by each loop thread when it exits.
+    let exit_evt = Event::new().context("failed to create exit event")?;
 
     for (conn, backend) in devices {
         let ex = Executor::new().context("failed to create executor")?;
+        let stop_evt = Event::new().context("failed to create stop event")?;
+        stop_evts.push(stop_evt.try_clone().context("failed to clone stop event")?);
+        let exit_evt = exit_evt.try_clone().context("failed to clone exit event")?;
 
         match conn {
             Connection::Socket(socket) => {
+                let cpu_affinity = opts.cpu_affinity.clone();
                 threads.push(thread::spawn(move || {
-                    NET_EXECUTOR.with(|thread_ex| {
-                        let _ = thread_ex.set(ex.clone());
-                    });
-                    let listener = VhostUserListener::new_socket(&socket, None)?;
-                    // run_until() returns an Result<Result<..>> which the ? operator lets us
-                    // flatten.
-                    ex.run_until(listener.run_backend(Box::new(backend), &ex))?
+                    let res = run_loop(ex, &socket, backend, &cpu_affinity, stop_evt);
+                    if let Err(e) = exit_evt.signal() {
+                        error!("failed to signal the exit of the loop thread: {}", e);
+                    }
+                    res
                 }));
             }
         };
     }
 
     info!("vhost-user net device ready, loop threads started.");
+
+    // The loop threads inherited the blocked SIGTERM, so it is only handled here. Wait until all the
+    // loop threads exit or SIGTERM is received.
+    let ex = Executor::new().context("failed to create executor")?;
+    let exit_evt = EventAsync::new(exit_evt, &ex).context("failed to create async exit event")?;
+    sigterm.run_until(&ex, async {
+        let mut num_exited = 0;
+        while num_exited < num_devices as u64 {
+            num_exited += exit_evt
+                .next_val()
+                .await
+                .context("failed to wait for the loop threads")?;
+        }
+        Ok(())
+    })?;
+
+    // On SIGTERM, stop the devices like the single device processes do, and wait until their queue
+    // workers finish the in-flight requests.
+    for stop_evt in stop_evts {
+        stop_evt.signal().context("failed to stop loop thread")?;
+    }
     for t in threads {
         match t.join() {
             Ok(r) => r?,
@@ -346,3 +382,21 @@ pub fn start_device(opts: Options) -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+/// Runs a net device on its loop thread, until the front-end disconnects or `stop_evt` is signaled.
+fn run_loop(
+    ex: Executor,
+    socket: &str,
+    backend: NetBackend<Tap>,
+    cpu_affinity: &[usize],
+    stop_evt: Event,
+) -> anyhow::Result<()> {
+    if !cpu_affinity.is_empty() {
+        set_worker_cpu_affinity(cpu_affinity)?;
+    }
+    NET_EXECUTOR.with(|thread_ex| {
+        let _ = thread_ex.set(ex.clone());
+    });
+    let listener = VhostUserListener::new_socket(socket, None)?;
+    run_until_stopped(&ex, listener.run_backend(Box::new(backend), &ex), stop_evt)
+}
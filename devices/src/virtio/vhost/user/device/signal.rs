@@ -0,0 +1,166 @@
+// Copyright 2023 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Graceful SIGTERM handling for vhost-user device processes.
+
+use anyhow::Context;
+use base::info;
+use base::Event;
+use base::SignalFd;
+use cros_async::AsyncWrapper;
+use cros_async::EventAsync;
+use cros_async::Executor;
+use futures::future::select;
+use futures::future::Either;
+use futures::pin_mut;
+use futures::Future;
+
+use crate::virtio::vhost::user::device::listener::VhostUserListenerTrait;
+use crate::virtio::vhost::user::VhostUserDevice;
+
+/// Lets a vhost-user device process shut down cleanly on SIGTERM instead of being killed by it.
+///
+/// Creating a `SigtermHandler` blocks SIGTERM for the calling thread and for the threads it spawns
+/// afterwards, so it must be created before the device starts any thread. The signal is then only
+/// observed by [`SigtermHandler::run_until`].
+pub struct SigtermHandler {
+    signal_fd: SignalFd,
+}
+
+impl SigtermHandler {
+    pub fn new() -> anyhow::Result<Self> {
+        let signal_fd =
+            SignalFd::new(libc::SIGTERM).context("failed to create signalfd for SIGTERM")?;
+        Ok(SigtermHandler { signal_fd })
+    }
+
+    /// Runs `fut`, which processes the requests for a device, on `ex` until it completes or the
+    /// process receives SIGTERM.
+    ///
+    /// On SIGTERM, `fut` is dropped. This closes the vhost-user connection so that no more requests
+    /// are accepted, and tears down the device, whose queue workers stop after finishing their
+    /// in-flight requests. `Ok(())` is then returned so that the process can exit with status 0.
+    pub fn run_until<F>(self, ex: &Executor, fut: F) -> anyhow::Result<()>
+    where
+        F: Future<Output = anyhow::Result<()>>,
+    {
+        let signal_fd = ex
+            .async_from(AsyncWrapper::new(self.signal_fd))
+            .context("failed to create async signalfd")?;
+        let sigterm = async {
+            loop {
+                signal_fd
+                    .wait_readable()
+                    .await
+                    .context("failed to wait for SIGTERM")?;
+                if signal_fd
+                    .as_source()
+                    .read()
+                    .context("failed to read signalfd")?
+                    .is_some()
+                {
+                    return anyhow::Ok(());
+                }
+            }
+        };
+        pin_mut!(fut);
+        pin_mut!(sigterm);
+
+        // Both futures are dropped after `run_until` returns, so that the device's teardown can
+        // use the executor if it needs to.
+        match ex.run_until(select(fut, sigterm))? {
+            Either::Left((res, _)) => res,
+            Either::Right((res, _)) => {
+                res?;
+                info!("received SIGTERM, stopping the device");
+                Ok(())
+            }
+        }
+    }
+
+    /// Like [`VhostUserListenerTrait::run_device`], but stops the device gracefully on SIGTERM.
+    pub fn run_device<L: VhostUserListenerTrait>(
+        self,
+        listener: L,
+        ex: Executor,
+        device: Box<dyn VhostUserDevice>,
+    ) -> anyhow::Result<()> {
+        let handler = device.into_req_handler(&ex)?;
+        self.run_until(&ex, listener.run_req_handler(handler, &ex))
+    }
+}
+
+/// Runs `fut`, which processes the requests for a device, on `ex` until it completes or `stop_evt`
+/// is signaled.
+///
+/// This stops the devices that run on their own threads once the main thread of the process
+/// received SIGTERM. Like in [`SigtermHandler::run_until`], `fut` is then dropped and `Ok(())` is
+/// returned.
+pub fn run_until_stopped<F>(ex: &Executor, fut: F, stop_evt: Event) -> anyhow::Result<()>
+where
+    F: Future<Output = anyhow::Result<()>>,
+{
+    let stop_evt = EventAsync::new(stop_evt, ex).context("failed to create async stop event")?;
+    let stop = stop_evt.next_val();
+    pin_mut!(fut);
+    pin_mut!(stop);
+
+    match ex.run_until(select(fut, stop))? {
+        Either::Left((res, _)) => res,
+        Either::Right((res, _)) => {
+            res.context("failed to wait for stop event")?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn sigterm_stops_device() {
+        let (tx, rx) = channel();
+        let device_thread = thread::spawn(move || {
+            let sigterm = SigtermHandler::new().unwrap();
+            // SAFETY: trivially safe
+            tx.send(unsafe { libc::pthread_self() }).unwrap();
+            let ex = Executor::new().unwrap();
+            // A stub device that runs until it is stopped, like one waiting for its front-end.
+            sigterm.run_until(&ex, futures::future::pending())
+        });
+
+        // SIGTERM is blocked on the device thread once it sent its ID, so sending the signal to
+        // this thread only doesn't kill the test process.
+        let device_thread_id = rx.recv().unwrap();
+        // SAFETY:
+        // Safe because the device thread is still alive, as it can only exit on SIGTERM.
+        let ret = unsafe { libc::pthread_kill(device_thread_id, libc::SIGTERM) };
+        assert_eq!(ret, 0);
+
+        device_thread
+            .join()
+            .unwrap()
+            .expect("device didn't stop cleanly");
+    }
+
+    #[test]
+    fn stop_event_stops_device() {
+        let stop_evt = Event::new().unwrap();
+        let device_stop_evt = stop_evt.try_clone().unwrap();
+        let device_thread = thread::spawn(move || {
+            let ex = Executor::new().unwrap();
+            run_until_stopped(&ex, futures::future::pending(), device_stop_evt)
+        });
+
+        stop_evt.signal().unwrap();
+        device_thread
+            .join()
+            .unwrap()
+            .expect("device didn't stop cleanly");
+    }
+}
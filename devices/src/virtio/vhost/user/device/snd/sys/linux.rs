@@ -8,7 +8,7 @@ use cros_async::Executor;
 
 use crate::virtio::snd::parameters::Parameters;
 use crate::virtio::vhost::user::device::listener::sys::VhostUserListener;
-use crate::virtio::vhost::user::device::listener::VhostUserListenerTrait;
+use crate::virtio::vhost::user::device::signal::SigtermHandler;
 use crate::virtio::vhost::user::device::snd::SndBackend;
 use crate::virtio::vhost::user::device::snd::SND_EXECUTOR;
 
@@ -48,6 +48,7 @@ fn snd_parameters_from_str(input: &str) -> Result<Parameters, String> {
 /// Starts a vhost-user snd device.
 /// Returns an error if the given `args` is invalid or the device fails to run.
 pub fn run_snd_device(opts: Options) -> anyhow::Result<()> {
+    let sigterm = SigtermHandler::new()?;
     let snd_device = Box::new(SndBackend::new(opts.params)?);
 
     let ex = Executor::new().context("Failed to create executor")?;
@@ -55,5 +56,5 @@ pub fn run_snd_device(opts: Options) -> anyhow::Result<()> {
 
     let listener = VhostUserListener::new_socket(&opts.socket, None)?;
 
-    listener.run_device(ex, snd_device)
+    sigterm.run_device(listener, ex, snd_device)
 }
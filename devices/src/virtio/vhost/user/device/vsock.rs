@@ -41,9 +41,9 @@ use crate::virtio::device_constants::vsock::NUM_QUEUES;
 use crate::virtio::vhost::user::device::handler::vmm_va_to_gpa;
 use crate::virtio::vhost::user::device::handler::MappingInfo;
 use crate::virtio::vhost::user::device::handler::VhostUserRegularOps;
+use crate::virtio::vhost::user::device::signal::SigtermHandler;
 use crate::virtio::vhost::user::VhostUserDevice;
 use crate::virtio::vhost::user::VhostUserListener;
-use crate::virtio::vhost::user::VhostUserListenerTrait;
 use crate::virtio::Queue;
 use crate::virtio::QueueConfig;
 
@@ -474,11 +474,12 @@ pub struct Options {
 
 /// Returns an error if the given `args` is invalid or the device fails to run.
 pub fn run_vsock_device(opts: Options) -> anyhow::Result<()> {
+    let sigterm = SigtermHandler::new()?;
     let ex = Executor::new().context("failed to create executor")?;
 
     let listener = VhostUserListener::new_socket(&opts.socket, None)?;
 
     let vsock_device = Box::new(VhostUserVsockDevice::new(opts.cid, opts.vhost_socket)?);
 
-    listener.run_device(ex, vsock_device)
+    sigterm.run_device(listener, ex, vsock_device)
 }
@@ -45,6 +45,7 @@ use crate::virtio::vhost::user::device::handler::VhostUserBackend;
 use crate::virtio::vhost::user::device::handler::WorkerState;
 use crate::virtio::vhost::user::device::listener::sys::VhostUserListener;
 use crate::virtio::vhost::user::device::listener::VhostUserListenerTrait;
+use crate::virtio::vhost::user::device::signal::SigtermHandler;
 use crate::virtio::wl;
 use crate::virtio::Interrupt;
 use crate::virtio::Queue;
@@ -376,6 +377,8 @@ pub struct Options {
 /// Starts a vhost-user wayland device.
 /// Returns an error if the given `args` is invalid or the device fails to run.
 pub fn run_wl_device(opts: Options) -> anyhow::Result<()> {
+    let sigterm = SigtermHandler::new()?;
+
     let Options {
         wayland_sock,
         socket,
@@ -408,6 +411,5 @@ pub fn run_wl_device(opts: Options) -> anyhow::Result<()> {
     let listener = VhostUserListener::new_socket(&socket, None)?;
 
     let backend = Box::new(WlBackend::new(&ex, wayland_paths, resource_bridge));
-    // run_until() returns an Result<Result<..>> which the ? operator lets us flatten.
-    ex.run_until(listener.run_backend(backend, &ex))?
+    sigterm.run_until(&ex, listener.run_backend(backend, &ex))
 }
@@ -167,9 +167,9 @@ mod tests {
     use crate::virtio::video::resource::VirtioObjectHandle;
 
     // Test video stream and its properties.
-    const H264_STREAM: &[u8] = include_bytes!("test-25fps.h264");
-    const H264_STREAM_WIDTH: i32 = 320;
-    const H264_STREAM_HEIGHT: i32 = 240;
+    pub const H264_STREAM: &[u8] = include_bytes!("test-25fps.h264");
+    pub const H264_STREAM_WIDTH: i32 = 320;
+    pub const H264_STREAM_HEIGHT: i32 = 240;
     const H264_STREAM_NUM_FRAMES: usize = 250;
     const H264_STREAM_CRCS: &str = include_str!("test-25fps.crc");
 
@@ -180,13 +180,13 @@ mod tests {
     /// We are not using `AVCodecParser` because it seems to modify the decoding context, which
     /// would result in testing conditions that diverge more from our real use case where parsing
     /// has already been done.
-    struct H264NalIterator<'a> {
+    pub struct H264NalIterator<'a> {
         stream: &'a [u8],
         pos: usize,
     }
 
     impl<'a> H264NalIterator<'a> {
-        fn new(stream: &'a [u8]) -> Self {
+        pub fn new(stream: &'a [u8]) -> Self {
             Self { stream, pos: 0 }
         }
 
@@ -13,6 +13,8 @@ use std::rc::Rc;
 
 use anyhow::anyhow;
 use anyhow::Result;
+use base::error;
+use base::warn;
 use base::IntoRawDescriptor;
 use base::MappedRegion;
 use base::MemoryMappingArena;
@@ -23,6 +25,7 @@ use cros_codecs::decoder::stateless::vp9::Vp9;
 use cros_codecs::decoder::stateless::DecodeError;
 use cros_codecs::decoder::stateless::StatelessVideoDecoder;
 use cros_codecs::decoder::DecodedHandle;
+use cros_codecs::decoder::StreamInfo;
 use cros_codecs::libva;
 use cros_codecs::libva::Display;
 use cros_codecs::multiple_desc_type;
@@ -158,6 +161,67 @@ enum OutputQueueState {
 pub struct VaapiDecoder {
     /// The capabilities for the decoder
     caps: Capability,
+    /// How the sessions check the timestamps of the submitted buffers.
+    timestamp_check: TimestampCheck,
+    /// Number of pending events above which the sessions stop retrieving decoded frames.
+    max_queued_events: usize,
+    /// Number of output buffers the sessions request on top of the codec's minimum.
+    extra_surfaces: u32,
+}
+
+/// Default number of pending events above which a session stops retrieving decoded frames.
+const DEFAULT_MAX_QUEUED_EVENTS: usize = 32;
+
+/// Maximum number of output buffers a session can request on top of the codec's minimum.
+const MAX_EXTRA_SURFACES: u32 = 16;
+
+/// What a session does when the timestamp of a submitted buffer is not greater than the previous
+/// one, which usually points to a bug in whatever feeds the decoder.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TimestampCheck {
+    /// Log a warning and decode the buffer anyway.
+    #[default]
+    Warn,
+    /// Reject the buffer with `VideoError::InvalidArgument`.
+    Error,
+}
+
+/// Checks that the timestamps submitted to a session are strictly increasing.
+struct TimestampValidator {
+    check: TimestampCheck,
+    /// Timestamp of the last accepted buffer.
+    last: Option<u64>,
+}
+
+impl TimestampValidator {
+    fn new(check: TimestampCheck) -> Self {
+        Self { check, last: None }
+    }
+
+    fn validate(&mut self, timestamp: u64) -> VideoResult<()> {
+        if let Some(last) = self.last {
+            if timestamp <= last {
+                if timestamp == last {
+                    warn!("duplicate timestamp {} submitted to decoder", timestamp);
+                } else {
+                    warn!(
+                        "timestamp {} submitted to decoder after timestamp {}",
+                        timestamp, last
+                    );
+                }
+                if self.check == TimestampCheck::Error {
+                    return Err(VideoError::InvalidArgument);
+                }
+            }
+        }
+        self.last = Some(timestamp);
+        Ok(())
+    }
+
+    /// Forgets the previous timestamp, e.g. after a seek.
+    fn reset(&mut self) {
+        self.last = None;
+    }
 }
 
 // The VA capabilities for the coded side
@@ -410,8 +474,45 @@ impl VaapiDecoder {
 
         Ok(Self {
             caps: Capability::new(in_fmts, out_fmts, profiles_map, levels),
+            timestamp_check: Default::default(),
+            max_queued_events: DEFAULT_MAX_QUEUED_EVENTS,
+            extra_surfaces: 0,
         })
     }
+
+    /// Sets how sessions created from now on check the timestamps of the submitted buffers.
+    // Not configurable from the command line yet.
+    #[allow(dead_code)]
+    pub fn set_timestamp_check(&mut self, timestamp_check: TimestampCheck) {
+        self.timestamp_check = timestamp_check;
+    }
+
+    /// Sets how many events sessions created from now on may queue for the client before they stop
+    /// retrieving decoded frames from the codec.
+    // Not configurable from the command line yet.
+    #[allow(dead_code)]
+    pub fn set_max_queued_events(&mut self, max_queued_events: usize) {
+        self.max_queued_events = max_queued_events;
+    }
+
+    /// Sets how many output buffers sessions created from now on request on top of the minimum
+    /// needed by the codec. Extra buffers let the hardware decode further ahead of the client,
+    /// which improves throughput of deep pipelines at the cost of memory.
+    ///
+    /// Returns `VideoError::InvalidArgument` if `extra_surfaces` is above `MAX_EXTRA_SURFACES`.
+    // Not configurable from the command line yet.
+    #[allow(dead_code)]
+    pub fn set_extra_surfaces(&mut self, extra_surfaces: u32) -> VideoResult<()> {
+        if extra_surfaces > MAX_EXTRA_SURFACES {
+            error!(
+                "{} extra surfaces requested, at most {} are supported",
+                extra_surfaces, MAX_EXTRA_SURFACES
+            );
+            return Err(VideoError::InvalidArgument);
+        }
+        self.extra_surfaces = extra_surfaces;
+        Ok(())
+    }
 }
 
 #[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
@@ -511,6 +612,22 @@ pub struct VaapiDecoderSession {
     event_queue: EventQueue<DecoderEvent>,
     /// Whether the decoder is currently flushing.
     flushing: bool,
+    /// The format of the input stream.
+    format: Format,
+    /// The capabilities of the decoder, to validate the stream against.
+    caps: Capability,
+    /// The level of the input stream, if known.
+    level: Option<Level>,
+    /// Checks the timestamps of the submitted buffers.
+    timestamps: TimestampValidator,
+    /// Once this many events are waiting for the client, decoded frames are left in the codec
+    /// until the client reads some of them. This bounds the memory used by a client that stops
+    /// reading events, and in turn stops the codec from accepting more input.
+    max_queued_events: usize,
+    /// Whether decoded frames were left in the codec because the event queue was full.
+    events_throttled: bool,
+    /// Number of output buffers to request on top of the codec's minimum.
+    extra_surfaces: u32,
 }
 
 impl VaapiDecoderSession {
@@ -566,8 +683,30 @@ impl VaapiDecoderSession {
         Ok(())
     }
 
+    /// Returns the event asking the client for the output buffers of a stream.
+    fn provide_picture_buffers_event(
+        stream_info: &StreamInfo,
+        extra_surfaces: u32,
+    ) -> DecoderEvent {
+        let coded_resolution = stream_info.coded_resolution;
+        let display_resolution = stream_info.display_resolution;
+
+        DecoderEvent::ProvidePictureBuffers {
+            min_num_buffers: stream_info.min_num_frames as u32 + extra_surfaces,
+            width: coded_resolution.width as i32,
+            height: coded_resolution.height as i32,
+            visible_rect: Rect {
+                left: 0,
+                top: 0,
+                right: display_resolution.width as i32,
+                bottom: display_resolution.height as i32,
+            },
+        }
+    }
+
     fn try_emit_flush_completed(&mut self) -> Result<()> {
-        if self.submit_queue.is_empty() {
+        // The flush is only complete once all the frames have been retrieved from the codec.
+        if self.submit_queue.is_empty() && !self.events_throttled {
             self.flushing = false;
 
             let event_queue = &mut self.event_queue;
@@ -635,7 +774,20 @@ impl VaapiDecoderSession {
     }
 
     fn process_decoder_events(&mut self) -> VideoResult<()> {
-        while let Some(event) = self.codec.next_event() {
+        self.process_decoder_events_up_to(self.max_queued_events)
+    }
+
+    /// Processes the codec's events until there are `max_queued_events` events waiting for the
+    /// client.
+    fn process_decoder_events_up_to(&mut self, max_queued_events: usize) -> VideoResult<()> {
+        loop {
+            self.events_throttled = self.event_queue.len() >= max_queued_events;
+            if self.events_throttled {
+                break;
+            }
+            let Some(event) = self.codec.next_event() else {
+                break;
+            };
             match event {
                 cros_codecs::decoder::DecoderEvent::FrameReady(frame) => {
                     Self::output_picture(frame.as_ref(), &mut self.event_queue)
@@ -645,22 +797,12 @@ impl VaapiDecoderSession {
                         .insert(picture_id, BorrowedFrame::Decoded(frame));
                 }
                 cros_codecs::decoder::DecoderEvent::FormatChanged(mut format) => {
-                    let coded_resolution = format.stream_info().coded_resolution;
-                    let display_resolution = format.stream_info().display_resolution;
-
                     // Ask the client for new buffers.
                     self.event_queue
-                        .queue_event(DecoderEvent::ProvidePictureBuffers {
-                            min_num_buffers: format.stream_info().min_num_frames as u32,
-                            width: coded_resolution.width as i32,
-                            height: coded_resolution.height as i32,
-                            visible_rect: Rect {
-                                left: 0,
-                                top: 0,
-                                right: display_resolution.width as i32,
-                                bottom: display_resolution.height as i32,
-                            },
-                        })
+                        .queue_event(Self::provide_picture_buffers_event(
+                            format.stream_info(),
+                            self.extra_surfaces,
+                        ))
                         .map_err(|e| VideoError::BackendFailure(e.into()))?;
 
                     format.frame_pool().clear();
@@ -691,10 +833,85 @@ impl VaapiDecoderSession {
 
         Ok(())
     }
+
+    /// Resumes decoding if it was stopped because the event queue was full and the client has
+    /// since read some events.
+    fn resume_if_throttled(&mut self) -> VideoResult<()> {
+        if !self.events_throttled || self.event_queue.len() >= self.max_queued_events {
+            return Ok(());
+        }
+
+        self.try_make_progress()?;
+        if self.flushing {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Reset the bitstream parser, e.g. after a seek, while keeping the output buffers.
+    ///
+    /// Like `reset`, this cancels all pending decoding requests, drops the pictures not yet read
+    /// by the client and emits a `ResetCompleted` event. Unlike `reset`, the frame pool stays
+    /// usable: free frames are not held until the client reuses them, and the frames of the
+    /// dropped pictures or held by a previous `reset` go back to the pool. Decoding can thus
+    /// resume without the client providing its output buffers again. Pictures already read by the
+    /// client still need to be returned with `reuse_output_buffer`.
+    // Not used by the virtio-video frontend, which has no notion of seeking.
+    #[allow(dead_code)]
+    pub fn reset_keep_buffers(&mut self) -> VideoResult<()> {
+        self.submit_queue.clear();
+        self.timestamps.reset();
+        self.flushing = false;
+
+        // Make sure the codec is not active. The pictures are dropped below, so the event queue
+        // limit doesn't apply.
+        self.codec
+            .flush()
+            .map_err(|e| VideoError::BackendFailure(e.into()))?;
+
+        self.process_decoder_events_up_to(usize::MAX)?;
+
+        // Drop the pictures decoded from the old position, and return their frames to the pool.
+        let mut dropped_pictures = Vec::new();
+        self.event_queue.retain(|event| match event {
+            DecoderEvent::PictureReady {
+                picture_buffer_id, ..
+            } => {
+                dropped_pictures.push(*picture_buffer_id);
+                false
+            }
+            DecoderEvent::FlushCompleted(_) => false,
+            _ => true,
+        });
+        for picture_buffer_id in dropped_pictures {
+            self.held_frames.remove(&picture_buffer_id);
+        }
+        self.held_frames
+            .retain(|_, frame| matches!(frame, BorrowedFrame::Decoded(_)));
+
+        self.event_queue
+            .queue_event(DecoderEvent::ResetCompleted(Ok(())))
+            .map_err(|e| {
+                VideoError::BackendFailure(anyhow!("Can't queue the ResetCompleted event {}", e))
+            })?;
+
+        Ok(())
+    }
 }
 
 impl DecoderSession for VaapiDecoderSession {
     fn set_output_parameters(&mut self, _: usize, _: Format) -> VideoResult<()> {
+        // The stream level is not parsed yet, so this only takes effect once it is.
+        if let Some(level) = self.level {
+            if !level.is_supported_for(self.format, &self.caps) {
+                error!(
+                    "{:?} is not supported for {} by this decoder",
+                    level, self.format
+                );
+                return Err(VideoError::InvalidParameter);
+            }
+        }
+
         let output_queue_state = &mut self.output_queue_state;
 
         // This logic can still be improved, in particular it needs better
@@ -801,6 +1018,8 @@ impl DecoderSession for VaapiDecoderSession {
         offset: u32,
         bytes_used: u32,
     ) -> VideoResult<()> {
+        self.timestamps.validate(timestamp)?;
+
         let job = PendingJob {
             resource_id,
             timestamp,
@@ -837,13 +1056,15 @@ impl DecoderSession for VaapiDecoderSession {
 
     fn reset(&mut self) -> VideoResult<()> {
         self.submit_queue.clear();
+        self.timestamps.reset();
 
-        // Make sure the codec is not active.
+        // Make sure the codec is not active. The pictures are dropped below, so the event queue
+        // limit doesn't apply.
         self.codec
             .flush()
             .map_err(|e| VideoError::BackendFailure(e.into()))?;
 
-        self.process_decoder_events()?;
+        self.process_decoder_events_up_to(usize::MAX)?;
 
         // Drop the queued output buffers.
         self.clear_output_buffers()?;
@@ -958,9 +1179,21 @@ impl DecoderSession for VaapiDecoderSession {
     }
 
     fn read_event(&mut self) -> VideoResult<DecoderEvent> {
-        self.event_queue
+        let event = self
+            .event_queue
             .dequeue_event()
-            .map_err(|e| VideoError::BackendFailure(anyhow!("Can't read event {}", e)))
+            .map_err(|e| VideoError::BackendFailure(anyhow!("Can't read event {}", e)))?;
+
+        // Failing here must not lose `event`, so report errors as an event instead.
+        if let Err(e) = self.resume_if_throttled() {
+            self.event_queue
+                .queue_event(DecoderEvent::NotifyError(e))
+                .map_err(|e| {
+                    VideoError::BackendFailure(anyhow!("Can't queue the NotifyError event {}", e))
+                })?;
+        }
+
+        Ok(event)
     }
 }
 
@@ -1010,14 +1243,26 @@ impl DecoderBackend for VaapiDecoder {
             submit_queue: Default::default(),
             event_queue: EventQueue::new().map_err(|e| VideoError::BackendFailure(anyhow!(e)))?,
             flushing: Default::default(),
+            format,
+            caps: self.caps.clone(),
+            level: None,
+            timestamps: TimestampValidator::new(self.timestamp_check),
+            max_queued_events: self.max_queued_events,
+            events_throttled: false,
+            extra_surfaces: self.extra_surfaces,
         })
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use base::MemoryMapping;
+    use base::MemoryMappingBuilder;
+    use base::SharedMemory;
+
     use super::super::tests::*;
     use super::*;
+    use crate::virtio::video::format::FramePlane;
 
     #[test]
     // Ignore this test by default as it requires libva-compatible hardware.
@@ -1029,6 +1274,276 @@ mod tests {
         assert!(!caps.output_formats().is_empty());
     }
 
+    #[test]
+    fn test_level_supported_for() {
+        let levels = BTreeMap::from([(Format::H264, vec![Level::H264_1_0, Level::H264_3_1])]);
+        let caps = Capability::new(vec![], vec![], Default::default(), levels);
+
+        assert!(Level::H264_2_0.is_supported_for(Format::H264, &caps));
+        assert!(Level::H264_3_1.is_supported_for(Format::H264, &caps));
+        // The capabilities lack level 4.0 and above.
+        assert!(!Level::H264_4_0.is_supported_for(Format::H264, &caps));
+        assert!(!Level::H264_5_1.is_supported_for(Format::H264, &caps));
+        // H.264 levels don't apply to other formats.
+        assert!(!Level::H264_1_0.is_supported_for(Format::VP9, &caps));
+
+        // Without any reported levels, all of them are assumed to be supported.
+        let caps = Capability::new(vec![], vec![], Default::default(), Default::default());
+        assert!(Level::H264_5_1.is_supported_for(Format::H264, &caps));
+    }
+
+    #[test]
+    fn test_extra_surfaces() {
+        let stream_info = StreamInfo {
+            format: DecodedFormat::NV12,
+            coded_resolution: cros_codecs::Resolution {
+                width: 336,
+                height: 240,
+            },
+            display_resolution: cros_codecs::Resolution {
+                width: 320,
+                height: 240,
+            },
+            min_num_frames: 5,
+        };
+        let min_num_buffers =
+            |extra_surfaces| match VaapiDecoderSession::provide_picture_buffers_event(
+                &stream_info,
+                extra_surfaces,
+            ) {
+                DecoderEvent::ProvidePictureBuffers {
+                    min_num_buffers, ..
+                } => min_num_buffers,
+                e => panic!("Unexpected event: {:?}", e),
+            };
+        assert_eq!(min_num_buffers(0), 5);
+        assert_eq!(min_num_buffers(3), 8);
+    }
+
+    #[test]
+    fn test_timestamp_validation() {
+        let mut warn_only = TimestampValidator::new(TimestampCheck::Warn);
+        let mut strict = TimestampValidator::new(TimestampCheck::Error);
+        for validator in [&mut warn_only, &mut strict] {
+            validator.validate(100).unwrap();
+            validator.validate(200).unwrap();
+        }
+
+        // Duplicate timestamp.
+        warn_only.validate(200).unwrap();
+        assert!(matches!(
+            strict.validate(200),
+            Err(VideoError::InvalidArgument)
+        ));
+        // Out-of-order timestamp.
+        warn_only.validate(150).unwrap();
+        assert!(matches!(
+            strict.validate(150),
+            Err(VideoError::InvalidArgument)
+        ));
+        // A rejected timestamp doesn't replace the previous one.
+        strict.validate(201).unwrap();
+
+        // Timestamps may start over after a reset.
+        strict.reset();
+        strict.validate(0).unwrap();
+    }
+
+    const NUM_OUTPUT_BUFFERS: usize = 4;
+    const INPUT_BUF_SIZE: usize = 0x4000;
+    const OUTPUT_BUFFER_SIZE: usize =
+        (H264_STREAM_WIDTH * (H264_STREAM_HEIGHT + H264_STREAM_HEIGHT / 2)) as usize;
+
+    /// Guest memory buffers to decode `H264_STREAM` with.
+    struct H264Buffers {
+        input_shm: SharedMemory,
+        input_mapping: MemoryMapping,
+        output_buffers: Vec<SharedMemory>,
+    }
+
+    impl H264Buffers {
+        fn new() -> Self {
+            let input_shm = SharedMemory::new("video-input-buffer", INPUT_BUF_SIZE as u64).unwrap();
+            let input_mapping = MemoryMappingBuilder::new(INPUT_BUF_SIZE)
+                .from_shared_memory(&input_shm)
+                .build()
+                .unwrap();
+            let output_buffers = (0..NUM_OUTPUT_BUFFERS)
+                .map(|i| {
+                    SharedMemory::new(
+                        format!("video-output-buffer-{}", i),
+                        OUTPUT_BUFFER_SIZE as u64,
+                    )
+                    .unwrap()
+                })
+                .collect();
+            Self {
+                input_shm,
+                input_mapping,
+                output_buffers,
+            }
+        }
+
+        /// Submits `slice` to `session` as input buffer `input_id`.
+        fn decode(
+            &self,
+            session: &mut VaapiDecoderSession,
+            input_id: u32,
+            timestamp: u64,
+            slice: &[u8],
+        ) {
+            self.input_mapping.write_slice(slice, 0).unwrap();
+            session
+                .decode(
+                    input_id,
+                    timestamp,
+                    build_guest_mem_handle(&self.input_shm),
+                    0,
+                    slice.len() as u32,
+                )
+                .unwrap();
+        }
+
+        /// Answers a `ProvidePictureBuffers` event.
+        fn provide_output_buffers(&self, session: &mut VaapiDecoderSession) {
+            let frame_size = (H264_STREAM_WIDTH * H264_STREAM_HEIGHT) as usize;
+            session
+                .set_output_parameters(NUM_OUTPUT_BUFFERS, Format::NV12)
+                .unwrap();
+            for (picture_buffer_id, buffer) in self.output_buffers.iter().enumerate() {
+                let resource = GuestResource {
+                    handle: build_guest_mem_handle(buffer),
+                    planes: vec![
+                        FramePlane {
+                            offset: 0,
+                            stride: H264_STREAM_WIDTH as usize,
+                            size: frame_size,
+                        },
+                        FramePlane {
+                            offset: frame_size,
+                            stride: H264_STREAM_WIDTH as usize,
+                            size: frame_size,
+                        },
+                    ],
+                    width: H264_STREAM_WIDTH as _,
+                    height: H264_STREAM_HEIGHT as _,
+                    format: Format::NV12,
+                    guest_cpu_mappable: false,
+                };
+                session
+                    .use_output_buffer(picture_buffer_id as i32, resource)
+                    .unwrap();
+            }
+        }
+    }
+
+    #[test]
+    // Ignore this test by default as it requires libva-compatible hardware.
+    #[ignore]
+    fn test_reset_keep_buffers() {
+        let mut decoder = VaapiDecoder::new().unwrap();
+        let mut session = decoder.new_session(Format::H264).unwrap();
+        let buffers = H264Buffers::new();
+
+        // Decodes the stream from the start until a picture is ready, and returns whether the
+        // client was asked for picture buffers.
+        let mut timestamp = 0;
+        let mut decode_first_picture = |session: &mut VaapiDecoderSession| {
+            let mut provide_picture_buffers = false;
+            for (input_id, slice) in H264NalIterator::new(H264_STREAM).enumerate() {
+                timestamp += 1;
+                buffers.decode(session, input_id as u32, timestamp, slice);
+                while session.event_queue.len() > 0 {
+                    match session.read_event().unwrap() {
+                        DecoderEvent::ProvidePictureBuffers { .. } => {
+                            provide_picture_buffers = true;
+                            buffers.provide_output_buffers(session);
+                        }
+                        DecoderEvent::PictureReady {
+                            picture_buffer_id, ..
+                        } => {
+                            session.reuse_output_buffer(picture_buffer_id).unwrap();
+                            return provide_picture_buffers;
+                        }
+                        DecoderEvent::NotifyEndOfBitstreamBuffer(_) => (),
+                        e => panic!("Unexpected event: {:?}", e),
+                    }
+                }
+            }
+            panic!("no picture decoded");
+        };
+
+        assert!(decode_first_picture(&mut session));
+        let num_frames = session.codec.frame_pool().num_managed_frames();
+
+        session.reset_keep_buffers().unwrap();
+        loop {
+            match session.read_event().unwrap() {
+                DecoderEvent::ResetCompleted(Ok(())) => break,
+                DecoderEvent::NotifyEndOfBitstreamBuffer(_) => (),
+                e => panic!("Unexpected event: {:?}", e),
+            }
+        }
+        assert_eq!(session.codec.frame_pool().num_managed_frames(), num_frames);
+
+        // Decoding resumes with the same buffers.
+        assert!(!decode_first_picture(&mut session));
+        assert_eq!(session.codec.frame_pool().num_managed_frames(), num_frames);
+    }
+
+    #[test]
+    // Ignore this test by default as it requires libva-compatible hardware.
+    #[ignore]
+    fn test_event_queue_backpressure() {
+        const MAX_QUEUED_EVENTS: usize = 2;
+
+        let mut decoder = VaapiDecoder::new().unwrap();
+        decoder.set_max_queued_events(MAX_QUEUED_EVENTS);
+        let mut session = decoder.new_session(Format::H264).unwrap();
+        let buffers = H264Buffers::new();
+
+        // Submit the whole stream without returning any picture to the client.
+        let mut buffers_provided = false;
+        for (input_id, slice) in H264NalIterator::new(H264_STREAM).enumerate() {
+            buffers.decode(&mut session, input_id as u32, input_id as u64, slice);
+            if !buffers_provided {
+                while session.event_queue.len() > 0 {
+                    match session.event_queue.dequeue_event().unwrap() {
+                        DecoderEvent::ProvidePictureBuffers { .. } => {
+                            buffers.provide_output_buffers(&mut session);
+                            buffers_provided = true;
+                        }
+                        DecoderEvent::NotifyEndOfBitstreamBuffer(_) => (),
+                        e => panic!("Unexpected event: {:?}", e),
+                    }
+                }
+            }
+        }
+
+        // Decoding stopped once the queue was full, and so did the consumption of input.
+        assert!(session.events_throttled);
+        assert!(!session.submit_queue.is_empty());
+        let mut num_pictures = 0;
+        while session.event_queue.len() > 0 {
+            if let DecoderEvent::PictureReady {
+                picture_buffer_id, ..
+            } = session.event_queue.dequeue_event().unwrap()
+            {
+                num_pictures += 1;
+                session.held_frames.remove(&picture_buffer_id);
+            }
+        }
+        assert!(num_pictures <= MAX_QUEUED_EVENTS);
+
+        // Decoding resumes once the client read the events.
+        session.resume_if_throttled().unwrap();
+        assert!(session.event_queue.len() > 0);
+        assert!((0..session.event_queue.len()).any(|_| matches!(
+            session.event_queue.dequeue_event().unwrap(),
+            DecoderEvent::PictureReady { .. }
+        )));
+    }
+
     // Decode using guest memory input and output buffers.
     #[test]
     // Ignore this test by default as it requires libva-compatible hardware.
@@ -43,6 +43,12 @@ impl Capability {
         &self.out_fmts
     }
 
+    /// Returns the levels supported for `format`, if the backend reported any.
+    #[cfg(feature = "vaapi")]
+    pub fn levels(&self, format: Format) -> Option<&[Level]> {
+        self.levels.get(&format).map(Vec::as_slice)
+    }
+
     pub fn query_control(&self, t: &QueryCtrlType) -> Option<QueryCtrlResponse> {
         use QueryCtrlType::*;
         match *t {
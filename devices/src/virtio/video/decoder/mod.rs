@@ -36,7 +36,7 @@ use crate::virtio::video::response::CmdResponse;
 pub mod backend;
 mod capability;
 
-use capability::*;
+pub use capability::*;
 
 type StreamId = u32;
 type ResourceId = u32;
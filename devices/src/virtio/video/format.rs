@@ -16,6 +16,8 @@ use data_model::Le32;
 use enumn::N;
 
 use crate::virtio::video::command::ReadCmdError;
+#[cfg(all(feature = "video-decoder", feature = "vaapi"))]
+use crate::virtio::video::decoder::Capability;
 use crate::virtio::video::protocol::*;
 use crate::virtio::video::response::Response;
 use crate::virtio::Writer;
@@ -92,6 +94,25 @@ pub enum Level {
 }
 impl_try_from_le32_for_enumn!(Level, "level");
 
+impl Level {
+    /// Returns whether `caps` can decode `format` streams of this level.
+    ///
+    /// A level is supported if it is at most the highest level `caps` reports for `format`. If
+    /// `caps` doesn't report any levels for `format`, the backend couldn't query them and all
+    /// levels are assumed to be supported.
+    #[cfg(all(feature = "video-decoder", feature = "vaapi"))]
+    pub fn is_supported_for(self, format: Format, caps: &Capability) -> bool {
+        // All the levels defined by virtio-video are H.264 levels.
+        if format != Format::H264 {
+            return false;
+        }
+        match caps.levels(format) {
+            Some(levels) => levels.iter().any(|level| *level >= self),
+            None => true,
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, N, Clone, Copy, Debug)]
 #[repr(u32)]
 pub enum Format {
@@ -82,7 +82,6 @@ impl<T> EventQueue<T> {
 
     /// Returns the number of events currently pending on this queue, i.e. the number of times
     /// `dequeue_event` can be called without blocking.
-    #[cfg(test)]
     pub fn len(&self) -> usize {
         self.pending_events.len()
     }
@@ -150,6 +150,31 @@ pub trait VcpuAArch64: Vcpu {
     fn restore(&self, _snapshot: &VcpuSnapshot) -> anyhow::Result<()> {
         Err(anyhow!("not yet implemented"))
     }
+
+    /// Get a small snapshot of the vCPU registers for debugging.
+    fn get_registers(&self) -> anyhow::Result<VcpuRegisters> {
+        let x = (0..31)
+            .map(|i| self.get_one_reg(VcpuRegAArch64::X(i)))
+            .collect::<Result<Vec<u64>>>()?;
+        Ok(VcpuRegisters {
+            vcpu_id: self.id(),
+            x,
+            sp: self.get_one_reg(VcpuRegAArch64::Sp)?,
+            pc: self.get_one_reg(VcpuRegAArch64::Pc)?,
+            pstate: self.get_one_reg(VcpuRegAArch64::Pstate)?,
+        })
+    }
+}
+
+/// Aarch64 specific vCPU registers, as reported for debugging.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct VcpuRegisters {
+    pub vcpu_id: usize,
+    /// General purpose registers X0 to X30.
+    pub x: Vec<u64>,
+    pub sp: u64,
+    pub pc: u64,
+    pub pstate: u64,
 }
 
 /// Aarch64 specific vCPU snapshot.
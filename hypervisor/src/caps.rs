@@ -55,3 +55,75 @@ pub enum VmCap {
     /// Supports read-only memory regions.
     ReadOnlyMemoryRegion,
 }
+
+/// Hypervisor features available on the host, probed without creating a VM.
+///
+/// All the features are reported as unavailable if the hypervisor device can't be opened.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct HypervisorCaps {
+    /// The KVM device could be opened.
+    pub kvm_present: bool,
+    /// Interrupts can be injected by signaling an event (`KVM_IRQFD`).
+    pub irqfd: bool,
+    /// MMIO and port I/O writes can signal an event (`KVM_IOEVENTFD`).
+    pub ioeventfd: bool,
+    /// vCPU runs can be cancelled by setting `immediate_exit`.
+    pub immediate_exit: bool,
+}
+
+/// Probes the capabilities of the host hypervisor.
+///
+/// This is cheap enough to be called before deciding how to configure a VM, or whether a request
+/// can be honored by the backend. For example, the run loops use it to pick how many times the IRQ
+/// handler is cycled to flush the pending IRQs before a snapshot.
+pub fn hypervisor_caps() -> HypervisorCaps {
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    {
+        kvm_caps(std::path::Path::new("/dev/kvm"))
+    }
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    {
+        // The Windows hypervisors don't support irqfds or ioeventfds.
+        HypervisorCaps::default()
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn kvm_caps(device_path: &std::path::Path) -> HypervisorCaps {
+    use crate::kvm::Kvm;
+    use crate::kvm::KvmCap;
+    use crate::Hypervisor;
+
+    let kvm = match Kvm::new_with_path(device_path) {
+        Ok(kvm) => kvm,
+        Err(_) => return HypervisorCaps::default(),
+    };
+    HypervisorCaps {
+        kvm_present: true,
+        irqfd: kvm.check_raw_capability(KvmCap::Irqfd),
+        ioeventfd: kvm.check_raw_capability(KvmCap::Ioeventfd),
+        immediate_exit: kvm.check_capability(HypervisorCap::ImmediateExit),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probe_caps() {
+        let caps = hypervisor_caps();
+        if !caps.kvm_present {
+            assert_eq!(caps, HypervisorCaps::default());
+        }
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn probe_absent_device() {
+        assert_eq!(
+            kvm_caps(std::path::Path::new("/nonexistent/kvm")),
+            HypervisorCaps::default()
+        );
+    }
+}
@@ -189,6 +189,15 @@ impl Kvm {
             errno_result()
         }
     }
+
+    /// Checks whether a particular KVM-specific capability is supported by the system, before
+    /// any VM is created.
+    pub fn check_raw_capability(&self, capability: KvmCap) -> bool {
+        // SAFETY:
+        // Safe because we know that our file is a KVM fd, and if the cap is invalid KVM assumes
+        // it's an unavailable extension and returns 0.
+        unsafe { ioctl_with_val(self, KVM_CHECK_EXTENSION(), capability as c_ulong) > 0 }
+    }
 }
 
 impl AsRawDescriptor for Kvm {
@@ -655,6 +664,17 @@ impl Vm for KvmVm {
         })
     }
 
+    fn populate_memory_region(&mut self, slot: MemSlot, offset: usize, size: usize) -> Result<()> {
+        let regions = self.mem_regions.lock();
+        let mem = regions.get(&slot).ok_or_else(|| Error::new(ENOENT))?;
+
+        mem.populate(offset, size).map_err(|err| match err {
+            MmapError::InvalidAddress => Error::new(EFAULT),
+            MmapError::SystemCallFailed(e) => e,
+            _ => Error::new(EIO),
+        })
+    }
+
     fn remove_memory_region(&mut self, slot: MemSlot) -> Result<Box<dyn MappedRegion>> {
         let mut regions = self.mem_regions.lock();
         if !regions.contains_key(&slot) {
@@ -133,6 +133,18 @@ pub trait Vm: Send {
     /// `offset` from the start of the region.  `offset` must be page aligned.
     fn msync_memory_region(&mut self, slot: MemSlot, offset: usize, size: usize) -> Result<()>;
 
+    /// Populates the host pages backing `size` bytes starting at `offset` from the start of the
+    /// region mapped at `slot`, so the guest's first access to them doesn't fault. `offset` must be
+    /// page aligned.
+    fn populate_memory_region(
+        &mut self,
+        _slot: MemSlot,
+        _offset: usize,
+        _size: usize,
+    ) -> Result<()> {
+        Err(base::Error::new(libc::ENOTSUP))
+    }
+
     /// Removes and drops the `UserMemoryRegion` that was previously added at the given slot.
     fn remove_memory_region(&mut self, slot: MemSlot) -> Result<Box<dyn MappedRegion>>;
 
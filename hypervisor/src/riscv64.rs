@@ -42,6 +42,19 @@ pub trait VcpuRiscv64: Vcpu {
     fn restore(&self, _snapshot: &VcpuSnapshot) -> anyhow::Result<()> {
         Err(anyhow!("not yet implemented"))
     }
+
+    /// Get a small snapshot of the vCPU registers for debugging.
+    fn get_registers(&self) -> anyhow::Result<VcpuRegisters> {
+        Err(anyhow!("not yet implemented"))
+    }
+}
+
+/// Riscv64 specific vCPU registers, as reported for debugging.
+///
+/// Not implemented yet.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct VcpuRegisters {
+    pub vcpu_id: usize,
 }
 
 /// Riscv64 specific vCPU snapshot.
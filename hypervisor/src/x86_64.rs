@@ -183,6 +183,21 @@ pub trait VcpuX86_64: Vcpu {
         self.restore_timekeeping(host_tsc_reference_moment, snapshot.tsc_offset)?;
         Ok(())
     }
+
+    /// Get a small snapshot of the vCPU registers for debugging.
+    fn get_registers(&self) -> anyhow::Result<VcpuRegisters> {
+        Ok(VcpuRegisters {
+            vcpu_id: self.id(),
+            regs: self.get_regs()?,
+        })
+    }
+}
+
+/// x86 specific vCPU registers, as reported for debugging.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct VcpuRegisters {
+    pub vcpu_id: usize,
+    pub regs: Regs,
 }
 
 /// x86 specific vCPU snapshot.
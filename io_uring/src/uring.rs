@@ -14,10 +14,13 @@ use std::os::unix::io::FromRawFd;
 use std::os::unix::io::RawFd;
 use std::pin::Pin;
 use std::ptr::null;
+use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicPtr;
 use std::sync::atomic::AtomicU32;
 use std::sync::atomic::Ordering;
+use std::time::Duration;
 
+use base::warn;
 use base::AsRawDescriptor;
 use base::EventType;
 use base::IoBufMut;
@@ -38,6 +41,9 @@ use crate::syscalls::*;
 /// for callers to identify each request.
 pub type UserData = u64;
 
+// `io_uring_sqe::flags` bit that makes the next sqe wait for this one to complete successfully.
+const IOSQE_IO_LINK: u8 = 1 << 2;
+
 #[sorted]
 #[derive(Debug, ThisError)]
 pub enum Error {
@@ -93,14 +99,6 @@ impl io_uring_sqe {
         self.__bindgen_anon_1.off = val;
     }
 
-    pub fn set_buf_index(&mut self, val: u16) {
-        self.__bindgen_anon_4.buf_index = val;
-    }
-
-    pub fn set_rw_flags(&mut self, val: libc::c_int) {
-        self.__bindgen_anon_3.rw_flags = val;
-    }
-
     pub fn set_poll_events(&mut self, val: u32) {
         let val = if cfg!(target_endian = "big") {
             // Swap words on big-endian platforms to match the original ABI where poll_events was 16
@@ -125,10 +123,11 @@ fn file_offset_to_raw_offset(offset: Option<u64>) -> u64 {
 
 impl SubmitQueue {
     // Call `f` with the next available sqe or return an error if none are available.
-    // After `f` returns, the sqe is appended to the kernel's queue.
-    fn prep_next_sqe<F>(&mut self, mut f: F) -> Result<()>
+    // The sqe is cleared before `f` is called, so that nothing from the operation that used the
+    // slot before carries over. After `f` returns, the sqe is appended to the kernel's queue.
+    fn prep_next_sqe<F>(&mut self, f: F) -> Result<()>
     where
-        F: FnMut(&mut io_uring_sqe),
+        F: FnOnce(&mut io_uring_sqe),
     {
         if self.added == self.num_sqes {
             return Err(Error::NoSpace);
@@ -146,6 +145,7 @@ impl SubmitQueue {
         let index = (tail & self.submit_ring.ring_mask) as usize;
         let sqe = self.submit_queue_entries.get_mut(index).unwrap();
 
+        *sqe = io_uring_sqe::default();
         f(sqe);
 
         // Tells the kernel to use the new index when processing the entry at that index.
@@ -184,6 +184,89 @@ impl SubmitQueue {
     }
 }
 
+/// An operation that can be submitted as part of a chain with `URingContext::submit_linked`.
+pub enum URingOp {
+    /// See `URingContext::add_nop`.
+    Nop,
+    /// See `URingContext::add_readv`.
+    Readv {
+        iovecs: Pin<Box<[IoBufMut<'static>]>>,
+        fd: RawFd,
+        offset: Option<u64>,
+    },
+    /// See `URingContext::add_writev`.
+    Writev {
+        iovecs: Pin<Box<[IoBufMut<'static>]>>,
+        fd: RawFd,
+        offset: Option<u64>,
+    },
+    /// See `URingContext::add_fsync`.
+    Fsync { fd: RawFd },
+    /// See `URingContext::add_fallocate`.
+    Fallocate {
+        fd: RawFd,
+        offset: u64,
+        len: u64,
+        mode: u32,
+    },
+}
+
+impl URingOp {
+    // Fill in `sqe` for this operation. Returns the iovecs that have to be kept alive until the
+    // operation completes, if any.
+    fn prep_sqe(
+        self,
+        sqe: &mut io_uring_sqe,
+        user_data: UserData,
+    ) -> Option<Pin<Box<[IoBufMut<'static>]>>> {
+        // `sqe` has already been cleared, so only the fields used by the operation are set.
+        sqe.user_data = user_data;
+        match self {
+            URingOp::Nop => {
+                sqe.opcode = io_uring_op_IORING_OP_NOP as u8;
+                sqe.fd = -1;
+                None
+            }
+            URingOp::Readv { iovecs, fd, offset } => {
+                sqe.opcode = io_uring_op_IORING_OP_READV as u8;
+                sqe.set_addr(iovecs.as_ptr() as *const _ as *const libc::c_void as u64);
+                sqe.len = iovecs.len() as u32;
+                sqe.set_off(file_offset_to_raw_offset(offset));
+                sqe.fd = fd;
+                Some(iovecs)
+            }
+            URingOp::Writev { iovecs, fd, offset } => {
+                sqe.opcode = io_uring_op_IORING_OP_WRITEV as u8;
+                sqe.set_addr(iovecs.as_ptr() as *const _ as *const libc::c_void as u64);
+                sqe.len = iovecs.len() as u32;
+                sqe.set_off(file_offset_to_raw_offset(offset));
+                sqe.fd = fd;
+                Some(iovecs)
+            }
+            URingOp::Fsync { fd } => {
+                sqe.opcode = io_uring_op_IORING_OP_FSYNC as u8;
+                sqe.fd = fd;
+                None
+            }
+            URingOp::Fallocate {
+                fd,
+                offset,
+                len,
+                mode,
+            } => {
+                // Note that len for fallocate in passed in the addr field of the sqe and the mode
+                // uses the len field.
+                sqe.opcode = io_uring_op_IORING_OP_FALLOCATE as u8;
+                sqe.fd = fd;
+                sqe.set_addr(len);
+                sqe.len = mode;
+                sqe.set_off(offset);
+                None
+            }
+        }
+    }
+}
+
 /// Enum to represent all io_uring operations
 #[repr(u32)]
 pub enum URingOperation {
@@ -425,19 +508,7 @@ impl URingContext {
         offset: Option<u64>,
         user_data: UserData,
     ) -> Result<()> {
-        self.submit_ring.lock().prep_next_sqe(|sqe| {
-            sqe.opcode = io_uring_op_IORING_OP_WRITEV as u8;
-            sqe.set_addr(iovecs.as_ptr() as *const _ as *const libc::c_void as u64);
-            sqe.len = iovecs.len() as u32;
-            sqe.set_off(file_offset_to_raw_offset(offset));
-            sqe.set_buf_index(0);
-            sqe.ioprio = 0;
-            sqe.user_data = user_data;
-            sqe.flags = 0;
-            sqe.fd = fd;
-        })?;
-        self.complete_ring.add_op_data(user_data, iovecs);
-        Ok(())
+        self.add_op(URingOp::Writev { iovecs, fd, offset }, user_data)
     }
 
     /// # Safety
@@ -484,55 +555,23 @@ impl URingContext {
         offset: Option<u64>,
         user_data: UserData,
     ) -> Result<()> {
-        self.submit_ring.lock().prep_next_sqe(|sqe| {
-            sqe.opcode = io_uring_op_IORING_OP_READV as u8;
-            sqe.set_addr(iovecs.as_ptr() as *const _ as *const libc::c_void as u64);
-            sqe.len = iovecs.len() as u32;
-            sqe.set_off(file_offset_to_raw_offset(offset));
-            sqe.set_buf_index(0);
-            sqe.ioprio = 0;
-            sqe.user_data = user_data;
-            sqe.flags = 0;
-            sqe.fd = fd;
-        })?;
-        self.complete_ring.add_op_data(user_data, iovecs);
-        Ok(())
+        self.add_op(URingOp::Readv { iovecs, fd, offset }, user_data)
     }
 
     /// Add a no-op operation that doesn't perform any IO. Useful for testing the performance of the
     /// io_uring itself and for waking up a thread that's blocked inside a wait() call.
     pub fn add_nop(&self, user_data: UserData) -> Result<()> {
-        self.submit_ring.lock().prep_next_sqe(|sqe| {
-            sqe.opcode = io_uring_op_IORING_OP_NOP as u8;
-            sqe.fd = -1;
-            sqe.user_data = user_data;
-
-            sqe.set_addr(0);
-            sqe.len = 0;
-            sqe.set_off(0);
-            sqe.set_buf_index(0);
-            sqe.set_rw_flags(0);
-            sqe.ioprio = 0;
-            sqe.flags = 0;
-        })
+        // SAFETY:
+        // Safe because a no-op doesn't access any memory.
+        unsafe { self.add_op(URingOp::Nop, user_data) }
     }
 
     /// Syncs all completed operations, the ordering with in-flight async ops is not
     /// defined.
     pub fn add_fsync(&self, fd: RawFd, user_data: UserData) -> Result<()> {
-        self.submit_ring.lock().prep_next_sqe(|sqe| {
-            sqe.opcode = io_uring_op_IORING_OP_FSYNC as u8;
-            sqe.fd = fd;
-            sqe.user_data = user_data;
-
-            sqe.set_addr(0);
-            sqe.len = 0;
-            sqe.set_off(0);
-            sqe.set_buf_index(0);
-            sqe.set_rw_flags(0);
-            sqe.ioprio = 0;
-            sqe.flags = 0;
-        })
+        // SAFETY:
+        // Safe because fsync doesn't access any memory.
+        unsafe { self.add_op(URingOp::Fsync { fd }, user_data) }
     }
 
     /// See the usage of `fallocate`, this asynchronously performs the same operations.
@@ -544,22 +583,104 @@ impl URingContext {
         mode: u32,
         user_data: UserData,
     ) -> Result<()> {
-        // Note that len for fallocate in passed in the addr field of the sqe and the mode uses the
-        // len field.
-        self.submit_ring.lock().prep_next_sqe(|sqe| {
-            sqe.opcode = io_uring_op_IORING_OP_FALLOCATE as u8;
+        // SAFETY:
+        // Safe because fallocate doesn't access any memory.
+        unsafe {
+            self.add_op(
+                URingOp::Fallocate {
+                    fd,
+                    offset,
+                    len,
+                    mode,
+                },
+                user_data,
+            )
+        }
+    }
 
-            sqe.fd = fd;
-            sqe.set_addr(len);
-            sqe.len = mode;
-            sqe.set_off(offset);
-            sqe.user_data = user_data;
+    /// Adds a chain of operations and sends them to the kernel.
+    ///
+    /// Each operation only starts after the previous one in `ops` completed successfully. If an
+    /// operation fails (including a read or write that transfers fewer bytes than requested), the
+    /// remaining operations of the chain complete with `ECANCELED`. Completions are returned from
+    /// `wait` as usual, keyed by the `UserData` given with each operation.
+    ///
+    /// Either all operations of the chain are added or, if there isn't enough space in the ring
+    /// for the whole chain, none of them are and `Error::NoSpace` is returned.
+    ///
+    /// # Safety
+    /// `Readv` and `Writev` operations have the same requirements as `add_readv` and `add_writev`.
+    pub unsafe fn submit_linked(&self, ops: Vec<(URingOp, UserData)>) -> Result<()> {
+        {
+            let mut submit_ring = self.submit_ring.lock();
+            if submit_ring.added + ops.len() > submit_ring.num_sqes {
+                return Err(Error::NoSpace);
+            }
+            let last = ops.len().saturating_sub(1);
+            for (i, (op, user_data)) in ops.into_iter().enumerate() {
+                let mut iovecs = None;
+                submit_ring.prep_next_sqe(|sqe| {
+                    iovecs = op.prep_sqe(sqe, user_data);
+                    if i < last {
+                        sqe.flags |= IOSQE_IO_LINK;
+                    }
+                })?;
+                if let Some(iovecs) = iovecs {
+                    self.complete_ring.add_op_data(user_data, iovecs);
+                }
+            }
+        }
+        self.submit()
+    }
 
-            sqe.set_buf_index(0);
-            sqe.set_rw_flags(0);
-            sqe.ioprio = 0;
-            sqe.flags = 0;
-        })
+    // Adds a single operation to the submit queue.
+    //
+    // # Safety
+    // `Readv` and `Writev` operations have the same requirements as `add_readv` and `add_writev`.
+    unsafe fn add_op(&self, op: URingOp, user_data: UserData) -> Result<()> {
+        let mut iovecs = None;
+        self.submit_ring
+            .lock()
+            .prep_next_sqe(|sqe| iovecs = op.prep_sqe(sqe, user_data))?;
+        if let Some(iovecs) = iovecs {
+            self.complete_ring.add_op_data(user_data, iovecs);
+        }
+        Ok(())
+    }
+
+    /// Returns the number of completions the kernel dropped because the completion ring was full.
+    ///
+    /// Kernels with `IORING_FEAT_NODROP` (5.5 and later) keep completions that don't fit in the
+    /// ring and return them from later calls to `wait`, so on those the count only increases if
+    /// the kernel fails to allocate memory for them. Dropped completions are never returned by
+    /// `wait`, and the resources of their operations are leaked.
+    pub fn dropped_completions(&self) -> u32 {
+        self.complete_ring.dropped()
+    }
+
+    /// Adds a timeout that expires after `dur` and sends it to the kernel, so that a later call to
+    /// `wait` returns once the timeout expires even if no other operation completes.
+    ///
+    /// The expiry is reported by `wait` as a successful completion with result 0, rather than as
+    /// the `ETIME` error the kernel uses for it. The timeout can be removed early with
+    /// `async_cancel`, in which case it completes with `ECANCELED`.
+    pub fn submit_timeout(&self, dur: Duration, user_data: UserData) -> Result<()> {
+        // The timespec has to stay alive until the timeout completes.
+        let timespec = Box::new(__kernel_timespec {
+            tv_sec: dur.as_secs().min(i64::MAX as u64) as i64,
+            tv_nsec: dur.subsec_nanos().into(),
+        });
+        self.submit_ring.lock().prep_next_sqe(|sqe| {
+            sqe.opcode = io_uring_op_IORING_OP_TIMEOUT as u8;
+            sqe.fd = -1;
+            sqe.user_data = user_data;
+            sqe.set_addr(&*timespec as *const __kernel_timespec as u64);
+            sqe.len = 1;
+            // A completion count of 0 makes this a pure timeout.
+            sqe.set_off(0);
+        })?;
+        self.complete_ring.add_timeout_data(user_data, timespec);
+        self.submit()
     }
 
     /// Adds an FD to be polled based on the given flags.
@@ -573,13 +694,6 @@ impl URingContext {
             sqe.fd = fd;
             sqe.user_data = user_data;
             sqe.set_poll_events(events.into());
-
-            sqe.set_addr(0);
-            sqe.len = 0;
-            sqe.set_off(0);
-            sqe.set_buf_index(0);
-            sqe.ioprio = 0;
-            sqe.flags = 0;
         })
     }
 
@@ -590,13 +704,6 @@ impl URingContext {
             sqe.fd = fd;
             sqe.user_data = user_data;
             sqe.set_poll_events(events.into());
-
-            sqe.set_addr(0);
-            sqe.len = 0;
-            sqe.set_off(0);
-            sqe.set_buf_index(0);
-            sqe.ioprio = 0;
-            sqe.flags = 0;
         })
     }
 
@@ -606,19 +713,12 @@ impl URingContext {
     /// res will contain -ENOENT. If found and attempted cancelled, the res field will contain
     /// -EALREADY. In this case, the request may or may not terminate. In general, requests that
     /// are interruptible (like socket IO) will get cancelled, while disk IO requests cannot be
-    /// cancelled if already started.
+    /// cancelled if already started. A request that was cancelled completes with -ECANCELED.
     pub fn async_cancel(&self, addr: UserData, user_data: UserData) -> Result<()> {
         self.submit_ring.lock().prep_next_sqe(|sqe| {
             sqe.opcode = io_uring_op_IORING_OP_ASYNC_CANCEL as u8;
             sqe.user_data = user_data;
             sqe.set_addr(addr);
-
-            sqe.len = 0;
-            sqe.fd = 0;
-            sqe.set_off(0);
-            sqe.set_buf_index(0);
-            sqe.ioprio = 0;
-            sqe.flags = 0;
         })
     }
 
@@ -684,8 +784,11 @@ impl URingContext {
             1
         };
 
+        let res = self.enter(wait_nr);
+        self.complete_ring.check_overflow();
+
         // The CompletionQueue will iterate all completed ops.
-        match self.enter(wait_nr) {
+        match res {
             Ok(()) => Ok(&self.complete_ring),
             // If we cannot submit any more entries then we need to pull stuff out of the completion
             // ring, so just return the completion ring. This can only happen when `wait_nr` is 0 so
@@ -722,8 +825,6 @@ impl SubmitQueueEntries {
         // Safe because the mut borrow of self resticts to one mutable reference at a time and
         // we trust that the kernel has returned enough memory in io_uring_setup and mmap.
         let mut_ref = unsafe { &mut *(self.mmap.as_ptr() as *mut io_uring_sqe).add(index) };
-        // Clear any state.
-        *mut_ref = io_uring_sqe::default();
         Some(mut_ref)
     }
 }
@@ -772,6 +873,8 @@ struct CompleteQueueData {
     //For ops that pass in arrays of iovecs, they need to be valid for the duration of the
     //operation because the kernel might read them at any time.
     pending_op_addrs: BTreeMap<UserData, Pin<Box<[IoBufMut<'static>]>>>,
+    // Timespecs of pending timeout ops, which also identify completions that are timeouts.
+    pending_timeouts: BTreeMap<UserData, Box<__kernel_timespec>>,
 }
 
 pub struct CompleteQueueState {
@@ -779,6 +882,9 @@ pub struct CompleteQueueState {
     pointers: QueuePointers,
     ring_mask: u32,
     cqes_offset: u32,
+    overflow_offset: u32,
+    // Set once a dropped completion has been logged.
+    overflow_reported: AtomicBool,
     data: Mutex<CompleteQueueData>,
 }
 
@@ -796,6 +902,8 @@ impl CompleteQueueState {
             pointers: QueuePointers { head, tail },
             ring_mask,
             cqes_offset: params.cq_off.cqes,
+            overflow_offset: params.cq_off.overflow,
+            overflow_reported: AtomicBool::new(false),
             data: Default::default(),
         }
     }
@@ -804,6 +912,30 @@ impl CompleteQueueState {
         self.data.lock().pending_op_addrs.insert(user_data, addrs);
     }
 
+    fn add_timeout_data(&self, user_data: UserData, timespec: Box<__kernel_timespec>) {
+        self.data
+            .lock()
+            .pending_timeouts
+            .insert(user_data, timespec);
+    }
+
+    // Returns the kernel's count of completions dropped because the ring was full.
+    fn dropped(&self) -> u32 {
+        // This offset is guaranteed to be within the mmap so unwrap the result.
+        self.mmap.read_obj(self.overflow_offset as usize).unwrap()
+    }
+
+    // Logs a warning the first time completions are dropped.
+    fn check_overflow(&self) {
+        let dropped = self.dropped();
+        if dropped > 0 && !self.overflow_reported.swap(true, Ordering::Relaxed) {
+            warn!(
+                "io_uring completion ring overflowed, {} completions were dropped",
+                dropped
+            );
+        }
+    }
+
     fn get_cqe(&self, head: u32) -> &io_uring_cqe {
         // SAFETY:
         // Safe because we trust that the kernel has returned enough memory in io_uring_setup
@@ -845,6 +977,7 @@ impl CompleteQueueState {
 
         // free the addrs saved for this op.
         let _ = data.pending_op_addrs.remove(&user_data);
+        let is_timeout = data.pending_timeouts.remove(&user_data).is_some();
 
         // Store the new head and ensure the reads above complete before the kernel sees the
         // update to head, `set_head` uses `Release` ordering
@@ -852,6 +985,8 @@ impl CompleteQueueState {
         self.pointers.set_head(new_head);
 
         let io_res = match res {
+            // An expired timeout is the expected outcome rather than an error.
+            r if r == -libc::ETIME && is_timeout => Ok(0),
             r if r < 0 => Err(std::io::Error::from_raw_os_error(-r)),
             r => Ok(r as u32),
         };
@@ -4,6 +4,7 @@
 
 #![cfg(any(target_os = "android", target_os = "linux"))]
 
+use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::fs::File;
 use std::fs::OpenOptions;
@@ -26,6 +27,7 @@ use std::sync::Arc;
 use std::sync::Barrier;
 use std::thread;
 use std::time::Duration;
+use std::time::Instant;
 
 use base::pipe;
 use base::EventType;
@@ -34,6 +36,7 @@ use base::WaitContext;
 use io_uring::Error;
 use io_uring::URingAllowlist;
 use io_uring::URingContext;
+use io_uring::URingOp;
 use io_uring::UserData;
 use libc::EACCES;
 use sync::Condvar;
@@ -218,6 +221,167 @@ fn write_one_block() {
     }
 }
 
+#[test]
+fn linked_write_fsync() {
+    let uring = URingContext::new(16, None).unwrap();
+    let buf = [0x55u8; 4096];
+    let mut f = create_test_file(0);
+
+    // SAFETY:
+    // Safe because the `wait` calls wait until the kernel is done reading `buf`.
+    unsafe {
+        uring
+            .submit_linked(vec![
+                (
+                    URingOp::Writev {
+                        iovecs: Pin::from(
+                            vec![IoBufMut::from_raw_parts(buf.as_ptr() as *mut u8, buf.len())]
+                                .into_boxed_slice(),
+                        ),
+                        fd: f.as_raw_fd(),
+                        offset: Some(0),
+                    },
+                    1,
+                ),
+                (URingOp::Fsync { fd: f.as_raw_fd() }, 2),
+            ])
+            .unwrap();
+    }
+
+    // Linked operations complete in order.
+    let mut completions = Vec::new();
+    while completions.len() < 2 {
+        completions.extend(
+            uring
+                .wait()
+                .unwrap()
+                .map(|(user_data, res)| (user_data, res.unwrap())),
+        );
+    }
+    assert_eq!(completions, [(1, buf.len() as u32), (2, 0)]);
+
+    let mut contents = Vec::new();
+    f.read_to_end(&mut contents).unwrap();
+    assert_eq!(contents, buf);
+}
+
+#[test]
+fn linked_failure_cancels_chain() {
+    let uring = URingContext::new(16, None).unwrap();
+    let buf = [0x55u8; 4096];
+    let tempdir = TempDir::new().unwrap();
+    let file_path = append_file_name(tempdir.path(), "test");
+    File::create(&file_path).unwrap();
+    // Writes to a file opened read-only fail with `EBADF`.
+    let f = File::open(&file_path).unwrap();
+
+    // SAFETY:
+    // Safe because the `wait` calls wait until the kernel is done reading `buf`.
+    unsafe {
+        uring
+            .submit_linked(vec![
+                (
+                    URingOp::Writev {
+                        iovecs: Pin::from(
+                            vec![IoBufMut::from_raw_parts(buf.as_ptr() as *mut u8, buf.len())]
+                                .into_boxed_slice(),
+                        ),
+                        fd: f.as_raw_fd(),
+                        offset: Some(0),
+                    },
+                    1,
+                ),
+                (URingOp::Fsync { fd: f.as_raw_fd() }, 2),
+            ])
+            .unwrap();
+    }
+
+    let mut completions = Vec::new();
+    while completions.len() < 2 {
+        completions.extend(
+            uring
+                .wait()
+                .unwrap()
+                .map(|(user_data, res)| (user_data, res.unwrap_err().raw_os_error())),
+        );
+    }
+    assert_eq!(
+        completions,
+        [(1, Some(libc::EBADF)), (2, Some(libc::ECANCELED))]
+    );
+}
+
+#[test]
+fn plain_op_after_linked_wraps_ring() {
+    // The ring has room for two entries, so the second submission reuses the slots of the chain.
+    let uring = URingContext::new(2, None).unwrap();
+    // SAFETY:
+    // Safe because no-ops don't access any memory.
+    unsafe {
+        uring
+            .submit_linked(vec![(URingOp::Nop, 1), (URingOp::Nop, 2)])
+            .unwrap();
+    }
+    let mut completions = Vec::new();
+    while completions.len() < 2 {
+        completions.extend(
+            uring
+                .wait()
+                .unwrap()
+                .map(|(user_data, res)| (user_data, res.unwrap())),
+        );
+    }
+    assert_eq!(completions, [(1, 0), (2, 0)]);
+
+    let buf = [0x55u8; 4096];
+    let tempdir = TempDir::new().unwrap();
+    let file_path = append_file_name(tempdir.path(), "test");
+    File::create(&file_path).unwrap();
+    // Writes to a file opened read-only fail with `EBADF`.
+    let f = File::open(&file_path).unwrap();
+    // SAFETY:
+    // Safe because the `wait` calls wait until the kernel is done reading `buf`.
+    unsafe {
+        add_one_write(&uring, buf.as_ptr(), buf.len(), f.as_raw_fd(), Some(0), 3).unwrap();
+    }
+    uring.add_nop(4).unwrap();
+    uring.submit().unwrap();
+
+    // The failed write is in the slot of the first operation of the chain, which was linked to the
+    // next one. The no-op must not be cancelled along with the write.
+    let mut completions = BTreeMap::new();
+    while completions.len() < 2 {
+        completions.extend(
+            uring
+                .wait()
+                .unwrap()
+                .map(|(user_data, res)| (user_data, res.map_err(|e| e.raw_os_error()))),
+        );
+    }
+    assert_eq!(completions[&3], Err(Some(libc::EBADF)));
+    assert_eq!(completions[&4], Ok(0));
+}
+
+#[test]
+fn timeout_wakes_wait() {
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    let uring = URingContext::new(16, None).unwrap();
+    let start = Instant::now();
+    uring.submit_timeout(TIMEOUT, 77).unwrap();
+    let (user_data, res) = uring.wait().unwrap().next().unwrap();
+    let elapsed = start.elapsed();
+
+    assert_eq!(user_data, 77);
+    assert_eq!(res.unwrap(), 0);
+    assert!(elapsed >= TIMEOUT, "timeout expired after {:?}", elapsed);
+    assert!(
+        elapsed < Duration::from_secs(5),
+        "timeout expired after {:?}",
+        elapsed
+    );
+}
+
 #[test]
 fn write_one_submit_poll() {
     let uring = URingContext::new(16, None).unwrap();
@@ -456,6 +620,78 @@ fn queue_many_ebusy_retry() {
     assert!(results.next().is_none());
 }
 
+#[test]
+fn cancel_pending_read() {
+    const PIPE_READ: UserData = 0;
+    const CANCEL: UserData = 1;
+
+    let uring = URingContext::new(4, None).unwrap();
+    // Nothing is ever written to the pipe, so the read stays pending until it is cancelled.
+    let (pipe_out, _pipe_in) = pipe(true).unwrap();
+    let mut buf = [0u8; 16];
+
+    // SAFETY:
+    // Safe because the `wait` calls below wait until the kernel is done with `buf`.
+    unsafe {
+        add_one_read(
+            &uring,
+            buf.as_mut_ptr(),
+            buf.len(),
+            pipe_out.as_raw_fd(),
+            None,
+            PIPE_READ,
+        )
+        .unwrap();
+    }
+    uring.submit().unwrap();
+    uring.async_cancel(PIPE_READ, CANCEL).unwrap();
+
+    let mut results = BTreeMap::new();
+    while results.len() < 2 {
+        for (user_data, res) in uring.wait().unwrap() {
+            results.insert(user_data, res.map_err(|e| e.raw_os_error()));
+        }
+    }
+    assert_eq!(results[&PIPE_READ], Err(Some(libc::ECANCELED)));
+    assert_eq!(results[&CANCEL], Ok(0));
+
+    // Cancelling a request that is no longer pending fails with `ENOENT`.
+    uring.async_cancel(PIPE_READ, CANCEL).unwrap();
+    let (user_data, res) = uring.wait().unwrap().next().unwrap();
+    assert_eq!(user_data, CANCEL);
+    assert_eq!(res.unwrap_err().raw_os_error(), Some(libc::ENOENT));
+}
+
+#[test]
+fn overflow_completion_ring() {
+    let num_entries = 4;
+    let num_ops = num_entries * 4;
+    let uring = URingContext::new(num_entries, None).unwrap();
+    // The completion ring is twice the size of the submit ring, so submitting four full batches
+    // without reaping overflows it.
+    for sqe_batch in 0..4 {
+        for i in 0..num_entries {
+            uring.add_nop((sqe_batch * num_entries + i) as u64).unwrap();
+        }
+        uring.submit().unwrap();
+    }
+
+    // Completions that don't fit in the ring are either dropped or, on kernels with
+    // `IORING_FEAT_NODROP`, returned by later calls to `wait`.
+    let mut completed = BTreeSet::new();
+    while completed.len() + (uring.dropped_completions() as usize) < num_ops {
+        for (user_data, res) in uring.wait().unwrap() {
+            assert_eq!(res.unwrap(), 0);
+            assert!(completed.insert(user_data));
+        }
+    }
+    assert!(completed.len() >= num_entries * 2);
+    assert_eq!(
+        completed.len() + uring.dropped_completions() as usize,
+        num_ops
+    );
+}
+
 #[test]
 fn wake_with_nop() {
     const PIPE_READ: UserData = 0;
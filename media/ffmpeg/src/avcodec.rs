@@ -16,6 +16,7 @@ use std::ops::Deref;
 use libc::c_char;
 use libc::c_int;
 use libc::c_void;
+use log::warn;
 use thiserror::Error as ThisError;
 
 use super::*;
@@ -155,6 +156,17 @@ impl AvCodec {
     }
 }
 
+/// Threading model used by a codec, see libavcodec's documentation for `thread_type`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ThreadType {
+    /// Decode several frames in parallel. Increases throughput, but adds one frame of latency per
+    /// thread.
+    Frame,
+    /// Decode several slices of a single frame in parallel. Does not add latency, but only helps
+    /// with streams using multiple slices per frame.
+    Slice,
+}
+
 /// A builder to create a [`AvCodecContext`] suitable for decoding.
 // This struct wraps an AvCodecContext directly, but the only way it can be taken out is to call
 // `build()`, which finalizes the context and prevent further modification to the callback, etc.
@@ -182,6 +194,34 @@ impl DecoderContextBuilder {
         context.opaque = opaque;
     }
 
+    /// Decode using `thread_count` threads with the `thread_type` threading model. A
+    /// `thread_count` of 0 lets libavcodec pick the number of threads.
+    ///
+    /// If the codec does not support `thread_type`, a warning is logged and the decoder stays
+    /// single-threaded.
+    pub fn set_threading(&mut self, thread_count: u32, thread_type: ThreadType) {
+        let (capability, ff_thread_type) = match thread_type {
+            ThreadType::Frame => (ffi::AV_CODEC_CAP_FRAME_THREADS, ffi::FF_THREAD_FRAME),
+            ThreadType::Slice => (ffi::AV_CODEC_CAP_SLICE_THREADS, ffi::FF_THREAD_SLICE),
+        };
+        // SAFETY:
+        // Safe because self.codec is a valid static AVCodec reference.
+        let capabilities = unsafe { (*self.codec).capabilities } as u32;
+        // SAFETY:
+        // Safe because self.context.0 is a pointer to a live AVCodecContext allocation.
+        let context = unsafe { &mut *(self.context.0) };
+        if capabilities & capability == 0 {
+            warn!(
+                "codec does not support {:?} threading, decoding on a single thread",
+                thread_type
+            );
+            context.thread_count = 1;
+            return;
+        }
+        context.thread_count = thread_count.try_into().unwrap_or(c_int::MAX);
+        context.thread_type = ff_thread_type as c_int;
+    }
+
     /// Build a decoder AvCodecContext from the configured options.
     pub fn build(mut self) -> Result<AvCodecContext, AvCodecOpenError> {
         self.context.init(self.codec)?;
@@ -463,6 +503,55 @@ pub enum TryReceiveResult {
     FlushCompleted,
 }
 
+/// Iterator over the frames still buffered by a decoder at the end of a stream, returned by
+/// [`AvCodecContext::flush`].
+pub struct FlushedFrames<'a> {
+    context: &'a mut AvCodecContext,
+    // Error that occurred while starting the flush, returned by the first iteration.
+    flush_error: Option<AvError>,
+    done: bool,
+}
+
+impl<'a> Iterator for FlushedFrames<'a> {
+    type Item = Result<AvFrame, AvFrameError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Some(e) = self.flush_error.take() {
+            self.done = true;
+            return Some(Err(e.into()));
+        }
+
+        let mut frame = match AvFrame::new() {
+            Ok(frame) => frame,
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+        };
+        match self.context.try_receive_frame(&mut frame) {
+            Ok(TryReceiveResult::Received) => Some(Ok(frame)),
+            Ok(TryReceiveResult::FlushCompleted) => {
+                self.done = true;
+                // The codec won't accept new packets after a flush until it is reset.
+                self.context.reset();
+                None
+            }
+            // A draining decoder never asks for more input.
+            Ok(TryReceiveResult::TryAgain) => {
+                self.done = true;
+                Some(Err(AvError(AVERROR_EAGAIN).into()))
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e.into()))
+            }
+        }
+    }
+}
+
 impl AvCodecContext {
     /// Internal helper for [`DecoderContextBuilder`] to initialize the context.
     fn init(&mut self, codec: *const ffi::AVCodec) -> Result<(), AvCodecOpenError> {
@@ -500,7 +589,7 @@ impl AvCodecContext {
     /// Returned `Received` if `frame` has been filled with the next decoded frame, `TryAgain` if
     /// no frame could be returned at that time (in which case `try_send_packet` should be called to
     /// submit more input to decode), or `FlushCompleted` to signal that a previous flush triggered
-    /// by calling the `flush` method has completed.
+    /// by calling the `flush_decoder` method has completed.
     ///
     /// Error codes are the same as those returned by `avcodec_receive_frame` with the exception of
     /// EAGAIN and EOF which are handled as `TryAgain` and `FlushCompleted` respectively.
@@ -576,6 +665,20 @@ impl AvCodecContext {
         AvError::result(unsafe { ffi::avcodec_send_packet(self.0, std::ptr::null()) })
     }
 
+    /// Signal the end of the stream to the decoder, and return an iterator over the frames it still
+    /// holds for the packets submitted so far.
+    ///
+    /// The context is reset once all the frames have been returned, so it can be used to decode a
+    /// new stream afterwards.
+    pub fn flush(&mut self) -> FlushedFrames<'_> {
+        let flush_error = self.flush_decoder().err();
+        FlushedFrames {
+            context: self,
+            flush_error,
+            done: false,
+        }
+    }
+
     /// Ask the context to start flushing, i.e. to process all pending input frames and produce
     /// packets for them.
     ///
@@ -885,6 +988,34 @@ impl AvFrame {
         // Safe because self.0 is a valid AVFrame reference.
         AvError::result(unsafe { ffi::av_frame_make_writable(self.0) }).map_err(Into::into)
     }
+
+    /// Reset the properties describing this frame's content (timestamps, picture type, side data,
+    /// metadata, ...) to their defaults, while keeping its dimensions, format and buffers. This
+    /// allows the frame to be reused for another picture without carrying over stale state.
+    pub fn reset_properties(&mut self) {
+        // SAFETY:
+        // Safe because self.0 is a valid AVFrame reference, and the side data, metadata and opaque
+        // reference released here are owned by the frame.
+        unsafe {
+            let frame = &mut *self.0;
+            while frame.nb_side_data > 0 {
+                let side_data = *frame.side_data.add(frame.nb_side_data as usize - 1);
+                ffi::av_frame_remove_side_data(frame, (*side_data).type_);
+            }
+            ffi::av_dict_free(&mut frame.metadata);
+            ffi::av_buffer_unref(&mut frame.opaque_ref);
+            frame.opaque = std::ptr::null_mut();
+            frame.pts = AV_NOPTS_VALUE as i64;
+            frame.pkt_dts = AV_NOPTS_VALUE as i64;
+            frame.best_effort_timestamp = AV_NOPTS_VALUE as i64;
+            frame.duration = 0;
+            frame.pict_type = ffi::AVPictureType_AV_PICTURE_TYPE_NONE;
+            frame.flags = 0;
+            frame.quality = 0;
+            frame.repeat_pict = 0;
+            frame.decode_error_flags = 0;
+        }
+    }
 }
 
 impl AvFrameBuilder {
@@ -1010,6 +1141,16 @@ impl AvFrameBuilder {
         }
         Ok(self.0)
     }
+
+    /// Build an AvFrame backed by newly allocated buffers large enough for the dimensions and
+    /// format set on this builder.
+    pub fn build_allocated(self) -> Result<AvFrame, AvFrameError> {
+        // SAFETY:
+        // Safe because self.0 is a valid AVFrame instance without any buffer attached, and whose
+        // dimensions and format have been set.
+        AvError::result(unsafe { ffi::av_frame_get_buffer(self.0 .0, 0) })?;
+        Ok(self.0)
+    }
 }
 
 impl AsRef<ffi::AVFrame> for AvFrame {
@@ -1102,4 +1243,119 @@ mod tests {
         drop(pkt);
         assert!(dropped.load(Ordering::SeqCst));
     }
+
+    /// Encode `num_frames` grey frames as MPEG-2 with B-frames, so that a decoder needs to hold
+    /// back the last reference frame until the end of the stream.
+    fn encode_mpeg2_clip(dimensions: Dimensions, num_frames: i64) -> Vec<AvPacket<'static>> {
+        let format = AvPixelFormat::try_from(AVPixelFormat_AV_PIX_FMT_YUV420P).unwrap();
+        let codec = AvCodecIterator::new()
+            .find(|c| c.is_encoder() && c.name() == "mpeg2video")
+            .expect("mpeg2video encoder not found");
+        let mut builder = codec.build_encoder().unwrap();
+        builder.set_dimensions(dimensions);
+        builder.set_time_base(AVRational { num: 1, den: 25 });
+        builder.set_pix_fmt(format);
+        // SAFETY:
+        // Safe because the context is a valid allocation that has not been opened yet.
+        unsafe {
+            (*builder.context.0).framerate = AVRational { num: 25, den: 1 };
+            (*builder.context.0).max_b_frames = 2;
+        }
+        let mut encoder = builder.build().unwrap();
+
+        let mut packets = Vec::new();
+        let mut receive_packets = |encoder: &mut AvCodecContext| loop {
+            let mut packet = AvPacket::empty();
+            match encoder.try_receive_packet(&mut packet).unwrap() {
+                TryReceiveResult::Received => packets.push(packet),
+                _ => break,
+            }
+        };
+        for pts in 0..num_frames {
+            let mut builder = AvFrame::builder().unwrap();
+            builder.set_dimensions(dimensions).unwrap();
+            builder.set_format(format).unwrap();
+            let mut frame = builder.build_allocated().unwrap();
+            for plane in 0..3 {
+                let height = if plane == 0 {
+                    dimensions.height
+                } else {
+                    dimensions.height / 2
+                };
+                // SAFETY:
+                // Safe because the plane was allocated with `linesize` bytes for each of its rows.
+                unsafe {
+                    std::ptr::write_bytes(
+                        frame.data[plane],
+                        0x80,
+                        frame.linesize[plane] as usize * height as usize,
+                    )
+                };
+            }
+            frame.set_pts(pts);
+            assert!(encoder.try_send_frame(&frame).unwrap());
+            receive_packets(&mut encoder);
+        }
+        encoder.flush_encoder().unwrap();
+        receive_packets(&mut encoder);
+        packets
+    }
+
+    #[test]
+    fn test_decoder_flush() {
+        const NUM_FRAMES: i64 = 10;
+        let dimensions = Dimensions {
+            width: 64,
+            height: 64,
+        };
+        let packets = encode_mpeg2_clip(dimensions, NUM_FRAMES);
+
+        let codec = AvCodecIterator::new()
+            .find(|c| c.is_decoder() && c.name() == "mpeg2video")
+            .expect("mpeg2video decoder not found");
+        let mut decoder = codec.build_decoder().unwrap().build().unwrap();
+
+        let mut num_decoded = 0;
+        for packet in &packets {
+            assert!(decoder.try_send_packet(packet).unwrap());
+            let mut frame = AvFrame::new().unwrap();
+            while let TryReceiveResult::Received = decoder.try_receive_frame(&mut frame).unwrap() {
+                num_decoded += 1;
+            }
+        }
+
+        let trailing_frames = decoder.flush().collect::<Result<Vec<_>, _>>().unwrap();
+        assert!(!trailing_frames.is_empty());
+        for frame in &trailing_frames {
+            assert_eq!(frame.dimensions(), dimensions);
+        }
+        assert_eq!(num_decoded + trailing_frames.len() as i64, NUM_FRAMES);
+
+        // The decoder can be used again once flushed.
+        assert!(decoder.try_send_packet(&packets[0]).unwrap());
+    }
+
+    fn find_decoder(name: &str) -> AvCodec {
+        AvCodecIterator::new()
+            .find(|c| c.is_decoder() && c.name() == name)
+            .unwrap_or_else(|| panic!("{} decoder not found", name))
+    }
+
+    #[test]
+    fn test_decoder_threading() {
+        let mut builder = find_decoder("h264").build_decoder().unwrap();
+        builder.set_threading(4, ThreadType::Frame);
+        let context = builder.build().unwrap();
+        assert_eq!(context.as_ref().thread_count, 4);
+        assert_eq!(context.as_ref().thread_type, ffi::FF_THREAD_FRAME as c_int);
+    }
+
+    #[test]
+    fn test_decoder_threading_unsupported() {
+        // The BMP decoder does not support any kind of threading.
+        let mut builder = find_decoder("bmp").build_decoder().unwrap();
+        builder.set_threading(4, ThreadType::Frame);
+        let context = builder.build().unwrap();
+        assert_eq!(context.as_ref().thread_count, 1);
+    }
 }
@@ -2,8 +2,16 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::cell::RefCell;
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::rc::Rc;
+
 use crate::avcodec::AvError;
+use crate::avcodec::AvFrame;
+use crate::avcodec::AvFrameError;
 use crate::avcodec::AvPixelFormat;
+use crate::avcodec::Dimensions;
 use crate::ffi;
 
 pub const AV_NOPTS_VALUE: u64 = 0x8000000000000000;
@@ -61,3 +69,167 @@ pub(crate) fn av_image_plane_sizes<I: IntoIterator<Item = u32>>(
         .take(planes)
         .collect())
 }
+
+/// A pool of `AvFrame`s sharing the same dimensions and pixel format.
+///
+/// Frames are handed out as [`PooledFrame`] guards and go back to the pool when dropped, so their
+/// buffers can be reused for the next picture instead of allocating new ones every time.
+#[derive(Clone)]
+pub struct FramePool(Rc<RefCell<FramePoolInner>>);
+
+struct FramePoolInner {
+    dimensions: Dimensions,
+    format: AvPixelFormat,
+    free_frames: Vec<AvFrame>,
+    hits: u64,
+    allocations: u64,
+}
+
+impl FramePool {
+    /// Create an empty pool of frames of size `dimensions` and pixel format `format`.
+    pub fn new(dimensions: Dimensions, format: AvPixelFormat) -> Self {
+        Self(Rc::new(RefCell::new(FramePoolInner {
+            dimensions,
+            format,
+            free_frames: Vec::new(),
+            hits: 0,
+            allocations: 0,
+        })))
+    }
+
+    /// Get a frame from the pool, allocating a new one if none is available.
+    ///
+    /// A recycled frame keeps its buffers but has its other properties reset, see
+    /// [`AvFrame::reset_properties`].
+    pub fn get(&self) -> Result<PooledFrame, AvFrameError> {
+        let mut inner = self.0.borrow_mut();
+        let frame = match inner.free_frames.pop() {
+            Some(mut frame) => {
+                inner.hits += 1;
+                frame.reset_properties();
+                frame
+            }
+            None => {
+                let mut builder = AvFrame::builder()?;
+                builder.set_dimensions(inner.dimensions)?;
+                builder.set_format(inner.format)?;
+                let frame = builder.build_allocated()?;
+                inner.allocations += 1;
+                frame
+            }
+        };
+
+        Ok(PooledFrame {
+            frame: Some(frame),
+            pool: self.0.clone(),
+        })
+    }
+
+    /// Return the number of times a frame was recycled instead of allocated.
+    pub fn hit_count(&self) -> u64 {
+        self.0.borrow().hits
+    }
+
+    /// Return the number of frames allocated by this pool.
+    pub fn allocation_count(&self) -> u64 {
+        self.0.borrow().allocations
+    }
+}
+
+/// A frame borrowed from a [`FramePool`], which is returned to it when dropped.
+pub struct PooledFrame {
+    // Only `None` while being dropped.
+    frame: Option<AvFrame>,
+    pool: Rc<RefCell<FramePoolInner>>,
+}
+
+impl Deref for PooledFrame {
+    type Target = AvFrame;
+
+    fn deref(&self) -> &Self::Target {
+        self.frame.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledFrame {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.frame.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledFrame {
+    fn drop(&mut self) {
+        let frame = match self.frame.take() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let mut pool = self.pool.borrow_mut();
+        // Only recycle frames that still match the pool's geometry and whose buffers are not
+        // referenced anywhere else.
+        if frame.dimensions() == pool.dimensions
+            && frame.format().pix_fmt() == pool.format.pix_fmt()
+            && frame.is_writable()
+        {
+            pool.free_frames.push(frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AVPixelFormat_AV_PIX_FMT_NV12;
+
+    fn nv12_pool() -> FramePool {
+        FramePool::new(
+            Dimensions {
+                width: 64,
+                height: 32,
+            },
+            AvPixelFormat::try_from(AVPixelFormat_AV_PIX_FMT_NV12).unwrap(),
+        )
+    }
+
+    #[test]
+    fn frame_pool_reuses_buffers() {
+        const NUM_FRAMES: i64 = 8;
+        let pool = nv12_pool();
+
+        let mut buffer = None;
+        for pts in 0..NUM_FRAMES {
+            let mut frame = pool.get().unwrap();
+            assert_eq!(
+                frame.dimensions(),
+                Dimensions {
+                    width: 64,
+                    height: 32
+                }
+            );
+            // The same buffer is handed out every time.
+            assert_eq!(*buffer.get_or_insert(frame.data[0]), frame.data[0]);
+            // Properties of the previous picture must not leak into this one.
+            assert_eq!(frame.pts, AV_NOPTS_VALUE as i64);
+            frame.set_pts(pts);
+        }
+
+        assert_eq!(pool.allocation_count(), 1);
+        assert_eq!(pool.hit_count(), NUM_FRAMES as u64 - 1);
+    }
+
+    #[test]
+    fn frame_pool_allocates_when_empty() {
+        let pool = nv12_pool();
+
+        let first = pool.get().unwrap();
+        let second = pool.get().unwrap();
+        assert_ne!(first.data[0], second.data[0]);
+        assert_eq!(pool.allocation_count(), 2);
+        assert_eq!(pool.hit_count(), 0);
+
+        drop(first);
+        drop(second);
+        let _third = pool.get().unwrap();
+        assert_eq!(pool.allocation_count(), 2);
+        assert_eq!(pool.hit_count(), 1);
+    }
+}
@@ -33,6 +33,10 @@ pub enum ConversionError {
     NotWritable,
     #[error("error during conversion with libswscale: {0}")]
     AvError(#[from] AvError),
+    #[error("pixel format {0} is not supported by libswscale")]
+    UnsupportedPixelFormat(ffi::AVPixelFormat),
+    #[error("error while creating the SWS context")]
+    ContextCreationFailed,
 }
 
 impl Drop for SwConverter {
@@ -48,12 +52,15 @@ impl SwConverter {
     ///
     /// `width` and `height` are the coded size of the frames to be converted. The source and target
     /// must have the same size in pixels.
+    ///
+    /// Returns `ConversionError::UnsupportedPixelFormat` with the offending format if libswscale
+    /// cannot read from `src_format` or write to `dst_format`.
     pub fn new(
         width: usize,
         height: usize,
         src_pix_format: ffi::AVPixelFormat,
         dst_pix_format: ffi::AVPixelFormat,
-    ) -> anyhow::Result<Self> {
+    ) -> Result<Self, ConversionError> {
         // SAFETY:
         // Safe because we don't pass any non-null pointer to this function.
         let sws_context = unsafe {
@@ -72,7 +79,21 @@ impl SwConverter {
         };
 
         if sws_context.is_null() {
-            anyhow::bail!("error while creating the SWS context")
+            // SAFETY:
+            // Safe because these functions only look up the given format in libswscale's tables.
+            let (src_supported, dst_supported) = unsafe {
+                (
+                    ffi::sws_isSupportedInput(src_pix_format) > 0,
+                    ffi::sws_isSupportedOutput(dst_pix_format) > 0,
+                )
+            };
+            return Err(if !src_supported {
+                ConversionError::UnsupportedPixelFormat(src_pix_format)
+            } else if !dst_supported {
+                ConversionError::UnsupportedPixelFormat(dst_pix_format)
+            } else {
+                ConversionError::ContextCreationFailed
+            });
         }
 
         Ok(Self {
@@ -131,3 +152,36 @@ impl SwConverter {
         .map_err(Into::into)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AVPixelFormat_AV_PIX_FMT_NV12;
+    use crate::AVPixelFormat_AV_PIX_FMT_YUV420P;
+
+    // A value outside of the range of valid `AVPixelFormat`s.
+    const INVALID_PIX_FMT: ffi::AVPixelFormat = 0x7fff;
+
+    #[test]
+    fn test_supported_conversion() {
+        assert!(SwConverter::new(
+            64,
+            64,
+            AVPixelFormat_AV_PIX_FMT_YUV420P,
+            AVPixelFormat_AV_PIX_FMT_NV12
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_unsupported_pixel_format() {
+        assert!(matches!(
+            SwConverter::new(64, 64, INVALID_PIX_FMT, AVPixelFormat_AV_PIX_FMT_NV12),
+            Err(ConversionError::UnsupportedPixelFormat(INVALID_PIX_FMT))
+        ));
+        assert!(matches!(
+            SwConverter::new(64, 64, AVPixelFormat_AV_PIX_FMT_YUV420P, INVALID_PIX_FMT),
+            Err(ConversionError::UnsupportedPixelFormat(INVALID_PIX_FMT))
+        ));
+    }
+}
@@ -4,6 +4,7 @@
 
 pub mod tap;
 use base::FileReadWriteVolatile;
+pub use tap::create_tap_multiqueue;
 pub use tap::Tap;
 
 use crate::TapTCommon;
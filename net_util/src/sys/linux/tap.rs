@@ -36,6 +36,9 @@ use crate::Result;
 use crate::TapT;
 use crate::TapTCommon;
 
+/// Maximum number of queues of a tap interface, `MAX_TAP_QUEUES` in the kernel.
+const MAX_TAP_QUEUES: usize = 256;
+
 /// Handle for a network tap interface.
 ///
 /// For now, this simply wraps the file descriptor for the tap device so methods
@@ -76,22 +79,7 @@ impl Tap {
     }
 
     pub fn create_tap_with_ifreq(ifreq: &mut net_sys::ifreq) -> Result<Tap> {
-        // SAFETY:
-        // Open calls are safe because we give a constant nul-terminated
-        // string and verify the result.
-        let rd = unsafe {
-            libc::open64(
-                b"/dev/net/tun\0".as_ptr() as *const c_char,
-                libc::O_RDWR | libc::O_NONBLOCK | libc::O_CLOEXEC,
-            )
-        };
-        if rd < 0 {
-            return Err(Error::OpenTun(SysError::last()));
-        }
-
-        // SAFETY:
-        // We just checked that the fd is valid.
-        let tuntap = unsafe { File::from_raw_descriptor(rd) };
+        let tuntap = open_tun()?;
         // SAFETY:
         // ioctl is safe since we call it with a valid tap fd and check the return
         // value.
@@ -143,6 +131,56 @@ impl Tap {
     }
 }
 
+/// Opens a new descriptor to the tun/tap driver.
+fn open_tun() -> Result<File> {
+    // SAFETY:
+    // Open calls are safe because we give a constant nul-terminated
+    // string and verify the result.
+    let rd = unsafe {
+        libc::open64(
+            b"/dev/net/tun\0".as_ptr() as *const c_char,
+            libc::O_RDWR | libc::O_NONBLOCK | libc::O_CLOEXEC,
+        )
+    };
+    if rd < 0 {
+        return Err(Error::OpenTun(SysError::last()));
+    }
+
+    // SAFETY:
+    // We just checked that the fd is valid.
+    Ok(unsafe { File::from_raw_descriptor(rd) })
+}
+
+/// Creates the tap interface `name` with `num_queues` queues and returns one `Tap` per queue.
+///
+/// The interface is created with `IFF_MULTI_QUEUE`, `IFF_NO_PI` and `IFF_VNET_HDR`. `name` may
+/// contain a `%d` to let the kernel pick a free interface number. Returns `EOPNOTSUPP` if the
+/// kernel doesn't support multiqueue taps.
+pub fn create_tap_multiqueue(name: &str, num_queues: usize) -> Result<Vec<Tap>> {
+    if num_queues == 0
+        || num_queues > MAX_TAP_QUEUES
+        || name.is_empty()
+        || name.len() >= libc::IFNAMSIZ
+        || name.contains('\0')
+    {
+        return Err(Error::CreateTap(SysError::new(libc::EINVAL)));
+    }
+
+    let mut features: c_uint = 0;
+    // SAFETY:
+    // ioctl is safe since we call it with a valid tun fd and a pointer to a c_uint, which is what
+    // TUNGETFEATURES writes, and check the return value.
+    let ret = unsafe { ioctl_with_mut_ref(&open_tun()?, net_sys::TUNGETFEATURES(), &mut features) };
+    if ret < 0 {
+        return Err(Error::IoctlError(SysError::last()));
+    }
+    if features & libc::IFF_MULTI_QUEUE as c_uint == 0 {
+        return Err(Error::CreateTap(SysError::new(libc::EOPNOTSUPP)));
+    }
+
+    Tap::new_with_name(name.as_bytes(), true, true)?.into_mq_taps(num_queues as u16)
+}
+
 impl TapTCommon for Tap {
     /// Create a new tap interface.
     ///
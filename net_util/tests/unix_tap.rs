@@ -7,6 +7,8 @@
 use std::net;
 
 use base::test_utils::call_test_with_sudo;
+use base::AsRawDescriptor;
+use net_util::sys::linux::create_tap_multiqueue;
 use net_util::sys::linux::Tap;
 use net_util::sys::linux::TapTLinux;
 use net_util::MacAddress;
@@ -55,3 +57,29 @@ fn tap_enable_impl() {
 
     tap.enable().unwrap();
 }
+
+#[test]
+fn tap_multiqueue_invalid() {
+    assert!(create_tap_multiqueue("mqtap%d", 0).is_err());
+    assert!(create_tap_multiqueue("a_too_long_tap_name", 2).is_err());
+}
+
+#[test]
+fn tap_multiqueue() {
+    call_test_with_sudo("tap_multiqueue_impl")
+}
+
+#[test]
+#[ignore = "Only to be called by tap_multiqueue"]
+fn tap_multiqueue_impl() {
+    let taps = create_tap_multiqueue("mqtap%d", 2).unwrap();
+
+    assert_eq!(taps.len(), 2);
+    assert_ne!(taps[0].as_raw_descriptor(), taps[1].as_raw_descriptor());
+    for tap in &taps {
+        assert_ne!(tap.if_flags() & libc::IFF_MULTI_QUEUE as u32, 0);
+        tap.set_vnet_hdr_size(12).unwrap();
+    }
+    // Both queues belong to the same interface.
+    assert_eq!(taps[0].mtu().unwrap(), taps[1].mtu().unwrap());
+}
@@ -129,6 +129,8 @@ pub enum CrossPlatformCommands {
     BalloonStats(BalloonStatsCommand),
     #[cfg(feature = "balloon")]
     BalloonWs(BalloonWsCommand),
+    #[cfg(feature = "balloon")]
+    BalloonSelfTest(BalloonSelfTestCommand),
     // TODO(b/288432539): remove once concierge is migrated
     #[cfg(feature = "balloon")]
     BalloonWss(BalloonWsCommand),
@@ -195,6 +197,16 @@ pub struct BalloonWsCommand {
     pub socket_path: String,
 }
 
+#[derive(argh::FromArgs)]
+#[argh(subcommand, name = "balloon_self_test")]
+/// Inflates and deflates the virtio balloon of a `VM_SOCKET` by a small amount, checking that the
+/// guest follows
+pub struct BalloonSelfTestCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM control socket path.
+    pub socket_path: String,
+}
+
 #[derive(FromArgs)]
 #[argh(subcommand, name = "battery")]
 /// Modify battery
@@ -302,6 +314,9 @@ pub struct StopCommand {
     #[argh(positional, arg_name = "VM_SOCKET")]
     /// VM Socket path
     pub socket_path: String,
+    /// stop VM VCPUs, sleep Devices and disable vmm-swap before exiting
+    #[argh(switch)]
+    pub clean: bool,
 }
 
 #[derive(FromArgs)]
@@ -355,6 +370,21 @@ pub struct SwapDisableCommand {
     pub slow_file_cleanup: bool,
 }
 
+#[derive(FromArgs)]
+#[argh(subcommand, name = "policy")]
+/// Configure how aggressively vmm-swap swaps out a VM
+pub struct SwapPolicyCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+    #[argh(option, arg_name = "BYTES")]
+    /// max bytes to write to the swap file per swap out
+    pub staging_max_bytes: u64,
+    #[argh(option, arg_name = "BYTES")]
+    /// size of chunks to write to the swap file at once
+    pub batch_size: u64,
+}
+
 #[derive(FromArgs)]
 #[argh(subcommand, name = "status")]
 /// Get vmm-swap status of a VM
@@ -380,6 +410,7 @@ pub enum SwapSubcommands {
     SwapOut(SwapOutCommand),
     Disable(SwapDisableCommand),
     Status(SwapStatusCommand),
+    Policy(SwapPolicyCommand),
 }
 
 #[derive(FromArgs)]
@@ -699,6 +730,9 @@ pub struct SnapshotTakeCommand {
     #[argh(positional, arg_name = "VM_SOCKET")]
     /// VM Socket path
     pub socket_path: String,
+    #[argh(switch)]
+    /// only snapshot the devices, for debugging. The snapshot can't be restored.
+    pub devices_only: bool,
 }
 
 #[derive(FromArgs)]
@@ -910,6 +944,22 @@ pub struct RunCommand {
     #[serde(skip)] // TODO(b/255223604)
     pub async_executor: Option<ExecutorKind>,
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(option, arg_name = "BYTES")]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// balloon size while the host is under memory pressure, in bytes. Enables the balloon auto
+    /// mode together with --balloon-auto-low-watermark.
+    pub balloon_auto_high_watermark: Option<u64>,
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(option, arg_name = "BYTES")]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// balloon size once the host memory pressure subsides, in bytes. Enables the balloon auto
+    /// mode together with --balloon-auto-high-watermark.
+    pub balloon_auto_low_watermark: Option<u64>,
+
     #[argh(option, arg_name = "N")]
     #[serde(skip)] // TODO(b/255223604)
     #[merge(strategy = overwrite_option)]
@@ -922,12 +972,25 @@ pub struct RunCommand {
     /// path for balloon controller socket.
     pub balloon_control: Option<PathBuf>,
 
+    #[argh(option, arg_name = "BYTES")]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// amount of memory to give back to the guest on each of its memory pressure events, if it
+    /// negotiated deflate-on-OOM (default = 0, disabled).
+    pub balloon_oom_deflate_step: Option<u64>,
+
     #[argh(switch)]
     #[serde(skip)] // TODO(b/255223604)
     #[merge(strategy = overwrite_option)]
     /// enable page reporting in balloon.
     pub balloon_page_reporting: Option<bool>,
 
+    #[argh(option, arg_name = "PATH")]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// append each working set report from the balloon to PATH as a line of JSON.
+    pub balloon_ws_log: Option<PathBuf>,
+
     #[argh(option)]
     #[serde(skip)] // TODO(b/255223604)
     #[merge(strategy = overwrite_option)]
@@ -2925,7 +2988,9 @@ impl TryFrom<RunCommand> for super::config::Config {
         cfg.usb = !cmd.no_usb.unwrap_or_default();
         cfg.rng = !cmd.no_rng.unwrap_or_default();
         cfg.balloon = !cmd.no_balloon.unwrap_or_default();
+        cfg.balloon_oom_deflate_step = cmd.balloon_oom_deflate_step.unwrap_or_default();
         cfg.balloon_page_reporting = cmd.balloon_page_reporting.unwrap_or_default();
+        cfg.balloon_ws_log = cmd.balloon_ws_log;
         cfg.balloon_ws_num_bins = cmd.balloon_ws_num_bins.unwrap_or(4);
         cfg.balloon_ws_reporting = cmd.balloon_ws_reporting.unwrap_or_default()
         // TODO(b/288432539): remove once concierge is migrated
@@ -3250,6 +3315,22 @@ impl TryFrom<RunCommand> for super::config::Config {
 
         cfg.strict_balloon = cmd.strict_balloon.unwrap_or_default();
 
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            cfg.balloon_auto_watermarks = match (
+                cmd.balloon_auto_low_watermark,
+                cmd.balloon_auto_high_watermark,
+            ) {
+                (Some(low), Some(high)) => Some((low, high)),
+                (None, None) => None,
+                _ => {
+                    return Err("'balloon-auto-low-watermark' and \
+                            'balloon-auto-high-watermark' must be set together"
+                        .to_string())
+                }
+            };
+        }
+
         #[cfg(target_os = "android")]
         {
             cfg.task_profiles = cmd.task_profiles;
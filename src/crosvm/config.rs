@@ -618,9 +618,13 @@ pub struct Config {
     pub android_fstab: Option<PathBuf>,
     pub async_executor: Option<ExecutorKind>,
     pub balloon: bool,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub balloon_auto_watermarks: Option<(u64, u64)>,
     pub balloon_bias: i64,
     pub balloon_control: Option<PathBuf>,
+    pub balloon_oom_deflate_step: u64,
     pub balloon_page_reporting: bool,
+    pub balloon_ws_log: Option<PathBuf>,
     pub balloon_ws_num_bins: u8,
     pub balloon_ws_reporting: bool,
     pub battery_config: Option<BatteryConfig>,
@@ -822,9 +826,13 @@ impl Default for Config {
             android_fstab: None,
             async_executor: None,
             balloon: true,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            balloon_auto_watermarks: None,
             balloon_bias: 0,
             balloon_control: None,
+            balloon_oom_deflate_step: 0,
             balloon_page_reporting: false,
+            balloon_ws_log: None,
             balloon_ws_num_bins: VIRTIO_BALLOON_WS_DEFAULT_NUM_BINS,
             balloon_ws_reporting: false,
             battery_config: None,
@@ -1170,6 +1178,25 @@ pub fn validate_config(cfg: &mut Config) -> std::result::Result<(), String> {
         return Err("'balloon_page_reporting' requires enabled balloon".to_string());
     }
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    if let Some((low, high)) = cfg.balloon_auto_watermarks {
+        if !cfg.balloon {
+            return Err("balloon auto mode requires enabled balloon".to_string());
+        }
+        if cfg.strict_balloon {
+            return Err(
+                "balloon auto mode and 'strict-balloon' are mutually exclusive".to_string(),
+            );
+        }
+        if low > high {
+            return Err(format!(
+                "'balloon-auto-low-watermark' ({}) must not exceed \
+                 'balloon-auto-high-watermark' ({})",
+                low, high
+            ));
+        }
+    }
+
     #[cfg(any(target_os = "android", target_os = "linux"))]
     if cfg.lock_guest_memory && cfg.jail_config.is_none() {
         return Err("'lock-guest-memory' and 'disable-sandbox' are mutually exclusive".to_string());
@@ -1526,6 +1553,43 @@ mod tests {
         from_key_values::<BatteryConfig>("type=xxx").expect_err("parse should have failed");
     }
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn parse_balloon_auto_watermarks() {
+        let cfg = TryInto::<Config>::try_into(
+            crate::crosvm::cmdline::RunCommand::from_args(
+                &[],
+                &[
+                    "--balloon-auto-low-watermark",
+                    "1048576",
+                    "--balloon-auto-high-watermark",
+                    "4194304",
+                    "/dev/null",
+                ],
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(cfg.balloon_auto_watermarks, Some((1 << 20, 4 << 20)));
+
+        // Both watermarks are required, in order.
+        for args in [
+            &["--balloon-auto-low-watermark", "1048576", "/dev/null"][..],
+            &[
+                "--balloon-auto-low-watermark",
+                "4194304",
+                "--balloon-auto-high-watermark",
+                "1048576",
+                "/dev/null",
+            ],
+        ] {
+            assert!(TryInto::<Config>::try_into(
+                crate::crosvm::cmdline::RunCommand::from_args(&[], args).unwrap()
+            )
+            .is_err());
+        }
+    }
+
     #[test]
     fn parse_irqchip_kernel() {
         let cfg = TryInto::<Config>::try_into(
@@ -175,6 +175,8 @@ use swap::SwapController;
 use sync::Condvar;
 use sync::Mutex;
 use vm_control::api::VmMemoryClient;
+use vm_control::sys::bind_control_socket;
+use vm_control::sys::drain_control_socket;
 use vm_control::*;
 use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
@@ -468,7 +470,12 @@ fn create_virtio_devices(
         devs.push(create_balloon_device(
             cfg.protection_type,
             &cfg.jail_config,
-            if cfg.strict_balloon {
+            if let Some((low_watermark, high_watermark)) = cfg.balloon_auto_watermarks {
+                BalloonMode::Auto {
+                    low_watermark,
+                    high_watermark,
+                }
+            } else if cfg.strict_balloon {
                 BalloonMode::Strict
             } else {
                 BalloonMode::Relaxed
@@ -484,6 +491,8 @@ fn create_virtio_devices(
                     .context("failed to clone registered_evt_q tube")?,
             ),
             cfg.balloon_ws_num_bins,
+            cfg.balloon_ws_log.as_deref(),
+            cfg.balloon_oom_deflate_step,
         )?);
     }
 
@@ -2568,6 +2577,30 @@ pub fn trigger_vm_suspend_and_wait_for_entry(
     }
 }
 
+/// The run mode the vcpus were last put in, reported by `VmRequest::GetRunMode`.
+///
+/// The run loop changes the run mode of the vcpus through `set`, so that the reported mode follows
+/// the suspends initiated by the guest as well as the ones requested over the control sockets.
+struct VcpusRunMode {
+    mode: VmRunMode,
+}
+
+impl VcpusRunMode {
+    fn new(mode: VmRunMode) -> Self {
+        VcpusRunMode { mode }
+    }
+
+    fn get(&self) -> VmRunMode {
+        self.mode
+    }
+
+    /// Puts the vcpus in `mode` by sending the message to `kick_all_vcpus`.
+    fn set(&mut self, mode: VmRunMode, kick_all_vcpus: impl FnOnce(VcpuControl)) {
+        self.mode = mode;
+        kick_all_vcpus(VcpuControl::RunState(mode));
+    }
+}
+
 #[cfg(target_arch = "x86_64")]
 fn handle_hotplug_command<V: VmArch, Vcpu: VcpuArch>(
     linux: &mut RunnableLinuxVm<V, Vcpu>,
@@ -2620,7 +2653,7 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
     mut linux: RunnableLinuxVm<V, Vcpu>,
     sys_allocator: SystemAllocator,
     cfg: Config,
-    control_server_socket: Option<UnlinkUnixSeqpacketListener>,
+    mut control_server_socket: Option<UnlinkUnixSeqpacketListener>,
     irq_control_tubes: Vec<Tube>,
     vm_memory_control_tubes: Vec<VmMemoryTube>,
     control_tubes: Vec<TaggedControlTube>,
@@ -2658,9 +2691,22 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
         BalloonTube,
     }
 
+    // Maximum number of events waiting to be sent to a registered listener. Older events are
+    // dropped when the listener doesn't read them fast enough.
+    #[cfg(feature = "registered_events")]
+    const REGISTERED_EVENT_QUEUE_CAPACITY: usize = 32;
+
+    // How long sending an event can block on a listener that doesn't read its socket. The listener
+    // is then considered gone, which stops its sender thread.
+    #[cfg(feature = "registered_events")]
+    const REGISTERED_EVENT_SEND_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+    #[cfg(feature = "registered_events")]
+    type RegisteredEventQueue = BoundedEventQueue<protos::registered_events::RegisteredEvent>;
+
     #[cfg(feature = "registered_events")]
     struct AddressedProtoTube {
-        tube: Rc<ProtoTube>,
+        queue: Rc<RegisteredEventQueue>,
         socket_addr: String,
     }
 
@@ -2683,8 +2729,12 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
 
     #[cfg(feature = "registered_events")]
     impl AddressedProtoTube {
-        pub fn send<M: protobuf::Message>(&self, msg: &M) -> Result<(), base::TubeError> {
-            self.tube.send_proto(msg)
+        /// Queues `event` to be sent to the listener without waiting for it to be read.
+        pub fn send(
+            &self,
+            event: protos::registered_events::RegisteredEvent,
+        ) -> Result<(), EventDeliveryFailed> {
+            self.queue.push(event)
         }
     }
 
@@ -2693,8 +2743,8 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
         registered_tubes: &'a HashMap<RegisteredEvent, HashSet<AddressedProtoTube>>,
         socket_addr: &str,
         event: RegisteredEvent,
-    ) -> (Option<&'a Rc<ProtoTube>>, bool) {
-        let mut registered_tube: Option<&Rc<ProtoTube>> = None;
+    ) -> (Option<&'a Rc<RegisteredEventQueue>>, bool) {
+        let mut registered_tube: Option<&Rc<RegisteredEventQueue>> = None;
         let mut already_registered = false;
         'outer: for (evt, addr_tubes) in registered_tubes {
             for addr_tube in addr_tubes {
@@ -2708,7 +2758,7 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                     // matter which one we get. But we do need
                     // to check for a registration for the
                     // current event, so can't break here.
-                    registered_tube = Some(&addr_tube.tube);
+                    registered_tube = Some(&addr_tube.queue);
                 }
             }
         }
@@ -2717,21 +2767,29 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
 
     #[cfg(feature = "registered_events")]
     fn make_addr_tube_from_maybe_existing(
-        tube: Option<&Rc<ProtoTube>>,
+        tube: Option<&Rc<RegisteredEventQueue>>,
         addr: String,
     ) -> Result<AddressedProtoTube> {
         if let Some(registered_tube) = tube {
             Ok(AddressedProtoTube {
-                tube: registered_tube.clone(),
+                queue: registered_tube.clone(),
                 socket_addr: addr,
             })
         } else {
             let sock = UnixSeqpacket::connect(addr.clone()).with_context(|| {
                 format!("failed to connect to registered listening socket {}", addr)
             })?;
+            sock.set_write_timeout(Some(REGISTERED_EVENT_SEND_TIMEOUT))
+                .context("failed to set send timeout of registered listening socket")?;
             let tube = ProtoTube::new_from_unix_seqpacket(sock)?;
+            let queue = BoundedEventQueue::new(
+                "registered_evt".to_string(),
+                REGISTERED_EVENT_QUEUE_CAPACITY,
+                move |event: protos::registered_events::RegisteredEvent| tube.send_proto(&event),
+            )
+            .context("failed to spawn registered event sender thread")?;
             Ok(AddressedProtoTube {
-                tube: Rc::new(tube),
+                queue: Rc::new(queue),
                 socket_addr: addr,
             })
         }
@@ -2995,6 +3053,7 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
     };
 
     let (irq_handler_control, irq_handler_control_for_thread) = Tube::pair()?;
+    let irq_flush_strategy = IrqFlushStrategy::from_caps(&hypervisor::hypervisor_caps());
     let sys_allocator_for_thread = sys_allocator_mutex.clone();
     let irq_chip_for_thread = linux.irq_chip.try_box_clone()?;
     let irq_handler_thread = std::thread::Builder::new()
@@ -3059,6 +3118,7 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
             VcpuControl::RunState(post_restore_run_mode),
         )
     }
+    let mut vcpus_run_mode = VcpusRunMode::new(post_restore_run_mode);
 
     #[cfg(feature = "swap")]
     if let Some(swap_controller) = &swap_controller {
@@ -3094,7 +3154,7 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                         let mut tubes_to_remove: Vec<String> = Vec::new();
                         if let Some(tubes) = registered_evt_tubes.get_mut(&evt) {
                             for tube in tubes.iter() {
-                                if let Err(e) = tube.send(&reg_evt.into_proto()) {
+                                if let Err(e) = tube.send(reg_evt.into_proto()) {
                                     warn!(
                                         "failed to send registered event {:?} to {}, removing from \
                                          registrations: {}",
@@ -3155,11 +3215,9 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                 Token::Suspend => {
                     info!("VM requested suspend");
                     linux.suspend_evt.wait().unwrap();
-                    vcpu::kick_all_vcpus(
-                        &vcpu_handles,
-                        linux.irq_chip.as_irq_chip(),
-                        VcpuControl::RunState(VmRunMode::Suspending),
-                    );
+                    vcpus_run_mode.set(VmRunMode::Suspending, |msg| {
+                        vcpu::kick_all_vcpus(&vcpu_handles, linux.irq_chip.as_irq_chip(), msg)
+                    });
                 }
                 Token::ChildSignal => {
                     // Print all available siginfo structs, then exit the loop if child process has
@@ -3234,9 +3292,12 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                     let mut add_irq_control_tubes = Vec::new();
                     #[cfg(any(target_arch = "x86_64", feature = "pci-hotplug"))]
                     let mut add_vm_memory_control_tubes = Vec::new();
+                    let mut retired_control_sockets = Vec::new();
                     if let Some(socket) = control_tubes.get(&id) {
                         match socket {
-                            TaggedControlTube::Vm(tube) => match tube.recv::<VmRequest>() {
+                            TaggedControlTube::Vm(tube) => match tube
+                                .recv_with_exact_descriptors::<VmRequest>()
+                            {
                                 Ok(request) => {
                                     let mut suspend_requested = false;
                                     let mut run_mode_opt = None;
@@ -3267,7 +3328,7 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                                             {
                                                 // Suppress warnings.
                                                 let _ = (device, add);
-                                                VmResponse::Ok
+                                                vfio_hotplug_not_implemented(next_request_id())
                                             }
                                         }
                                         #[cfg(feature = "pci-hotplug")]
@@ -3288,6 +3349,12 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                                                 )
                                             }
                                         }
+                                        #[cfg(feature = "pci-hotplug")]
+                                        VmRequest::ListHotplugSlots => handle_list_hotplug_slots(
+                                            hotplug_manager
+                                                .as_ref()
+                                                .map(|m| m as &dyn HotplugSlotProvider),
+                                        ),
                                         #[cfg(feature = "registered_events")]
                                         VmRequest::RegisterListener { socket_addr, event } => {
                                             let (registered_tube, already_registered) =
@@ -3336,31 +3403,80 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                                                 .retain(|_, tubes| !tubes.is_empty());
                                             VmResponse::Ok
                                         }
+                                        #[cfg(feature = "registered_events")]
+                                        VmRequest::RegisteredEventsStatus => {
+                                            let dropped_events = registered_evt_tubes
+                                                .values()
+                                                .flatten()
+                                                .map(|t| {
+                                                    (t.socket_addr.clone(), t.queue.dropped_count())
+                                                })
+                                                .collect();
+                                            VmResponse::RegisteredEventsStatus { dropped_events }
+                                        }
+                                        // Without a balloon device, balloon requests fall
+                                        // through to `execute`, which fails them with ENOTSUP.
                                         #[cfg(feature = "balloon")]
-                                        VmRequest::BalloonCommand(cmd) => {
-                                            if let Some(tube) = balloon_tube.as_mut() {
-                                                let Some((r, key)) = tube.send_cmd(cmd, Some(id))
+                                        VmRequest::BalloonCommand(_) | VmRequest::BalloonStats
+                                            if balloon_tube.is_some() =>
+                                        {
+                                            let tube = balloon_tube.as_mut().unwrap();
+                                            let cmd = request
+                                                .balloon_command()
+                                                .expect("not a balloon request");
+                                            let Some((r, key)) = tube.send_cmd(cmd, Some(id))
+                                            else {
+                                                continue;
+                                            };
+                                            if key != id {
+                                                let Some(TaggedControlTube::Vm(tube)) =
+                                                    control_tubes.get(&key)
                                                 else {
                                                     continue;
                                                 };
-                                                if key != id {
-                                                    let Some(TaggedControlTube::Vm(tube)) =
-                                                        control_tubes.get(&key)
-                                                    else {
-                                                        continue;
-                                                    };
-                                                    if let Err(e) = tube.send(&r) {
-                                                        error!("failed to send VmResponse: {}", e);
+                                                if let Err(e) = tube.send(&r) {
+                                                    error!("failed to send VmResponse: {}", e);
+                                                }
+                                                continue;
+                                            }
+                                            r
+                                        }
+                                        VmRequest::GetRunMode => {
+                                            VmResponse::RunMode(vcpus_run_mode.get())
+                                        }
+                                        VmRequest::RebindControlSocket { new_path } => {
+                                            match bind_control_socket(&new_path) {
+                                                Ok(new_socket) => {
+                                                    wait_ctx
+                                                        .add(&new_socket, Token::VmControlServer)
+                                                        .context(
+                                                            "failed to add descriptor to wait context",
+                                                        )?;
+                                                    if let Some(old_socket) =
+                                                        control_server_socket.replace(new_socket)
+                                                    {
+                                                        wait_ctx.delete(&old_socket).context(
+                                                            "failed to remove descriptor from wait context",
+                                                        )?;
+                                                        // Dropping the old listener unlinks its path.
+                                                        retired_control_sockets =
+                                                            drain_control_socket(&old_socket);
                                                     }
-                                                    continue;
+                                                    VmResponse::Ok
+                                                }
+                                                Err(e) => {
+                                                    error!(
+                                                        "failed to rebind control socket to {}: {}",
+                                                        new_path.display(),
+                                                        e
+                                                    );
+                                                    VmResponse::Err(e)
                                                 }
-                                                r
-                                            } else {
-                                                VmResponse::Err(base::Error::new(libc::ENOTSUP))
                                             }
                                         }
                                         _ => {
                                             let response = request.execute(
+                                                next_request_id(),
                                                 &mut run_mode_opt,
                                                 disk_host_tubes,
                                                 &mut linux.pm,
@@ -3386,11 +3502,15 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                                                     )
                                                 },
                                                 cfg.force_s2idle,
+                                                // Don't strand a suspended guest if it can't
+                                                // be woken through the power button.
+                                                false,
                                                 #[cfg(feature = "swap")]
                                                 swap_controller.as_ref(),
                                                 &device_ctrl_tube,
                                                 vcpu_handles.len(),
                                                 &irq_handler_control,
+                                                irq_flush_strategy,
                                                 || linux.irq_chip.snapshot(linux.vcpu_count),
                                                 |image| {
                                                     linux
@@ -3460,11 +3580,13 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                                                 // will be performed by s2idle_wait thread when
                                                 // needed.
                                                 if !suspend_requested {
-                                                    vcpu::kick_all_vcpus(
-                                                        &vcpu_handles,
-                                                        linux.irq_chip.as_irq_chip(),
-                                                        VcpuControl::RunState(other),
-                                                    );
+                                                    vcpus_run_mode.set(other, |msg| {
+                                                        vcpu::kick_all_vcpus(
+                                                            &vcpu_handles,
+                                                            linux.irq_chip.as_irq_chip(),
+                                                            msg,
+                                                        )
+                                                    });
                                                 }
                                             }
                                         }
@@ -3513,6 +3635,17 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                             },
                         }
                     }
+                    for socket in retired_control_sockets {
+                        let id = next_control_id;
+                        next_control_id += 1;
+                        wait_ctx
+                            .add(&socket, Token::VmControl { id })
+                            .context("failed to add descriptor to wait context")?;
+                        control_tubes.insert(
+                            id,
+                            TaggedControlTube::Vm(Tube::new_from_unix_seqpacket(socket)?),
+                        );
+                    }
                     #[cfg(target_arch = "x86_64")]
                     for socket in add_tubes {
                         let id = next_control_id;
@@ -4197,10 +4330,7 @@ fn process_vhost_user_control_request(tube: Tube, disk_host_tubes: &[Tube]) -> R
         VmRequest::DiskCommand {
             disk_index,
             ref command,
-        } => match &disk_host_tubes.get(disk_index) {
-            Some(tube) => handle_disk_command(command, tube),
-            None => VmResponse::Err(base::Error::new(libc::ENODEV)),
-        },
+        } => handle_disk_request(next_request_id(), disk_index, command, disk_host_tubes),
         request => {
             error!(
                 "Request {:?} currently not supported in vhost user backend",
@@ -4569,4 +4699,26 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn vcpus_run_mode_follows_guest_suspend() {
+        let mut vcpus_run_mode = VcpusRunMode::new(VmRunMode::Running);
+        let mut sent = Vec::new();
+
+        // What the run loop does on `Token::Suspend`, when the guest suspends itself.
+        vcpus_run_mode.set(VmRunMode::Suspending, |msg| sent.push(msg));
+        assert_eq!(vcpus_run_mode.get(), VmRunMode::Suspending);
+
+        // What it does on a `VmRequest::ResumeVcpus`.
+        vcpus_run_mode.set(VmRunMode::Running, |msg| sent.push(msg));
+        assert_eq!(vcpus_run_mode.get(), VmRunMode::Running);
+
+        assert!(matches!(
+            sent[..],
+            [
+                VcpuControl::RunState(VmRunMode::Suspending),
+                VcpuControl::RunState(VmRunMode::Running),
+            ]
+        ));
+    }
 }
@@ -648,7 +648,18 @@ pub fn create_balloon_device(
     enabled_features: u64,
     #[cfg(feature = "registered_events")] registered_evt_q: Option<SendTube>,
     ws_num_bins: u8,
+    ws_log_path: Option<&Path>,
+    oom_deflate_step: u64,
 ) -> DeviceResult {
+    let ws_log = ws_log_path
+        .map(|path| {
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .with_context(|| format!("failed to open balloon WS log {}", path.display()))
+        })
+        .transpose()?;
     let dev = virtio::Balloon::new(
         virtio::base_features(protection_type),
         tube,
@@ -659,6 +670,8 @@ pub fn create_balloon_device(
         #[cfg(feature = "registered_events")]
         registered_evt_q,
         ws_num_bins,
+        ws_log,
+        oom_deflate_step,
     )
     .context("failed to create balloon")?;
 
@@ -29,6 +29,9 @@ use resources::SystemAllocator;
 #[cfg(feature = "swap")]
 use swap::SwapDeviceHelper;
 use sync::Mutex;
+use vm_control::HotPlugDeviceType;
+use vm_control::HotplugSlot;
+use vm_control::HotplugSlotProvider;
 use vm_memory::GuestMemory;
 
 use crate::crosvm::sys::linux::JailWarden;
@@ -287,3 +290,22 @@ impl PciHotPlugManager {
         }
     }
 }
+
+impl HotplugSlotProvider for PciHotPlugManager {
+    fn hotplug_slots(&self) -> Vec<HotplugSlot> {
+        let available = self.available_ports.values().map(|port_stub| HotplugSlot {
+            bus: port_stub.downstream_bus,
+            occupied: false,
+            device_type: None,
+        });
+        // Only endpoint devices are hotplugged by the manager.
+        let occupied = self.occupied_ports.keys().map(|&bus| HotplugSlot {
+            bus,
+            occupied: true,
+            device_type: Some(HotPlugDeviceType::EndPoint),
+        });
+        let mut slots: Vec<_> = available.chain(occupied).collect();
+        slots.sort_by_key(|slot| slot.bus);
+        slots
+    }
+}
@@ -40,6 +40,7 @@ use riscv64::Riscv64 as Arch;
 #[cfg(target_arch = "x86_64")]
 use sync::Mutex;
 use vm_control::*;
+use vm_memory::GuestAddress;
 #[cfg(feature = "gdb")]
 use vm_memory::GuestMemory;
 #[cfg(target_arch = "x86_64")]
@@ -213,6 +214,27 @@ pub fn remove_vcpu_signal_handler() -> Result<()> {
     clear_signal_handler(SIGRTMIN() + 0).context("error unregistering signal handler")
 }
 
+/// Enables or disables single-stepping of `vcpu`, keeping the hardware `breakpoints` set by the
+/// debugger.
+#[cfg(any(target_arch = "arm", target_arch = "aarch64", target_arch = "x86_64"))]
+fn set_single_step<V: VcpuArch>(
+    vcpu: &V,
+    breakpoints: &[GuestAddress],
+    enable: bool,
+) -> Result<()> {
+    vcpu.set_guest_debug(breakpoints, enable)
+        .context("failed to set guest debug state")
+}
+
+#[cfg(target_arch = "riscv64")]
+fn set_single_step<V: VcpuArch>(
+    _vcpu: &V,
+    _breakpoints: &[GuestAddress],
+    _enable: bool,
+) -> Result<()> {
+    anyhow::bail!("single-step is not supported on this architecture")
+}
+
 fn vcpu_loop<V>(
     mut run_mode: VmRunMode,
     cpu_id: usize,
@@ -231,6 +253,14 @@ where
     V: VcpuArch,
 {
     let mut interrupted_by_signal = false;
+    let mut single_step_waiters = SingleStepWaiters::default();
+    // Hardware breakpoints last set by the debugger, which single-stepping must not clear.
+    #[cfg(feature = "gdb")]
+    let mut hw_breakpoints: Vec<GuestAddress> = Vec::new();
+    #[cfg(not(feature = "gdb"))]
+    let hw_breakpoints: Vec<GuestAddress> = Vec::new();
+    // Restores the scheduling policy of the thread when `VcpuControl::SetRealtime` reverts it.
+    let mut realtime_guard: Option<RealtimeGuard> = None;
 
     loop {
         // Start by checking for messages to process and the run state of the CPU.
@@ -241,9 +271,11 @@ where
                 // Tries to get a pending message without blocking first.
                 let msg = match from_main_tube.try_recv() {
                     Ok(m) => m,
-                    Err(mpsc::TryRecvError::Empty) if run_mode == VmRunMode::Running => {
-                        // If the VM is running and no message is pending, the state won't
-                        // change.
+                    Err(mpsc::TryRecvError::Empty)
+                        if run_mode == VmRunMode::Running || run_mode == VmRunMode::SingleStep =>
+                    {
+                        // If the VM is running or about to execute a single step and no
+                        // message is pending, the state won't change.
                         break 'state_loop;
                     }
                     Err(mpsc::TryRecvError::Empty) => {
@@ -269,7 +301,28 @@ where
                 for msg in messages {
                     match msg {
                         VcpuControl::RunState(new_mode) => {
+                            let was_single_step = run_mode == VmRunMode::SingleStep;
                             run_mode = new_mode;
+                            if run_mode == VmRunMode::SingleStep {
+                                if let Err(e) = set_single_step(&vcpu, &hw_breakpoints, true) {
+                                    error!(
+                                        "failed to enable single-step on vcpu {}: {:#}",
+                                        cpu_id, e
+                                    );
+                                    run_mode = VmRunMode::Suspending;
+                                }
+                            } else if was_single_step {
+                                // The step was cancelled before it completed.
+                                if let Err(e) = set_single_step(&vcpu, &hw_breakpoints, false) {
+                                    error!(
+                                        "failed to disable single-step on vcpu {}: {:#}",
+                                        cpu_id, e
+                                    );
+                                }
+                            }
+                            if run_mode != VmRunMode::SingleStep {
+                                single_step_waiters.reply(run_mode);
+                            }
                             match run_mode {
                                 VmRunMode::Running => {}
                                 VmRunMode::Suspending => {
@@ -280,12 +333,15 @@ where
                                         );
                                     }
                                 }
-                                VmRunMode::Breakpoint => {}
+                                VmRunMode::Breakpoint | VmRunMode::SingleStep => {}
                                 VmRunMode::Exiting => return ExitState::Stop,
                             }
                         }
                         #[cfg(feature = "gdb")]
                         VcpuControl::Debug(d) => {
+                            if let VcpuDebug::SetHwBreakPoint(addrs) = &d {
+                                hw_breakpoints = addrs.clone();
+                            }
                             if let Err(e) = crate::crosvm::gdb::vcpu_control_debug(
                                 cpu_id,
                                 &vcpu,
@@ -309,14 +365,41 @@ where
                                 }
                             }
                         }
+                        VcpuControl::SetRealtime {
+                            enable,
+                            priority,
+                            response_chan,
+                        } => {
+                            // Restore the original policy before changing the priority, so that
+                            // reverting always goes back to the policy from before the first
+                            // request.
+                            drop(realtime_guard.take());
+                            let mut resp = Ok(());
+                            if enable {
+                                info!("Making vcpu {} RT with priority {}", cpu_id, priority);
+                                match RealtimeGuard::enable(priority) {
+                                    Ok(guard) => realtime_guard = Some(guard),
+                                    Err(e) => {
+                                        warn!("Failed to set vcpu to real time: {}", e);
+                                        resp = Err(e);
+                                    }
+                                }
+                            }
+                            if let Err(e) = response_chan.send(resp) {
+                                error!("Failed to send real time response: {}", e);
+                            }
+                        }
+                        // Exiting the run loop to handle the message is all a kick is for.
+                        VcpuControl::Kick => {}
                         VcpuControl::GetStates(response_chan) => {
-                            if let Err(e) = response_chan.send(run_mode) {
+                            if let Err(e) = single_step_waiters.get_state(run_mode, response_chan) {
                                 error!("Failed to send GetState: {}", e);
                             };
                         }
                         VcpuControl::Snapshot(response_chan) => {
                             let resp = vcpu
                                 .snapshot()
+                                .and_then(|snapshot| SerializedVcpuSnapshot::new(&snapshot))
                                 .with_context(|| format!("Failed to snapshot Vcpu #{}", vcpu.id()));
                             if let Err(e) = response_chan.send(resp) {
                                 error!("Failed to send snapshot response: {}", e);
@@ -334,9 +417,17 @@ where
                                 error!("Failed to send restore response: {}", e);
                             }
                         }
+                        VcpuControl::GetRegisters(response_chan) => {
+                            let resp = vcpu.get_registers().with_context(|| {
+                                format!("Failed to get registers of Vcpu #{}", vcpu.id())
+                            });
+                            if let Err(e) = response_chan.send(resp) {
+                                error!("Failed to send registers response: {}", e);
+                            }
+                        }
                     }
                 }
-                if run_mode == VmRunMode::Running {
+                if run_mode == VmRunMode::Running || run_mode == VmRunMode::SingleStep {
                     break 'state_loop;
                 }
             }
@@ -400,6 +491,18 @@ where
                     info!("system crash event on vcpu {}", cpu_id);
                     return ExitState::Stop;
                 }
+                Ok(VcpuExit::Debug) if run_mode == VmRunMode::SingleStep => {
+                    if let Err(e) = set_single_step(&vcpu, &hw_breakpoints, false) {
+                        error!("failed to disable single-step on vcpu {}: {:#}", cpu_id, e);
+                    }
+                    run_mode = single_step_waiters.step_complete();
+                    if let Err(e) = vcpu.on_suspend() {
+                        error!(
+                            "failed to tell hypervisor vcpu {} is suspending: {}",
+                            cpu_id, e
+                        );
+                    }
+                }
                 Ok(VcpuExit::Debug) => {
                     #[cfg(feature = "gdb")]
                     if let Err(e) =
@@ -611,3 +714,256 @@ pub fn kick_vcpu(
     }
     irq_chip.kick_halted_vcpus();
 }
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use base::Result;
+    use base::Tube;
+    use devices::BusType;
+    use devices::UserspaceIrqChip;
+    use hypervisor::CpuId;
+    use hypervisor::CpuIdEntry;
+    use hypervisor::DebugRegs;
+    use hypervisor::Fpu;
+    use hypervisor::HypervHypercall;
+    use hypervisor::Register;
+    use hypervisor::Regs;
+    use hypervisor::Sregs;
+    use hypervisor::Vcpu;
+    use hypervisor::VcpuX86_64;
+    use hypervisor::Xsave;
+
+    use super::*;
+
+    /// Vcpu that stops on a debug exit after each instruction, and records its guest debug state.
+    struct FakeVcpu {
+        runs: Arc<Mutex<usize>>,
+        guest_debug: Arc<Mutex<Vec<(Vec<GuestAddress>, bool)>>>,
+    }
+
+    impl Vcpu for FakeVcpu {
+        fn try_clone(&self) -> Result<Self> {
+            Ok(FakeVcpu {
+                runs: self.runs.clone(),
+                guest_debug: self.guest_debug.clone(),
+            })
+        }
+        fn as_vcpu(&self) -> &dyn Vcpu {
+            self
+        }
+        fn run(&mut self) -> Result<VcpuExit> {
+            *self.runs.lock() += 1;
+            Ok(VcpuExit::Debug)
+        }
+        fn id(&self) -> usize {
+            0
+        }
+        fn set_immediate_exit(&self, _exit: bool) {}
+        fn signal_handle(&self) -> VcpuSignalHandle {
+            unimplemented!()
+        }
+        fn handle_mmio(
+            &self,
+            _handle_fn: &mut dyn FnMut(IoParams) -> Option<[u8; 8]>,
+        ) -> Result<()> {
+            unimplemented!()
+        }
+        fn handle_io(&self, _handle_fn: &mut dyn FnMut(IoParams) -> Option<[u8; 8]>) -> Result<()> {
+            unimplemented!()
+        }
+        fn handle_hyperv_hypercall(
+            &self,
+            _func: &mut dyn FnMut(HypervHypercall) -> u64,
+        ) -> Result<()> {
+            unimplemented!()
+        }
+        fn handle_rdmsr(&self, _data: u64) -> Result<()> {
+            unimplemented!()
+        }
+        fn handle_wrmsr(&self) {
+            unimplemented!()
+        }
+        fn on_suspend(&self) -> Result<()> {
+            Ok(())
+        }
+        unsafe fn enable_raw_capability(&self, _cap: u32, _args: &[u64; 4]) -> Result<()> {
+            unimplemented!()
+        }
+    }
+
+    impl VcpuX86_64 for FakeVcpu {
+        fn set_interrupt_window_requested(&self, _requested: bool) {}
+        fn ready_for_interrupt(&self) -> bool {
+            false
+        }
+        fn interrupt(&self, _irq: u32) -> Result<()> {
+            unimplemented!()
+        }
+        fn inject_nmi(&self) -> Result<()> {
+            unimplemented!()
+        }
+        fn get_regs(&self) -> Result<Regs> {
+            unimplemented!()
+        }
+        fn set_regs(&self, _regs: &Regs) -> Result<()> {
+            unimplemented!()
+        }
+        fn get_sregs(&self) -> Result<Sregs> {
+            unimplemented!()
+        }
+        fn set_sregs(&self, _sregs: &Sregs) -> Result<()> {
+            unimplemented!()
+        }
+        fn get_fpu(&self) -> Result<Fpu> {
+            unimplemented!()
+        }
+        fn set_fpu(&self, _fpu: &Fpu) -> Result<()> {
+            unimplemented!()
+        }
+        fn get_debugregs(&self) -> Result<DebugRegs> {
+            unimplemented!()
+        }
+        fn set_debugregs(&self, _debugregs: &DebugRegs) -> Result<()> {
+            unimplemented!()
+        }
+        fn get_xcrs(&self) -> Result<Vec<Register>> {
+            unimplemented!()
+        }
+        fn set_xcrs(&self, _xcrs: &[Register]) -> Result<()> {
+            unimplemented!()
+        }
+        fn get_xsave(&self) -> Result<Xsave> {
+            unimplemented!()
+        }
+        fn set_xsave(&self, _xsave: &Xsave) -> Result<()> {
+            unimplemented!()
+        }
+        fn get_interrupt_state(&self) -> Result<serde_json::Value> {
+            unimplemented!()
+        }
+        fn set_interrupt_state(&self, _data: serde_json::Value) -> Result<()> {
+            unimplemented!()
+        }
+        fn get_msrs(&self, _msrs: &mut Vec<Register>) -> Result<()> {
+            unimplemented!()
+        }
+        fn get_all_msrs(&self) -> Result<Vec<Register>> {
+            unimplemented!()
+        }
+        fn set_msrs(&self, _msrs: &[Register]) -> Result<()> {
+            unimplemented!()
+        }
+        fn set_cpuid(&self, _cpuid: &CpuId) -> Result<()> {
+            unimplemented!()
+        }
+        fn get_hyperv_cpuid(&self) -> Result<CpuId> {
+            unimplemented!()
+        }
+        fn set_guest_debug(&self, addrs: &[GuestAddress], enable_singlestep: bool) -> Result<()> {
+            self.guest_debug
+                .lock()
+                .push((addrs.to_vec(), enable_singlestep));
+            Ok(())
+        }
+        fn handle_cpuid(&mut self, _entry: &CpuIdEntry) -> Result<()> {
+            unimplemented!()
+        }
+        fn get_tsc_offset(&self) -> Result<u64> {
+            unimplemented!()
+        }
+        fn set_tsc_offset(&self, _offset: u64) -> Result<()> {
+            unimplemented!()
+        }
+        fn set_tsc_value(&self, _value: u64) -> Result<()> {
+            unimplemented!()
+        }
+        fn restore_timekeeping(
+            &self,
+            _host_tsc_reference_moment: u64,
+            _tsc_offset: u64,
+        ) -> Result<()> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn vcpu_single_step() {
+        let vcpu = FakeVcpu {
+            runs: Arc::new(Mutex::new(0)),
+            guest_debug: Arc::new(Mutex::new(Vec::new())),
+        };
+        let runs = vcpu.runs.clone();
+        let guest_debug = vcpu.guest_debug.clone();
+        let (_, irq_tube) = Tube::pair().unwrap();
+        let mut irq_chip = UserspaceIrqChip::<FakeVcpu>::new(1, irq_tube, None).unwrap();
+        irq_chip.add_vcpu(0, &vcpu).unwrap();
+        let (to_vcpu, from_main_tube) = mpsc::channel();
+        #[cfg(feature = "gdb")]
+        let (to_gdb_tube, from_vcpu_debug) = mpsc::channel();
+
+        let vcpu_thread = thread::spawn(move || {
+            vcpu_loop(
+                VmRunMode::Suspending,
+                0,
+                vcpu,
+                Box::new(irq_chip),
+                false,
+                false,
+                Bus::new(BusType::Io),
+                Bus::new(BusType::Mmio),
+                from_main_tube,
+                #[cfg(feature = "gdb")]
+                Some(to_gdb_tube),
+                #[cfg(feature = "gdb")]
+                GuestMemory::new(&[(GuestAddress(0), 0x1000)]).unwrap(),
+                Arc::new(Mutex::new(Ratelimit::new())),
+            )
+        });
+
+        #[cfg(feature = "gdb")]
+        let breakpoints = {
+            let breakpoints = vec![GuestAddress(0x1000)];
+            to_vcpu
+                .send(VcpuControl::Debug(VcpuDebug::SetHwBreakPoint(
+                    breakpoints.clone(),
+                )))
+                .unwrap();
+            from_vcpu_debug.recv().unwrap();
+            breakpoints
+        };
+        #[cfg(not(feature = "gdb"))]
+        let breakpoints: Vec<GuestAddress> = Vec::new();
+
+        // The state is only reported once the step completed.
+        let (state_send, state_recv) = mpsc::channel();
+        to_vcpu
+            .send(VcpuControl::RunState(VmRunMode::SingleStep))
+            .unwrap();
+        to_vcpu
+            .send(VcpuControl::GetStates(state_send.clone()))
+            .unwrap();
+        assert_eq!(state_recv.recv().unwrap(), VmRunMode::Suspending);
+        assert_eq!(*runs.lock(), 1);
+
+        // The vcpu stays suspended after the step.
+        to_vcpu.send(VcpuControl::GetStates(state_send)).unwrap();
+        assert_eq!(state_recv.recv().unwrap(), VmRunMode::Suspending);
+        assert_eq!(*runs.lock(), 1);
+
+        to_vcpu
+            .send(VcpuControl::RunState(VmRunMode::Exiting))
+            .unwrap();
+        assert_eq!(vcpu_thread.join().unwrap(), ExitState::Stop);
+
+        // Single-stepping kept the breakpoints of the debugger.
+        #[cfg(feature = "gdb")]
+        let expected = vec![
+            (breakpoints.clone(), false),
+            (breakpoints.clone(), true),
+            (breakpoints, false),
+        ];
+        #[cfg(not(feature = "gdb"))]
+        let expected = vec![(breakpoints.clone(), true), (breakpoints, false)];
+        assert_eq!(*guest_debug.lock(), expected);
+    }
+}
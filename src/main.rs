@@ -174,7 +174,11 @@ fn run_vm(cmd: RunCommand, log_config: LogConfig) -> Result<CommandStatus> {
 }
 
 fn stop_vms(cmd: cmdline::StopCommand) -> std::result::Result<(), ()> {
-    vms_request(&VmRequest::Exit, cmd.socket_path)
+    if cmd.clean {
+        vms_request(&VmRequest::ExitClean, cmd.socket_path)
+    } else {
+        vms_request(&VmRequest::Exit, cmd.socket_path)
+    }
 }
 
 fn suspend_vms(cmd: cmdline::SuspendCommand) -> std::result::Result<(), ()> {
@@ -198,6 +202,13 @@ fn swap_vms(cmd: cmdline::SwapCommand) -> std::result::Result<(), ()> {
             &params.socket_path,
         ),
         Status(params) => (VmRequest::Swap(SwapCommand::Status), &params.socket_path),
+        Policy(params) => (
+            VmRequest::Swap(SwapCommand::SetPolicy {
+                staging_max_bytes: params.staging_max_bytes,
+                batch_size: params.batch_size,
+            }),
+            &params.socket_path,
+        ),
     };
     if let VmRequest::Swap(SwapCommand::Status) = req {
         do_swap_status(path)
@@ -271,6 +282,17 @@ fn balloon_ws(cmd: cmdline::BalloonWsCommand) -> std::result::Result<(), ()> {
     }
 }
 
+#[cfg(feature = "balloon")]
+fn balloon_self_test(cmd: cmdline::BalloonSelfTestCommand) -> std::result::Result<(), ()> {
+    let request = &VmRequest::BalloonCommand(BalloonControlCommand::SelfTest);
+    let response = handle_request(request, cmd.socket_path)?;
+    println!("{}", response);
+    match response {
+        VmResponse::BalloonSelfTest { passed: true, .. } => Ok(()),
+        _ => Err(()),
+    }
+}
+
 fn modify_battery(cmd: cmdline::BatteryCommand) -> std::result::Result<(), ()> {
     do_modify_battery(
         cmd.socket_path,
@@ -590,9 +612,15 @@ fn snapshot_vm(cmd: cmdline::SnapshotCommand) -> std::result::Result<(), ()> {
     use cmdline::SnapshotSubCommands::*;
     let (socket_path, request) = match cmd.snapshot_command {
         Take(path) => {
-            let req = VmRequest::Snapshot(SnapshotCommand::Take {
-                snapshot_path: path.snapshot_path,
-            });
+            let req = if path.devices_only {
+                VmRequest::Snapshot(SnapshotCommand::TakeDevicesOnly {
+                    snapshot_path: path.snapshot_path,
+                })
+            } else {
+                VmRequest::Snapshot(SnapshotCommand::Take {
+                    snapshot_path: path.snapshot_path,
+                })
+            };
             (path.socket_path, req)
         }
         Restore(path) => {
@@ -742,6 +770,9 @@ fn crosvm_main<I: IntoIterator<Item = String>>(args: I) -> Result<CommandStatus>
                     CrossPlatformCommands::BalloonWs(cmd) => {
                         balloon_ws(cmd).map_err(|_| anyhow!("balloon_ws subcommand failed"))
                     }
+                    #[cfg(feature = "balloon")]
+                    CrossPlatformCommands::BalloonSelfTest(cmd) => balloon_self_test(cmd)
+                        .map_err(|_| anyhow!("balloon_self_test subcommand failed")),
                     // TODO(b/288432539): remove once concierge is migrated
                     #[cfg(feature = "balloon")]
                     CrossPlatformCommands::BalloonWss(cmd) => {
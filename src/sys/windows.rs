@@ -189,11 +189,14 @@ use sync::Mutex;
 use tube_transporter::TubeToken;
 use tube_transporter::TubeTransporterReader;
 use vm_control::api::VmMemoryClient;
+use vm_control::next_request_id;
+use vm_control::vfio_hotplug_not_implemented;
 #[cfg(feature = "balloon")]
 use vm_control::BalloonControlCommand;
 #[cfg(feature = "balloon")]
 use vm_control::BalloonTube;
 use vm_control::DeviceControlCommand;
+use vm_control::IrqFlushStrategy;
 use vm_control::IrqHandlerRequest;
 use vm_control::PvClockCommand;
 use vm_control::VcpuControl;
@@ -508,6 +511,8 @@ fn create_balloon_device(
         #[cfg(feature = "registered_events")]
         None,
         VIRTIO_BALLOON_WS_DEFAULT_NUM_BINS,
+        None,
+        cfg.balloon_oom_deflate_step,
     )
     .exit_context(Exit::BalloonDeviceNew, "failed to create balloon")?;
 
@@ -888,15 +893,20 @@ fn handle_readable_event<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
     region_state: &mut VmMemoryRegionState,
     vm_control_server: Option<&mut ControlServer>,
     irq_handler_control: &Tube,
+    irq_flush_strategy: IrqFlushStrategy,
     device_ctrl_tube: &Tube,
     wait_ctx: &WaitContext<Token>,
     force_s2idle: bool,
     vcpu_control_channels: &[mpsc::Sender<VcpuControl>],
 ) -> Result<Option<ExitState>> {
     let execute_vm_request = |request: VmRequest, guest_os: &mut RunnableLinuxVm<V, Vcpu>| {
+        if let VmRequest::GetRunMode = request {
+            return (VmResponse::RunMode(run_mode_arc.get_mode()), None);
+        }
         let mut run_mode_opt = None;
         let vcpu_size = vcpu_boxes.lock().len();
         let resp = request.execute(
+            next_request_id(),
             &mut run_mode_opt,
             disk_host_tubes,
             &mut guest_os.pm,
@@ -926,11 +936,13 @@ fn handle_readable_event<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                 );
             },
             force_s2idle,
+            false,
             #[cfg(feature = "swap")]
             None,
             device_ctrl_tube,
             vcpu_size,
             irq_handler_control,
+            irq_flush_strategy,
             || guest_os.irq_chip.as_ref().snapshot(vcpu_size),
             |snapshot| {
                 guest_os
@@ -1010,14 +1022,17 @@ fn handle_readable_event<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                             ipc_main_loop_tube,
                         )
                     }
-                    TaggedControlTube::Vm(tube) => match tube.0.recv::<VmRequest>() {
+                    TaggedControlTube::Vm(tube) => match tube
+                        .0
+                        .recv_with_exact_descriptors::<VmRequest>()
+                    {
                         Ok(request) => {
                             let mut run_mode_opt = None;
                             let response = match request {
                                 VmRequest::HotPlugVfioCommand { device, add } => {
                                     // Suppress warnings.
                                     let _ = (device, add);
-                                    unimplemented!("not implemented on Windows");
+                                    Some(vfio_hotplug_not_implemented(next_request_id()))
                                 }
                                 #[cfg(feature = "registered_events")]
                                 VmRequest::RegisterListener { socket_addr, event } => {
@@ -1031,20 +1046,21 @@ fn handle_readable_event<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                                 VmRequest::Unregister { socket_addr } => {
                                     unimplemented!("not implemented on Windows");
                                 }
+                                // Without a balloon device, balloon requests fall through to
+                                // `execute`, which fails them with ENOTSUP.
                                 #[cfg(feature = "balloon")]
-                                VmRequest::BalloonCommand(cmd) => {
-                                    if let Some(balloon_tube) = balloon_tube {
-                                        if let Some((r, key)) = balloon_tube.send_cmd(cmd, Some(id))
-                                        {
-                                            if key != id {
-                                                unimplemented!("not implemented on Windows");
-                                            }
-                                            Some(r)
-                                        } else {
-                                            None
+                                VmRequest::BalloonCommand(_) | VmRequest::BalloonStats
+                                    if balloon_tube.is_some() =>
+                                {
+                                    let balloon_tube = balloon_tube.unwrap();
+                                    let cmd =
+                                        request.balloon_command().expect("not a balloon request");
+                                    if let Some((r, key)) = balloon_tube.send_cmd(cmd, Some(id)) {
+                                        if key != id {
+                                            unimplemented!("not implemented on Windows");
                                         }
+                                        Some(r)
                                     } else {
-                                        error!("balloon not enabled");
                                         None
                                     }
                                 }
@@ -1315,6 +1331,7 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
         Exit::CreateTube,
         "failed to create IRQ handler control Tube",
     )?;
+    let irq_flush_strategy = IrqFlushStrategy::from_caps(&hypervisor::hypervisor_caps());
 
     // Create a separate thread to wait on IRQ events. This is a natural division
     // because IRQ interrupts have no dependencies on other events, and this lets
@@ -1548,6 +1565,7 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                 &mut region_state,
                 vm_control_server.as_mut(),
                 &irq_handler_control,
+                irq_flush_strategy,
                 &device_ctrl_tube,
                 &wait_ctx,
                 force_s2idle,
@@ -68,6 +68,7 @@ use hypervisor::VcpuExit;
 use hypervisor::VcpuInitX86_64;
 use sync::Condvar;
 use sync::Mutex;
+use vm_control::SerializedVcpuSnapshot;
 use vm_control::VcpuControl;
 use vm_control::VmRunMode;
 use winapi::shared::winerror::ERROR_RETRY;
@@ -478,7 +479,9 @@ impl VcpuStallMonitor {
                     loop {
                         match *run_mode_lock {
                             VmRunMode::Running => break,
-                            VmRunMode::Suspending | VmRunMode::Breakpoint => {
+                            VmRunMode::Suspending
+                            | VmRunMode::Breakpoint
+                            | VmRunMode::SingleStep => {
                                 info!("vcpu monitor pausing until end of suspension");
                                 run_mode_lock = self.run_mode.cvar.wait(run_mode_lock);
                                 reset_timer = true;
@@ -909,6 +912,17 @@ where
                         }
                     }
                     VmRunMode::Breakpoint => {}
+                    VmRunMode::SingleStep => {
+                        // Single-stepping is not supported, so suspend without executing the
+                        // step rather than waiting for it forever.
+                        error!(
+                            "single-step is not supported, suspending vcpu {} instead",
+                            context.cpu_id
+                        );
+                        *run_mode_lock = VmRunMode::Suspending;
+                        run_mode_arc.cvar.notify_all();
+                        continue;
+                    }
                     VmRunMode::Exiting => {
                         #[cfg(feature = "stats")]
                         if let Some(stats) = stats {
@@ -963,9 +977,11 @@ fn process_vcpu_control_messages<V>(
             VcpuControl::Debug(d) => {
                 unimplemented!("Windows VCPUs do not support debug yet.");
             }
-            VcpuControl::MakeRT => {
+            VcpuControl::MakeRT | VcpuControl::SetRealtime { .. } => {
                 unimplemented!("Windows VCPUs do not support on demand RT.");
             }
+            // Exiting the run loop to handle the message is all a kick is for.
+            VcpuControl::Kick => {}
             VcpuControl::GetStates(response_chan) => {
                 // Wondering why we need this given that the state value is already in an Arc?
                 //
@@ -980,6 +996,7 @@ fn process_vcpu_control_messages<V>(
             VcpuControl::Snapshot(response_chan) => {
                 let resp = vcpu
                     .snapshot()
+                    .and_then(|snapshot| SerializedVcpuSnapshot::new(&snapshot))
                     .with_context(|| format!("Failed to snapshot Vcpu #{}", vcpu.id()));
                 if let Err(e) = response_chan.send(resp) {
                     error!("Failed to send snapshot response: {}", e);
@@ -993,6 +1010,14 @@ fn process_vcpu_control_messages<V>(
                     error!("Failed to send restore response: {}", e);
                 }
             }
+            VcpuControl::GetRegisters(response_chan) => {
+                let resp = vcpu
+                    .get_registers()
+                    .with_context(|| format!("Failed to get registers of Vcpu #{}", vcpu.id()));
+                if let Err(e) = response_chan.send(resp) {
+                    error!("Failed to send registers response: {}", e);
+                }
+            }
         }
     }
 }
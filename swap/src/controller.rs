@@ -56,6 +56,7 @@ use crate::page_handler::MoveToStaging;
 use crate::page_handler::PageHandler;
 use crate::page_handler::MLOCK_BUDGET;
 use crate::pagesize::bytes_to_pages;
+use crate::pagesize::pages_to_bytes;
 use crate::pagesize::THP_SIZE;
 use crate::processes::freeze_child_processes;
 use crate::processes::ProcessesGuard;
@@ -71,6 +72,7 @@ use crate::userfaultfd::Userfaultfd;
 use crate::worker::BackgroundJobControl;
 use crate::worker::Worker;
 use crate::SwapMetrics;
+use crate::SwapOutProgress;
 use crate::SwapState;
 use crate::SwapStateTransition;
 use crate::SwapStatus;
@@ -113,6 +115,8 @@ enum Command {
     },
     Exit,
     Status,
+    SwapOutProgress,
+    SetPolicy(SwapPolicy),
     ProcessForked {
         #[serde(with = "base::with_as_descriptor")]
         uffd: Userfaultfd,
@@ -121,6 +125,53 @@ enum Command {
     StaticDeviceSetupComplete(u32),
 }
 
+/// Pacing of swap-out configured by [SwapController::set_policy()].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+struct SwapPolicy {
+    /// The max bytes to write from the staging memory to the swap file per swap-out request.
+    staging_max_bytes: u64,
+    /// The max size of chunks to swap out at once.
+    batch_size: u64,
+}
+
+impl Default for SwapPolicy {
+    fn default() -> Self {
+        Self {
+            staging_max_bytes: u64::MAX,
+            batch_size: MAX_SWAP_CHUNK_SIZE as u64,
+        }
+    }
+}
+
+/// Returns the size of the next chunk to swap out, or `None` if `swapped_pages` already reached
+/// `policy.staging_max_bytes`.
+///
+/// The chunk is at least 1 page so that a small `batch_size` still makes progress.
+fn swap_out_chunk_size(policy: &SwapPolicy, swapped_pages: u64) -> Option<usize> {
+    let page_size = pages_to_bytes(1) as u64;
+    let remaining = policy
+        .staging_max_bytes
+        .saturating_sub(swapped_pages.saturating_mul(page_size));
+    if remaining < page_size {
+        return None;
+    }
+    let chunk_size = policy.batch_size.min(remaining).max(page_size);
+    Some(chunk_size.try_into().unwrap_or(usize::MAX))
+}
+
+/// Swaps out the next chunk of the staging memory with [PageHandler::swap_out()] as paced by
+/// `policy`.
+///
+/// Returns `None` without swapping out anything if `swapped_pages` already reached
+/// `policy.staging_max_bytes`.
+fn swap_out_next_chunk(
+    page_handler: &PageHandler,
+    policy: &SwapPolicy,
+    swapped_pages: u64,
+) -> Option<Result<usize, PageHandlerError>> {
+    swap_out_chunk_size(policy, swapped_pages).map(|chunk_size| page_handler.swap_out(chunk_size))
+}
+
 /// [SwapController] provides APIs to control vmm-swap.
 pub struct SwapController {
     child_process: Option<Child>,
@@ -339,6 +390,28 @@ impl SwapController {
         Ok(())
     }
 
+    /// Configure how aggressively [Self::swap_out()] writes pages to the swap file.
+    ///
+    /// Each swap-out writes `batch_size` bytes at once and stops after writing `staging_max_bytes`
+    /// bytes, leaving the rest of the pages in the staging memory for the next swap-out. The
+    /// policy persists until it is set again.
+    pub fn set_policy(&self, staging_max_bytes: u64, batch_size: u64) -> anyhow::Result<()> {
+        if staging_max_bytes == 0 || batch_size == 0 {
+            bail!(
+                "invalid swap policy: staging_max_bytes: {}, batch_size: {}",
+                staging_max_bytes,
+                batch_size
+            );
+        }
+        self.command_tube
+            .send(&Command::SetPolicy(SwapPolicy {
+                staging_max_bytes,
+                batch_size,
+            }))
+            .context("send swap policy request")?;
+        Ok(())
+    }
+
     /// Swap in all the guest memory and disable monitoring page faults.
     ///
     /// This returns as soon as it succeeds to send request to the monitor process.
@@ -360,6 +433,20 @@ impl SwapController {
         Ok(status)
     }
 
+    /// Return the progress of the current swap-out.
+    ///
+    /// This blocks until response from the monitor process arrives to the main process.
+    pub fn swap_out_progress(&self) -> anyhow::Result<SwapOutProgress> {
+        self.command_tube
+            .send(&Command::SwapOutProgress)
+            .context("send swap-out progress request")?;
+        let progress = self
+            .command_tube
+            .recv()
+            .context("receive swap-out progress")?;
+        Ok(progress)
+    }
+
     /// Suspend device processes using `SIGSTOP` signal.
     ///
     /// When the returned `ProcessesGuard` is dropped, the devices resume.
@@ -580,6 +667,7 @@ fn monitor_process(
     let mut uffd_list =
         UffdList::new(uffd, dead_uffd_checker, &wait_ctx).context("create uffd list")?;
     let mut state_transition = SwapStateTransition::default();
+    let mut policy = SwapPolicy::default();
     let mut try_gc_uffds = false;
 
     loop {
@@ -690,6 +778,7 @@ fn monitor_process(
                                 &worker,
                                 &mutex_transition,
                                 &bg_job_control,
+                                &mut policy,
                                 #[cfg(feature = "log_page_fault")]
                                 &mut page_fault_logger,
                             );
@@ -729,6 +818,10 @@ fn monitor_process(
                     Command::SwapOut => {
                         warn!("swap out while disabled");
                     }
+                    Command::SetPolicy(new_policy) => {
+                        info!("set swap policy: {:?}", new_policy);
+                        policy = new_policy;
+                    }
                     Command::Disable { slow_file_cleanup } => {
                         if !slow_file_cleanup {
                             if let Some(worker) = truncate_worker.take() {
@@ -753,6 +846,11 @@ fn monitor_process(
                         command_tube.send(&status).context("send status response")?;
                         debug!("swap status: {:?}", status);
                     }
+                    Command::SwapOutProgress => {
+                        command_tube
+                            .send(&SwapOutProgress::default())
+                            .context("send swap-out progress response")?;
+                    }
                 },
                 Token::BackgroundJobCompleted => {
                     error!("unexpected background job completed event while swap is disabled");
@@ -794,6 +892,25 @@ impl From<&State<'_>> for SwapState {
     }
 }
 
+// Returns the progress of the swap-out. While swapping out, `state_transition.pages` counts the
+// pages written to the swap files so far.
+fn swap_out_progress(
+    state: &State<'_>,
+    state_transition: &SwapStateTransition,
+    metrics: &SwapMetrics,
+) -> SwapOutProgress {
+    match state {
+        State::SwapOutInProgress { .. } | State::SwapOutCompleted => {
+            let swapped_pages = state_transition.pages as usize;
+            SwapOutProgress {
+                bytes_swapped: pages_to_bytes(swapped_pages) as u64,
+                bytes_total: pages_to_bytes(swapped_pages + metrics.staging_pages as usize) as u64,
+            }
+        }
+        _ => SwapOutProgress::default(),
+    }
+}
+
 fn handle_enable_command<'scope>(
     state: State,
     bg_job_control: &BackgroundJobControl,
@@ -901,6 +1018,7 @@ fn handle_vmm_swap<'scope, 'env>(
     worker: &Worker<MoveToStaging>,
     state_transition: &'env Mutex<SwapStateTransition>,
     bg_job_control: &'env BackgroundJobControl,
+    policy: &mut SwapPolicy,
     #[cfg(feature = "log_page_fault")] page_fault_logger: &mut PageFaultEventLogger,
 ) -> anyhow::Result<VmmSwapResult> {
     let mut state = match move_guest_to_staging(page_handler, guest_memory, worker) {
@@ -933,7 +1051,17 @@ fn handle_vmm_swap<'scope, 'env>(
                 // TODO(b/273129441): swap out on a background thread.
                 // Proceed swap out only when there is no page fault (or other) events.
                 if events.is_empty() {
-                    match page_handler.swap_out(MAX_SWAP_CHUNK_SIZE) {
+                    let swapped_pages = state_transition.lock().pages;
+                    let Some(result) = swap_out_next_chunk(page_handler, policy, swapped_pages)
+                    else {
+                        info!(
+                            "pause swap out after {} pages by policy: {:?}",
+                            swapped_pages, policy
+                        );
+                        state = State::SwapOutPending;
+                        continue;
+                    };
+                    match result {
                         Ok(num_pages) => {
                             let mut state_transition = state_transition.lock();
                             state_transition.pages += num_pages as u64;
@@ -1103,6 +1231,10 @@ fn handle_vmm_swap<'scope, 'env>(
                             warn!("swap out is not ready. state: {:?}", SwapState::from(state));
                         }
                     },
+                    Command::SetPolicy(new_policy) => {
+                        info!("set swap policy: {:?}", new_policy);
+                        *policy = new_policy;
+                    }
                     Command::Disable { slow_file_cleanup } => {
                         match state {
                             State::Trim(join_handle) => {
@@ -1202,6 +1334,16 @@ fn handle_vmm_swap<'scope, 'env>(
                         command_tube.send(&status).context("send status response")?;
                         debug!("swap status: {:?}", status);
                     }
+                    Command::SwapOutProgress => {
+                        let mut metrics = SwapMetrics::default();
+                        page_handler.load_metrics(&mut metrics);
+                        let progress =
+                            swap_out_progress(&state, &state_transition.lock(), &metrics);
+                        command_tube
+                            .send(&progress)
+                            .context("send swap-out progress response")?;
+                        debug!("swap-out progress: {:?}", progress);
+                    }
                 },
                 Token::BackgroundJobCompleted => {
                     // Reset the completed event.
@@ -1261,3 +1403,137 @@ fn handle_vmm_swap<'scope, 'env>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use base::test_utils::call_test_with_sudo;
+    use base::MappedRegion;
+    use base::MemoryMappingBuilder;
+    use userfaultfd::UffdBuilder;
+
+    use super::*;
+
+    #[test]
+    fn swap_out_progress_increases() {
+        let page_size = pages_to_bytes(1) as u64;
+        let in_progress = State::SwapOutInProgress {
+            started_time: Instant::now(),
+        };
+        let mut state_transition = SwapStateTransition::default();
+        let mut metrics = SwapMetrics {
+            staging_pages: 100,
+            ..Default::default()
+        };
+
+        // Nothing is reported before the swap-out starts.
+        assert_eq!(
+            swap_out_progress(&State::SwapOutPending, &state_transition, &metrics),
+            SwapOutProgress::default()
+        );
+
+        let mut last_swapped = 0;
+        for _ in 0..4 {
+            let progress = swap_out_progress(&in_progress, &state_transition, &metrics);
+            assert_eq!(progress.bytes_total, 100 * page_size);
+            assert!(progress.bytes_swapped >= last_swapped);
+            last_swapped = progress.bytes_swapped;
+            // Simulate a chunk being swapped out.
+            state_transition.pages += 25;
+            metrics.staging_pages -= 25;
+        }
+
+        let progress = swap_out_progress(&State::SwapOutCompleted, &state_transition, &metrics);
+        assert_eq!(progress.bytes_swapped, 100 * page_size);
+        assert_eq!(progress.bytes_total, 100 * page_size);
+    }
+
+    /// Runs a swap-out request like `handle_vmm_swap()` and returns the swapped pages and the
+    /// size of the swapped chunks.
+    fn run_swap_out(page_handler: &PageHandler, policy: &SwapPolicy) -> (u64, Vec<usize>) {
+        let mut swapped_pages = 0;
+        let mut chunks = Vec::new();
+        while let Some(result) = swap_out_next_chunk(page_handler, policy, swapped_pages) {
+            let pages = result.unwrap();
+            if pages == 0 {
+                break;
+            }
+            chunks.push(pages);
+            swapped_pages += pages as u64;
+        }
+        (swapped_pages, chunks)
+    }
+
+    #[test]
+    fn swap_out_respects_policy() {
+        call_test_with_sudo("controller::tests::swap_out_respects_policy_impl")
+    }
+
+    #[ignore = "Only to be called by swap_out_respects_policy"]
+    #[test]
+    fn swap_out_respects_policy_impl() {
+        let page_size = pages_to_bytes(1);
+        let worker = Worker::new(2, 2);
+        let uffd: Userfaultfd = UffdBuilder::new()
+            .non_blocking(false)
+            .create()
+            .unwrap()
+            .into();
+        let file = tempfile::tempfile().unwrap();
+        let staging_shmem =
+            SharedMemory::new("test staging memory", 100 * page_size as u64).unwrap();
+        let shm = SharedMemory::new("shm", 100 * page_size as u64).unwrap();
+        let mmap = MemoryMappingBuilder::new(100 * page_size)
+            .from_shared_memory(&shm)
+            .build()
+            .unwrap();
+        let base_addr = mmap.as_ptr() as usize;
+        let regions = [base_addr..(base_addr + 100 * page_size)];
+        let page_handler =
+            PageHandler::create(&file, &staging_shmem, &regions, worker.channel.clone()).unwrap();
+        // Write data to all the pages so that all of them are moved to the staging memory.
+        for i in 0..100 {
+            mmap.write_obj(1u8, i * page_size).unwrap();
+        }
+        // SAFETY:
+        // Safe because the regions are mapped by `mmap` which outlives the page handler.
+        unsafe { register_regions(&regions, std::array::from_ref(&uffd)) }.unwrap();
+        // SAFETY:
+        // Safe because nothing else accesses the guest memory while moving.
+        unsafe { page_handler.move_to_staging(base_addr, &shm, 0) }.unwrap();
+        worker.channel.wait_complete();
+
+        // The policy survives serialization to the monitor process.
+        let policy = SwapPolicy {
+            staging_max_bytes: 30 * page_size as u64,
+            batch_size: 4 * page_size as u64,
+        };
+        let Command::SetPolicy(policy) =
+            serde_json::from_str(&serde_json::to_string(&Command::SetPolicy(policy)).unwrap())
+                .unwrap()
+        else {
+            panic!("unexpected command");
+        };
+
+        // Each chunk is at most batch_size and the request stops at staging_max_bytes.
+        let (swapped_pages, chunks) = run_swap_out(&page_handler, &policy);
+        assert_eq!(swapped_pages, 30);
+        assert_eq!(chunks, [4, 4, 4, 4, 4, 4, 4, 2]);
+        // A subsequent swap-out continues with the same policy.
+        let (swapped_pages, _) = run_swap_out(&page_handler, &policy);
+        assert_eq!(swapped_pages, 30);
+
+        // A batch size smaller than a page still makes progress.
+        let policy = SwapPolicy {
+            staging_max_bytes: u64::MAX,
+            batch_size: 1,
+        };
+        let (swapped_pages, chunks) = run_swap_out(&page_handler, &policy);
+        assert_eq!(swapped_pages, 40);
+        assert!(chunks.iter().all(|&pages| pages == 1));
+
+        // Nothing is left in the staging memory.
+        let (swapped_pages, _) = run_swap_out(&page_handler, &SwapPolicy::default());
+        assert_eq!(swapped_pages, 0);
+        worker.close();
+    }
+}
@@ -101,6 +101,21 @@ pub struct SwapMetrics {
     pub swap_pages: u64,
 }
 
+/// Progress of swapping out the staging memory to the swap files.
+///
+/// This is only filled while the swap-out is in progress and after it completed.
+#[repr(C)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SwapOutProgress {
+    /// Size of the pages written to the swap files so far.
+    pub bytes_swapped: u64,
+    /// Size of the pages written so far plus the pages remaining in the staging memory.
+    ///
+    /// Pages faulted back in from the staging memory while the swap-out is in progress are not
+    /// swapped out, so this can decrease over time.
+    pub bytes_total: u64,
+}
+
 /// The response to `crosvm swap status` command.
 #[repr(C)]
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
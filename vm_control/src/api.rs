@@ -114,7 +114,35 @@ impl VmMemoryClient {
 
     /// Unregister the given memory slot that was previously registered with `RegisterMemory`.
     pub fn unregister_memory(&self, region: VmMemoryRegionId) -> Result<()> {
-        self.request_unit(&VmMemoryRequest::UnregisterMemory(region))
+        self.request_unit(&VmMemoryRequest::UnregisterMemory {
+            id: region,
+            release_pages: false,
+        })
+    }
+
+    /// Like `unregister_memory`, but also releases the host pages backing the region so they are
+    /// returned to the host promptly. The contents of the shared memory or file backing the region
+    /// are discarded.
+    pub fn unregister_memory_and_release(&self, region: VmMemoryRegionId) -> Result<()> {
+        self.request_unit(&VmMemoryRequest::UnregisterMemory {
+            id: region,
+            release_pages: true,
+        })
+    }
+
+    /// Populate the host pages backing a region previously registered with `RegisterMemory`.
+    pub fn prefault_region(&self, id: VmMemoryRegionId) -> Result<()> {
+        self.request_unit(&VmMemoryRequest::PrefaultRegion { id })
+    }
+
+    /// Returns the number and total size in bytes of the regions registered with
+    /// `register_memory` that are still mapped.
+    pub fn query_registered(&self) -> Result<(usize, u64)> {
+        match self.request(&VmMemoryRequest::QueryRegistered)? {
+            VmMemoryResponse::Err(e) => Err(ApiClientError::RequestFailed(e)),
+            VmMemoryResponse::Registered { count, total_bytes } => Ok((count, total_bytes)),
+            _other => Err(ApiClientError::UnexpectedResponse),
+        }
     }
 
     /// Register an ioeventfd by looking up using Alloc info.
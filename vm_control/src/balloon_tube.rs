@@ -22,6 +22,7 @@ use base::Tube;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::VmRequest;
 use crate::VmResponse;
 
 // Balloon commands that are sent on the crosvm control socket.
@@ -39,6 +40,20 @@ pub enum BalloonControlCommand {
         refresh_threshold: u32,
         report_threshold: u32,
     },
+    /// Inflate the balloon by a small amount and deflate it back, checking that the guest follows.
+    SelfTest,
+}
+
+impl VmRequest {
+    /// Returns the command to send to the balloon device if this is a balloon request, so that
+    /// control loops can route it to their `BalloonTube`.
+    pub fn balloon_command(&self) -> Option<BalloonControlCommand> {
+        match self {
+            VmRequest::BalloonCommand(cmd) => Some(cmd.clone()),
+            VmRequest::BalloonStats => Some(BalloonControlCommand::Stats),
+            _ => None,
+        }
+    }
 }
 
 fn do_send(tube: &Tube, cmd: &BalloonControlCommand) -> Option<VmResponse> {
@@ -83,6 +98,10 @@ fn do_send(tube: &Tube, cmd: &BalloonControlCommand) -> Option<VmResponse> {
             Ok(_) => None,
             Err(_) => Some(VmResponse::Err(SysError::last())),
         },
+        BalloonControlCommand::SelfTest => match tube.send(&BalloonTubeCommand::SelfTest) {
+            Ok(_) => None,
+            Err(_) => Some(VmResponse::Err(SysError::last())),
+        },
     }
 }
 
@@ -186,6 +205,9 @@ impl BalloonTube {
                 BalloonControlCommand::WorkingSet,
                 BalloonTubeResult::WorkingSet { ws, balloon_actual },
             ) => VmResponse::BalloonWS { ws, balloon_actual },
+            (BalloonControlCommand::SelfTest, BalloonTubeResult::SelfTest { passed, detail }) => {
+                VmResponse::BalloonSelfTest { passed, detail }
+            }
             (_, resp) => {
                 bail!("Unexpected balloon tube result {:?}", resp);
             }
@@ -242,6 +264,64 @@ mod tests {
         assert!(matches!(resp[0].0, VmResponse::BalloonStats { .. }));
     }
 
+    #[test]
+    fn test_balloon_stats_request() {
+        let (host, device) = Tube::pair().unwrap();
+        let mut balloon_tube = BalloonTube::new(host);
+
+        let cmd = VmRequest::BalloonStats
+            .balloon_command()
+            .expect("not a balloon request");
+        let resp = balloon_tube.send_cmd(cmd, Some(0xc0ffee));
+        assert!(resp.is_none());
+
+        balloon_device_respond_stats(&device);
+
+        let resp = balloon_tube.recv().unwrap();
+        assert_eq!(resp.len(), 1);
+        assert_eq!(resp[0].1, 0xc0ffee);
+        assert!(matches!(resp[0].0, VmResponse::BalloonStats { .. }));
+
+        assert!(VmRequest::MakeRT.balloon_command().is_none());
+    }
+
+    #[test]
+    fn test_self_test_command() {
+        let (host, device) = Tube::pair().unwrap();
+        let mut balloon_tube = BalloonTube::new(host);
+
+        let resp = balloon_tube.send_cmd(BalloonControlCommand::SelfTest, Some(0xc0ffee));
+        assert!(resp.is_none());
+        // Queued until the self-test completes.
+        let resp = balloon_tube.send_cmd(BalloonControlCommand::Stats, Some(0xbadcafe));
+        assert!(resp.is_none());
+
+        let BalloonTubeCommand::SelfTest = device.recv::<BalloonTubeCommand>().unwrap() else {
+            panic!("unexpected command");
+        };
+        device
+            .send(&BalloonTubeResult::SelfTest {
+                passed: true,
+                detail: "balloon followed the target".to_string(),
+            })
+            .unwrap();
+
+        let resp = balloon_tube.recv().unwrap();
+        assert_eq!(resp.len(), 1);
+        assert_eq!(resp[0].1, 0xc0ffee);
+        assert!(matches!(
+            resp[0].0,
+            VmResponse::BalloonSelfTest { passed: true, .. }
+        ));
+
+        balloon_device_respond_stats(&device);
+
+        let resp = balloon_tube.recv().unwrap();
+        assert_eq!(resp.len(), 1);
+        assert_eq!(resp[0].1, 0xbadcafe);
+        assert!(matches!(resp[0].0, VmResponse::BalloonStats { .. }));
+    }
+
     #[test]
     fn test_multiple_stat_command() {
         let (host, device) = Tube::pair().unwrap();
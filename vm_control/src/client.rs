@@ -5,19 +5,35 @@
 use std::fs::OpenOptions;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[cfg(feature = "pci-hotplug")]
 use anyhow::anyhow;
 use anyhow::Result as AnyHowResult;
 use base::open_file_or_duplicate;
+use base::warn;
+use base::Error as SysError;
+use base::Tube;
+use base::TubeError;
+use libc::EAGAIN;
+use libc::EINTR;
+use libc::EIO;
 use remain::sorted;
 use thiserror::Error;
 
+use crate::api::VmMemoryClient;
 #[cfg(feature = "gpu")]
 pub use crate::gpu::*;
 pub use crate::sys::handle_request;
 #[cfg(any(target_os = "android", target_os = "linux"))]
+pub use crate::sys::handle_request_with_buffer_size;
+#[cfg(any(target_os = "android", target_os = "linux"))]
 pub use crate::sys::handle_request_with_timeout;
+#[cfg(all(
+    feature = "registered_events",
+    any(target_os = "android", target_os = "linux")
+))]
+pub use crate::sys::EventSubscription;
 pub use crate::*;
 
 #[sorted]
@@ -195,3 +211,281 @@ pub fn do_swap_status<T: AsRef<Path> + std::fmt::Debug>(socket_path: T) -> VmsRe
 }
 
 pub type HandleRequestResult = std::result::Result<VmResponse, ()>;
+
+/// A connection to the VM process over which a `VmRequest` can be sent and its `VmResponse`
+/// received.
+pub trait RequestTransport {
+    /// Sends `request` to the VM.
+    fn send_request(&self, request: &VmRequest) -> std::result::Result<(), SysError>;
+    /// Receives the response to the last request sent.
+    fn recv_response(&self) -> std::result::Result<VmResponse, SysError>;
+}
+
+impl RequestTransport for Tube {
+    fn send_request(&self, request: &VmRequest) -> std::result::Result<(), SysError> {
+        self.send(request).map_err(tube_sys_error)
+    }
+
+    fn recv_response(&self) -> std::result::Result<VmResponse, SysError> {
+        self.recv().map_err(tube_sys_error)
+    }
+}
+
+fn tube_sys_error(e: TubeError) -> SysError {
+    match e {
+        TubeError::Recv(e) | TubeError::Send(e) => SysError::from(e),
+        _ => SysError::new(EIO),
+    }
+}
+
+/// Controls how often and how quickly `send_with_retry` retries a request.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry. It doubles after each retry.
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between retries.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+fn is_retryable(e: SysError) -> bool {
+    matches!(e.errno(), EINTR | EAGAIN)
+}
+
+/// Sends `request` over `socket`, retrying with exponential backoff while sending fails or the VM
+/// responds with a transient error (`EINTR` or `EAGAIN`).
+///
+/// Returns the response or transport error of the last attempt. Any other error, such as `ENODEV`
+/// or `ENOTSUP`, is returned immediately. Failing to receive the response, e.g. on a timeout, is
+/// never retried: the response may still arrive later on `socket` and be mistaken for the response
+/// to the next attempt. Since a request may be sent more than once, only use this for requests
+/// that are safe to repeat.
+pub fn send_with_retry<T: RequestTransport + ?Sized>(
+    socket: &T,
+    request: &VmRequest,
+    policy: RetryPolicy,
+) -> std::result::Result<VmResponse, SysError> {
+    let mut backoff = policy.initial_backoff;
+    let mut attempt = 1;
+    loop {
+        let result = match socket.send_request(request) {
+            Ok(()) => Ok(socket.recv_response()?),
+            Err(e) => Err(e),
+        };
+        let e = match result {
+            Ok(VmResponse::Err(e)) | Err(e) => e,
+            Ok(_) => return result,
+        };
+        if !is_retryable(e) || attempt >= policy.max_attempts {
+            return result;
+        }
+        warn!(
+            "request {:?} failed on attempt {}/{}: {}, retrying in {:?}",
+            request, attempt, policy.max_attempts, e, backoff
+        );
+        std::thread::sleep(backoff);
+        backoff = backoff.saturating_mul(2).min(policy.max_backoff);
+        attempt += 1;
+    }
+}
+
+/// A memory region registered with `VmMemoryRequest::RegisterMemory`, which is unregistered with
+/// [`VmMemoryClient::unregister_memory`] when the guard is dropped.
+///
+/// Use [`RegisteredMemoryGuard::into_id`] to keep the region mapped.
+pub struct RegisteredMemoryGuard<'a> {
+    client: &'a VmMemoryClient,
+    id: VmMemoryRegionId,
+}
+
+impl<'a> RegisteredMemoryGuard<'a> {
+    /// Takes ownership of the region `id`, which was registered through `client`.
+    pub fn new(client: &'a VmMemoryClient, id: VmMemoryRegionId) -> Self {
+        RegisteredMemoryGuard { client, id }
+    }
+
+    pub fn id(&self) -> VmMemoryRegionId {
+        self.id
+    }
+
+    /// Releases the guard without unregistering the region, and returns its id.
+    pub fn into_id(self) -> VmMemoryRegionId {
+        let id = self.id;
+        std::mem::forget(self);
+        id
+    }
+}
+
+impl Drop for RegisteredMemoryGuard<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.client.unregister_memory(self.id) {
+            warn!("failed to unregister {:?}: {}", self.id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use libc::ENOTSUP;
+
+    use super::*;
+
+    /// Fails to send the first `failures` requests with `errno`, then responds with
+    /// `VmResponse::Ok`.
+    struct FlakyTransport {
+        errno: i32,
+        failures: u32,
+        attempts: Cell<u32>,
+    }
+
+    impl FlakyTransport {
+        fn new(errno: i32, failures: u32) -> Self {
+            FlakyTransport {
+                errno,
+                failures,
+                attempts: Cell::new(0),
+            }
+        }
+    }
+
+    impl RequestTransport for FlakyTransport {
+        fn send_request(&self, _request: &VmRequest) -> std::result::Result<(), SysError> {
+            let attempt = self.attempts.get() + 1;
+            self.attempts.set(attempt);
+            if attempt <= self.failures {
+                Err(SysError::new(self.errno))
+            } else {
+                Ok(())
+            }
+        }
+
+        fn recv_response(&self) -> std::result::Result<VmResponse, SysError> {
+            Ok(VmResponse::Ok)
+        }
+    }
+
+    fn test_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn retry_until_success() {
+        let transport = FlakyTransport::new(EAGAIN, 3);
+        let response = send_with_retry(&transport, &VmRequest::Powerbtn, test_policy(5));
+        assert!(matches!(response, Ok(VmResponse::Ok)));
+        assert_eq!(transport.attempts.get(), 4);
+    }
+
+    #[test]
+    fn retry_gives_up_after_max_attempts() {
+        let transport = FlakyTransport::new(EINTR, 10);
+        let response = send_with_retry(&transport, &VmRequest::Powerbtn, test_policy(3));
+        assert_eq!(response.err(), Some(SysError::new(EINTR)));
+        assert_eq!(transport.attempts.get(), 3);
+    }
+
+    #[test]
+    fn non_retryable_error_not_retried() {
+        let transport = FlakyTransport::new(ENOTSUP, 1);
+        let response = send_with_retry(&transport, &VmRequest::Powerbtn, test_policy(5));
+        assert_eq!(response.err(), Some(SysError::new(ENOTSUP)));
+        assert_eq!(transport.attempts.get(), 1);
+    }
+
+    #[test]
+    fn retryable_error_response_retried() {
+        struct BusyVm(Cell<u32>);
+        impl RequestTransport for BusyVm {
+            fn send_request(&self, _request: &VmRequest) -> std::result::Result<(), SysError> {
+                self.0.set(self.0.get() + 1);
+                Ok(())
+            }
+
+            fn recv_response(&self) -> std::result::Result<VmResponse, SysError> {
+                if self.0.get() == 1 {
+                    Ok(VmResponse::Err(SysError::new(EAGAIN)))
+                } else {
+                    Ok(VmResponse::Err(SysError::new(ENOTSUP)))
+                }
+            }
+        }
+
+        let transport = BusyVm(Cell::new(0));
+        let response = send_with_retry(&transport, &VmRequest::Powerbtn, test_policy(5));
+        assert!(matches!(response, Ok(VmResponse::Err(e)) if e == SysError::new(ENOTSUP)));
+        assert_eq!(transport.0.get(), 2);
+    }
+
+    // Receive timeouts aren't implemented for Windows tubes.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn recv_timeout_not_retried() {
+        let (tube, vm) = Tube::pair().unwrap();
+        tube.set_recv_timeout(Some(Duration::from_millis(1)))
+            .unwrap();
+
+        // The VM doesn't respond in time, so the late response must not be taken for the response
+        // to a second attempt.
+        let response = send_with_retry(&tube, &VmRequest::Powerbtn, test_policy(5));
+        assert_eq!(response.err(), Some(SysError::new(EAGAIN)));
+        assert!(matches!(
+            vm.recv::<VmRequest>().unwrap(),
+            VmRequest::Powerbtn
+        ));
+        vm.set_recv_timeout(Some(Duration::from_millis(1))).unwrap();
+        assert!(vm.recv::<VmRequest>().is_err());
+    }
+
+    #[test]
+    fn registered_memory_guard_unregisters_on_drop() {
+        let (tube, vm) = Tube::pair().unwrap();
+        let client = VmMemoryClient::new(tube);
+        vm.send(&VmMemoryResponse::Ok).unwrap();
+
+        let guard = RegisteredMemoryGuard::new(&client, VmMemoryRegionId(0x1000));
+        assert_eq!(guard.id(), VmMemoryRegionId(0x1000));
+        drop(guard);
+
+        let request = vm.recv::<VmMemoryRequest>().unwrap();
+        assert!(matches!(
+            request,
+            VmMemoryRequest::UnregisterMemory {
+                id: VmMemoryRegionId(0x1000),
+                release_pages: false,
+            }
+        ));
+    }
+
+    #[test]
+    fn registered_memory_guard_into_id() {
+        let (tube, vm) = Tube::pair().unwrap();
+        let client = VmMemoryClient::new(tube);
+
+        let guard = RegisteredMemoryGuard::new(&client, VmMemoryRegionId(0x1000));
+        assert_eq!(guard.into_id(), VmMemoryRegionId(0x1000));
+        drop(client);
+
+        // The VM doesn't receive an unregister request before the client disconnects.
+        assert!(matches!(
+            vm.recv::<VmMemoryRequest>(),
+            Err(TubeError::Disconnected)
+        ));
+    }
+}
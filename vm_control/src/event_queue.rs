@@ -0,0 +1,214 @@
+// Copyright 2023 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Bounded queue used to deliver events to a listener without blocking the sender.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::thread;
+
+use base::warn;
+use sync::Condvar;
+use sync::Mutex;
+use thiserror::Error;
+
+/// Returned by [`BoundedEventQueue::push`] once delivering an event to the listener failed.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("failed to deliver an earlier event to the listener")]
+pub struct EventDeliveryFailed;
+
+struct QueueState<T> {
+    events: VecDeque<T>,
+    dropped: u64,
+    closed: bool,
+    failed: bool,
+}
+
+struct Shared<T> {
+    state: Mutex<QueueState<T>>,
+    cvar: Condvar,
+}
+
+/// Queue of at most `capacity` events, which are delivered in order to a listener on a dedicated
+/// thread.
+///
+/// Pushing an event never blocks: when the listener doesn't keep up and the queue is full, the
+/// oldest queued event is dropped and counted in [`BoundedEventQueue::dropped_count`]. This keeps a
+/// slow listener from stalling the sender and the other listeners.
+pub struct BoundedEventQueue<T> {
+    shared: Arc<Shared<T>>,
+    capacity: usize,
+}
+
+impl<T: Send + 'static> BoundedEventQueue<T> {
+    /// Creates a queue whose events are passed to `deliver` on a thread named `name`.
+    ///
+    /// The thread stops once the queue is dropped, or after `deliver` returns an error. In the
+    /// latter case, the queued events are discarded and further pushes fail. `deliver` must not
+    /// block forever, e.g. it should send with a timeout, or the thread never stops if the listener
+    /// stops reading.
+    pub fn new<F, E>(name: String, capacity: usize, mut deliver: F) -> std::io::Result<Self>
+    where
+        F: FnMut(T) -> Result<(), E> + Send + 'static,
+        E: std::fmt::Display,
+    {
+        assert!(capacity > 0, "event queue capacity must not be 0");
+        let shared = Arc::new(Shared {
+            state: Mutex::new(QueueState {
+                events: VecDeque::with_capacity(capacity),
+                dropped: 0,
+                closed: false,
+                failed: false,
+            }),
+            cvar: Condvar::new(),
+        });
+        let worker_shared = shared.clone();
+        // The worker is detached rather than joined on drop, since it may be blocked on a listener
+        // that doesn't read its events. It exits once the pending delivery completes or times out.
+        thread::Builder::new().name(name).spawn(move || loop {
+            let event = {
+                let mut state = worker_shared.state.lock();
+                loop {
+                    if let Some(event) = state.events.pop_front() {
+                        break event;
+                    }
+                    if state.closed {
+                        return;
+                    }
+                    state = worker_shared.cvar.wait(state);
+                }
+            };
+            if let Err(e) = deliver(event) {
+                warn!("failed to deliver event: {}", e);
+                let mut state = worker_shared.state.lock();
+                state.failed = true;
+                state.events.clear();
+                return;
+            }
+        })?;
+        Ok(BoundedEventQueue { shared, capacity })
+    }
+
+    /// Queues `event` for delivery, dropping the oldest queued event if the queue is full.
+    ///
+    /// Fails if an earlier event couldn't be delivered, meaning the listener is gone.
+    pub fn push(&self, event: T) -> Result<(), EventDeliveryFailed> {
+        let mut state = self.shared.state.lock();
+        if state.failed {
+            return Err(EventDeliveryFailed);
+        }
+        if state.events.len() == self.capacity {
+            state.events.pop_front();
+            state.dropped += 1;
+        }
+        state.events.push_back(event);
+        self.shared.cvar.notify_one();
+        Ok(())
+    }
+
+    /// Returns the number of events dropped because the queue was full.
+    pub fn dropped_count(&self) -> u64 {
+        self.shared.state.lock().dropped
+    }
+}
+
+impl<T> Drop for BoundedEventQueue<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().closed = true;
+        self.shared.cvar.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::sync::mpsc::RecvTimeoutError;
+    use std::time::Duration;
+
+    use base::Tube;
+
+    use super::*;
+
+    #[test]
+    fn non_draining_listener_drops_oldest() {
+        let (started_tx, started_rx) = channel();
+        let (unblock_tx, unblock_rx) = channel::<()>();
+        let (delivered_tx, delivered_rx) = channel();
+        let queue = BoundedEventQueue::new("test_event_queue".to_string(), 4, move |event: u32| {
+            delivered_tx.send(event).unwrap();
+            if event == 0 {
+                // Stop reading events, like a listener that went unresponsive.
+                started_tx.send(()).unwrap();
+                unblock_rx.recv().unwrap();
+            }
+            Ok::<(), std::io::Error>(())
+        })
+        .unwrap();
+
+        queue.push(0).unwrap();
+        started_rx.recv().unwrap();
+        // None of these block even though the listener isn't reading.
+        for event in 1..10 {
+            queue.push(event).unwrap();
+        }
+        assert_eq!(queue.dropped_count(), 5);
+
+        unblock_tx.send(()).unwrap();
+        let delivered: Vec<u32> = delivered_rx.iter().take(5).collect();
+        assert_eq!(delivered, vec![0, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn push_after_delivery_failure() {
+        let (failed_tx, failed_rx) = channel();
+        let queue = BoundedEventQueue::new("test_event_queue".to_string(), 4, move |_: u32| {
+            failed_tx.send(()).unwrap();
+            Err("listener closed")
+        })
+        .unwrap();
+
+        queue.push(0).unwrap();
+        failed_rx.recv().unwrap();
+        // The worker marks the queue as failed right after `deliver` returns.
+        let mut result = Ok(());
+        for _ in 0..100 {
+            result = queue.push(1);
+            if result.is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(result, Err(EventDeliveryFailed));
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn send_timeout_stops_worker() {
+        let (tube, _listener) = Tube::pair().unwrap();
+        tube.set_send_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let (worker_tx, worker_rx) = channel::<()>();
+        let queue =
+            BoundedEventQueue::new("test_event_queue".to_string(), 4, move |event: Vec<u8>| {
+                // Dropped with `deliver` once the worker exits.
+                let _worker_tx = &worker_tx;
+                tube.send(&event)
+            })
+            .unwrap();
+
+        // The listener never reads, so a send eventually blocks on the full socket buffer and times
+        // out.
+        for _ in 0..1000 {
+            if queue.push(vec![0; 16 * 1024]).is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            worker_rx.recv_timeout(Duration::from_secs(5)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+        assert_eq!(queue.push(vec![]), Err(EventDeliveryFailed));
+    }
+}
@@ -7,8 +7,16 @@
 //! The VM Control IPC protocol is synchronous, meaning that each `VmRequest` sent over a connection
 //! will receive a `VmResponse` for that request next time data is received over that connection.
 //!
-//! The wire message format is a little-endian C-struct of fixed size, along with a file descriptor
-//! if the request type expects one.
+//! Each message is serialized as JSON and sent over a `Tube` as a single message of whatever size
+//! it needs. On Linux the control socket is `SOCK_SEQPACKET`, which preserves message boundaries, so
+//! variable-length requests need no extra framing. Any file descriptors a message refers to (e.g.
+//! the device file of `UsbControlCommand::AttachDevice`) are sent along with it. The VM receives
+//! requests with `Tube::recv_with_exact_descriptors`, so a request must refer to exactly the
+//! descriptors sent with it, otherwise it is rejected with `TubeError::MissingDescriptors` or
+//! `TubeError::UnusedDescriptors`.
+//!
+//! The JSON encoding doesn't depend on the byte order of the host. The only binary framing, the
+//! message sizes a `Tube` prepends on Windows, is always little-endian `u64`.
 
 pub mod api;
 #[cfg(feature = "gdb")]
@@ -26,6 +34,7 @@ use hypervisor::MemRegion;
 #[cfg(feature = "balloon")]
 mod balloon_tube;
 pub mod client;
+mod event_queue;
 pub mod sys;
 
 #[cfg(target_arch = "x86_64")]
@@ -33,13 +42,18 @@ use std::arch::x86_64::_rdtsc;
 use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::convert::TryInto;
 use std::fmt;
 use std::fmt::Display;
 use std::fs::File;
+use std::ops::RangeInclusive;
+use std::path::Path;
 use std::path::PathBuf;
 use std::result::Result as StdResult;
 use std::str::FromStr;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::mpsc;
 use std::sync::Arc;
 
@@ -47,6 +61,8 @@ use anyhow::bail;
 use anyhow::Context;
 use base::error;
 use base::info;
+use base::pagesize;
+use base::syslog::LogLevel;
 use base::warn;
 use base::with_as_descriptor;
 use base::AsRawDescriptor;
@@ -64,10 +80,12 @@ use base::SafeDescriptor;
 use base::SharedMemory;
 use base::Tube;
 use hypervisor::Datamatch;
+use hypervisor::HypervisorCaps;
 use hypervisor::IoEventAddress;
 use hypervisor::IrqRoute;
 use hypervisor::IrqSource;
 pub use hypervisor::MemSlot;
+pub use hypervisor::VcpuRegisters;
 use hypervisor::VcpuSnapshot;
 use hypervisor::Vm;
 use libc::EINVAL;
@@ -87,8 +105,11 @@ use rutabaga_gfx::RutabagaGralloc;
 use rutabaga_gfx::RutabagaHandle;
 use rutabaga_gfx::RutabagaMappedRegion;
 use rutabaga_gfx::VulkanInfo;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde::Serialize;
+use serde_json::value::RawValue;
+use swap::SwapOutProgress;
 use swap::SwapStatus;
 use sync::Mutex;
 #[cfg(any(target_os = "android", target_os = "linux"))]
@@ -105,6 +126,8 @@ use vm_memory::GuestAddress;
 
 #[cfg(feature = "balloon")]
 pub use crate::balloon_tube::*;
+pub use crate::event_queue::BoundedEventQueue;
+pub use crate::event_queue::EventDeliveryFailed;
 #[cfg(feature = "gdb")]
 pub use crate::gdb::VcpuDebug;
 #[cfg(feature = "gdb")]
@@ -123,10 +146,112 @@ pub enum VcpuControl {
     Debug(VcpuDebug),
     RunState(VmRunMode),
     MakeRT,
+    // Schedule the vCPU thread with the `SCHED_FIFO` real-time policy at `priority`, or restore
+    // the policy it had before if `enable` is false. The result is sent back over the included
+    // channel.
+    SetRealtime {
+        enable: bool,
+        priority: u32,
+        response_chan: mpsc::Sender<StdResult<(), SysError>>,
+    },
+    // Force the vCPU out of the hypervisor run loop once, without changing its run mode.
+    Kick,
     // Request the current state of the vCPU. The result is sent back over the included channel.
     GetStates(mpsc::Sender<VmRunMode>),
-    Snapshot(mpsc::Sender<anyhow::Result<VcpuSnapshot>>),
+    Snapshot(mpsc::Sender<anyhow::Result<SerializedVcpuSnapshot>>),
     Restore(VcpuRestoreRequest),
+    // Request a snapshot of the vCPU registers for debugging. The result is sent back over the
+    // included channel.
+    GetRegisters(mpsc::Sender<anyhow::Result<VcpuRegisters>>),
+}
+
+/// A vCPU snapshot serialized to JSON by the vCPU's own thread.
+///
+/// Serializing the vCPU state (the x86 xsave area in particular) is the bulk of the work of
+/// snapshotting a vCPU. Having each vCPU thread do it for its own snapshot lets it happen in
+/// parallel, and leaves the control thread with only joining the results in
+/// `write_vcpu_snapshots`.
+#[derive(Debug)]
+pub struct SerializedVcpuSnapshot {
+    vcpu_id: usize,
+    json: Vec<u8>,
+}
+
+impl SerializedVcpuSnapshot {
+    pub fn new(snapshot: &VcpuSnapshot) -> anyhow::Result<Self> {
+        Self::from_serializable(snapshot.vcpu_id, snapshot)
+    }
+
+    fn from_serializable<T: Serialize>(vcpu_id: usize, snapshot: &T) -> anyhow::Result<Self> {
+        Ok(SerializedVcpuSnapshot {
+            vcpu_id,
+            json: serde_json::to_vec(snapshot)
+                .with_context(|| format!("failed to serialize snapshot of vcpu {}", vcpu_id))?,
+        })
+    }
+}
+
+/// Writes `snapshots` ordered by vCPU id as a JSON array, which is byte-for-byte what serializing
+/// the original snapshots as a `Vec` would produce.
+fn write_vcpu_snapshots(
+    mut writer: impl std::io::Write,
+    snapshots: &mut [SerializedVcpuSnapshot],
+) -> std::io::Result<()> {
+    snapshots.sort_by_key(|s| s.vcpu_id);
+    writer.write_all(b"[")?;
+    for (i, snapshot) in snapshots.iter().enumerate() {
+        if i != 0 {
+            writer.write_all(b",")?;
+        }
+        writer.write_all(&snapshot.json)?;
+    }
+    writer.write_all(b"]")
+}
+
+/// Reads the JSON array of vCPU snapshots written by `write_vcpu_snapshots`, in the same order.
+///
+/// Only splitting the array into the vCPUs' snapshots is done on the calling thread. Like when
+/// serializing them, deserializing the vCPU state is the bulk of the work, so the snapshots are
+/// deserialized in parallel, spread over at most one thread per host CPU.
+fn read_vcpu_snapshots<T: DeserializeOwned + Send>(
+    mut reader: impl std::io::Read,
+) -> anyhow::Result<Vec<T>> {
+    let mut json = Vec::new();
+    reader.read_to_end(&mut json)?;
+    let raw_snapshots: Vec<&RawValue> = serde_json::from_slice(&json)?;
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = std::cmp::max(1, (raw_snapshots.len() + threads - 1) / threads);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = raw_snapshots
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(chunk_index, chunk)| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .enumerate()
+                        .map(|(i, raw)| {
+                            serde_json::from_str(raw.get()).with_context(|| {
+                                format!(
+                                    "failed to deserialize vcpu snapshot {}",
+                                    chunk_index * chunk_size + i
+                                )
+                            })
+                        })
+                        .collect::<anyhow::Result<Vec<T>>>()
+                })
+            })
+            .collect();
+        let mut snapshots = Vec::with_capacity(raw_snapshots.len());
+        for worker in workers {
+            snapshots.extend(
+                worker
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))?,
+            );
+        }
+        Ok(snapshots)
+    })
 }
 
 /// Request to restore a Vcpu from a given snapshot, and report the results
@@ -140,7 +265,7 @@ pub struct VcpuRestoreRequest {
 }
 
 /// Mode of execution for the VM.
-#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum VmRunMode {
     /// The default run mode indicating the VCPUs are running.
     #[default]
@@ -151,6 +276,8 @@ pub enum VmRunMode {
     Exiting,
     /// Indicates that the VM is in a breakpoint waiting for the debugger to do continue.
     Breakpoint,
+    /// Indicates that the VCPUs execute a single instruction and then switch to `Suspending`.
+    SingleStep,
 }
 
 impl Display for VmRunMode {
@@ -162,8 +289,50 @@ impl Display for VmRunMode {
             Suspending => write!(f, "suspending"),
             Exiting => write!(f, "exiting"),
             Breakpoint => write!(f, "breakpoint"),
+            SingleStep => write!(f, "single-step"),
+        }
+    }
+}
+
+/// Holds the `VcpuControl::GetStates` replies of a vCPU that is executing a single step.
+///
+/// While in `VmRunMode::SingleStep`, a vCPU replies to `GetStates` only once the step has finished,
+/// so sending `RunState(SingleStep)` followed by `GetStates` lets the sender wait for the step.
+#[derive(Default)]
+pub struct SingleStepWaiters {
+    waiters: Vec<mpsc::Sender<VmRunMode>>,
+}
+
+impl SingleStepWaiters {
+    /// Replies to a `GetStates` request with `run_mode`, or holds the reply if a step is in
+    /// progress.
+    pub fn get_state(
+        &mut self,
+        run_mode: VmRunMode,
+        response_chan: mpsc::Sender<VmRunMode>,
+    ) -> StdResult<(), mpsc::SendError<VmRunMode>> {
+        if run_mode == VmRunMode::SingleStep {
+            self.waiters.push(response_chan);
+            Ok(())
+        } else {
+            response_chan.send(run_mode)
+        }
+    }
+
+    /// Replies to all held `GetStates` requests with `run_mode`, e.g. when the step was cancelled
+    /// by switching to another mode.
+    pub fn reply(&mut self, run_mode: VmRunMode) {
+        for waiter in self.waiters.drain(..) {
+            // The requester may have given up waiting.
+            let _ = waiter.send(run_mode);
         }
     }
+
+    /// Finishes a single step, returning the mode the vCPU switches to.
+    pub fn step_complete(&mut self) -> VmRunMode {
+        self.reply(VmRunMode::Suspending);
+        VmRunMode::Suspending
+    }
 }
 
 // Trait for devices that get notification on specific GPE trigger
@@ -180,8 +349,24 @@ pub trait PmResource {
     fn pwrbtn_evt(&mut self) {}
     fn slpbtn_evt(&mut self) {}
     fn rtc_evt(&mut self) {}
+    /// Arms a one-shot RTC wake alarm, replacing any pending one. `rtc_evt` is raised when it
+    /// expires.
+    fn set_rtc_alarm(&mut self, _seconds_from_now: u64) {}
+    /// Disarms the pending RTC wake alarm, if any.
+    fn clear_rtc_alarm(&mut self) {}
     fn gpe_evt(&mut self, _gpe: u32) {}
     fn pme_evt(&mut self, _requester_id: u16) {}
+    /// Injects a PME for each of `requester_ids`, in order, as one operation, and returns the
+    /// result of each injection in the same order.
+    fn pme_evt_batch(&mut self, requester_ids: &[u16]) -> Vec<Result<()>> {
+        requester_ids
+            .iter()
+            .map(|requester_id| {
+                self.pme_evt(*requester_id);
+                Ok(())
+            })
+            .collect()
+    }
     fn register_gpe_notify_dev(&mut self, _gpe: u32, _notify_dev: Arc<Mutex<dyn GpeNotify>>) {}
     fn register_pme_notify_dev(&mut self, _bus: u8, _notify_dev: Arc<Mutex<dyn PmeNotify>>) {}
 }
@@ -197,6 +382,13 @@ pub const USB_CONTROL_MAX_PORTS: usize = 16;
 pub enum DiskControlCommand {
     /// Resize a disk to `new_size` in bytes.
     Resize { new_size: u64 },
+    /// Flush the disk's pending writes to its backing storage. Read-only disks succeed
+    /// immediately.
+    Flush,
+    /// Switch the disk between read-only and read-write. In-flight writes are drained before the
+    /// disk becomes read-only; if they can't be, the disk is left writable and an error is
+    /// returned. Making a disk writable fails with `ENOTSUP` if its backend was opened read-only.
+    SetReadOnly { read_only: bool },
 }
 
 impl Display for DiskControlCommand {
@@ -205,6 +397,8 @@ impl Display for DiskControlCommand {
 
         match self {
             Resize { new_size } => write!(f, "disk_resize {}", new_size),
+            Flush => write!(f, "disk_flush"),
+            SetReadOnly { read_only } => write!(f, "disk_set_read_only {}", read_only),
         }
     }
 }
@@ -223,6 +417,34 @@ pub enum NetControlCommand {
     RemoveTap(u8),
 }
 
+/// A PCI hotplug slot, as reported by `VmRequest::ListHotplugSlots`.
+#[cfg(feature = "pci-hotplug")]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HotplugSlot {
+    /// Downstream bus number of the hotplug port.
+    pub bus: u8,
+    /// Whether devices are hotplugged on the bus.
+    pub occupied: bool,
+    /// Type of the hotplugged devices, if any.
+    pub device_type: Option<HotPlugDeviceType>,
+}
+
+/// Provides the slot map for `VmRequest::ListHotplugSlots`.
+#[cfg(feature = "pci-hotplug")]
+pub trait HotplugSlotProvider {
+    /// Returns all the hotplug slots, sorted by bus number.
+    fn hotplug_slots(&self) -> Vec<HotplugSlot>;
+}
+
+/// Handles `VmRequest::ListHotplugSlots`, where `provider` is `None` if PCI hotplug is disabled.
+#[cfg(feature = "pci-hotplug")]
+pub fn handle_list_hotplug_slots(provider: Option<&dyn HotplugSlotProvider>) -> VmResponse {
+    match provider {
+        Some(provider) => VmResponse::HotplugSlots(provider.hotplug_slots()),
+        None => VmResponse::ErrString("PCI hotplug is not enabled.".to_owned()),
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum UsbControlCommand {
     AttachDevice {
@@ -235,9 +457,12 @@ pub enum UsbControlCommand {
     ListDevice {
         ports: [u8; USB_CONTROL_MAX_PORTS],
     },
+    /// Lists the devices attached to every port. The result is a `UsbControlResult::DeviceList`,
+    /// which isn't limited to `USB_CONTROL_MAX_PORTS` entries.
+    ListDeviceV2,
 }
 
-#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
 pub struct UsbControlAttachedDevice {
     pub port: u8,
     pub vendor_id: u16,
@@ -275,13 +500,25 @@ impl Display for PciControlResult {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum UsbControlResult {
-    Ok { port: u8 },
+    Ok {
+        port: u8,
+    },
     NoAvailablePort,
     NoSuchDevice,
     NoSuchPort,
     FailedToOpenDevice,
     Devices([UsbControlAttachedDevice; USB_CONTROL_MAX_PORTS]),
     FailedToInitHostDevice,
+    /// Valid devices attached to the hub, in response to `UsbControlCommand::ListDeviceV2`.
+    DeviceList(Vec<UsbControlAttachedDevice>),
+}
+
+fn write_devices(f: &mut fmt::Formatter, devices: &[UsbControlAttachedDevice]) -> fmt::Result {
+    write!(f, "devices")?;
+    for d in devices.iter().filter(|d| d.valid()) {
+        write!(f, " {} {:04x} {:04x}", d.port, d.vendor_id, d.product_id)?;
+    }
+    std::result::Result::Ok(())
 }
 
 impl Display for UsbControlResult {
@@ -294,14 +531,9 @@ impl Display for UsbControlResult {
             NoSuchDevice => write!(f, "no_such_device"),
             NoSuchPort => write!(f, "no_such_port"),
             FailedToOpenDevice => write!(f, "failed_to_open_device"),
-            Devices(devices) => {
-                write!(f, "devices")?;
-                for d in devices.iter().filter(|d| d.valid()) {
-                    write!(f, " {} {:04x} {:04x}", d.port, d.vendor_id, d.product_id)?;
-                }
-                std::result::Result::Ok(())
-            }
+            Devices(devices) => write_devices(f, devices),
             FailedToInitHostDevice => write!(f, "failed_to_init_host_device"),
+            DeviceList(devices) => write_devices(f, devices),
         }
     }
 }
@@ -309,7 +541,14 @@ impl Display for UsbControlResult {
 /// Commands for snapshot feature
 #[derive(Serialize, Deserialize, Debug)]
 pub enum SnapshotCommand {
-    Take { snapshot_path: PathBuf },
+    Take {
+        snapshot_path: PathBuf,
+    },
+    /// Only snapshot the state of the devices to `snapshot_path`, for debugging. The vCPUs and the
+    /// irqchip are not captured, so the resulting snapshot can't be restored as a VM.
+    TakeDevicesOnly {
+        snapshot_path: PathBuf,
+    },
 }
 
 /// Commands for restore feature
@@ -349,6 +588,92 @@ pub enum IrqHandlerRequest {
 
 const EXPECTED_MAX_IRQ_FLUSH_ITERATIONS: usize = 100;
 
+/// How the IRQ handler thread is cycled to flush the pending IRQs before a snapshot.
+///
+/// With either strategy, the handler is cycled until an iteration services no tokens.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IrqFlushStrategy {
+    /// IRQs are delivered through irqfds, including the MSIs raised by a userspace IOAPIC (e.g.
+    /// KVM's split irqchip), so a single iteration of the handler delivers them to the LAPICs.
+    SinglePass,
+    /// Without irqfds (e.g. WHPX), legacy IRQs going through the userspace IOAPIC need a second
+    /// iteration to deliver their underlying MSI. Iterate at least twice.
+    MultiPass,
+}
+
+impl IrqFlushStrategy {
+    /// Returns the strategy for a hypervisor with the capabilities `caps`, usually the ones
+    /// returned by `hypervisor::hypervisor_caps`.
+    pub fn from_caps(caps: &HypervisorCaps) -> Self {
+        if caps.irqfd {
+            IrqFlushStrategy::SinglePass
+        } else {
+            IrqFlushStrategy::MultiPass
+        }
+    }
+}
+
+/// Priorities accepted for `VmRequest::SetVcpuRealtime`, which is the range of the Linux
+/// `SCHED_FIFO` policy.
+const VCPU_REALTIME_PRIORITY_RANGE: RangeInclusive<u32> = 1..=99;
+
+/// Version of the snapshot format, stored in the `.version` file of a snapshot.
+///
+/// The major version must be bumped on changes that older versions of crosvm can't restore, and
+/// snapshots with a different major version are rejected by `do_restore`.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+struct SnapshotVersion {
+    major: u32,
+    minor: u32,
+}
+
+impl Display for SnapshotVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+const SNAPSHOT_VERSION: SnapshotVersion = SnapshotVersion { major: 1, minor: 0 };
+
+fn write_snapshot_version(snapshot_path: &Path, version: SnapshotVersion) -> anyhow::Result<()> {
+    let version_path = snapshot_path.with_extension("version");
+    let version_file = File::create(&version_path)
+        .with_context(|| format!("failed to open path {}", version_path.display()))?;
+    serde_json::to_writer(version_file, &version)
+        .with_context(|| format!("failed to write {}", version_path.display()))
+}
+
+/// Checks that the snapshot at `restore_path` can be restored by this version of crosvm.
+fn check_snapshot_version(restore_path: &Path) -> anyhow::Result<()> {
+    let version_path = restore_path.with_extension("version");
+    let version_file = match File::open(&version_path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            // Snapshots taken before the format was versioned.
+            warn!(
+                "snapshot has no version file at {}, assuming version {}",
+                version_path.display(),
+                SNAPSHOT_VERSION
+            );
+            return Ok(());
+        }
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("failed to open path {}", version_path.display()))
+        }
+    };
+    let version: SnapshotVersion = serde_json::from_reader(version_file)
+        .with_context(|| format!("failed to parse {}", version_path.display()))?;
+    if version.major != SNAPSHOT_VERSION.major {
+        bail!(
+            "incompatible snapshot version {}, this crosvm supports version {}",
+            version,
+            SNAPSHOT_VERSION
+        );
+    }
+    Ok(())
+}
+
 /// Response for [IrqHandlerRequest].
 #[derive(Serialize, Deserialize, Debug)]
 pub enum IrqHandlerResponse {
@@ -385,6 +710,16 @@ pub enum VmMemorySource {
     },
     /// Register the current rutabaga external mapping.
     ExternalMapping { ptr: u64, size: u64 },
+    /// Register several file mappings as one contiguous region, laid out in order. Each part is
+    /// a `(descriptor, offset, size)` tuple whose offset and size must be page aligned.
+    ///
+    /// Not supported on Windows.
+    Composite {
+        parts: Vec<(SafeDescriptor, u64, u64)>,
+    },
+    /// Register `size` bytes of zero-initialized anonymous memory, with no backing file. `size`
+    /// must be a nonzero multiple of the page size.
+    Anonymous { size: u64 },
 }
 
 // The following are wrappers to avoid base dependencies in the rutabaga crate
@@ -419,6 +754,18 @@ unsafe impl MappedRegion for RutabagaMemoryRegion {
 }
 
 impl VmMemorySource {
+    /// Whether the host pages backing this source may be released once it is unregistered. Vulkan
+    /// and external mappings are owned by their graphics allocator, which may still be using them.
+    fn pages_releasable(&self) -> bool {
+        match self {
+            VmMemorySource::SharedMemory(_)
+            | VmMemorySource::Descriptor { .. }
+            | VmMemorySource::Composite { .. }
+            | VmMemorySource::Anonymous { .. } => true,
+            VmMemorySource::Vulkan { .. } | VmMemorySource::ExternalMapping { .. } => false,
+        }
+    }
+
     /// Map the resource and return its mapping and size in bytes.
     pub fn map(
         self,
@@ -481,6 +828,11 @@ impl VmMemorySource {
                 });
                 (mapped_region, size, None)
             }
+            VmMemorySource::Composite { parts } => {
+                let (mapped_region, size) = sys::map_composite(&parts, prot)?;
+                (mapped_region, size, None)
+            }
+            VmMemorySource::Anonymous { size } => (map_anonymous(size, prot)?, size, None),
         };
         Ok((mem_region, size, descriptor))
     }
@@ -497,12 +849,15 @@ pub enum VmMemoryDestination {
 
 impl VmMemoryDestination {
     /// Allocate and return the guest address of a memory mapping destination.
-    pub fn allocate(self, allocator: &mut SystemAllocator, size: u64) -> Result<GuestAddress> {
+    pub fn allocate(
+        self,
+        allocator: &mut SystemAllocator,
+        size: u64,
+    ) -> StdResult<GuestAddress, resources::Error> {
         let addr = match self {
             VmMemoryDestination::ExistingAllocation { allocation, offset } => allocator
                 .mmio_allocator_any()
-                .address_from_pci_offset(allocation, offset, size)
-                .map_err(|_e| SysError::new(EINVAL))?,
+                .address_from_pci_offset(allocation, offset, size)?,
             VmMemoryDestination::GuestPhysicalAddress(gpa) => gpa,
         };
         Ok(GuestAddress(addr))
@@ -528,7 +883,8 @@ pub enum VmMemoryRequest {
         source: VmMemorySource,
         /// Where to map the memory in the guest.
         dest: VmMemoryDestination,
-        /// Whether to map the memory read only (true) or read-write (false).
+        /// Protection of the mapping. Read-only protections are also mapped read only in the
+        /// guest.
         prot: Protection,
     },
     /// Call hypervisor to free the given memory range.
@@ -544,7 +900,21 @@ pub enum VmMemoryRequest {
     /// Balloon allocation/deallocation target reached.
     BalloonTargetReached { size: u64 },
     /// Unregister the given memory slot that was previously registered with `RegisterMemory`.
-    UnregisterMemory(VmMemoryRegionId),
+    UnregisterMemory {
+        id: VmMemoryRegionId,
+        /// Release the host pages backing the region before unmapping it so they are returned to
+        /// the host promptly. This discards the contents of the shared memory or file backing the
+        /// region. Only done for shared memory, file descriptor and anonymous mappings; ignored
+        /// for Vulkan and external mappings.
+        release_pages: bool,
+    },
+    /// Populate the host pages backing a region previously registered with `RegisterMemory`, so
+    /// the guest's first access to each page doesn't take a fault. Useful for latency sensitive
+    /// workloads.
+    PrefaultRegion { id: VmMemoryRegionId },
+    /// Query the number and total size of the regions registered with `RegisterMemory` that are
+    /// still mapped. Expects a `VmMemoryResponse::Registered`.
+    QueryRegistered,
     /// Register an ioeventfd by looking up using Alloc info.
     IoEventWithAlloc {
         evt: Event,
@@ -557,6 +927,53 @@ pub enum VmMemoryRequest {
     IoEventRaw(IoEventUpdateRequest),
 }
 
+/// Reasons a `VmMemoryRequest` can fail. They are logged by `VmMemoryRequest::execute` and
+/// converted to a `SysError` for the `VmMemoryResponse`.
+#[sorted]
+#[derive(Error, Debug)]
+pub enum VmControlError {
+    #[error("failed to add memory region: {0}")]
+    AddMemoryRegion(SysError),
+    #[error("failed to allocate guest address: {0}")]
+    AddressAllocation(resources::Error),
+    #[error("balloon event failed: {0}")]
+    Balloon(SysError),
+    #[error("failed to update ioevent: {0}")]
+    IoEvent(SysError),
+    #[error("IOMMU rejected the request: {0:?}")]
+    IommuRejected(VirtioIOMMURequestResult),
+    #[error("failed to map memory: {0}")]
+    Mapping(SysError),
+    #[error("failed to populate memory region: {0}")]
+    PopulateMemoryRegion(SysError),
+    #[error("failed to prepare shared memory region: {0}")]
+    PrepareSharedMemoryRegion(SysError),
+    #[error("failed to remove memory region: {0}")]
+    RemoveMemoryRegion(SysError),
+    #[error("no memory region registered as {0:?}")]
+    UnknownRegion(VmMemoryRegionId),
+    #[error("memory source can't be mapped into a prepared region")]
+    UnsupportedSource,
+}
+
+impl From<VmControlError> for SysError {
+    fn from(e: VmControlError) -> Self {
+        use self::VmControlError::*;
+        match e {
+            AddMemoryRegion(e)
+            | Balloon(e)
+            | IoEvent(e)
+            | Mapping(e)
+            | PopulateMemoryRegion(e)
+            | PrepareSharedMemoryRegion(e)
+            | RemoveMemoryRegion(e) => e,
+            AddressAllocation(_) | IommuRejected(_) | UnknownRegion(_) | UnsupportedSource => {
+                SysError::new(EINVAL)
+            }
+        }
+    }
+}
+
 /// Struct for managing `VmMemoryRequest`s IOMMU related state.
 pub struct VmMemoryRequestIommuClient {
     tube: Arc<Mutex<Tube>>,
@@ -573,11 +990,21 @@ impl VmMemoryRequestIommuClient {
     }
 }
 
+/// A region registered with `VmMemoryRequest::RegisterMemory`.
+#[derive(Clone, Copy)]
+struct MappedRegionInfo {
+    slot: MemSlot,
+    // Offset of the region within `slot`, only set for regions mapped into part of a prepared slot.
+    offset: Option<usize>,
+    size: usize,
+    // Whether the backing pages may be released when unregistering.
+    pages_releasable: bool,
+}
+
 pub struct VmMemoryRegionState {
     // alloc -> (pfn, slot)
     slot_map: HashMap<Alloc, (u64, MemSlot)>,
-    // id -> (slot, Option<offset, size>)
-    mapped_regions: BTreeMap<VmMemoryRegionId, (MemSlot, Option<(usize, usize)>)>,
+    mapped_regions: BTreeMap<VmMemoryRegionId, MappedRegionInfo>,
 }
 
 impl VmMemoryRegionState {
@@ -587,6 +1014,29 @@ impl VmMemoryRegionState {
             mapped_regions: BTreeMap::new(),
         }
     }
+
+    /// Returns the number of memory slots held in the VM: the prepared slots plus the slots of
+    /// regions registered outside of them.
+    pub fn live_descriptor_count(&self) -> usize {
+        self.slot_map.len()
+            + self
+                .mapped_regions
+                .values()
+                .filter(|info| info.offset.is_none())
+                .count()
+    }
+
+    /// Returns whether `slot_map` and `mapped_regions` agree on which slots are live: regions
+    /// mapped into part of a slot must point at a prepared slot, and every other region must own
+    /// a slot of its own.
+    fn descriptor_accounting_consistent(&self) -> bool {
+        let prepared: HashSet<MemSlot> = self.slot_map.values().map(|(_, slot)| *slot).collect();
+        let mut owned = HashSet::new();
+        self.mapped_regions.values().all(|info| match info.offset {
+            Some(_) => prepared.contains(&info.slot),
+            None => !prepared.contains(&info.slot) && owned.insert(info.slot),
+        })
+    }
 }
 
 impl Default for VmMemoryRegionState {
@@ -601,7 +1051,7 @@ fn handle_prepared_region(
     source: &VmMemorySource,
     dest: &VmMemoryDestination,
     prot: &Protection,
-) -> Option<VmMemoryResponse> {
+) -> Option<StdResult<VmMemoryResponse, VmControlError>> {
     let VmMemoryDestination::ExistingAllocation { allocation, offset } = dest else {
         return None;
     };
@@ -622,7 +1072,7 @@ fn handle_prepared_region(
             let size = shm.size() as usize;
             (Descriptor(shm.as_raw_descriptor()), 0, size)
         }
-        _ => return Some(VmMemoryResponse::Err(SysError::new(EINVAL))),
+        _ => return Some(Err(VmControlError::UnsupportedSource)),
     };
     if let Err(err) = vm.add_fd_mapping(
         *slot,
@@ -632,14 +1082,83 @@ fn handle_prepared_region(
         file_offset,
         *prot,
     ) {
-        return Some(VmMemoryResponse::Err(err));
+        return Some(Err(VmControlError::Mapping(err)));
     }
     let pfn = pfn + (offset >> 12);
     region_state.mapped_regions.insert(
         VmMemoryRegionId(pfn),
-        (*slot, Some((*offset as usize, size))),
+        MappedRegionInfo {
+            slot: *slot,
+            offset: Some(*offset as usize),
+            size,
+            pages_releasable: source.pages_releasable(),
+        },
+    );
+    Some(Ok(VmMemoryResponse::RegisterMemory(VmMemoryRegionId(pfn))))
+}
+
+fn register_memory(
+    vm: &mut impl Vm,
+    sys_allocator: &mut SystemAllocator,
+    gralloc: &mut RutabagaGralloc,
+    iommu_client: Option<&mut VmMemoryRequestIommuClient>,
+    region_state: &mut VmMemoryRegionState,
+    source: VmMemorySource,
+    dest: VmMemoryDestination,
+    prot: Protection,
+) -> StdResult<VmMemoryResponse, VmControlError> {
+    if let Some(resp) = handle_prepared_region(vm, region_state, &source, &dest, &prot) {
+        return resp;
+    }
+
+    let pages_releasable = source.pages_releasable();
+    // Correct on Windows because callers of this IPC guarantee descriptor is a mapping
+    // handle.
+    let (mapped_region, size, descriptor) =
+        source.map(gralloc, prot).map_err(VmControlError::Mapping)?;
+
+    let guest_addr = dest
+        .allocate(sys_allocator, size)
+        .map_err(VmControlError::AddressAllocation)?;
+
+    let slot = vm
+        .add_memory_region(guest_addr, mapped_region, prot.is_read_only(), false)
+        .map_err(VmControlError::AddMemoryRegion)?;
+
+    if let (Some(descriptor), Some(iommu_client)) = (descriptor, iommu_client) {
+        let request = VirtioIOMMURequest::VfioCommand(VirtioIOMMUVfioCommand::VfioDmabufMap {
+            mem_slot: slot,
+            gfn: guest_addr.0 >> 12,
+            size,
+            dma_buf: descriptor,
+        });
+
+        match virtio_iommu_request(&iommu_client.tube.lock(), &request) {
+            Ok(VirtioIOMMUResponse::VfioResponse(VirtioIOMMUVfioResult::Ok)) => (),
+            resp => {
+                if let Err(e) = vm.remove_memory_region(slot) {
+                    // The slot is still live in the VM, report that rather than the rejection.
+                    error!("viommu rejected memory region {}: {:?}", slot, resp);
+                    return Err(VmControlError::RemoveMemoryRegion(e));
+                }
+                return Err(VmControlError::IommuRejected(resp));
+            }
+        };
+
+        iommu_client.gpu_memory.insert(slot);
+    }
+
+    let pfn = guest_addr.0 >> 12;
+    region_state.mapped_regions.insert(
+        VmMemoryRegionId(pfn),
+        MappedRegionInfo {
+            slot,
+            offset: None,
+            size: size as usize,
+            pages_releasable,
+        },
     );
-    Some(VmMemoryResponse::RegisterMemory(VmMemoryRegionId(pfn)))
+    Ok(VmMemoryResponse::RegisterMemory(VmMemoryRegionId(pfn)))
 }
 
 impl VmMemoryRequest {
@@ -660,6 +1179,28 @@ impl VmMemoryRequest {
         iommu_client: Option<&mut VmMemoryRequestIommuClient>,
         region_state: &mut VmMemoryRegionState,
     ) -> VmMemoryResponse {
+        let resp = match self.try_execute(vm, sys_allocator, gralloc, iommu_client, region_state) {
+            Ok(resp) => resp,
+            Err(e) => {
+                error!("failed to execute VmMemoryRequest: {}", e);
+                VmMemoryResponse::Err(e.into())
+            }
+        };
+        debug_assert!(
+            region_state.descriptor_accounting_consistent(),
+            "memory slot leaked or double freed"
+        );
+        resp
+    }
+
+    fn try_execute(
+        self,
+        vm: &mut impl Vm,
+        sys_allocator: &mut SystemAllocator,
+        gralloc: &mut RutabagaGralloc,
+        iommu_client: Option<&mut VmMemoryRequestIommuClient>,
+        region_state: &mut VmMemoryRegionState,
+    ) -> StdResult<VmMemoryResponse, VmControlError> {
         use self::VmMemoryRequest::*;
         match self {
             PrepareSharedMemoryRegion { alloc } => {
@@ -669,131 +1210,119 @@ impl VmMemoryRequest {
                 assert!(iommu_client.is_none());
 
                 if !sys::should_prepare_memory_region() {
-                    return VmMemoryResponse::Ok;
+                    return Ok(VmMemoryResponse::Ok);
                 }
 
-                match sys::prepare_shared_memory_region(vm, sys_allocator, alloc) {
-                    Ok(info) => {
-                        region_state.slot_map.insert(alloc, info);
-                        VmMemoryResponse::Ok
-                    }
-                    Err(e) => VmMemoryResponse::Err(e),
-                }
+                let info = sys::prepare_shared_memory_region(vm, sys_allocator, alloc)
+                    .map_err(VmControlError::PrepareSharedMemoryRegion)?;
+                region_state.slot_map.insert(alloc, info);
+                Ok(VmMemoryResponse::Ok)
             }
-            RegisterMemory { source, dest, prot } => {
-                if let Some(resp) = handle_prepared_region(vm, region_state, &source, &dest, &prot)
-                {
-                    return resp;
-                }
-
-                // Correct on Windows because callers of this IPC guarantee descriptor is a mapping
-                // handle.
-                let (mapped_region, size, descriptor) = match source.map(gralloc, prot) {
-                    Ok((region, size, descriptor)) => (region, size, descriptor),
-                    Err(e) => return VmMemoryResponse::Err(e),
-                };
-
-                let guest_addr = match dest.allocate(sys_allocator, size) {
-                    Ok(addr) => addr,
-                    Err(e) => return VmMemoryResponse::Err(e),
-                };
-
-                let slot = match vm.add_memory_region(
-                    guest_addr,
-                    mapped_region,
-                    prot == Protection::read(),
-                    false,
-                ) {
-                    Ok(slot) => slot,
-                    Err(e) => return VmMemoryResponse::Err(e),
-                };
-
-                if let (Some(descriptor), Some(iommu_client)) = (descriptor, iommu_client) {
-                    let request =
-                        VirtioIOMMURequest::VfioCommand(VirtioIOMMUVfioCommand::VfioDmabufMap {
-                            mem_slot: slot,
-                            gfn: guest_addr.0 >> 12,
-                            size,
-                            dma_buf: descriptor,
-                        });
-
-                    match virtio_iommu_request(&iommu_client.tube.lock(), &request) {
-                        Ok(VirtioIOMMUResponse::VfioResponse(VirtioIOMMUVfioResult::Ok)) => (),
-                        resp => {
-                            error!("Unexpected message response: {:?}", resp);
-                            // Ignore the result because there is nothing we can do with a failure.
-                            let _ = vm.remove_memory_region(slot);
-                            return VmMemoryResponse::Err(SysError::new(EINVAL));
+            RegisterMemory { source, dest, prot } => register_memory(
+                vm,
+                sys_allocator,
+                gralloc,
+                iommu_client,
+                region_state,
+                source,
+                dest,
+                prot,
+            ),
+            UnregisterMemory { id, release_pages } => match region_state.mapped_regions.remove(&id)
+            {
+                Some(info @ MappedRegionInfo { offset: None, .. }) => {
+                    let slot = info.slot;
+                    let region = match vm.remove_memory_region(slot) {
+                        Ok(region) => region,
+                        Err(e) => {
+                            // The mapping is still live, keep tracking it.
+                            region_state.mapped_regions.insert(id, info);
+                            return Err(VmControlError::RemoveMemoryRegion(e));
                         }
                     };
-
-                    iommu_client.gpu_memory.insert(slot);
-                }
-
-                let pfn = guest_addr.0 >> 12;
-                region_state
-                    .mapped_regions
-                    .insert(VmMemoryRegionId(pfn), (slot, None));
-                VmMemoryResponse::RegisterMemory(VmMemoryRegionId(pfn))
-            }
-            UnregisterMemory(id) => match region_state.mapped_regions.remove(&id) {
-                Some((slot, None)) => match vm.remove_memory_region(slot) {
-                    Ok(_) => {
-                        if let Some(iommu_client) = iommu_client {
-                            if iommu_client.gpu_memory.remove(&slot) {
-                                let request = VirtioIOMMURequest::VfioCommand(
-                                    VirtioIOMMUVfioCommand::VfioDmabufUnmap(slot),
-                                );
-
-                                match virtio_iommu_request(&iommu_client.tube.lock(), &request) {
-                                    Ok(VirtioIOMMUResponse::VfioResponse(
-                                        VirtioIOMMUVfioResult::Ok,
-                                    )) => VmMemoryResponse::Ok,
-                                    resp => {
-                                        error!("Unexpected message response: {:?}", resp);
-                                        VmMemoryResponse::Err(SysError::new(EINVAL))
-                                    }
-                                }
-                            } else {
-                                VmMemoryResponse::Ok
+                    // The guest can no longer access the region, so its pages can be dropped
+                    // before it is unmapped.
+                    if release_pages && info.pages_releasable {
+                        if let Err(e) = sys::release_region_pages(region.as_ref()) {
+                            warn!("failed to release pages of memory region {:?}: {}", id, e);
+                        }
+                    }
+                    if let Some(iommu_client) = iommu_client {
+                        if iommu_client.gpu_memory.remove(&slot) {
+                            let request = VirtioIOMMURequest::VfioCommand(
+                                VirtioIOMMUVfioCommand::VfioDmabufUnmap(slot),
+                            );
+
+                            match virtio_iommu_request(&iommu_client.tube.lock(), &request) {
+                                Ok(VirtioIOMMUResponse::VfioResponse(
+                                    VirtioIOMMUVfioResult::Ok,
+                                )) => (),
+                                resp => return Err(VmControlError::IommuRejected(resp)),
                             }
-                        } else {
-                            VmMemoryResponse::Ok
                         }
                     }
-                    Err(e) => VmMemoryResponse::Err(e),
-                },
-                Some((slot, Some((offset, size)))) => match vm.remove_mapping(slot, offset, size) {
-                    Ok(()) => VmMemoryResponse::Ok,
-                    Err(e) => VmMemoryResponse::Err(e),
-                },
-                None => VmMemoryResponse::Err(SysError::new(EINVAL)),
+                    Ok(VmMemoryResponse::Ok)
+                }
+                // Removing a mapping from a prepared slot replaces it with fresh anonymous memory, so
+                // its pages are released regardless of `release_pages`.
+                Some(
+                    info @ MappedRegionInfo {
+                        offset: Some(offset),
+                        ..
+                    },
+                ) => {
+                    if let Err(e) = vm.remove_mapping(info.slot, offset, info.size) {
+                        // The mapping is still live, keep tracking it.
+                        region_state.mapped_regions.insert(id, info);
+                        return Err(VmControlError::RemoveMemoryRegion(e));
+                    }
+                    Ok(VmMemoryResponse::Ok)
+                }
+                None => Err(VmControlError::UnknownRegion(id)),
             },
+            PrefaultRegion { id } => {
+                let info = *region_state
+                    .mapped_regions
+                    .get(&id)
+                    .ok_or(VmControlError::UnknownRegion(id))?;
+                vm.populate_memory_region(info.slot, info.offset.unwrap_or(0), info.size)
+                    .map_err(VmControlError::PopulateMemoryRegion)?;
+                Ok(VmMemoryResponse::Ok)
+            }
+            QueryRegistered => Ok(VmMemoryResponse::Registered {
+                count: region_state.mapped_regions.len(),
+                total_bytes: region_state
+                    .mapped_regions
+                    .values()
+                    .map(|info| info.size as u64)
+                    .sum(),
+            }),
             DynamicallyFreeMemoryRange {
                 guest_address,
                 size,
-            } => match vm.handle_balloon_event(BalloonEvent::Inflate(MemRegion {
-                guest_address,
-                size,
-            })) {
-                Ok(_) => VmMemoryResponse::Ok,
-                Err(e) => VmMemoryResponse::Err(e),
-            },
+            } => {
+                vm.handle_balloon_event(BalloonEvent::Inflate(MemRegion {
+                    guest_address,
+                    size,
+                }))
+                .map_err(VmControlError::Balloon)?;
+                Ok(VmMemoryResponse::Ok)
+            }
             DynamicallyReclaimMemoryRange {
                 guest_address,
                 size,
-            } => match vm.handle_balloon_event(BalloonEvent::Deflate(MemRegion {
-                guest_address,
-                size,
-            })) {
-                Ok(_) => VmMemoryResponse::Ok,
-                Err(e) => VmMemoryResponse::Err(e),
-            },
+            } => {
+                vm.handle_balloon_event(BalloonEvent::Deflate(MemRegion {
+                    guest_address,
+                    size,
+                }))
+                .map_err(VmControlError::Balloon)?;
+                Ok(VmMemoryResponse::Ok)
+            }
             BalloonTargetReached { size } => {
-                match vm.handle_balloon_event(BalloonEvent::BalloonTargetReached(size)) {
-                    Ok(_) => VmMemoryResponse::Ok,
-                    Err(e) => VmMemoryResponse::Err(e),
-                }
+                vm.handle_balloon_event(BalloonEvent::BalloonTargetReached(size))
+                    .map_err(VmControlError::Balloon)?;
+                Ok(VmMemoryResponse::Ok)
             }
             IoEventWithAlloc {
                 evt,
@@ -809,25 +1338,17 @@ impl VmMemoryRequest {
                     Datamatch::U32(_) => 4,
                     Datamatch::U64(_) => 8,
                 };
-                let addr = match sys_allocator
+                let addr = sys_allocator
                     .mmio_allocator_any()
                     .address_from_pci_offset(allocation, offset, len)
-                {
-                    Ok(addr) => addr,
-                    Err(e) => {
-                        error!("error getting target address: {:#}", e);
-                        return VmMemoryResponse::Err(SysError::new(EINVAL));
-                    }
-                };
+                    .map_err(VmControlError::AddressAllocation)?;
                 let res = if register {
                     vm.register_ioevent(&evt, IoEventAddress::Mmio(addr), datamatch)
                 } else {
                     vm.unregister_ioevent(&evt, IoEventAddress::Mmio(addr), datamatch)
                 };
-                match res {
-                    Ok(_) => VmMemoryResponse::Ok,
-                    Err(e) => VmMemoryResponse::Err(e),
-                }
+                res.map_err(VmControlError::IoEvent)?;
+                Ok(VmMemoryResponse::Ok)
             }
             IoEventRaw(request) => {
                 let res = if request.register {
@@ -843,10 +1364,8 @@ impl VmMemoryRequest {
                         request.datamatch,
                     )
                 };
-                match res {
-                    Ok(_) => VmMemoryResponse::Ok,
-                    Err(e) => VmMemoryResponse::Err(e),
-                }
+                res.map_err(VmControlError::IoEvent)?;
+                Ok(VmMemoryResponse::Ok)
             }
         }
     }
@@ -861,6 +1380,11 @@ pub struct VmMemoryRegionId(u64);
 pub enum VmMemoryResponse {
     /// The request to register memory into guest address space was successful.
     RegisterMemory(VmMemoryRegionId),
+    /// The regions currently registered with `VmMemoryRequest::RegisterMemory`.
+    Registered {
+        count: usize,
+        total_bytes: u64,
+    },
     Ok,
     Err(SysError),
 }
@@ -1193,7 +1717,7 @@ pub struct BatControl {
 }
 
 // Used to mark hotplug pci device's device type
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum HotPlugDeviceType {
     UpstreamPort,
     DownstreamPort,
@@ -1228,8 +1752,15 @@ pub enum SwapCommand {
     Enable,
     Trim,
     SwapOut,
-    Disable { slow_file_cleanup: bool },
+    Disable {
+        slow_file_cleanup: bool,
+    },
     Status,
+    SwapOutProgress,
+    SetPolicy {
+        staging_max_bytes: u64,
+        batch_size: u64,
+    },
 }
 
 ///
@@ -1240,29 +1771,55 @@ pub enum SwapCommand {
 pub enum VmRequest {
     /// Break the VM's run loop and exit.
     Exit,
+    /// Stop the VCPUs, put the devices to sleep and disable vmm-swap, in that order, before
+    /// breaking the VM's run loop. If any of these steps fails, the earlier ones are undone and the
+    /// VM keeps running, so that the caller can decide whether to force the exit with `Exit`.
+    ExitClean,
     /// Trigger a power button event in the guest.
     Powerbtn,
     /// Trigger a sleep button event in the guest.
     Sleepbtn,
     /// Trigger a RTC interrupt in the guest.
     Rtc,
+    /// Trigger a RTC interrupt in the guest `seconds_from_now` seconds from now, replacing any
+    /// pending alarm.
+    SetRtcAlarm { seconds_from_now: u64 },
+    /// Cancel the pending RTC alarm set by `SetRtcAlarm`.
+    ClearRtcAlarm,
     /// Suspend the VM's VCPUs until resume.
     SuspendVcpus,
     /// Swap the memory content into files on a disk
     Swap(SwapCommand),
     /// Resume the VM's VCPUs that were previously suspended.
     ResumeVcpus,
+    /// Get the VM's current run mode, without querying the VCPUs.
+    GetRunMode,
+    /// Change the level of messages that are logged, replacing the filter crosvm was started
+    /// with.
+    ///
+    /// Only the main crosvm process is affected. Devices running in jailed child processes keep
+    /// logging at the level crosvm was started with.
+    SetLogLevel(LogLevel),
     /// Inject a general-purpose event.
     Gpe(u32),
     /// Inject a PCI PME
     PciPme(u16),
+    /// Inject a PCI PME for each requester id without releasing the PM resource in between, so
+    /// the guest doesn't observe intermediate states. Responds with `VmResponse::Partial` if some
+    /// of the PMEs could not be delivered.
+    PciPmeBatch(Vec<u16>),
     /// Make the VM's RT VCPU real-time.
     MakeRT,
     /// Command for balloon driver.
     #[cfg(feature = "balloon")]
     BalloonCommand(BalloonControlCommand),
+    /// Get the balloon device's memory statistics. Replied to with `VmResponse::BalloonStats`, or
+    /// `ENOTSUP` if the VM has no balloon device.
+    #[cfg(feature = "balloon")]
+    BalloonStats,
     /// Send a command to a disk chosen by `disk_index`.
     /// `disk_index` is a 0-based count of `--disk`, `--rwdisk`, and `-r` command-line options.
+    /// An index without a disk is reported as `VmResponse::ErrString`.
     DiskCommand {
         disk_index: usize,
         command: DiskControlCommand,
@@ -1275,6 +1832,9 @@ pub enum VmRequest {
     /// Command to set battery.
     BatCommand(BatteryType, BatControlCommand),
     /// Command to add/remove multiple vfio-pci devices
+    ///
+    /// Handled by the main run loop. Builds that can't hotplug vfio devices, and `execute`,
+    /// respond with [`vfio_hotplug_not_implemented`].
     HotPlugVfioCommand {
         device: HotPlugDeviceInfo,
         add: bool,
@@ -1282,6 +1842,9 @@ pub enum VmRequest {
     /// Command to add/remove network tap device as virtio-pci device
     #[cfg(feature = "pci-hotplug")]
     HotPlugNetCommand(NetControlCommand),
+    /// Command to list the PCI hotplug slots and what is plugged into them
+    #[cfg(feature = "pci-hotplug")]
+    ListHotplugSlots,
     /// Command to Snapshot devices
     Snapshot(SnapshotCommand),
     /// Command to Restore devices
@@ -1301,10 +1864,39 @@ pub enum VmRequest {
     /// Unregister for all event notification
     #[cfg(feature = "registered_events")]
     Unregister { socket_addr: String },
+    /// Get the number of events dropped for each registered listener because it didn't read them
+    /// fast enough.
+    ///
+    /// Expects a `VmResponse::RegisteredEventsStatus` on success. Only supported on Linux, where it
+    /// is handled by the main run loop.
+    #[cfg(feature = "registered_events")]
+    RegisteredEventsStatus,
     /// Suspend VM VCPUs and Devices until resume.
     SuspendVm,
     /// Resume VM VCPUs and Devices.
     ResumeVm,
+    /// Get the registers of the VCPU `vcpu_id` for debugging.
+    ///
+    /// Expects a `VmResponse::VcpuRegisters` on success.
+    GetVcpuRegisters { vcpu_id: usize },
+    /// Make the VCPU `vcpu_id` real-time with `priority`, or revert it to the scheduling policy it
+    /// had before if `enable` is false. `priority` is ignored when reverting.
+    ///
+    /// Fails with the error of the VCPU thread, e.g. `EPERM` if crosvm may not use real-time
+    /// priorities. Not supported on Windows.
+    SetVcpuRealtime {
+        vcpu_id: usize,
+        enable: bool,
+        priority: u32,
+    },
+    /// Force the VCPU `vcpu_id` to exit the hypervisor run loop once, without changing its run
+    /// mode. Useful to unstick a VCPU for diagnostics.
+    KickVcpu { vcpu_id: usize },
+    /// Move the control server socket to `new_path`. Connections that were already pending on the
+    /// old socket are still serviced, and the old socket path is unlinked.
+    ///
+    /// Only supported on Linux, where it is handled by the main run loop.
+    RebindControlSocket { new_path: PathBuf },
 }
 
 /// NOTE: when making any changes to this enum please also update
@@ -1373,6 +1965,31 @@ impl RegisteredEventWithData {
         }
     }
 
+    /// Decodes an event sent to a registered listener. Returns `None` for an event that this
+    /// build doesn't know about.
+    pub fn from_proto(event: &registered_events::RegisteredEvent) -> Option<Self> {
+        if event.has_ws_report() {
+            let report = event.ws_report();
+            Some(Self::VirtioBalloonWsReport {
+                ws_buckets: report
+                    .ws_buckets
+                    .iter()
+                    .map(|ws| WSBucket {
+                        age: ws.age,
+                        bytes: [ws.file_bytes, ws.anon_bytes],
+                    })
+                    .collect(),
+                balloon_actual: report.balloon_actual,
+            })
+        } else if event.has_resize() {
+            Some(Self::VirtioBalloonResize)
+        } else if event.has_oom_deflation() {
+            Some(Self::VirtioBalloonOOMDeflation)
+        } else {
+            None
+        }
+    }
+
     pub fn from_ws(ws: &BalloonWS, balloon_actual: u64) -> Self {
         RegisteredEventWithData::VirtioBalloonWsReport {
             ws_buckets: ws.ws.clone(),
@@ -1399,6 +2016,40 @@ pub fn handle_disk_command(command: &DiskControlCommand, disk_host_tube: &Tube)
     }
 }
 
+/// Sends `command` to the disk at `disk_index` in `disk_host_tubes`, for the request `request_id`
+/// (see `next_request_id`).
+///
+/// An index without a disk is reported as `VmResponse::ErrString` naming the index and the number
+/// of disks, so it can be told apart from the `VmResponse::Err` of a failed command.
+pub fn handle_disk_request(
+    request_id: u64,
+    disk_index: usize,
+    command: &DiskControlCommand,
+    disk_host_tubes: &[Tube],
+) -> VmResponse {
+    match disk_host_tubes.get(disk_index) {
+        Some(tube) => {
+            let resp = handle_disk_command(command, tube);
+            if let VmResponse::Err(e) = &resp {
+                error!(
+                    "request {}: disk {} command {} failed: {}",
+                    request_id, disk_index, command, e
+                );
+            }
+            resp
+        }
+        None => {
+            let msg = format!(
+                "disk index {} out of range (have {} disks)",
+                disk_index,
+                disk_host_tubes.len()
+            );
+            error!("request {}: {}", request_id, msg);
+            VmResponse::ErrString(msg)
+        }
+    }
+}
+
 /// WARNING: descriptor must be a mapping handle on Windows.
 fn map_descriptor(
     descriptor: &dyn AsRawDescriptor,
@@ -1419,6 +2070,18 @@ fn map_descriptor(
     }
 }
 
+fn map_anonymous(size: u64, prot: Protection) -> Result<Box<dyn MappedRegion>> {
+    if size == 0 || size % pagesize() as u64 != 0 {
+        return Err(SysError::new(EINVAL));
+    }
+    let size: usize = size.try_into().map_err(|_e| SysError::new(ERANGE))?;
+    match MemoryMappingBuilder::new(size).protection(prot).build() {
+        Ok(mmap) => Ok(Box::new(mmap)),
+        Err(MmapError::SystemCallFailed(e)) => Err(e),
+        _ => Err(SysError::new(EINVAL)),
+    }
+}
+
 // Get vCPU state. vCPUs are expected to all hold the same state.
 // In this function, there may be a time where vCPUs are not
 fn get_vcpu_state(kick_vcpus: impl Fn(VcpuControl), vcpu_num: usize) -> anyhow::Result<VmRunMode> {
@@ -1559,14 +2222,87 @@ impl Drop for DeviceSleepGuard<'_> {
     }
 }
 
+/// Crash report annotation key naming the snapshot or restore phase in progress.
+#[cfg(feature = "crash-report")]
+const VM_PHASE_ANNOTATION: &str = "vm_phase";
+
+/// Records the current phase of a snapshot or restore in crash reports, so that a crash in the
+/// middle of one can be attributed to the phase it happened in. The annotation is cleared when the
+/// guard is dropped, whether or not the operation succeeded.
+#[cfg(feature = "crash-report")]
+struct VmPhaseAnnotation {
+    operation: &'static str,
+}
+
+#[cfg(feature = "crash-report")]
+impl VmPhaseAnnotation {
+    fn new(operation: &'static str) -> Self {
+        Self { operation }
+    }
+
+    fn enter(&self, phase: &str) {
+        crash_report::set_annotation(
+            VM_PHASE_ANNOTATION,
+            &format!("{}:{}", self.operation, phase),
+        );
+    }
+}
+
+#[cfg(feature = "crash-report")]
+impl Drop for VmPhaseAnnotation {
+    fn drop(&mut self) {
+        crash_report::clear_annotation(VM_PHASE_ANNOTATION);
+    }
+}
+
+/// Without crash reports, there is nothing to record the phases in.
+#[cfg(not(feature = "crash-report"))]
+struct VmPhaseAnnotation;
+
+#[cfg(not(feature = "crash-report"))]
+impl VmPhaseAnnotation {
+    fn new(_operation: &'static str) -> Self {
+        VmPhaseAnnotation
+    }
+
+    fn enter(&self, _phase: &str) {}
+}
+
+/// Returns a new id for an incoming `VmRequest`, unique within this process.
+pub fn next_request_id() -> u64 {
+    static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+    NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Returns the response to the `VmRequest::HotPlugVfioCommand` request `request_id` in builds that
+/// can't hotplug vfio devices.
+pub fn vfio_hotplug_not_implemented(request_id: u64) -> VmResponse {
+    VmResponse::ErrString(format!(
+        "request {}: vfio hotplug not implemented in this build",
+        request_id
+    ))
+}
+
 impl VmRequest {
     /// Executes this request on the given Vm and other mutable state.
     ///
     /// This does not return a result, instead encapsulating the success or failure in a
     /// `VmResponse` with the intended purpose of sending the response back over the  socket that
     /// received this `VmRequest`.
+    ///
+    /// `request_id` (see `next_request_id`) is included in every message logged while handling
+    /// the request and in any `VmResponse::ErrString` returned, so that they can be correlated.
+    ///
+    /// `run_mode` is set to the new run mode if the request changes it. The caller is expected to
+    /// track the VM's current run mode and answer `VmRequest::GetRunMode` itself; `execute` can
+    /// only report a mode already present in `run_mode`.
+    ///
+    /// With `force_s2idle`, `VmRequest::ResumeVcpus` also triggers a power button event to wake the
+    /// guest. If there is no PM resource to do so, the resume fails with `ENOTSUP` when
+    /// `strict_s2idle` is set, and otherwise proceeds with a warning.
     pub fn execute(
         &self,
+        request_id: u64,
         run_mode: &mut Option<VmRunMode>,
         disk_host_tubes: &[Tube],
         pm: &mut Option<Arc<Mutex<dyn PmResource + Send>>>,
@@ -1576,10 +2312,12 @@ impl VmRequest {
         kick_vcpus: impl Fn(VcpuControl),
         kick_vcpu: impl Fn(VcpuControl, usize),
         force_s2idle: bool,
+        strict_s2idle: bool,
         #[cfg(feature = "swap")] swap_controller: Option<&swap::SwapController>,
         device_control_tube: &Tube,
         vcpu_size: usize,
         irq_handler_control: &Tube,
+        irq_flush_strategy: IrqFlushStrategy,
         snapshot_irqchip: impl Fn() -> anyhow::Result<serde_json::Value>,
         restore_irqchip: impl FnMut(serde_json::Value) -> anyhow::Result<()>,
     ) -> VmResponse {
@@ -1588,12 +2326,36 @@ impl VmRequest {
                 *run_mode = Some(VmRunMode::Exiting);
                 VmResponse::Ok
             }
-            VmRequest::Powerbtn => {
+            VmRequest::ExitClean => {
+                info!("request {}: Starting crosvm clean exit", request_id);
+                match do_exit_clean(
+                    request_id,
+                    kick_vcpus,
+                    vcpu_size,
+                    device_control_tube,
+                    #[cfg(feature = "swap")]
+                    swap_controller,
+                ) {
+                    Ok(()) => {
+                        info!(
+                            "request {}: Finished crosvm clean exit preparation",
+                            request_id
+                        );
+                        *run_mode = Some(VmRunMode::Exiting);
+                        VmResponse::Ok
+                    }
+                    Err(e) => {
+                        error!("request {}: failed to exit cleanly: {:?}", request_id, e);
+                        VmResponse::Err(SysError::new(EIO))
+                    }
+                }
+            }
+            VmRequest::Powerbtn => {
                 if let Some(pm) = pm {
                     pm.lock().pwrbtn_evt();
                     VmResponse::Ok
                 } else {
-                    error!("{:#?} not supported", *self);
+                    error!("request {}: {:#?} not supported", request_id, *self);
                     VmResponse::Err(SysError::new(ENOTSUP))
                 }
             }
@@ -1602,7 +2364,7 @@ impl VmRequest {
                     pm.lock().slpbtn_evt();
                     VmResponse::Ok
                 } else {
-                    error!("{:#?} not supported", *self);
+                    error!("request {}: {:#?} not supported", request_id, *self);
                     VmResponse::Err(SysError::new(ENOTSUP))
                 }
             }
@@ -1611,7 +2373,25 @@ impl VmRequest {
                     pm.lock().rtc_evt();
                     VmResponse::Ok
                 } else {
-                    error!("{:#?} not supported", *self);
+                    error!("request {}: {:#?} not supported", request_id, *self);
+                    VmResponse::Err(SysError::new(ENOTSUP))
+                }
+            }
+            VmRequest::SetRtcAlarm { seconds_from_now } => {
+                if let Some(pm) = pm {
+                    pm.lock().set_rtc_alarm(seconds_from_now);
+                    VmResponse::Ok
+                } else {
+                    error!("request {}: {:#?} not supported", request_id, *self);
+                    VmResponse::Err(SysError::new(ENOTSUP))
+                }
+            }
+            VmRequest::ClearRtcAlarm => {
+                if let Some(pm) = pm {
+                    pm.lock().clear_rtc_alarm();
+                    VmResponse::Ok
+                } else {
+                    error!("request {}: {:#?} not supported", request_id, *self);
                     VmResponse::Err(SysError::new(ENOTSUP))
                 }
             }
@@ -1619,38 +2399,73 @@ impl VmRequest {
                 *run_mode = Some(VmRunMode::Suspending);
                 VmResponse::Ok
             }
+            VmRequest::SetLogLevel(level) => {
+                base::syslog::set_log_level(level);
+                info!(
+                    "request {}: log level set to {:?} in the main process, jailed devices are \
+                     not affected",
+                    request_id, level
+                );
+                VmResponse::Ok
+            }
+            VmRequest::GetRunMode => match *run_mode {
+                Some(mode) => VmResponse::RunMode(mode),
+                None => {
+                    // The current run mode is tracked by the caller, which should answer this
+                    // request itself.
+                    error!("request {}: run mode is not known", request_id);
+                    VmResponse::Err(SysError::new(ENOTSUP))
+                }
+            },
             VmRequest::ResumeVcpus => {
                 if let Err(e) = device_control_tube.send(&DeviceControlCommand::GetDevicesState) {
-                    error!("failed to send GetDevicesState: {}", e);
+                    error!(
+                        "request {}: failed to send GetDevicesState: {}",
+                        request_id, e
+                    );
                     return VmResponse::Err(SysError::new(EIO));
                 }
                 let devices_state = match device_control_tube.recv() {
                     Ok(VmResponse::DevicesState(state)) => state,
                     Ok(resp) => {
-                        error!("failed to get devices state. Unexpected behavior: {}", resp);
+                        error!(
+                            "request {}: failed to get devices state. Unexpected behavior: {}",
+                            request_id, resp
+                        );
                         return VmResponse::Err(SysError::new(EINVAL));
                     }
                     Err(e) => {
-                        error!("failed to get devices state. Unexpected behavior: {}", e);
+                        error!(
+                            "request {}: failed to get devices state. Unexpected behavior: {}",
+                            request_id, e
+                        );
                         return VmResponse::Err(SysError::new(EINVAL));
                     }
                 };
                 if let DevicesState::Sleep = devices_state {
-                    error!("Trying to wake Vcpus while Devices are asleep. Did you mean to use `crosvm resume --full`?");
+                    error!("request {}: Trying to wake Vcpus while Devices are asleep. Did you mean to use `crosvm resume --full`?", request_id);
                     return VmResponse::Err(SysError::new(EINVAL));
                 }
-                *run_mode = Some(VmRunMode::Running);
-
                 if force_s2idle {
                     // During resume also emulate powerbtn event which will allow to wakeup fully
                     // suspended guest.
                     if let Some(pm) = pm {
                         pm.lock().pwrbtn_evt();
-                    } else {
-                        error!("triggering power btn during resume not supported");
+                    } else if strict_s2idle {
+                        error!(
+                            "request {}: triggering power btn during resume not supported",
+                            request_id
+                        );
                         return VmResponse::Err(SysError::new(ENOTSUP));
+                    } else {
+                        warn!(
+                            "request {}: triggering power btn during resume not supported, \
+                             resuming without emulating s2idle wakeup",
+                            request_id
+                        );
                     }
                 }
+                *run_mode = Some(VmRunMode::Running);
                 VmResponse::Ok
             }
             VmRequest::Swap(SwapCommand::Enable) => {
@@ -1662,7 +2477,7 @@ impl VmRequest {
                     let _vcpu_guard = match VcpuSuspendGuard::new(&kick_vcpus, vcpu_size) {
                         Ok(guard) => guard,
                         Err(e) => {
-                            error!("failed to suspend vcpus: {:?}", e);
+                            error!("request {}: failed to suspend vcpus: {:?}", request_id, e);
                             return VmResponse::Err(SysError::new(EINVAL));
                         }
                     };
@@ -1671,7 +2486,7 @@ impl VmRequest {
                     let _devices_guard = match swap_controller.suspend_devices() {
                         Ok(guard) => guard,
                         Err(e) => {
-                            error!("failed to suspend devices: {:?}", e);
+                            error!("request {}: failed to suspend devices: {:?}", request_id, e);
                             return VmResponse::Err(SysError::new(EINVAL));
                         }
                     };
@@ -1679,7 +2494,7 @@ impl VmRequest {
                     return match swap_controller.enable() {
                         Ok(()) => VmResponse::Ok,
                         Err(e) => {
-                            error!("swap enable failed: {}", e);
+                            error!("request {}: swap enable failed: {}", request_id, e);
                             VmResponse::Err(SysError::new(EINVAL))
                         }
                     };
@@ -1692,7 +2507,7 @@ impl VmRequest {
                     return match swap_controller.trim() {
                         Ok(()) => VmResponse::Ok,
                         Err(e) => {
-                            error!("swap trim failed: {}", e);
+                            error!("request {}: swap trim failed: {}", request_id, e);
                             VmResponse::Err(SysError::new(EINVAL))
                         }
                     };
@@ -1705,7 +2520,7 @@ impl VmRequest {
                     return match swap_controller.swap_out() {
                         Ok(()) => VmResponse::Ok,
                         Err(e) => {
-                            error!("swap out failed: {}", e);
+                            error!("request {}: swap out failed: {}", request_id, e);
                             VmResponse::Err(SysError::new(EINVAL))
                         }
                     };
@@ -1722,20 +2537,55 @@ impl VmRequest {
                     return match swap_controller.disable(slow_file_cleanup) {
                         Ok(()) => VmResponse::Ok,
                         Err(e) => {
-                            error!("swap disable failed: {}", e);
+                            error!("request {}: swap disable failed: {}", request_id, e);
                             VmResponse::Err(SysError::new(EINVAL))
                         }
                     };
                 }
                 VmResponse::Err(SysError::new(ENOTSUP))
             }
+            VmRequest::Swap(SwapCommand::SetPolicy {
+                #[cfg(feature = "swap")]
+                staging_max_bytes,
+                #[cfg(feature = "swap")]
+                batch_size,
+                ..
+            }) => {
+                #[cfg(feature = "swap")]
+                if let Some(swap_controller) = swap_controller {
+                    return match swap_controller.set_policy(staging_max_bytes, batch_size) {
+                        Ok(()) => VmResponse::Ok,
+                        Err(e) => {
+                            error!("request {}: swap set policy failed: {:#}", request_id, e);
+                            VmResponse::ErrString(format!(
+                                "request {}: swap set policy failed: {:#}",
+                                request_id, e
+                            ))
+                        }
+                    };
+                }
+                VmResponse::Err(SysError::new(ENOTSUP))
+            }
             VmRequest::Swap(SwapCommand::Status) => {
                 #[cfg(feature = "swap")]
                 if let Some(swap_controller) = swap_controller {
                     return match swap_controller.status() {
                         Ok(status) => VmResponse::SwapStatus(status),
                         Err(e) => {
-                            error!("swap status failed: {}", e);
+                            error!("request {}: swap status failed: {}", request_id, e);
+                            VmResponse::Err(SysError::new(EINVAL))
+                        }
+                    };
+                }
+                VmResponse::Err(SysError::new(ENOTSUP))
+            }
+            VmRequest::Swap(SwapCommand::SwapOutProgress) => {
+                #[cfg(feature = "swap")]
+                if let Some(swap_controller) = swap_controller {
+                    return match swap_controller.swap_out_progress() {
+                        Ok(progress) => VmResponse::SwapOutProgress(progress),
+                        Err(e) => {
+                            error!("request {}: swap-out progress failed: {}", request_id, e);
                             VmResponse::Err(SysError::new(EINVAL))
                         }
                     };
@@ -1743,24 +2593,24 @@ impl VmRequest {
                 VmResponse::Err(SysError::new(ENOTSUP))
             }
             VmRequest::SuspendVm => {
-                info!("Starting crosvm suspend");
+                info!("request {}: Starting crosvm suspend", request_id);
                 kick_vcpus(VcpuControl::RunState(VmRunMode::Suspending));
                 let current_mode = match get_vcpu_state(kick_vcpus, vcpu_size) {
                     Ok(state) => state,
                     Err(e) => {
-                        error!("failed to get vcpu state: {e}");
+                        error!("request {}: failed to get vcpu state: {e}", request_id);
                         return VmResponse::Err(SysError::new(EIO));
                     }
                 };
                 if current_mode != VmRunMode::Suspending {
-                    error!("vCPUs failed to all suspend.");
+                    error!("request {}: vCPUs failed to all suspend.", request_id);
                     return VmResponse::Err(SysError::new(EIO));
                 }
                 if let Err(e) = device_control_tube
                     .send(&DeviceControlCommand::SleepDevices)
                     .context("send command to devices control socket")
                 {
-                    error!("{:?}", e);
+                    error!("request {}: {:?}", request_id, e);
                     return VmResponse::Err(SysError::new(EIO));
                 };
                 match device_control_tube
@@ -1768,26 +2618,32 @@ impl VmRequest {
                     .context("receive from devices control socket")
                 {
                     Ok(VmResponse::Ok) => {
-                        info!("Finished crosvm suspend successfully");
+                        info!(
+                            "request {}: Finished crosvm suspend successfully",
+                            request_id
+                        );
                         VmResponse::Ok
                     }
                     Ok(resp) => {
-                        error!("device sleep failed: {}", resp);
+                        error!("request {}: device sleep failed: {}", request_id, resp);
                         VmResponse::Err(SysError::new(EIO))
                     }
                     Err(e) => {
-                        error!("receive from devices control socket: {:?}", e);
+                        error!(
+                            "request {}: receive from devices control socket: {:?}",
+                            request_id, e
+                        );
                         VmResponse::Err(SysError::new(EIO))
                     }
                 }
             }
             VmRequest::ResumeVm => {
-                info!("Starting crosvm resume");
+                info!("request {}: Starting crosvm resume", request_id);
                 if let Err(e) = device_control_tube
                     .send(&DeviceControlCommand::WakeDevices)
                     .context("send command to devices control socket")
                 {
-                    error!("{:?}", e);
+                    error!("request {}: {:?}", request_id, e);
                     return VmResponse::Err(SysError::new(EIO));
                 };
                 match device_control_tube
@@ -1795,14 +2651,20 @@ impl VmRequest {
                     .context("receive from devices control socket")
                 {
                     Ok(VmResponse::Ok) => {
-                        info!("Finished crosvm resume successfully");
+                        info!(
+                            "request {}: Finished crosvm resume successfully",
+                            request_id
+                        );
                     }
                     Ok(resp) => {
-                        error!("device wake failed: {}", resp);
+                        error!("request {}: device wake failed: {}", request_id, resp);
                         return VmResponse::Err(SysError::new(EIO));
                     }
                     Err(e) => {
-                        error!("receive from devices control socket: {:?}", e);
+                        error!(
+                            "request {}: receive from devices control socket: {:?}",
+                            request_id, e
+                        );
                         return VmResponse::Err(SysError::new(EIO));
                     }
                 }
@@ -1814,7 +2676,7 @@ impl VmRequest {
                     pm.lock().gpe_evt(gpe);
                     VmResponse::Ok
                 } else {
-                    error!("{:#?} not supported", *self);
+                    error!("request {}: {:#?} not supported", request_id, *self);
                     VmResponse::Err(SysError::new(ENOTSUP))
                 }
             }
@@ -1823,7 +2685,21 @@ impl VmRequest {
                     pm.lock().pme_evt(requester_id);
                     VmResponse::Ok
                 } else {
-                    error!("{:#?} not supported", *self);
+                    error!("request {}: {:#?} not supported", request_id, *self);
+                    VmResponse::Err(SysError::new(ENOTSUP))
+                }
+            }
+            VmRequest::PciPmeBatch(ref requester_ids) => {
+                if let Some(pm) = pm.as_ref() {
+                    let results = pm.lock().pme_evt_batch(requester_ids);
+                    VmResponse::from_batch_results(
+                        requester_ids
+                            .iter()
+                            .map(|requester_id| format!("{:#06x}", requester_id))
+                            .zip(results),
+                    )
+                } else {
+                    error!("request {}: {:#?} not supported", request_id, *self);
                     VmResponse::Err(SysError::new(ENOTSUP))
                 }
             }
@@ -1831,33 +2707,44 @@ impl VmRequest {
                 kick_vcpus(VcpuControl::MakeRT);
                 VmResponse::Ok
             }
+            // Balloon requests are routed to the `BalloonTube` by the control loop when the VM has
+            // a balloon device, so reaching here means there is none.
             #[cfg(feature = "balloon")]
-            VmRequest::BalloonCommand(_) => unreachable!("Should be handled with BalloonTube"),
+            VmRequest::BalloonCommand(_) | VmRequest::BalloonStats => {
+                error!("request {}: balloon is not configured", request_id);
+                VmResponse::Err(SysError::new(ENOTSUP))
+            }
             VmRequest::DiskCommand {
                 disk_index,
                 ref command,
-            } => match &disk_host_tubes.get(disk_index) {
-                Some(tube) => handle_disk_command(command, tube),
-                None => VmResponse::Err(SysError::new(ENODEV)),
-            },
+            } => handle_disk_request(request_id, disk_index, command, disk_host_tubes),
             #[cfg(feature = "gpu")]
             VmRequest::GpuCommand(ref cmd) => match gpu_control_tube {
                 Some(gpu_control) => {
                     let res = gpu_control.send(cmd);
                     if let Err(e) = res {
-                        error!("fail to send command to gpu control socket: {}", e);
+                        error!(
+                            "request {}: fail to send command to gpu control socket: {}",
+                            request_id, e
+                        );
                         return VmResponse::Err(SysError::new(EIO));
                     }
                     match gpu_control.recv() {
                         Ok(response) => VmResponse::GpuResponse(response),
                         Err(e) => {
-                            error!("fail to recv command from gpu control socket: {}", e);
+                            error!(
+                                "request {}: fail to recv command from gpu control socket: {}",
+                                request_id, e
+                            );
                             VmResponse::Err(SysError::new(EIO))
                         }
                     }
                 }
                 None => {
-                    error!("gpu control is not enabled in crosvm");
+                    error!(
+                        "request {}: gpu control is not enabled in crosvm",
+                        request_id
+                    );
                     VmResponse::Err(SysError::new(EIO))
                 }
             },
@@ -1865,75 +2752,122 @@ impl VmRequest {
                 let usb_control_tube = match usb_control_tube {
                     Some(t) => t,
                     None => {
-                        error!("attempted to execute USB request without control tube");
+                        error!(
+                            "request {}: attempted to execute USB request without control tube",
+                            request_id
+                        );
                         return VmResponse::Err(SysError::new(ENODEV));
                     }
                 };
                 let res = usb_control_tube.send(cmd);
                 if let Err(e) = res {
-                    error!("fail to send command to usb control socket: {}", e);
+                    error!(
+                        "request {}: fail to send command to usb control socket: {}",
+                        request_id, e
+                    );
                     return VmResponse::Err(SysError::new(EIO));
                 }
                 match usb_control_tube.recv() {
                     Ok(response) => VmResponse::UsbResponse(response),
                     Err(e) => {
-                        error!("fail to recv command from usb control socket: {}", e);
+                        error!(
+                            "request {}: fail to recv command from usb control socket: {}",
+                            request_id, e
+                        );
                         VmResponse::Err(SysError::new(EIO))
                     }
                 }
             }
-            VmRequest::BatCommand(type_, ref cmd) => {
-                match bat_control {
-                    Some(battery) => {
-                        if battery.type_ != type_ {
-                            error!("ignored battery command due to battery type: expected {:?}, got {:?}", battery.type_, type_);
-                            return VmResponse::Err(SysError::new(EINVAL));
-                        }
+            VmRequest::BatCommand(type_, ref cmd) => match bat_control {
+                Some(battery) => {
+                    if battery.type_ != type_ {
+                        error!("request {}: ignored battery command due to battery type: expected {:?}, got {:?}", request_id, battery.type_, type_);
+                        return VmResponse::Err(SysError::new(EINVAL));
+                    }
 
-                        let res = battery.control_tube.send(cmd);
-                        if let Err(e) = res {
-                            error!("fail to send command to bat control socket: {}", e);
-                            return VmResponse::Err(SysError::new(EIO));
-                        }
+                    let res = battery.control_tube.send(cmd);
+                    if let Err(e) = res {
+                        error!(
+                            "request {}: fail to send command to bat control socket: {}",
+                            request_id, e
+                        );
+                        return VmResponse::Err(SysError::new(EIO));
+                    }
 
-                        match battery.control_tube.recv() {
-                            Ok(response) => VmResponse::BatResponse(response),
-                            Err(e) => {
-                                error!("fail to recv command from bat control socket: {}", e);
-                                VmResponse::Err(SysError::new(EIO))
-                            }
+                    match battery.control_tube.recv() {
+                        Ok(response) => VmResponse::BatResponse(response),
+                        Err(e) => {
+                            error!(
+                                "request {}: fail to recv command from bat control socket: {}",
+                                request_id, e
+                            );
+                            VmResponse::Err(SysError::new(EIO))
                         }
                     }
-                    None => VmResponse::BatResponse(BatControlResult::NoBatDevice),
                 }
-            }
-            VmRequest::HotPlugVfioCommand { device: _, add: _ } => VmResponse::Ok,
+                None => VmResponse::BatResponse(BatControlResult::NoBatDevice),
+            },
+            // Builds that can hotplug vfio devices handle this in the main run loop.
+            VmRequest::HotPlugVfioCommand { .. } => vfio_hotplug_not_implemented(request_id),
             #[cfg(feature = "pci-hotplug")]
             VmRequest::HotPlugNetCommand(ref _net_cmd) => {
-                VmResponse::ErrString("hot plug not supported".to_owned())
+                VmResponse::ErrString(format!("request {}: hot plug not supported", request_id))
+            }
+            #[cfg(feature = "pci-hotplug")]
+            VmRequest::ListHotplugSlots => {
+                VmResponse::ErrString(format!("request {}: hot plug not supported", request_id))
             }
             VmRequest::Snapshot(SnapshotCommand::Take { ref snapshot_path }) => {
-                info!("Starting crosvm snapshot");
+                info!("request {}: Starting crosvm snapshot", request_id);
                 match do_snapshot(
                     snapshot_path.to_path_buf(),
                     kick_vcpus,
                     irq_handler_control,
+                    irq_flush_strategy,
                     device_control_tube,
                     vcpu_size,
                     snapshot_irqchip,
                 ) {
                     Ok(()) => {
-                        info!("Finished crosvm snapshot successfully");
+                        info!(
+                            "request {}: Finished crosvm snapshot successfully",
+                            request_id
+                        );
                         VmResponse::Ok
                     }
                     Err(e) => {
-                        error!("failed to handle snapshot: {:?}", e);
-                        VmResponse::Err(SysError::new(EIO))
+                        error!("request {}: failed to handle snapshot: {:?}", request_id, e);
+                        VmResponse::ErrString(format!(
+                            "request {}: failed to handle snapshot: {:#}",
+                            request_id, e
+                        ))
+                    }
+                }
+            }
+            VmRequest::Snapshot(SnapshotCommand::TakeDevicesOnly { ref snapshot_path }) => {
+                info!("request {}: Starting devices-only snapshot", request_id);
+                match do_snapshot_devices_only(snapshot_path.to_path_buf(), device_control_tube) {
+                    Ok(()) => {
+                        info!(
+                            "request {}: Finished devices-only snapshot successfully",
+                            request_id
+                        );
+                        VmResponse::Ok
+                    }
+                    Err(e) => {
+                        error!(
+                            "request {}: failed to handle devices-only snapshot: {:?}",
+                            request_id, e
+                        );
+                        VmResponse::ErrString(format!(
+                            "request {}: failed to handle devices-only snapshot: {:#}",
+                            request_id, e
+                        ))
                     }
                 }
             }
             VmRequest::Restore(RestoreCommand::Apply { ref restore_path }) => {
-                info!("Starting crosvm restore");
+                info!("request {}: Starting crosvm restore", request_id);
                 match do_restore(
                     restore_path.clone(),
                     kick_vcpus,
@@ -1944,12 +2878,18 @@ impl VmRequest {
                     restore_irqchip,
                 ) {
                     Ok(()) => {
-                        info!("Finished crosvm restore successfully");
+                        info!(
+                            "request {}: Finished crosvm restore successfully",
+                            request_id
+                        );
                         VmResponse::Ok
                     }
                     Err(e) => {
-                        error!("failed to handle restore: {:?}", e);
-                        VmResponse::Err(SysError::new(EIO))
+                        error!("request {}: failed to handle restore: {:?}", request_id, e);
+                        VmResponse::ErrString(format!(
+                            "request {}: failed to handle restore: {:#}",
+                            request_id, e
+                        ))
                     }
                 }
             }
@@ -1965,6 +2905,189 @@ impl VmRequest {
             } => VmResponse::Ok,
             #[cfg(feature = "registered_events")]
             VmRequest::Unregister { socket_addr: _ } => VmResponse::Ok,
+            #[cfg(feature = "registered_events")]
+            VmRequest::RegisteredEventsStatus => {
+                error!(
+                    "request {}: registered events status is not supported on this platform",
+                    request_id
+                );
+                VmResponse::Err(SysError::new(ENOTSUP))
+            }
+            VmRequest::GetVcpuRegisters { vcpu_id } => {
+                if vcpu_id >= vcpu_size {
+                    error!("request {}: no such vcpu: {}", request_id, vcpu_id);
+                    return VmResponse::Err(SysError::new(EINVAL));
+                }
+                let (send_chan, recv_chan) = mpsc::channel();
+                kick_vcpu(VcpuControl::GetRegisters(send_chan), vcpu_id);
+                match recv_chan.recv() {
+                    Ok(Ok(regs)) => VmResponse::VcpuRegisters(regs),
+                    Ok(Err(e)) => {
+                        error!(
+                            "request {}: failed to get registers of vcpu {}: {:?}",
+                            request_id, vcpu_id, e
+                        );
+                        VmResponse::Err(SysError::new(EIO))
+                    }
+                    Err(e) => {
+                        error!(
+                            "request {}: failed to recv registers of vcpu {}: {}",
+                            request_id, vcpu_id, e
+                        );
+                        VmResponse::Err(SysError::new(EIO))
+                    }
+                }
+            }
+            VmRequest::SetVcpuRealtime {
+                vcpu_id,
+                enable,
+                priority,
+            } => {
+                if vcpu_id >= vcpu_size {
+                    error!("request {}: no such vcpu: {}", request_id, vcpu_id);
+                    return VmResponse::Err(SysError::new(EINVAL));
+                }
+                if enable && !VCPU_REALTIME_PRIORITY_RANGE.contains(&priority) {
+                    error!(
+                        "request {}: invalid real-time priority {}, expected {:?}",
+                        request_id, priority, VCPU_REALTIME_PRIORITY_RANGE
+                    );
+                    return VmResponse::Err(SysError::new(EINVAL));
+                }
+                if cfg!(windows) {
+                    error!(
+                        "request {}: real-time vcpus are not supported on this platform",
+                        request_id
+                    );
+                    return VmResponse::Err(SysError::new(ENOTSUP));
+                }
+                let (send_chan, recv_chan) = mpsc::channel();
+                kick_vcpu(
+                    VcpuControl::SetRealtime {
+                        enable,
+                        priority,
+                        response_chan: send_chan,
+                    },
+                    vcpu_id,
+                );
+                match recv_chan.recv() {
+                    Ok(Ok(())) => VmResponse::Ok,
+                    Ok(Err(e)) => {
+                        error!(
+                            "request {}: failed to set real-time policy of vcpu {}: {}",
+                            request_id, vcpu_id, e
+                        );
+                        VmResponse::Err(e)
+                    }
+                    Err(e) => {
+                        error!(
+                            "request {}: failed to recv real-time result of vcpu {}: {}",
+                            request_id, vcpu_id, e
+                        );
+                        VmResponse::Err(SysError::new(EIO))
+                    }
+                }
+            }
+            VmRequest::KickVcpu { vcpu_id } => {
+                if vcpu_id >= vcpu_size {
+                    error!("request {}: no such vcpu: {}", request_id, vcpu_id);
+                    return VmResponse::Err(SysError::new(EINVAL));
+                }
+                kick_vcpu(VcpuControl::Kick, vcpu_id);
+                VmResponse::Ok
+            }
+            VmRequest::RebindControlSocket { .. } => {
+                error!(
+                    "request {}: rebinding the control socket is not supported on this platform",
+                    request_id
+                );
+                VmResponse::Err(SysError::new(ENOTSUP))
+            }
+        }
+    }
+}
+
+/// Prepare the VM for a clean exit by stopping the vCPUs, putting the devices to sleep and
+/// disabling vmm-swap, in that order. `request_id` is the id of the `VmRequest::ExitClean` request,
+/// for the log messages.
+fn do_exit_clean(
+    request_id: u64,
+    kick_vcpus: impl Fn(VcpuControl),
+    vcpu_size: usize,
+    device_control_tube: &Tube,
+    #[cfg(feature = "swap")] swap_controller: Option<&swap::SwapController>,
+) -> anyhow::Result<()> {
+    info!("request {}: clean exit: stopping vCPUs", request_id);
+    // Resumes the vCPUs if any of the following steps fails.
+    let vcpu_guard = VcpuSuspendGuard::new(&kick_vcpus, vcpu_size)?;
+
+    info!(
+        "request {}: clean exit: putting devices to sleep",
+        request_id
+    );
+    device_control_tube
+        .send(&DeviceControlCommand::SleepDevices)
+        .context("send command to devices control socket")?;
+    match device_control_tube
+        .recv()
+        .context("receive from devices control socket")?
+    {
+        VmResponse::Ok => (),
+        resp => bail!("device sleep failed: {}", resp),
+    }
+
+    #[cfg(feature = "swap")]
+    if let Some(swap_controller) = swap_controller {
+        info!("request {}: clean exit: disabling vmm-swap", request_id);
+        if let Err(e) = swap_controller.disable(false) {
+            match device_control_tube
+                .send(&DeviceControlCommand::WakeDevices)
+                .and_then(|()| device_control_tube.recv())
+            {
+                Ok(VmResponse::Ok) => (),
+                Ok(resp) => error!(
+                    "request {}: failed to wake devices after clean exit failure: {}",
+                    request_id, resp
+                ),
+                Err(wake_err) => error!(
+                    "request {}: failed to wake devices after clean exit failure: {}",
+                    request_id, wake_err
+                ),
+            }
+            return Err(e).context("failed to disable vmm-swap");
+        }
+    }
+    // The VM is exiting, so leave the vCPUs stopped.
+    std::mem::forget(vcpu_guard);
+    Ok(())
+}
+
+/// Cycles the IRQ handler thread to flush all pending IRQs to the LAPICs, following `strategy`.
+///
+/// Returns the number of handler iterations.
+fn flush_irqs(irq_handler_control: &Tube, strategy: IrqFlushStrategy) -> anyhow::Result<usize> {
+    let min_iterations = match strategy {
+        IrqFlushStrategy::SinglePass => 1,
+        IrqFlushStrategy::MultiPass => 2,
+    };
+    let mut flush_attempts = 0;
+    loop {
+        irq_handler_control
+            .send(&IrqHandlerRequest::WakeAndNotifyIteration)
+            .context("failed to send flush command to IRQ handler thread")?;
+        let resp = irq_handler_control
+            .recv()
+            .context("failed to recv flush response from IRQ handler thread")?;
+        flush_attempts += 1;
+        let tokens_serviced = match resp {
+            IrqHandlerResponse::HandlerIterationComplete(tokens_serviced) => tokens_serviced,
+            _ => bail!("received unexpected reply from IRQ handler: {:?}", resp),
+        };
+        if tokens_serviced == 0 && flush_attempts >= min_iterations {
+            return Ok(flush_attempts);
+        }
+        if flush_attempts > EXPECTED_MAX_IRQ_FLUSH_ITERATIONS {
+            warn!("flushing IRQs for snapshot may be stalled after iteration {}, expected <= {} iterations", flush_attempts, EXPECTED_MAX_IRQ_FLUSH_ITERATIONS);
         }
     }
 }
@@ -1974,10 +3097,14 @@ fn do_snapshot(
     snapshot_path: PathBuf,
     kick_vcpus: impl Fn(VcpuControl),
     irq_handler_control: &Tube,
+    irq_flush_strategy: IrqFlushStrategy,
     device_control_tube: &Tube,
     vcpu_size: usize,
     snapshot_irqchip: impl Fn() -> anyhow::Result<serde_json::Value>,
 ) -> anyhow::Result<()> {
+    // Declared first so the annotation covers the vcpu and device wake-ups on drop.
+    let phase = VmPhaseAnnotation::new("snapshot");
+    phase.enter("suspend");
     let _vcpu_guard = VcpuSuspendGuard::new(&kick_vcpus, vcpu_size)?;
     let _device_guard = DeviceSleepGuard::new(device_control_tube)?;
 
@@ -1994,37 +3121,24 @@ fn do_snapshot(
     // LAPIC. This is why we cycle the handler thread twice (doing so ensures we
     // process the underlying MSI).
     //
-    // We can handle both of these cases by iterating until there are no tokens
-    // serviced on the requested iteration. Note that in the legacy case, this
-    // ensures at least two iterations.
+    // When irqfds are supported, a single iteration is enough. Otherwise, we
+    // iterate at least twice. In both cases, we keep iterating until there are
+    // no tokens serviced on the requested iteration.
     //
     // Note: within CrosVM, *all* interrupts are eventually converted into the
     // same mechanicism that MSIs use. This is why we say "underlying" MSI for
     // a legacy IRQ.
-    let mut flush_attempts = 0;
-    loop {
-        irq_handler_control
-            .send(&IrqHandlerRequest::WakeAndNotifyIteration)
-            .context("failed to send flush command to IRQ handler thread")?;
-        let resp = irq_handler_control
-            .recv()
-            .context("failed to recv flush response from IRQ handler thread")?;
-        match resp {
-            IrqHandlerResponse::HandlerIterationComplete(tokens_serviced) => {
-                if tokens_serviced == 0 {
-                    break;
-                }
-            }
-            _ => bail!("received unexpected reply from IRQ handler: {:?}", resp),
-        }
-        flush_attempts += 1;
-        if flush_attempts > EXPECTED_MAX_IRQ_FLUSH_ITERATIONS {
-            warn!("flushing IRQs for snapshot may be stalled after iteration {}, expected <= {} iterations", flush_attempts, EXPECTED_MAX_IRQ_FLUSH_ITERATIONS);
-        }
-    }
-    info!("flushed IRQs in {} iterations", flush_attempts);
+    phase.enter("irq-flush");
+    let flush_attempts = flush_irqs(irq_handler_control, irq_flush_strategy)?;
+    info!(
+        "flushed IRQs in {} iterations using {:?} strategy",
+        flush_attempts, irq_flush_strategy
+    );
+
+    write_snapshot_version(&snapshot_path, SNAPSHOT_VERSION)?;
 
     // Snapshot Vcpus
+    phase.enter("vcpu");
     let vcpu_path = snapshot_path.with_extension("vcpu");
     let cpu_file = File::create(&vcpu_path)
         .with_context(|| format!("failed to open path {}", vcpu_path.display()))?;
@@ -2043,9 +3157,11 @@ fn do_snapshot(
             Err(e) => bail!("Failed to snapshot Vcpu, aborting snapshot: {}", e),
         }
     }
-    serde_json::to_writer(cpu_file, &cpu_vec).expect("Failed to write Vcpu state");
+    write_vcpu_snapshots(std::io::BufWriter::new(cpu_file), &mut cpu_vec)
+        .with_context(|| format!("failed to write {}", vcpu_path.display()))?;
 
     // Snapshot irqchip
+    phase.enter("irqchip");
     let irqchip_path = snapshot_path.with_extension("irqchip");
     let irqchip_file = File::create(&irqchip_path)
         .with_context(|| format!("failed to open path {}", irqchip_path.display()))?;
@@ -2053,6 +3169,25 @@ fn do_snapshot(
     serde_json::to_writer(irqchip_file, &irqchip_snap).expect("Failed to write irqchip state");
 
     // Snapshot devices
+    phase.enter("devices");
+    snapshot_devices(device_control_tube, snapshot_path)
+}
+
+/// Snapshot only the devices to file at `snapshot_path`, leaving the vCPUs running.
+fn do_snapshot_devices_only(
+    snapshot_path: PathBuf,
+    device_control_tube: &Tube,
+) -> anyhow::Result<()> {
+    let phase = VmPhaseAnnotation::new("snapshot-devices");
+    phase.enter("suspend");
+    let _device_guard = DeviceSleepGuard::new(device_control_tube)?;
+
+    phase.enter("devices");
+    snapshot_devices(device_control_tube, snapshot_path)
+}
+
+/// Asks the devices control thread to snapshot the sleeping devices to `snapshot_path`.
+fn snapshot_devices(device_control_tube: &Tube, snapshot_path: PathBuf) -> anyhow::Result<()> {
     device_control_tube
         .send(&DeviceControlCommand::SnapshotDevices { snapshot_path })
         .context("send command to devices control socket")?;
@@ -2078,10 +3213,16 @@ pub fn do_restore(
     vcpu_size: usize,
     mut restore_irqchip: impl FnMut(serde_json::Value) -> anyhow::Result<()>,
 ) -> anyhow::Result<()> {
+    check_snapshot_version(&restore_path)?;
+
+    // Declared first so the annotation covers the vcpu and device wake-ups on drop.
+    let phase = VmPhaseAnnotation::new("restore");
+    phase.enter("suspend");
     let _guard = VcpuSuspendGuard::new(&kick_vcpus, vcpu_size);
     let _devices_guard = DeviceSleepGuard::new(device_control_tube)?;
 
     // Restore IrqChip
+    phase.enter("irqchip");
     let irq_path = restore_path.with_extension("irqchip");
     let irq_file = File::open(&irq_path)
         .with_context(|| format!("failed to open path {}", irq_path.display()))?;
@@ -2089,10 +3230,12 @@ pub fn do_restore(
     restore_irqchip(irq_snapshot)?;
 
     // Restore Vcpu(s)
+    phase.enter("vcpu");
     let vcpu_path = restore_path.with_extension("vcpu");
     let cpu_file = File::open(&vcpu_path)
         .with_context(|| format!("failed to open path {}", vcpu_path.display()))?;
-    let vcpu_snapshots: Vec<VcpuSnapshot> = serde_json::from_reader(cpu_file)?;
+    let vcpu_snapshots: Vec<VcpuSnapshot> = read_vcpu_snapshots(cpu_file)
+        .with_context(|| format!("failed to read {}", vcpu_path.display()))?;
     if vcpu_snapshots.len() != vcpu_size {
         bail!(
             "bad cpu count in snapshot: expected={} got={}",
@@ -2127,6 +3270,7 @@ pub fn do_restore(
     }
 
     // Restore devices
+    phase.enter("devices");
     device_control_tube
         .send(&DeviceControlCommand::RestoreDevices { restore_path })
         .context("send command to devices control socket")?;
@@ -2137,6 +3281,7 @@ pub fn do_restore(
         bail!("unexpected RestoreDevices response: {resp}");
     }
 
+    phase.enter("irq-refresh");
     irq_handler_control
         .send(&IrqHandlerRequest::RefreshIrqEventTokens)
         .context("failed to send refresh irq event token command to IRQ handler thread")?;
@@ -2176,9 +3321,15 @@ pub enum VmResponse {
     /// Results of balloon WS-R command
     #[cfg(feature = "balloon")]
     BalloonWS { ws: BalloonWS, balloon_actual: u64 },
+    /// Outcome of a balloon self-test.
+    #[cfg(feature = "balloon")]
+    BalloonSelfTest { passed: bool, detail: String },
     /// Results of PCI hot plug
     #[cfg(feature = "pci-hotplug")]
     PciHotPlugResponse { bus: u8 },
+    /// Results of listing the PCI hotplug slots
+    #[cfg(feature = "pci-hotplug")]
+    HotplugSlots(Vec<HotplugSlot>),
     /// Results of usb control commands.
     UsbResponse(UsbControlResult),
     #[cfg(feature = "gpu")]
@@ -2188,8 +3339,45 @@ pub enum VmResponse {
     BatResponse(BatControlResult),
     /// Results of swap status command.
     SwapStatus(SwapStatus),
+    /// Results of swap-out progress command.
+    SwapOutProgress(SwapOutProgress),
     /// Gets the state of Devices (sleep/wake)
     DevicesState(DevicesState),
+    /// Registers of a VCPU, for debugging.
+    VcpuRegisters(VcpuRegisters),
+    /// Current run mode of the VM.
+    RunMode(VmRunMode),
+    /// Number of events dropped for each registered listener, keyed by its socket address.
+    #[cfg(feature = "registered_events")]
+    RegisteredEventsStatus {
+        dropped_events: BTreeMap<String, u64>,
+    },
+    /// Some operations of a batch request failed. Each operation is identified by a name chosen by
+    /// the request, e.g. a device label.
+    Partial {
+        succeeded: Vec<String>,
+        failed: Vec<(String, SysError)>,
+    },
+}
+
+impl VmResponse {
+    /// Summarizes the named results of a batch request: `Ok` if every operation succeeded, or
+    /// `Partial` listing which ones succeeded and which failed otherwise.
+    pub fn from_batch_results(results: impl IntoIterator<Item = (String, Result<()>)>) -> Self {
+        let mut succeeded = Vec::new();
+        let mut failed = Vec::new();
+        for (name, result) in results {
+            match result {
+                Ok(()) => succeeded.push(name),
+                Err(e) => failed.push((name, e)),
+            }
+        }
+        if failed.is_empty() {
+            VmResponse::Ok
+        } else {
+            VmResponse::Partial { succeeded, failed }
+        }
+    }
 }
 
 impl Display for VmResponse {
@@ -2228,9 +3416,26 @@ impl Display for VmResponse {
                     balloon_actual,
                 )
             }
+            #[cfg(feature = "balloon")]
+            VmResponse::BalloonSelfTest { passed, detail } => {
+                let outcome = if *passed { "passed" } else { "failed" };
+                write!(f, "balloon self-test {}: {}", outcome, detail)
+            }
             UsbResponse(result) => write!(f, "usb control request get result {:?}", result),
             #[cfg(feature = "pci-hotplug")]
             PciHotPlugResponse { bus } => write!(f, "pci hotplug bus {:?}", bus),
+            #[cfg(feature = "pci-hotplug")]
+            HotplugSlots(slots) => {
+                write!(f, "hotplug slots")?;
+                for slot in slots {
+                    match &slot.device_type {
+                        Some(device_type) => write!(f, " {}:{:?}", slot.bus, device_type)?,
+                        None if slot.occupied => write!(f, " {}:occupied", slot.bus)?,
+                        None => write!(f, " {}:empty", slot.bus)?,
+                    }
+                }
+                StdResult::Ok(())
+            }
             #[cfg(feature = "gpu")]
             GpuResponse(result) => write!(f, "gpu control request result {:?}", result),
             BatResponse(result) => write!(f, "{}", result),
@@ -2242,7 +3447,43 @@ impl Display for VmResponse {
                         .unwrap_or_else(|_| "invalid_response".to_string()),
                 )
             }
+            SwapOutProgress(progress) => {
+                write!(
+                    f,
+                    "{}",
+                    serde_json::to_string(&progress)
+                        .unwrap_or_else(|_| "invalid_response".to_string()),
+                )
+            }
             DevicesState(status) => write!(f, "devices status: {:?}", status),
+            VcpuRegisters(regs) => {
+                write!(
+                    f,
+                    "{}",
+                    serde_json::to_string(&regs).unwrap_or_else(|_| "invalid_response".to_string()),
+                )
+            }
+            RunMode(mode) => write!(f, "run mode: {}", mode),
+            #[cfg(feature = "registered_events")]
+            RegisteredEventsStatus { dropped_events } => {
+                write!(f, "registered listeners:")?;
+                for (socket_addr, dropped) in dropped_events {
+                    write!(f, "\n{}: {} dropped events", socket_addr, dropped)?;
+                }
+                StdResult::Ok(())
+            }
+            Partial { succeeded, failed } => {
+                write!(
+                    f,
+                    "partial success: {} succeeded, {} failed",
+                    succeeded.len(),
+                    failed.len()
+                )?;
+                for (name, e) in failed {
+                    write!(f, "\n{}: {}", name, e)?;
+                }
+                StdResult::Ok(())
+            }
         }
     }
 }
@@ -2388,3 +3629,1748 @@ pub fn virtio_iommu_request(
     };
     Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::cell::RefCell;
+
+    use super::*;
+
+    fn execute_with_mocks(
+        request: VmRequest,
+        run_mode: &mut Option<VmRunMode>,
+        kick_vcpus: impl Fn(VcpuControl),
+        kick_vcpu: impl Fn(VcpuControl, usize),
+        device_control_tube: &Tube,
+        vcpu_size: usize,
+    ) -> VmResponse {
+        execute_with_pm(
+            next_request_id(),
+            request,
+            &mut None,
+            run_mode,
+            kick_vcpus,
+            kick_vcpu,
+            device_control_tube,
+            vcpu_size,
+        )
+    }
+
+    fn execute_with_pm(
+        request_id: u64,
+        request: VmRequest,
+        pm: &mut Option<Arc<Mutex<dyn PmResource + Send>>>,
+        run_mode: &mut Option<VmRunMode>,
+        kick_vcpus: impl Fn(VcpuControl),
+        kick_vcpu: impl Fn(VcpuControl, usize),
+        device_control_tube: &Tube,
+        vcpu_size: usize,
+    ) -> VmResponse {
+        let (irq_handler_control, _irq_handler) = Tube::pair().unwrap();
+        request.execute(
+            request_id,
+            run_mode,
+            &[],
+            pm,
+            #[cfg(feature = "gpu")]
+            None,
+            None,
+            &mut None,
+            kick_vcpus,
+            kick_vcpu,
+            false,
+            false,
+            #[cfg(feature = "swap")]
+            None,
+            device_control_tube,
+            vcpu_size,
+            &irq_handler_control,
+            IrqFlushStrategy::SinglePass,
+            || Ok(serde_json::Value::Null),
+            |_| Ok(()),
+        )
+    }
+
+    /// Executes `VmRequest::ResumeVcpus` with `force_s2idle` and without a PM resource.
+    fn resume_s2idle_without_pm(
+        strict_s2idle: bool,
+        run_mode: &mut Option<VmRunMode>,
+    ) -> VmResponse {
+        let (device_control_tube, device) = Tube::pair().unwrap();
+        device
+            .send(&VmResponse::DevicesState(DevicesState::Wake))
+            .unwrap();
+        let (irq_handler_control, _irq_handler) = Tube::pair().unwrap();
+        VmRequest::ResumeVcpus.execute(
+            next_request_id(),
+            run_mode,
+            &[],
+            &mut None,
+            #[cfg(feature = "gpu")]
+            None,
+            None,
+            &mut None,
+            |_| {},
+            |_, _| {},
+            true,
+            strict_s2idle,
+            #[cfg(feature = "swap")]
+            None,
+            &device_control_tube,
+            1,
+            &irq_handler_control,
+            IrqFlushStrategy::SinglePass,
+            || Ok(serde_json::Value::Null),
+            |_| Ok(()),
+        )
+    }
+
+    /// Returns a `kick_vcpus` function emulating `vcpu_size` vCPUs that follow
+    /// `VcpuControl::RunState` requests. Each run state change is recorded in `phases`.
+    fn mock_vcpus(phases: Arc<Mutex<Vec<String>>>, vcpu_size: usize) -> impl Fn(VcpuControl) {
+        let mode = Cell::new(VmRunMode::Running);
+        move |msg| match msg {
+            VcpuControl::RunState(new_mode) => {
+                phases.lock().push(format!("vcpus {}", new_mode));
+                mode.set(new_mode);
+            }
+            VcpuControl::GetStates(sender) => {
+                for _ in 0..vcpu_size {
+                    sender.send(mode.get()).unwrap();
+                }
+            }
+            _ => panic!("unexpected vcpu control message"),
+        }
+    }
+
+    #[test]
+    fn exit_clean_phase_order() {
+        let phases = Arc::new(Mutex::new(Vec::new()));
+        let (device_control_tube, device) = Tube::pair().unwrap();
+        let device_phases = phases.clone();
+        let device_thread = std::thread::spawn(move || {
+            let cmd = device.recv::<DeviceControlCommand>().unwrap();
+            assert!(matches!(cmd, DeviceControlCommand::SleepDevices));
+            device_phases.lock().push("devices sleep".to_owned());
+            device.send(&VmResponse::Ok).unwrap();
+        });
+
+        let mut run_mode = None;
+        let resp = execute_with_mocks(
+            VmRequest::ExitClean,
+            &mut run_mode,
+            mock_vcpus(phases.clone(), 2),
+            |_, _| {},
+            &device_control_tube,
+            2,
+        );
+        device_thread.join().unwrap();
+
+        assert!(matches!(resp, VmResponse::Ok));
+        assert_eq!(run_mode, Some(VmRunMode::Exiting));
+        assert_eq!(*phases.lock(), vec!["vcpus suspending", "devices sleep"]);
+    }
+
+    #[test]
+    fn exit_clean_device_sleep_failure() {
+        let phases = Arc::new(Mutex::new(Vec::new()));
+        let (device_control_tube, device) = Tube::pair().unwrap();
+        device.send(&VmResponse::Err(SysError::new(EIO))).unwrap();
+
+        let mut run_mode = None;
+        let resp = execute_with_mocks(
+            VmRequest::ExitClean,
+            &mut run_mode,
+            mock_vcpus(phases.clone(), 1),
+            |_, _| {},
+            &device_control_tube,
+            1,
+        );
+
+        assert!(matches!(resp, VmResponse::Err(_)));
+        assert_eq!(run_mode, None);
+        let cmd = device.recv::<DeviceControlCommand>().unwrap();
+        assert!(matches!(cmd, DeviceControlCommand::SleepDevices));
+        // The vCPUs are resumed so that the VM keeps running.
+        assert_eq!(*phases.lock(), vec!["vcpus suspending", "vcpus running"]);
+    }
+
+    #[cfg(feature = "balloon")]
+    #[test]
+    fn balloon_stats_without_balloon() {
+        let (device_control_tube, _device) = Tube::pair().unwrap();
+        let resp = execute_with_mocks(
+            VmRequest::BalloonStats,
+            &mut None,
+            |_| {},
+            |_, _| {},
+            &device_control_tube,
+            1,
+        );
+        assert!(matches!(resp, VmResponse::Err(e) if e == SysError::new(ENOTSUP)));
+    }
+
+    #[test]
+    fn snapshot_devices_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot_path = dir.path().join("snapshot");
+        let (device_control_tube, device) = Tube::pair().unwrap();
+        let device_thread = std::thread::spawn(move || loop {
+            let resp = match device.recv::<DeviceControlCommand>().unwrap() {
+                DeviceControlCommand::GetDevicesState => {
+                    VmResponse::DevicesState(DevicesState::Wake)
+                }
+                DeviceControlCommand::SleepDevices => VmResponse::Ok,
+                DeviceControlCommand::SnapshotDevices { snapshot_path } => {
+                    std::fs::write(snapshot_path, b"{}").unwrap();
+                    VmResponse::Ok
+                }
+                DeviceControlCommand::WakeDevices => {
+                    device.send(&VmResponse::Ok).unwrap();
+                    return;
+                }
+                cmd => panic!("unexpected device control command: {:?}", cmd),
+            };
+            device.send(&resp).unwrap();
+        });
+
+        let resp = execute_with_mocks(
+            VmRequest::Snapshot(SnapshotCommand::TakeDevicesOnly {
+                snapshot_path: snapshot_path.clone(),
+            }),
+            &mut None,
+            |_| panic!("vcpus should not be kicked"),
+            |_, _| panic!("vcpu should not be kicked"),
+            &device_control_tube,
+            1,
+        );
+        device_thread.join().unwrap();
+
+        assert!(matches!(resp, VmResponse::Ok));
+        assert!(snapshot_path.exists());
+        for extension in ["vcpu", "irqchip", "version"] {
+            assert!(!snapshot_path.with_extension(extension).exists());
+        }
+    }
+
+    #[test]
+    fn snapshot_failure_reports_request_id() {
+        let phases = Arc::new(Mutex::new(Vec::new()));
+        let (device_control_tube, device) = Tube::pair().unwrap();
+        device.send(&VmResponse::Err(SysError::new(EIO))).unwrap();
+
+        let resp = execute_with_pm(
+            0xfeed,
+            VmRequest::Snapshot(SnapshotCommand::Take {
+                snapshot_path: PathBuf::from("/nonexistent/snapshot"),
+            }),
+            &mut None,
+            &mut None,
+            mock_vcpus(phases, 1),
+            |_, _| {},
+            &device_control_tube,
+            1,
+        );
+
+        match resp {
+            VmResponse::ErrString(e) => assert!(
+                e.starts_with(&format!("request {}: ", 0xfeed)),
+                "unexpected error: {}",
+                e
+            ),
+            r => panic!("unexpected response: {}", r),
+        }
+    }
+
+    #[test]
+    fn get_vcpu_registers() {
+        let (vcpu_send, vcpu_recv) = mpsc::channel();
+        let vcpu_thread = std::thread::spawn(move || match vcpu_recv.recv().unwrap() {
+            VcpuControl::GetRegisters(sender) => sender
+                .send(Ok(VcpuRegisters {
+                    vcpu_id: 1,
+                    ..Default::default()
+                }))
+                .unwrap(),
+            _ => panic!("unexpected vcpu control message"),
+        });
+        let (device_control_tube, _device) = Tube::pair().unwrap();
+
+        let mut run_mode = None;
+        let resp = execute_with_mocks(
+            VmRequest::GetVcpuRegisters { vcpu_id: 1 },
+            &mut run_mode,
+            |_| {},
+            |msg, index| {
+                assert_eq!(index, 1);
+                vcpu_send.send(msg).unwrap();
+            },
+            &device_control_tube,
+            2,
+        );
+        vcpu_thread.join().unwrap();
+
+        let VmResponse::VcpuRegisters(regs) = &resp else {
+            panic!("unexpected response: {}", resp);
+        };
+        assert_eq!(regs.vcpu_id, 1);
+        let json: serde_json::Value = serde_json::from_str(&resp.to_string()).unwrap();
+        assert_eq!(json["vcpu_id"], 1);
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn set_vcpu_realtime() {
+        let (vcpu_send, vcpu_recv) = mpsc::channel();
+        let vcpu_thread = std::thread::spawn(move || {
+            // Records the real-time requests received by each vCPU.
+            let mut requests = Vec::new();
+            while let Ok((msg, index)) = vcpu_recv.recv() {
+                match msg {
+                    VcpuControl::SetRealtime {
+                        enable,
+                        priority,
+                        response_chan,
+                    } => {
+                        requests.push((index, enable, priority));
+                        response_chan.send(Ok(())).unwrap();
+                    }
+                    _ => panic!("unexpected vcpu control message"),
+                }
+            }
+            requests
+        });
+        let (device_control_tube, _device) = Tube::pair().unwrap();
+
+        let mut run_mode = None;
+        for request in [
+            VmRequest::SetVcpuRealtime {
+                vcpu_id: 1,
+                enable: true,
+                priority: 10,
+            },
+            VmRequest::SetVcpuRealtime {
+                vcpu_id: 1,
+                enable: false,
+                priority: 0,
+            },
+        ] {
+            let resp = execute_with_mocks(
+                request,
+                &mut run_mode,
+                |_| panic!("only the target vcpu should be kicked"),
+                |msg, index| vcpu_send.send((msg, index)).unwrap(),
+                &device_control_tube,
+                2,
+            );
+            assert!(matches!(resp, VmResponse::Ok));
+        }
+        drop(vcpu_send);
+
+        assert_eq!(
+            vcpu_thread.join().unwrap(),
+            vec![(1, true, 10), (1, false, 0)]
+        );
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn set_vcpu_realtime_not_permitted() {
+        let (vcpu_send, vcpu_recv) = mpsc::channel();
+        // A vCPU thread that isn't allowed to use real-time priorities.
+        let vcpu_thread = std::thread::spawn(move || match vcpu_recv.recv().unwrap() {
+            VcpuControl::SetRealtime { response_chan, .. } => {
+                response_chan.send(Err(SysError::new(libc::EPERM))).unwrap()
+            }
+            _ => panic!("unexpected vcpu control message"),
+        });
+        let (device_control_tube, _device) = Tube::pair().unwrap();
+
+        let mut run_mode = None;
+        let resp = execute_with_mocks(
+            VmRequest::SetVcpuRealtime {
+                vcpu_id: 0,
+                enable: true,
+                priority: 10,
+            },
+            &mut run_mode,
+            |_| panic!("only the target vcpu should be kicked"),
+            |msg, _| vcpu_send.send(msg).unwrap(),
+            &device_control_tube,
+            1,
+        );
+        vcpu_thread.join().unwrap();
+        assert!(matches!(resp, VmResponse::Err(e) if e.errno() == libc::EPERM));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn set_vcpu_realtime_not_supported() {
+        let (device_control_tube, _device) = Tube::pair().unwrap();
+
+        let mut run_mode = None;
+        let resp = execute_with_mocks(
+            VmRequest::SetVcpuRealtime {
+                vcpu_id: 0,
+                enable: true,
+                priority: 10,
+            },
+            &mut run_mode,
+            |_| panic!("vcpus should not be kicked"),
+            |_, _| panic!("vcpu should not be kicked"),
+            &device_control_tube,
+            1,
+        );
+        assert!(matches!(resp, VmResponse::Err(e) if e.errno() == ENOTSUP));
+    }
+
+    #[test]
+    fn set_vcpu_realtime_invalid() {
+        let (device_control_tube, _device) = Tube::pair().unwrap();
+
+        let mut run_mode = None;
+        for (vcpu_id, priority) in [(2, 10), (0, 0), (0, 100)] {
+            let resp = execute_with_mocks(
+                VmRequest::SetVcpuRealtime {
+                    vcpu_id,
+                    enable: true,
+                    priority,
+                },
+                &mut run_mode,
+                |_| panic!("only the target vcpu should be kicked"),
+                |_, _| panic!("vcpu should not be kicked"),
+                &device_control_tube,
+                2,
+            );
+            assert!(matches!(resp, VmResponse::Err(_)));
+        }
+    }
+
+    #[test]
+    fn hotplug_vfio_not_implemented() {
+        match vfio_hotplug_not_implemented(7) {
+            VmResponse::ErrString(e) => assert!(
+                e.contains("vfio hotplug not implemented in this build"),
+                "unexpected error: {}",
+                e
+            ),
+            r => panic!("unexpected response: {}", r),
+        }
+    }
+
+    #[test]
+    fn execute_hotplug_vfio_not_implemented() {
+        let (device_control_tube, _device) = Tube::pair().unwrap();
+
+        let mut run_mode = None;
+        let resp = execute_with_mocks(
+            VmRequest::HotPlugVfioCommand {
+                device: HotPlugDeviceInfo {
+                    device_type: HotPlugDeviceType::EndPoint,
+                    path: PathBuf::from("/sys/bus/pci/devices/0000:00:01.0"),
+                    hp_interrupt: true,
+                },
+                add: true,
+            },
+            &mut run_mode,
+            |_| panic!("vcpus should not be kicked"),
+            |_, _| panic!("vcpu should not be kicked"),
+            &device_control_tube,
+            1,
+        );
+
+        assert!(
+            matches!(resp, VmResponse::ErrString(ref e) if e.contains("vfio hotplug not implemented")),
+            "unexpected response: {}",
+            resp
+        );
+    }
+
+    #[test]
+    fn kick_vcpu() {
+        let (device_control_tube, _device) = Tube::pair().unwrap();
+        let kicks = RefCell::new(Vec::new());
+
+        let mut run_mode = None;
+        let resp = execute_with_mocks(
+            VmRequest::KickVcpu { vcpu_id: 1 },
+            &mut run_mode,
+            |_| panic!("only the target vcpu should be kicked"),
+            |msg, index| {
+                assert!(matches!(msg, VcpuControl::Kick));
+                kicks.borrow_mut().push(index);
+            },
+            &device_control_tube,
+            2,
+        );
+
+        assert!(matches!(resp, VmResponse::Ok));
+        assert_eq!(kicks.into_inner(), vec![1]);
+        assert_eq!(run_mode, None);
+    }
+
+    #[test]
+    fn kick_vcpu_invalid_vcpu() {
+        let (device_control_tube, _device) = Tube::pair().unwrap();
+
+        let mut run_mode = None;
+        let resp = execute_with_mocks(
+            VmRequest::KickVcpu { vcpu_id: 2 },
+            &mut run_mode,
+            |_| panic!("only the target vcpu should be kicked"),
+            |_, _| panic!("vcpu should not be kicked"),
+            &device_control_tube,
+            2,
+        );
+
+        assert!(matches!(resp, VmResponse::Err(_)));
+    }
+
+    #[test]
+    fn get_vcpu_registers_invalid_vcpu() {
+        let (device_control_tube, _device) = Tube::pair().unwrap();
+
+        let mut run_mode = None;
+        let resp = execute_with_mocks(
+            VmRequest::GetVcpuRegisters { vcpu_id: 2 },
+            &mut run_mode,
+            |_| {},
+            |_, _| panic!("vcpu should not be kicked"),
+            &device_control_tube,
+            2,
+        );
+
+        assert!(matches!(resp, VmResponse::Err(_)));
+    }
+
+    /// Runs `flush_irqs` against an IRQ handler that services `tokens_serviced[i]` tokens on its
+    /// i-th iteration, and returns the number of iterations.
+    fn flush_irqs_with_strategy(strategy: IrqFlushStrategy, tokens_serviced: &[usize]) -> usize {
+        let (irq_handler_control, irq_handler) = Tube::pair().unwrap();
+        for &tokens in tokens_serviced {
+            irq_handler
+                .send(&IrqHandlerResponse::HandlerIterationComplete(tokens))
+                .unwrap();
+        }
+        let iterations = flush_irqs(&irq_handler_control, strategy).unwrap();
+        for _ in 0..iterations {
+            assert!(matches!(
+                irq_handler.recv().unwrap(),
+                IrqHandlerRequest::WakeAndNotifyIteration
+            ));
+        }
+        iterations
+    }
+
+    #[test]
+    fn flush_irqs_single_pass() {
+        // KVM, with a kernel or split irqchip.
+        let strategy = IrqFlushStrategy::from_caps(&HypervisorCaps {
+            kvm_present: true,
+            irqfd: true,
+            ioeventfd: true,
+            immediate_exit: true,
+        });
+        assert_eq!(strategy, IrqFlushStrategy::SinglePass);
+        assert_eq!(flush_irqs_with_strategy(strategy, &[0]), 1);
+        assert_eq!(flush_irqs_with_strategy(strategy, &[3, 1, 0]), 3);
+    }
+
+    #[test]
+    fn flush_irqs_kvm_split_irqchip() {
+        // With a split irqchip, the MSI raised by the userspace IOAPIC for a legacy IRQ goes
+        // through an irqfd, so no extra pass is needed.
+        let caps = HypervisorCaps {
+            kvm_present: true,
+            irqfd: true,
+            ..Default::default()
+        };
+        let strategy = IrqFlushStrategy::from_caps(&caps);
+        assert_eq!(strategy, IrqFlushStrategy::SinglePass);
+        assert_eq!(flush_irqs_with_strategy(strategy, &[1, 0]), 2);
+    }
+
+    #[test]
+    fn flush_irqs_multi_pass() {
+        // A hypervisor without irqfds, e.g. WHPX.
+        let strategy = IrqFlushStrategy::from_caps(&HypervisorCaps::default());
+        assert_eq!(strategy, IrqFlushStrategy::MultiPass);
+        assert_eq!(flush_irqs_with_strategy(strategy, &[0, 0]), 2);
+        assert_eq!(flush_irqs_with_strategy(strategy, &[1, 0]), 2);
+        assert_eq!(flush_irqs_with_strategy(strategy, &[3, 1, 0]), 3);
+    }
+
+    /// A `Vm` that only keeps track of the memory regions added to it.
+    #[derive(Default)]
+    struct MockVm {
+        regions: BTreeMap<MemSlot, (GuestAddress, Box<dyn MappedRegion>, bool)>,
+        next_slot: MemSlot,
+        // (slot, offset, size) of each `populate_memory_region` call.
+        populated: Vec<(MemSlot, usize, usize)>,
+        // Whether `remove_memory_region` fails and leaves the region in place.
+        fail_remove: bool,
+    }
+
+    impl Vm for MockVm {
+        fn try_clone(&self) -> Result<Self> {
+            unimplemented!()
+        }
+
+        fn check_capability(&self, _c: hypervisor::VmCap) -> bool {
+            false
+        }
+
+        fn get_guest_phys_addr_bits(&self) -> u8 {
+            40
+        }
+
+        fn get_memory(&self) -> &vm_memory::GuestMemory {
+            unimplemented!()
+        }
+
+        fn add_memory_region(
+            &mut self,
+            guest_addr: GuestAddress,
+            mem_region: Box<dyn MappedRegion>,
+            read_only: bool,
+            _log_dirty_pages: bool,
+        ) -> Result<MemSlot> {
+            let slot = self.next_slot;
+            self.next_slot += 1;
+            self.regions
+                .insert(slot, (guest_addr, mem_region, read_only));
+            Ok(slot)
+        }
+
+        fn msync_memory_region(
+            &mut self,
+            _slot: MemSlot,
+            _offset: usize,
+            _size: usize,
+        ) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn populate_memory_region(
+            &mut self,
+            slot: MemSlot,
+            offset: usize,
+            size: usize,
+        ) -> Result<()> {
+            if !self.regions.contains_key(&slot) {
+                return Err(SysError::new(libc::ENOENT));
+            }
+            self.populated.push((slot, offset, size));
+            Ok(())
+        }
+
+        fn remove_memory_region(&mut self, slot: MemSlot) -> Result<Box<dyn MappedRegion>> {
+            if self.fail_remove {
+                return Err(SysError::new(libc::EBUSY));
+            }
+            match self.regions.remove(&slot) {
+                Some((_, region, _)) => Ok(region),
+                None => Err(SysError::new(libc::ENOENT)),
+            }
+        }
+
+        fn create_device(&self, _kind: hypervisor::DeviceKind) -> Result<SafeDescriptor> {
+            unimplemented!()
+        }
+
+        fn get_dirty_log(&self, _slot: MemSlot, _dirty_log: &mut [u8]) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn register_ioevent(
+            &mut self,
+            _evt: &Event,
+            _addr: IoEventAddress,
+            _datamatch: Datamatch,
+        ) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn unregister_ioevent(
+            &mut self,
+            _evt: &Event,
+            _addr: IoEventAddress,
+            _datamatch: Datamatch,
+        ) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn handle_io_events(&self, _addr: IoEventAddress, _data: &[u8]) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn get_pvclock(&self) -> Result<hypervisor::ClockState> {
+            unimplemented!()
+        }
+
+        fn set_pvclock(&self, _state: &hypervisor::ClockState) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn add_fd_mapping(
+            &mut self,
+            _slot: u32,
+            _offset: usize,
+            _size: usize,
+            _fd: &dyn AsRawDescriptor,
+            _fd_offset: u64,
+            _prot: Protection,
+        ) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn remove_mapping(&mut self, _slot: u32, _offset: usize, _size: usize) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn handle_balloon_event(&mut self, _event: BalloonEvent) -> Result<()> {
+            unimplemented!()
+        }
+    }
+
+    fn test_system_allocator() -> SystemAllocator {
+        SystemAllocator::new(
+            resources::SystemAllocatorConfig {
+                io: None,
+                low_mmio: resources::AddressRange {
+                    start: 0x3000_0000,
+                    end: 0x3fff_ffff,
+                },
+                high_mmio: resources::AddressRange {
+                    start: 0x1_0000_0000,
+                    end: 0x1_ffff_ffff,
+                },
+                platform_mmio: None,
+                first_irq: 5,
+            },
+            None,
+            &[],
+        )
+        .unwrap()
+    }
+
+    fn shm_source(size: u64) -> VmMemorySource {
+        let shm = SharedMemory::new("vm_control_test", size).unwrap();
+        VmMemorySource::Descriptor {
+            descriptor: SafeDescriptor::from(shm),
+            offset: 0,
+            size,
+        }
+    }
+
+    #[test]
+    fn register_memory_iommu_failure_releases_descriptor() {
+        let mut vm = MockVm::default();
+        let mut sys_allocator = test_system_allocator();
+        let mut gralloc = RutabagaGralloc::new().unwrap();
+        let mut region_state = VmMemoryRegionState::new();
+        let (iommu_tube, iommu_device_tube) = Tube::pair().unwrap();
+        let mut iommu_client = VmMemoryRequestIommuClient::new(Arc::new(Mutex::new(iommu_tube)));
+        // Queue the viommu's rejection up front so no responder thread is needed.
+        iommu_device_tube
+            .send(&VirtioIOMMUResponse::Err(SysError::new(EINVAL)))
+            .unwrap();
+
+        let before = region_state.live_descriptor_count();
+        let resp = VmMemoryRequest::RegisterMemory {
+            source: shm_source(0x1000),
+            dest: VmMemoryDestination::GuestPhysicalAddress(0x1_0000_0000),
+            prot: Protection::read_write(),
+        }
+        .execute(
+            &mut vm,
+            &mut sys_allocator,
+            &mut gralloc,
+            Some(&mut iommu_client),
+            &mut region_state,
+        );
+
+        assert!(matches!(resp, VmMemoryResponse::Err(_)));
+        assert_eq!(region_state.live_descriptor_count(), before);
+        assert!(region_state.descriptor_accounting_consistent());
+        assert!(vm.regions.is_empty());
+    }
+
+    #[test]
+    fn register_memory_iommu_failure_remove_error() {
+        let mut vm = MockVm {
+            fail_remove: true,
+            ..Default::default()
+        };
+        let mut sys_allocator = test_system_allocator();
+        let mut gralloc = RutabagaGralloc::new().unwrap();
+        let mut region_state = VmMemoryRegionState::new();
+        let (iommu_tube, iommu_device_tube) = Tube::pair().unwrap();
+        let mut iommu_client = VmMemoryRequestIommuClient::new(Arc::new(Mutex::new(iommu_tube)));
+        iommu_device_tube
+            .send(&VirtioIOMMUResponse::Err(SysError::new(EINVAL)))
+            .unwrap();
+
+        let result = VmMemoryRequest::RegisterMemory {
+            source: shm_source(0x1000),
+            dest: VmMemoryDestination::GuestPhysicalAddress(0x1_0000_0000),
+            prot: Protection::read_write(),
+        }
+        .try_execute(
+            &mut vm,
+            &mut sys_allocator,
+            &mut gralloc,
+            Some(&mut iommu_client),
+            &mut region_state,
+        );
+
+        // The slot could not be freed, so the failure to remove it is what gets reported.
+        assert!(matches!(result, Err(VmControlError::RemoveMemoryRegion(_))));
+        assert_eq!(region_state.live_descriptor_count(), 0);
+        assert!(region_state.descriptor_accounting_consistent());
+    }
+
+    fn register_memory_read_only_flag(prot: Protection) -> bool {
+        let mut vm = MockVm::default();
+        let mut sys_allocator = test_system_allocator();
+        let mut gralloc = RutabagaGralloc::new().unwrap();
+        let mut region_state = VmMemoryRegionState::new();
+
+        let resp = VmMemoryRequest::RegisterMemory {
+            source: shm_source(0x1000),
+            dest: VmMemoryDestination::GuestPhysicalAddress(0x1_0000_0000),
+            prot,
+        }
+        .execute(
+            &mut vm,
+            &mut sys_allocator,
+            &mut gralloc,
+            None,
+            &mut region_state,
+        );
+
+        assert!(matches!(resp, VmMemoryResponse::RegisterMemory(_)));
+        let (guest_addr, _, read_only) = vm.regions.values().next().unwrap();
+        assert_eq!(*guest_addr, GuestAddress(0x1_0000_0000));
+        *read_only
+    }
+
+    #[test]
+    fn register_memory_read_only() {
+        assert!(register_memory_read_only_flag(Protection::read()));
+    }
+
+    #[test]
+    fn register_memory_read_write() {
+        assert!(!register_memory_read_only_flag(Protection::read_write()));
+    }
+
+    #[test]
+    fn register_memory_read_write_exec() {
+        assert!(!register_memory_read_only_flag(
+            Protection::read_write_exec()
+        ));
+    }
+
+    fn try_execute_memory_request(
+        request: VmMemoryRequest,
+        iommu_client: Option<&mut VmMemoryRequestIommuClient>,
+    ) -> StdResult<VmMemoryResponse, VmControlError> {
+        let mut vm = MockVm::default();
+        let mut sys_allocator = test_system_allocator();
+        let mut gralloc = RutabagaGralloc::new().unwrap();
+        let mut region_state = VmMemoryRegionState::new();
+        request.try_execute(
+            &mut vm,
+            &mut sys_allocator,
+            &mut gralloc,
+            iommu_client,
+            &mut region_state,
+        )
+    }
+
+    #[test]
+    fn register_memory_address_allocation_error() {
+        let result = try_execute_memory_request(
+            VmMemoryRequest::RegisterMemory {
+                source: shm_source(0x1000),
+                dest: VmMemoryDestination::ExistingAllocation {
+                    allocation: Alloc::PciBar {
+                        bus: 0,
+                        dev: 1,
+                        func: 0,
+                        bar: 0,
+                    },
+                    offset: 0,
+                },
+                prot: Protection::read_write(),
+            },
+            None,
+        );
+        assert!(matches!(result, Err(VmControlError::AddressAllocation(_))));
+    }
+
+    #[test]
+    fn register_memory_mapping_error() {
+        let shm = SharedMemory::new("vm_control_test", 0x1000).unwrap();
+        let result = try_execute_memory_request(
+            VmMemoryRequest::RegisterMemory {
+                source: VmMemorySource::Descriptor {
+                    descriptor: SafeDescriptor::from(shm),
+                    // mmap rejects offsets that aren't page aligned.
+                    offset: 1,
+                    size: 0x1000,
+                },
+                dest: VmMemoryDestination::GuestPhysicalAddress(0x1_0000_0000),
+                prot: Protection::read_write(),
+            },
+            None,
+        );
+        assert!(matches!(result, Err(VmControlError::Mapping(_))));
+    }
+
+    #[test]
+    fn register_memory_iommu_rejected() {
+        let (iommu_tube, iommu_device_tube) = Tube::pair().unwrap();
+        let mut iommu_client = VmMemoryRequestIommuClient::new(Arc::new(Mutex::new(iommu_tube)));
+        iommu_device_tube
+            .send(&VirtioIOMMUResponse::Err(SysError::new(EINVAL)))
+            .unwrap();
+
+        let result = try_execute_memory_request(
+            VmMemoryRequest::RegisterMemory {
+                source: shm_source(0x1000),
+                dest: VmMemoryDestination::GuestPhysicalAddress(0x1_0000_0000),
+                prot: Protection::read_write(),
+            },
+            Some(&mut iommu_client),
+        );
+        assert!(matches!(result, Err(VmControlError::IommuRejected(_))));
+    }
+
+    #[test]
+    fn unregister_memory_unknown_region() {
+        let result = try_execute_memory_request(
+            VmMemoryRequest::UnregisterMemory {
+                id: VmMemoryRegionId(7),
+                release_pages: false,
+            },
+            None,
+        );
+        let Err(e) = result else {
+            panic!("unregistering an unknown region should fail");
+        };
+        assert!(matches!(
+            e,
+            VmControlError::UnknownRegion(VmMemoryRegionId(7))
+        ));
+        assert_eq!(SysError::from(e), SysError::new(EINVAL));
+    }
+
+    #[test]
+    fn prefault_registered_region() {
+        let mut vm = MockVm::default();
+        let mut sys_allocator = test_system_allocator();
+        let mut gralloc = RutabagaGralloc::new().unwrap();
+        let mut region_state = VmMemoryRegionState::new();
+
+        let resp = VmMemoryRequest::RegisterMemory {
+            source: shm_source(0x2000),
+            dest: VmMemoryDestination::GuestPhysicalAddress(0x1_0000_0000),
+            prot: Protection::read_write(),
+        }
+        .execute(
+            &mut vm,
+            &mut sys_allocator,
+            &mut gralloc,
+            None,
+            &mut region_state,
+        );
+        let VmMemoryResponse::RegisterMemory(id) = resp else {
+            panic!("failed to register memory: {:?}", resp);
+        };
+        let slot = *vm.regions.keys().next().unwrap();
+
+        let resp = VmMemoryRequest::PrefaultRegion { id }.execute(
+            &mut vm,
+            &mut sys_allocator,
+            &mut gralloc,
+            None,
+            &mut region_state,
+        );
+        assert!(matches!(resp, VmMemoryResponse::Ok));
+        assert_eq!(vm.populated, vec![(slot, 0, 0x2000)]);
+    }
+
+    #[test]
+    fn map_anonymous_source() {
+        let mut gralloc = RutabagaGralloc::new().unwrap();
+        let size = 2 * pagesize();
+        let (region, mapped_size, descriptor) = VmMemorySource::Anonymous { size: size as u64 }
+            .map(&mut gralloc, Protection::read_write())
+            .unwrap();
+        assert_eq!(mapped_size, size as u64);
+        assert_eq!(region.size(), size);
+        assert!(descriptor.is_none());
+
+        // SAFETY:
+        // `region` owns `region.size()` writable bytes starting at `as_ptr()` for as long as it
+        // lives.
+        let bytes = unsafe { std::slice::from_raw_parts_mut(region.as_ptr(), region.size()) };
+        assert!(bytes.iter().all(|&b| b == 0));
+        bytes[size - 1] = 0x55;
+        assert_eq!(bytes[size - 1], 0x55);
+    }
+
+    #[test]
+    fn map_anonymous_source_invalid_size() {
+        let mut gralloc = RutabagaGralloc::new().unwrap();
+        for size in [0, pagesize() as u64 + 1] {
+            let result = VmMemorySource::Anonymous { size }.map(&mut gralloc, Protection::read());
+            assert_eq!(result.err(), Some(SysError::new(EINVAL)));
+        }
+    }
+
+    #[test]
+    fn query_registered_regions() {
+        let mut vm = MockVm::default();
+        let mut sys_allocator = test_system_allocator();
+        let mut gralloc = RutabagaGralloc::new().unwrap();
+        let mut region_state = VmMemoryRegionState::new();
+
+        for (size, addr) in [(0x2000, 0x1_0000_0000), (0x3000, 0x1_0001_0000)] {
+            let resp = VmMemoryRequest::RegisterMemory {
+                source: shm_source(size),
+                dest: VmMemoryDestination::GuestPhysicalAddress(addr),
+                prot: Protection::read_write(),
+            }
+            .execute(
+                &mut vm,
+                &mut sys_allocator,
+                &mut gralloc,
+                None,
+                &mut region_state,
+            );
+            assert!(matches!(resp, VmMemoryResponse::RegisterMemory(_)));
+        }
+
+        let resp = VmMemoryRequest::QueryRegistered.execute(
+            &mut vm,
+            &mut sys_allocator,
+            &mut gralloc,
+            None,
+            &mut region_state,
+        );
+        assert!(matches!(
+            resp,
+            VmMemoryResponse::Registered {
+                count: 2,
+                total_bytes: 0x5000
+            }
+        ));
+    }
+
+    #[test]
+    fn prefault_unknown_region() {
+        let result = try_execute_memory_request(
+            VmMemoryRequest::PrefaultRegion {
+                id: VmMemoryRegionId(7),
+            },
+            None,
+        );
+        let Err(e) = result else {
+            panic!("prefaulting an unknown region should fail");
+        };
+        assert!(matches!(
+            e,
+            VmControlError::UnknownRegion(VmMemoryRegionId(7))
+        ));
+        assert_eq!(SysError::from(e), SysError::new(EINVAL));
+    }
+
+    /// A private anonymous mapping filled with non-zero bytes.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    struct PrivateMapping {
+        addr: *mut u8,
+        size: usize,
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    impl PrivateMapping {
+        fn new(size: usize) -> PrivateMapping {
+            // SAFETY:
+            // Safe because we create a new mapping and check the return value.
+            let addr = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    size,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                    -1,
+                    0,
+                )
+            };
+            assert_ne!(addr, libc::MAP_FAILED);
+            // SAFETY:
+            // Safe because the mapping was just created with `size` writable bytes.
+            unsafe { std::ptr::write_bytes(addr as *mut u8, 0x45, size) };
+            PrivateMapping {
+                addr: addr as *mut u8,
+                size,
+            }
+        }
+
+        fn is_zeroed(&self) -> bool {
+            // SAFETY:
+            // Safe because the mapping is `size` readable bytes owned by `self`.
+            let contents = unsafe { std::slice::from_raw_parts(self.addr, self.size) };
+            contents.iter().all(|&b| b == 0)
+        }
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    impl Drop for PrivateMapping {
+        fn drop(&mut self) {
+            // SAFETY:
+            // Safe because we own the mapping and nothing references it anymore.
+            unsafe { libc::munmap(self.addr as *mut libc::c_void, self.size) };
+        }
+    }
+
+    /// Returns a shared memory object of `size` bytes filled with non-zero bytes.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn filled_shm(size: usize) -> File {
+        use std::os::unix::fs::FileExt;
+
+        let shm = SharedMemory::new("vm_control_test", size as u64).unwrap();
+        let file = File::from(SafeDescriptor::from(shm));
+        file.write_all_at(&vec![0x45; size], 0).unwrap();
+        file
+    }
+
+    /// Returns whether the contents of `file` were discarded.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn is_released(file: &File) -> bool {
+        use std::os::unix::fs::FileExt;
+        use std::os::unix::fs::MetadataExt;
+
+        let mut contents = vec![0; file.metadata().unwrap().len() as usize];
+        file.read_exact_at(&mut contents, 0).unwrap();
+        let zeroed = contents.iter().all(|&b| b == 0);
+        // The shared memory object must not hold any page anymore.
+        let freed = file.metadata().unwrap().blocks() == 0;
+        assert_eq!(zeroed, freed);
+        zeroed
+    }
+
+    /// Registers `source` then unregisters it.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn register_and_unregister(source: VmMemorySource, release_pages: bool) {
+        let mut vm = MockVm::default();
+        let mut sys_allocator = test_system_allocator();
+        let mut gralloc = RutabagaGralloc::new().unwrap();
+        let mut region_state = VmMemoryRegionState::new();
+
+        let resp = VmMemoryRequest::RegisterMemory {
+            source,
+            dest: VmMemoryDestination::GuestPhysicalAddress(0x1_0000_0000),
+            prot: Protection::read_write(),
+        }
+        .execute(
+            &mut vm,
+            &mut sys_allocator,
+            &mut gralloc,
+            None,
+            &mut region_state,
+        );
+        let VmMemoryResponse::RegisterMemory(id) = resp else {
+            panic!("failed to register memory: {:?}", resp);
+        };
+        assert_eq!(region_state.live_descriptor_count(), 1);
+        assert!(region_state.descriptor_accounting_consistent());
+
+        let resp = VmMemoryRequest::UnregisterMemory { id, release_pages }.execute(
+            &mut vm,
+            &mut sys_allocator,
+            &mut gralloc,
+            None,
+            &mut region_state,
+        );
+        assert!(matches!(resp, VmMemoryResponse::Ok));
+        assert_eq!(region_state.live_descriptor_count(), 0);
+        assert!(region_state.descriptor_accounting_consistent());
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn shm_file_source(file: &File) -> VmMemorySource {
+        VmMemorySource::Descriptor {
+            descriptor: SafeDescriptor::from(file.try_clone().unwrap()),
+            offset: 0,
+            size: file.metadata().unwrap().len(),
+        }
+    }
+
+    #[test]
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn unregister_memory_releases_shm_pages() {
+        let shm = filled_shm(0x2000);
+        register_and_unregister(shm_file_source(&shm), true);
+        assert!(is_released(&shm));
+    }
+
+    #[test]
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn unregister_memory_keeps_pages_by_default() {
+        let shm = filled_shm(0x2000);
+        register_and_unregister(shm_file_source(&shm), false);
+        assert!(!is_released(&shm));
+    }
+
+    #[test]
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn unregister_memory_skips_release_for_external_mapping() {
+        let shm = filled_shm(0x2000);
+        let mapping = MemoryMappingBuilder::new(0x2000)
+            .from_file(&shm)
+            .build()
+            .unwrap();
+        let source = VmMemorySource::ExternalMapping {
+            ptr: mapping.as_ptr() as u64,
+            size: 0x2000,
+        };
+        register_and_unregister(source, true);
+        assert!(!is_released(&shm));
+    }
+
+    #[test]
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn release_region_pages_private_mapping() {
+        let mapping = PrivateMapping::new(0x2000);
+        let region = ExternalMapping {
+            ptr: mapping.addr as u64,
+            size: mapping.size,
+        };
+        sys::release_region_pages(&region).unwrap();
+        assert!(mapping.is_zeroed());
+    }
+
+    /// A `PmResource` that records the calls made to it.
+    #[derive(Default)]
+    struct MockPm {
+        calls: Vec<String>,
+        rtc_alarm: Option<u64>,
+    }
+
+    impl MockPm {
+        /// Simulates the expiry of the pending RTC alarm.
+        fn expire_rtc_alarm(&mut self) {
+            if self.rtc_alarm.take().is_some() {
+                self.rtc_evt();
+            }
+        }
+    }
+
+    impl PmResource for MockPm {
+        fn rtc_evt(&mut self) {
+            self.calls.push("rtc".to_owned());
+        }
+
+        fn set_rtc_alarm(&mut self, seconds_from_now: u64) {
+            self.calls.push(format!("set alarm {}", seconds_from_now));
+            self.rtc_alarm = Some(seconds_from_now);
+        }
+
+        fn clear_rtc_alarm(&mut self) {
+            self.calls.push("clear alarm".to_owned());
+            self.rtc_alarm = None;
+        }
+
+        fn pme_evt(&mut self, requester_id: u16) {
+            self.calls.push(format!("pme {:#x}", requester_id));
+        }
+
+        fn pme_evt_batch(&mut self, requester_ids: &[u16]) -> Vec<Result<()>> {
+            self.calls.push("pme batch".to_owned());
+            requester_ids
+                .iter()
+                .map(|requester_id| {
+                    self.pme_evt(*requester_id);
+                    // Only bus 0x3 has no root port.
+                    if requester_id >> 8 == 0x3 {
+                        Err(SysError::new(ENODEV))
+                    } else {
+                        Ok(())
+                    }
+                })
+                .collect()
+        }
+    }
+
+    fn execute_pm_request(
+        request: VmRequest,
+        pm: &mut Option<Arc<Mutex<dyn PmResource + Send>>>,
+    ) -> VmResponse {
+        let (device_control_tube, _device) = Tube::pair().unwrap();
+        execute_with_pm(
+            next_request_id(),
+            request,
+            pm,
+            &mut None,
+            |_| {},
+            |_, _| {},
+            &device_control_tube,
+            1,
+        )
+    }
+
+    #[test]
+    fn rtc_alarm() {
+        let mock_pm = Arc::new(Mutex::new(MockPm::default()));
+        let mut pm: Option<Arc<Mutex<dyn PmResource + Send>>> = Some(mock_pm.clone());
+
+        let resp = execute_pm_request(
+            VmRequest::SetRtcAlarm {
+                seconds_from_now: 5,
+            },
+            &mut pm,
+        );
+        assert!(matches!(resp, VmResponse::Ok));
+        mock_pm.lock().expire_rtc_alarm();
+
+        let resp = execute_pm_request(
+            VmRequest::SetRtcAlarm {
+                seconds_from_now: 10,
+            },
+            &mut pm,
+        );
+        assert!(matches!(resp, VmResponse::Ok));
+        let resp = execute_pm_request(VmRequest::ClearRtcAlarm, &mut pm);
+        assert!(matches!(resp, VmResponse::Ok));
+        // A cleared alarm must not fire.
+        mock_pm.lock().expire_rtc_alarm();
+
+        assert_eq!(
+            mock_pm.lock().calls,
+            ["set alarm 5", "rtc", "set alarm 10", "clear alarm"]
+        );
+    }
+
+    #[test]
+    fn rtc_alarm_without_pm() {
+        let resp = execute_pm_request(
+            VmRequest::SetRtcAlarm {
+                seconds_from_now: 5,
+            },
+            &mut None,
+        );
+        assert!(matches!(resp, VmResponse::Err(e) if e == SysError::new(ENOTSUP)));
+        let resp = execute_pm_request(VmRequest::ClearRtcAlarm, &mut None);
+        assert!(matches!(resp, VmResponse::Err(e) if e == SysError::new(ENOTSUP)));
+    }
+
+    #[test]
+    fn pci_pme_batch() {
+        let mock_pm = Arc::new(Mutex::new(MockPm::default()));
+        let mut pm: Option<Arc<Mutex<dyn PmResource + Send>>> = Some(mock_pm.clone());
+
+        let resp = execute_pm_request(VmRequest::PciPmeBatch(vec![0x100, 0x208, 0x410]), &mut pm);
+        assert!(matches!(resp, VmResponse::Ok));
+
+        // All PMEs are delivered by the single batch call, i.e. under one lock acquisition.
+        assert_eq!(
+            mock_pm.lock().calls,
+            ["pme batch", "pme 0x100", "pme 0x208", "pme 0x410"]
+        );
+    }
+
+    #[test]
+    fn pci_pme_batch_partial() {
+        let mut pm: Option<Arc<Mutex<dyn PmResource + Send>>> =
+            Some(Arc::new(Mutex::new(MockPm::default())));
+
+        let resp = execute_pm_request(VmRequest::PciPmeBatch(vec![0x100, 0x310, 0x208]), &mut pm);
+        match resp {
+            VmResponse::Partial { succeeded, failed } => {
+                assert_eq!(succeeded, ["0x0100", "0x0208"]);
+                assert_eq!(failed, [("0x0310".to_owned(), SysError::new(ENODEV))]);
+            }
+            r => panic!("unexpected response: {}", r),
+        }
+    }
+
+    #[test]
+    fn usb_device_list_serde() {
+        let devices: Vec<UsbControlAttachedDevice> = (1..=USB_CONTROL_MAX_PORTS as u8 + 4)
+            .map(|port| UsbControlAttachedDevice {
+                port,
+                vendor_id: 0x18d1,
+                product_id: port.into(),
+            })
+            .collect();
+
+        let cmd = serde_json::to_string(&UsbControlCommand::ListDeviceV2).unwrap();
+        assert!(matches!(
+            serde_json::from_str(&cmd).unwrap(),
+            UsbControlCommand::ListDeviceV2
+        ));
+
+        let result = UsbControlResult::DeviceList(devices.clone());
+        let result: UsbControlResult =
+            serde_json::from_str(&serde_json::to_string(&result).unwrap()).unwrap();
+        match &result {
+            UsbControlResult::DeviceList(list) => assert_eq!(list, &devices),
+            r => panic!("unexpected result: {}", r),
+        }
+        assert!(result.to_string().ends_with(" 20 18d1 0014"));
+    }
+
+    #[cfg(feature = "pci-hotplug")]
+    #[test]
+    fn list_hotplug_slots() {
+        struct MockHotplugManager;
+
+        impl HotplugSlotProvider for MockHotplugManager {
+            fn hotplug_slots(&self) -> Vec<HotplugSlot> {
+                vec![
+                    HotplugSlot {
+                        bus: 1,
+                        occupied: true,
+                        device_type: Some(HotPlugDeviceType::EndPoint),
+                    },
+                    HotplugSlot {
+                        bus: 2,
+                        occupied: false,
+                        device_type: None,
+                    },
+                ]
+            }
+        }
+
+        let resp = handle_list_hotplug_slots(Some(&MockHotplugManager));
+        let resp: VmResponse =
+            serde_json::from_str(&serde_json::to_string(&resp).unwrap()).unwrap();
+        match &resp {
+            VmResponse::HotplugSlots(slots) => {
+                assert_eq!(slots, &MockHotplugManager.hotplug_slots())
+            }
+            r => panic!("unexpected response: {}", r),
+        }
+        assert_eq!(resp.to_string(), "hotplug slots 1:EndPoint 2:empty");
+
+        assert!(matches!(
+            handle_list_hotplug_slots(None),
+            VmResponse::ErrString(_)
+        ));
+    }
+
+    #[test]
+    fn batch_results_partial() {
+        let resp = VmResponse::from_batch_results([
+            ("serial".to_owned(), Ok(())),
+            ("block".to_owned(), Err(SysError::new(EIO))),
+            ("net".to_owned(), Ok(())),
+        ]);
+        let resp: VmResponse =
+            serde_json::from_str(&serde_json::to_string(&resp).unwrap()).unwrap();
+        match &resp {
+            VmResponse::Partial { succeeded, failed } => {
+                assert_eq!(succeeded, &["serial", "net"]);
+                assert_eq!(failed, &[("block".to_owned(), SysError::new(EIO))]);
+            }
+            r => panic!("unexpected response: {}", r),
+        }
+        assert!(resp
+            .to_string()
+            .starts_with("partial success: 2 succeeded, 1 failed\nblock: "));
+
+        let resp = VmResponse::from_batch_results([("serial".to_owned(), Ok(()))]);
+        assert!(matches!(resp, VmResponse::Ok));
+    }
+
+    #[test]
+    fn pci_pme_batch_without_pm() {
+        let resp = execute_pm_request(VmRequest::PciPmeBatch(vec![0x100]), &mut None);
+        assert!(matches!(resp, VmResponse::Err(e) if e == SysError::new(ENOTSUP)));
+    }
+
+    #[test]
+    fn single_step_cancelled() {
+        let (state_send, state_recv) = mpsc::channel();
+        let mut waiters = SingleStepWaiters::default();
+        waiters
+            .get_state(VmRunMode::SingleStep, state_send)
+            .unwrap();
+        assert!(state_recv.try_recv().is_err());
+        waiters.reply(VmRunMode::Running);
+        assert_eq!(state_recv.recv().unwrap(), VmRunMode::Running);
+    }
+
+    #[test]
+    fn get_run_mode_after_suspend() {
+        let (device_control_tube, _device) = Tube::pair().unwrap();
+        let mut run_mode = None;
+        let resp = execute_with_mocks(
+            VmRequest::SuspendVcpus,
+            &mut run_mode,
+            |_| {},
+            |_, _| {},
+            &device_control_tube,
+            1,
+        );
+        assert!(matches!(resp, VmResponse::Ok));
+
+        let resp = execute_with_mocks(
+            VmRequest::GetRunMode,
+            &mut run_mode,
+            |_| panic!("vcpus must not be kicked"),
+            |_, _| panic!("vcpus must not be kicked"),
+            &device_control_tube,
+            1,
+        );
+        assert!(matches!(resp, VmResponse::RunMode(VmRunMode::Suspending)));
+        assert_eq!(resp.to_string(), "run mode: suspending");
+    }
+
+    #[test]
+    fn resume_s2idle_without_pm_strict() {
+        let mut run_mode = None;
+        let resp = resume_s2idle_without_pm(true, &mut run_mode);
+        assert!(matches!(resp, VmResponse::Err(e) if e == SysError::new(ENOTSUP)));
+        assert_eq!(run_mode, None);
+    }
+
+    #[test]
+    fn resume_s2idle_without_pm_lenient() {
+        let mut run_mode = None;
+        let resp = resume_s2idle_without_pm(false, &mut run_mode);
+        assert!(matches!(resp, VmResponse::Ok));
+        assert_eq!(run_mode, Some(VmRunMode::Running));
+    }
+
+    #[test]
+    fn snapshot_version_matching() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot");
+        write_snapshot_version(&path, SNAPSHOT_VERSION).unwrap();
+        check_snapshot_version(&path).unwrap();
+
+        // Newer minor versions remain compatible.
+        write_snapshot_version(
+            &path,
+            SnapshotVersion {
+                minor: SNAPSHOT_VERSION.minor + 1,
+                ..SNAPSHOT_VERSION
+            },
+        )
+        .unwrap();
+        check_snapshot_version(&path).unwrap();
+    }
+
+    #[test]
+    fn restore_incompatible_snapshot_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot");
+        let version = SnapshotVersion {
+            major: SNAPSHOT_VERSION.major + 1,
+            minor: 0,
+        };
+        write_snapshot_version(&path, version).unwrap();
+
+        let (irq_handler_control, _irq_handler) = Tube::pair().unwrap();
+        let (device_control_tube, _device) = Tube::pair().unwrap();
+        let err = do_restore(
+            path,
+            |_| panic!("vcpus must not be kicked"),
+            |_, _| panic!("vcpus must not be kicked"),
+            &irq_handler_control,
+            &device_control_tube,
+            1,
+            |_| panic!("irqchip must not be restored"),
+        )
+        .unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains(&version.to_string()), "{}", msg);
+        assert!(msg.contains(&SNAPSHOT_VERSION.to_string()), "{}", msg);
+    }
+
+    #[cfg(feature = "crash-report")]
+    #[test]
+    fn restore_annotates_crash_reports_with_phase() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot");
+        write_snapshot_version(&path, SNAPSHOT_VERSION).unwrap();
+        std::fs::write(path.with_extension("irqchip"), "null").unwrap();
+
+        let (irq_handler_control, _irq_handler) = Tube::pair().unwrap();
+        let (device_control_tube, device) = Tube::pair().unwrap();
+        // Devices are already asleep, so the guard doesn't need to wake them afterwards.
+        device
+            .send(&VmResponse::DevicesState(DevicesState::Sleep))
+            .unwrap();
+        let mut annotations = Vec::new();
+        do_restore(
+            path,
+            // Dropping the state channel fails the vcpu suspend, which restore ignores.
+            |_| {},
+            |_, _| panic!("vcpus must not be restored"),
+            &irq_handler_control,
+            &device_control_tube,
+            1,
+            |_| {
+                annotations = crash_report::list_annotations();
+                bail!("simulated irqchip restore failure")
+            },
+        )
+        .unwrap_err();
+
+        assert!(
+            annotations.contains(&(VM_PHASE_ANNOTATION.to_owned(), "restore:irqchip".to_owned()))
+        );
+        assert!(!crash_report::list_annotations()
+            .iter()
+            .any(|(key, _)| key == VM_PHASE_ANNOTATION));
+    }
+
+    #[test]
+    fn parallel_vcpu_snapshot_matches_serial() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct MockVcpuSnapshot {
+            vcpu_id: usize,
+            regs: Vec<u64>,
+            hypervisor_data: serde_json::Value,
+        }
+
+        let snapshots: Vec<MockVcpuSnapshot> = (0..8)
+            .map(|vcpu_id| MockVcpuSnapshot {
+                vcpu_id,
+                regs: (0..64).map(|r| (vcpu_id as u64) << 32 | r).collect(),
+                hypervisor_data: serde_json::json!({ "tsc": vcpu_id * 1000 }),
+            })
+            .collect();
+        let serial = serde_json::to_vec(&snapshots).unwrap();
+
+        // Serialize on one thread per vCPU and collect the results out of order.
+        let (send_chan, recv_chan) = mpsc::channel();
+        std::thread::scope(|scope| {
+            for snapshot in snapshots.iter().rev() {
+                let send_chan = send_chan.clone();
+                scope.spawn(move || {
+                    send_chan
+                        .send(SerializedVcpuSnapshot::from_serializable(
+                            snapshot.vcpu_id,
+                            snapshot,
+                        ))
+                        .unwrap()
+                });
+            }
+        });
+        drop(send_chan);
+        let mut serialized: Vec<_> = recv_chan.iter().map(|s| s.unwrap()).collect();
+        let mut parallel = Vec::new();
+        write_vcpu_snapshots(&mut parallel, &mut serialized).unwrap();
+
+        assert_eq!(parallel, serial);
+
+        let restored: Vec<MockVcpuSnapshot> = read_vcpu_snapshots(&parallel[..]).unwrap();
+        assert_eq!(restored, snapshots);
+    }
+
+    #[test]
+    fn read_vcpu_snapshots_invalid() {
+        #[derive(Deserialize, Debug)]
+        struct MockVcpuSnapshot {
+            #[allow(dead_code)]
+            vcpu_id: usize,
+        }
+
+        let err = read_vcpu_snapshots::<MockVcpuSnapshot>(&br#"[{"vcpu_id":0},{"id":1}]"#[..])
+            .unwrap_err();
+        assert_eq!(err.to_string(), "failed to deserialize vcpu snapshot 1");
+        assert!(read_vcpu_snapshots::<MockVcpuSnapshot>(&b"[{"[..]).is_err());
+    }
+
+    #[test]
+    fn disk_flush() {
+        let (disk_host_tube, disk_device_tube) = Tube::pair().unwrap();
+        let disk_thread = std::thread::spawn(move || {
+            let command: DiskControlCommand = disk_device_tube.recv().unwrap();
+            assert!(matches!(command, DiskControlCommand::Flush));
+            disk_device_tube
+                .send(&DiskControlResult::Err(SysError::new(EIO)))
+                .unwrap();
+            let command: DiskControlCommand = disk_device_tube.recv().unwrap();
+            assert!(matches!(command, DiskControlCommand::Flush));
+            disk_device_tube.send(&DiskControlResult::Ok).unwrap();
+        });
+
+        let resp = handle_disk_command(&DiskControlCommand::Flush, &disk_host_tube);
+        assert!(matches!(resp, VmResponse::Err(e) if e == SysError::new(EIO)));
+        let resp = handle_disk_command(&DiskControlCommand::Flush, &disk_host_tube);
+        assert!(matches!(resp, VmResponse::Ok));
+        disk_thread.join().unwrap();
+    }
+
+    #[test]
+    fn disk_index_out_of_range() {
+        let (disk_host_tube, _disk_device_tube) = Tube::pair().unwrap();
+        let resp = handle_disk_request(
+            next_request_id(),
+            3,
+            &DiskControlCommand::Flush,
+            &[disk_host_tube],
+        );
+        match resp {
+            VmResponse::ErrString(msg) => {
+                assert!(msg.contains("index 3"), "{}", msg);
+                assert!(msg.contains("have 1 disks"), "{}", msg);
+            }
+            r => panic!("unexpected response: {}", r),
+        }
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn recv_request_without_expected_descriptor() {
+        let (host_tube, peer_tube) = Tube::pair().unwrap();
+        // An `AttachDevice` request that refers to a descriptor but doesn't send one.
+        peer_tube
+            .send(&serde_json::json!({ "UsbCommand": { "AttachDevice": { "file": 0 } } }))
+            .unwrap();
+        assert!(matches!(
+            host_tube.recv_with_exact_descriptors::<VmRequest>(),
+            Err(base::TubeError::MissingDescriptors)
+        ));
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn recv_request_with_unexpected_descriptor() {
+        // Serializes like a `DetachDevice` request, which doesn't expect a descriptor, but sends
+        // one along with it.
+        #[derive(Serialize)]
+        enum BadRequest {
+            UsbCommand(BadUsbCommand),
+        }
+        #[derive(Serialize)]
+        enum BadUsbCommand {
+            DetachDevice {
+                port: u8,
+                #[serde(with = "with_as_descriptor")]
+                file: File,
+            },
+        }
+
+        let (host_tube, peer_tube) = Tube::pair().unwrap();
+        peer_tube
+            .send(&BadRequest::UsbCommand(BadUsbCommand::DetachDevice {
+                port: 1,
+                file: tempfile::tempfile().unwrap(),
+            }))
+            .unwrap();
+        assert!(matches!(
+            host_tube.recv_with_exact_descriptors::<VmRequest>(),
+            Err(base::TubeError::UnusedDescriptors(1))
+        ));
+    }
+}
@@ -9,7 +9,11 @@ cfg_if::cfg_if! {
         pub use platform::{VmMsyncRequest, VmMsyncResponse, FsMappingRequest};
         #[cfg(feature = "gpu")]
         pub use platform::gpu::UnixDisplayMode as DisplayMode;
+        pub use platform::handle_request_with_buffer_size;
         pub use platform::handle_request_with_timeout;
+        pub use platform::{bind_control_socket, drain_control_socket};
+        #[cfg(feature = "registered_events")]
+        pub use platform::EventSubscription;
     } else if #[cfg(windows)] {
         pub mod windows;
         pub use windows as platform;
@@ -21,5 +25,7 @@ cfg_if::cfg_if! {
 }
 
 pub use platform::handle_request;
+pub use platform::map_composite;
 pub use platform::prepare_shared_memory_region;
+pub use platform::release_region_pages;
 pub use platform::should_prepare_memory_region;
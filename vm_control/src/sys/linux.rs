@@ -2,26 +2,38 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+#[cfg(feature = "registered_events")]
+mod event_subscription;
 #[cfg(feature = "gpu")]
 pub(crate) mod gpu;
 
+use std::io::ErrorKind;
+use std::os::unix::fs::FileTypeExt;
 use std::path::Path;
 use std::time::Duration;
 
+use base::errno_result;
 use base::error;
+use base::pagesize;
 use base::AsRawDescriptor;
 use base::Descriptor;
 use base::Error as SysError;
+use base::MappedRegion;
 use base::MemoryMappingArena;
 use base::MmapError;
 use base::Protection;
 use base::SafeDescriptor;
 use base::Tube;
 use base::UnixSeqpacket;
+use base::UnixSeqpacketListener;
+use base::UnlinkUnixSeqpacketListener;
 use hypervisor::MemSlot;
 use hypervisor::Vm;
+use libc::EADDRINUSE;
+use libc::EEXIST;
 use libc::EINVAL;
 use libc::ERANGE;
+use libc::ETIMEDOUT;
 use once_cell::sync::Lazy;
 use resources::Alloc;
 use resources::SystemAllocator;
@@ -29,6 +41,8 @@ use serde::Deserialize;
 use serde::Serialize;
 use vm_memory::GuestAddress;
 
+#[cfg(feature = "registered_events")]
+pub use self::event_subscription::EventSubscription;
 use crate::client::HandleRequestResult;
 use crate::VmRequest;
 use crate::VmResponse;
@@ -44,9 +58,43 @@ pub fn handle_request_with_timeout<T: AsRef<Path> + std::fmt::Debug>(
     request: &VmRequest,
     socket_path: T,
     timeout: Option<Duration>,
+) -> HandleRequestResult {
+    handle_request_with_options(request, socket_path, timeout, None)
+}
+
+/// Like [`handle_request`], but sets the control socket's send and receive buffers to
+/// `buffer_size` bytes so that messages larger than the default buffer size can be exchanged.
+///
+/// `buffer_size` must not exceed the system limits in `/proc/sys/net/core/{r,w}mem_max`. The
+/// server's send buffer still limits the size of the response.
+pub fn handle_request_with_buffer_size<T: AsRef<Path> + std::fmt::Debug>(
+    request: &VmRequest,
+    socket_path: T,
+    buffer_size: usize,
+) -> HandleRequestResult {
+    handle_request_with_options(request, socket_path, None, Some(buffer_size))
+}
+
+fn handle_request_with_options<T: AsRef<Path> + std::fmt::Debug>(
+    request: &VmRequest,
+    socket_path: T,
+    timeout: Option<Duration>,
+    buffer_size: Option<usize>,
 ) -> HandleRequestResult {
     match UnixSeqpacket::connect(&socket_path) {
         Ok(s) => {
+            if let Some(size) = buffer_size {
+                if let Err(e) = s
+                    .set_send_buffer_size(size)
+                    .and_then(|()| s.set_recv_buffer_size(size))
+                {
+                    error!(
+                        "failed to set buffer size on socket at '{:?}': {}",
+                        socket_path, e
+                    );
+                    return Err(());
+                }
+            }
             let socket = Tube::new_from_unix_seqpacket(s).map_err(|_| ())?;
             if timeout.is_some() {
                 if let Err(e) = socket.set_recv_timeout(timeout) {
@@ -82,6 +130,47 @@ pub fn handle_request_with_timeout<T: AsRef<Path> + std::fmt::Debug>(
     }
 }
 
+/// Binds a new control server socket at `path` for `VmRequest::RebindControlSocket`.
+///
+/// A stale socket left behind at `path` is removed, but the bind is refused if `path` is a socket
+/// that something is still listening on (`EADDRINUSE`) or if it is not a socket at all (`EEXIST`).
+pub fn bind_control_socket(path: &Path) -> Result<UnlinkUnixSeqpacketListener, SysError> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) => {
+            if !metadata.file_type().is_socket() {
+                return Err(SysError::new(EEXIST));
+            }
+            if UnixSeqpacket::connect(path).is_ok() {
+                return Err(SysError::new(EADDRINUSE));
+            }
+            std::fs::remove_file(path)?;
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(UnlinkUnixSeqpacketListener(UnixSeqpacketListener::bind(
+        path,
+    )?))
+}
+
+/// Accepts every connection already queued on a control server socket that is being retired, so
+/// that clients which connected before a rebind still get serviced.
+pub fn drain_control_socket(socket: &UnixSeqpacketListener) -> Vec<UnixSeqpacket> {
+    let mut pending = Vec::new();
+    loop {
+        match socket.accept_with_timeout(Duration::ZERO) {
+            Ok(s) => pending.push(s),
+            Err(e) => {
+                if e.raw_os_error() != Some(ETIMEDOUT) {
+                    error!("failed to drain retired control socket: {}", e);
+                }
+                break;
+            }
+        }
+    }
+    pending
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum VmMsyncRequest {
     /// Flush the content of a memory mapping to its backing file.
@@ -182,6 +271,71 @@ pub fn prepare_shared_memory_region(
     }
 }
 
+/// Maps the `(descriptor, offset, size)` parts of a `VmMemorySource::Composite` back to back into a
+/// single arena, returning the arena and its total size in bytes.
+pub fn map_composite(
+    parts: &[(SafeDescriptor, u64, u64)],
+    prot: Protection,
+) -> Result<(Box<dyn MappedRegion>, u64), SysError> {
+    let pagesize = pagesize() as u64;
+    let mut total_size: u64 = 0;
+    for (_, offset, size) in parts {
+        if *size == 0 || offset % pagesize != 0 || size % pagesize != 0 {
+            return Err(SysError::new(EINVAL));
+        }
+        total_size = total_size
+            .checked_add(*size)
+            .ok_or_else(|| SysError::new(ERANGE))?;
+    }
+    if total_size == 0 {
+        return Err(SysError::new(EINVAL));
+    }
+    let arena_size: usize = total_size.try_into().map_err(|_| SysError::new(ERANGE))?;
+
+    let mmap_err = |e| match e {
+        MmapError::SystemCallFailed(e) => e,
+        _ => SysError::new(EINVAL),
+    };
+    let mut arena = MemoryMappingArena::new(arena_size).map_err(mmap_err)?;
+    let mut arena_offset = 0;
+    for (descriptor, offset, size) in parts {
+        let size = *size as usize;
+        arena
+            .add_fd_offset_protection(arena_offset, size, descriptor, *offset, prot)
+            .map_err(mmap_err)?;
+        arena_offset += size;
+    }
+    Ok((Box::new(arena), total_size))
+}
+
+/// Releases the host pages backing `region` so they are returned to the host right away.
+///
+/// Shared mappings, e.g. of shared memory, are released with `MADV_REMOVE`, which frees their
+/// backing store: `MADV_DONTNEED` would only unmap the pages and leave them in the shared memory
+/// object. Private mappings, for which `MADV_REMOVE` is not supported, are released with
+/// `MADV_DONTNEED`.
+///
+/// Must only be used on mappings owned by crosvm whose guest mapping has already been removed,
+/// since their contents are discarded, including the contents of the underlying file or shared
+/// memory object.
+pub fn release_region_pages(region: &dyn MappedRegion) -> Result<(), SysError> {
+    let madvise = |advice| {
+        // SAFETY:
+        // Safe because `MappedRegion` guarantees the pointer and size describe a mapping owned by
+        // `region`, and dropping its pages doesn't affect Rust memory safety.
+        let ret =
+            unsafe { libc::madvise(region.as_ptr() as *mut libc::c_void, region.size(), advice) };
+        if ret < 0 {
+            return errno_result();
+        }
+        Ok(())
+    };
+    match madvise(libc::MADV_REMOVE) {
+        Err(e) if e.errno() == EINVAL => madvise(libc::MADV_DONTNEED),
+        res => res,
+    }
+}
+
 static SHOULD_PREPARE_MEMORY_REGION: Lazy<bool> = Lazy::new(|| {
     if cfg!(target_arch = "x86_64") {
         // The legacy x86 MMU allocates an rmap and a page tracking array
@@ -238,3 +392,183 @@ impl FsMappingRequest {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn rebind_control_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let old_path = dir.path().join("old.sock");
+        let new_path = dir.path().join("new.sock");
+        let old = UnlinkUnixSeqpacketListener(UnixSeqpacketListener::bind(&old_path).unwrap());
+        // A client that connected before the rebind must not be dropped.
+        let _early_client = UnixSeqpacket::connect(&old_path).unwrap();
+
+        let new = bind_control_socket(&new_path).unwrap();
+        assert_eq!(drain_control_socket(&old).len(), 1);
+        drop(old);
+        assert!(!old_path.exists());
+
+        let _client = UnixSeqpacket::connect(&new_path).unwrap();
+        new.accept_with_timeout(Duration::from_secs(1)).unwrap();
+    }
+
+    #[test]
+    fn rebind_control_socket_in_use() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("in_use.sock");
+        let _listener = UnixSeqpacketListener::bind(&path).unwrap();
+        assert_eq!(
+            bind_control_socket(&path).err(),
+            Some(SysError::new(EADDRINUSE))
+        );
+    }
+
+    #[test]
+    fn rebind_control_socket_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stale.sock");
+        // A plain listener does not unlink its path when dropped.
+        drop(UnixSeqpacketListener::bind(&path).unwrap());
+        assert!(path.exists());
+        let _listener = bind_control_socket(&path).unwrap();
+        let _client = UnixSeqpacket::connect(&path).unwrap();
+    }
+
+    #[test]
+    fn rebind_control_socket_not_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        std::fs::write(&path, b"").unwrap();
+        assert_eq!(
+            bind_control_socket(&path).err(),
+            Some(SysError::new(EEXIST))
+        );
+    }
+
+    #[test]
+    fn handle_request_variable_length() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        let listener = UnixSeqpacketListener::bind(&path).unwrap();
+        let requester_ids: Vec<u16> = (0..4096).collect();
+
+        let expected_ids = requester_ids.clone();
+        let server = std::thread::spawn(move || {
+            let tube = Tube::new_from_unix_seqpacket(listener.accept().unwrap()).unwrap();
+            match tube.recv().unwrap() {
+                VmRequest::PciPmeBatch(ids) => assert_eq!(ids, expected_ids),
+                r => panic!("unexpected request: {:?}", r),
+            }
+            tube.send(&VmResponse::Ok).unwrap();
+        });
+
+        // Each request is a single seqpacket message, so its size is not fixed by the protocol.
+        let response = handle_request(&VmRequest::PciPmeBatch(requester_ids), &path).unwrap();
+        assert!(matches!(response, VmResponse::Ok));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn handle_request_large_buffer() {
+        const BUFFER_SIZE: usize = 2 << 20;
+        // Skip the test if the host doesn't allow buffers this large.
+        for max_path in ["/proc/sys/net/core/rmem_max", "/proc/sys/net/core/wmem_max"] {
+            let max: usize = std::fs::read_to_string(max_path)
+                .unwrap()
+                .trim()
+                .parse()
+                .unwrap();
+            if max < BUFFER_SIZE {
+                return;
+            }
+        }
+        // Each requester id takes up to 6 bytes serialized, which is larger than the default
+        // buffer size but fits in `BUFFER_SIZE`.
+        let requester_ids: Vec<u16> = (0..200_000).map(|i| (i % 0x10000) as u16).collect();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        let listener = UnixSeqpacketListener::bind(&path).unwrap();
+
+        let expected_ids = requester_ids.clone();
+        let server = std::thread::spawn(move || {
+            // The first connection sends nothing because the request doesn't fit in the default
+            // buffer.
+            let tube = Tube::new_from_unix_seqpacket(listener.accept().unwrap()).unwrap();
+            assert!(tube.recv::<VmRequest>().is_err());
+
+            let tube = Tube::new_from_unix_seqpacket(listener.accept().unwrap()).unwrap();
+            match tube.recv().unwrap() {
+                VmRequest::PciPmeBatch(ids) => assert_eq!(ids, expected_ids),
+                r => panic!("unexpected request: {:?}", r),
+            }
+            tube.send(&VmResponse::Ok).unwrap();
+        });
+
+        let request = VmRequest::PciPmeBatch(requester_ids);
+        assert!(handle_request(&request, &path).is_err());
+        let response = handle_request_with_buffer_size(&request, &path, BUFFER_SIZE).unwrap();
+        assert!(matches!(response, VmResponse::Ok));
+        server.join().unwrap();
+
+        // Sizes above the system limit are rejected.
+        let (s, _) = UnixSeqpacket::pair().unwrap();
+        assert_eq!(
+            s.set_recv_buffer_size(usize::MAX).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    fn page_file(fill: &[u8]) -> SafeDescriptor {
+        let mut file = tempfile::tempfile().unwrap();
+        for b in fill {
+            file.write_all(&vec![*b; pagesize()]).unwrap();
+        }
+        SafeDescriptor::from(file)
+    }
+
+    #[test]
+    fn map_composite_two_segments() {
+        let page = pagesize() as u64;
+        // Use the second page of the first file followed by the first page of the second.
+        let parts = vec![
+            (page_file(&[0x11, 0x22]), page, page),
+            (page_file(&[0x33, 0x44]), 0, page),
+        ];
+
+        let (region, size) = map_composite(&parts, Protection::read()).unwrap();
+        assert_eq!(size, 2 * page);
+        assert_eq!(region.size(), 2 * pagesize());
+
+        // SAFETY:
+        // The arena owns `region.size()` readable bytes starting at `as_ptr()` for as long as
+        // `region` lives.
+        let bytes = unsafe { std::slice::from_raw_parts(region.as_ptr(), region.size()) };
+        assert_eq!(bytes[pagesize() - 1], 0x22);
+        assert_eq!(bytes[pagesize()], 0x33);
+    }
+
+    #[test]
+    fn map_composite_unaligned() {
+        let page = pagesize() as u64;
+        let parts = vec![(page_file(&[0x11]), 1, page)];
+        assert_eq!(
+            map_composite(&parts, Protection::read()).err(),
+            Some(SysError::new(EINVAL))
+        );
+        let parts = vec![(page_file(&[0x11]), 0, page - 1)];
+        assert_eq!(
+            map_composite(&parts, Protection::read()).err(),
+            Some(SysError::new(EINVAL))
+        );
+        assert_eq!(
+            map_composite(&[], Protection::read()).err(),
+            Some(SysError::new(EINVAL))
+        );
+    }
+}
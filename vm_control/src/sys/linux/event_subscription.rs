@@ -0,0 +1,217 @@
+// Copyright 2023 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Client side of the registered events mechanism.
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use base::warn;
+use base::ProtoTube;
+use base::UnixSeqpacketListener;
+use base::UnlinkUnixSeqpacketListener;
+use protos::registered_events;
+
+use super::handle_request;
+use crate::RegisteredEvent;
+use crate::RegisteredEventWithData;
+use crate::VmRequest;
+use crate::VmResponse;
+
+// The VM connects to the listening socket while handling the first registration, so the connection
+// is normally already pending once the response is received.
+const ACCEPT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Subscription to registered events of the VM controlled through a control socket.
+///
+/// The subscription owns the listening socket that the VM sends the events to. When it is dropped,
+/// the listener is unregistered from all events and its socket path is removed.
+pub struct EventSubscription {
+    control_socket_path: PathBuf,
+    socket_addr: String,
+    tube: ProtoTube,
+    _listener: UnlinkUnixSeqpacketListener,
+}
+
+impl EventSubscription {
+    /// Binds a listening socket at `listener_path` and registers it for `events` with the VM
+    /// controlled through `control_socket_path`.
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(
+        control_socket_path: P,
+        listener_path: Q,
+        events: &[RegisteredEvent],
+    ) -> Result<Self> {
+        let control_socket_path = control_socket_path.as_ref().to_path_buf();
+        let listener_path = listener_path.as_ref();
+        let socket_addr = listener_path
+            .to_str()
+            .ok_or_else(|| anyhow!("invalid listener path {}", listener_path.display()))?
+            .to_string();
+        let Some((first_event, other_events)) = events.split_first() else {
+            bail!("no events to subscribe to");
+        };
+        let listener = UnlinkUnixSeqpacketListener(
+            UnixSeqpacketListener::bind(listener_path)
+                .with_context(|| format!("failed to bind {}", listener_path.display()))?,
+        );
+
+        register(&control_socket_path, &socket_addr, *first_event)?;
+        let tube = match listener
+            .accept_with_timeout(ACCEPT_TIMEOUT)
+            .context("VM didn't connect to the listener")
+            .and_then(|sock| {
+                ProtoTube::new_from_unix_seqpacket(sock).context("failed to create tube")
+            }) {
+            Ok(tube) => tube,
+            Err(e) => {
+                unregister(&control_socket_path, &socket_addr);
+                return Err(e);
+            }
+        };
+        // Dropping the subscription unregisters the listener if any other registration fails.
+        let subscription = EventSubscription {
+            control_socket_path,
+            socket_addr,
+            tube,
+            _listener: listener,
+        };
+        for event in other_events {
+            register(
+                &subscription.control_socket_path,
+                &subscription.socket_addr,
+                *event,
+            )?;
+        }
+        Ok(subscription)
+    }
+
+    /// Waits for the next event. Events that this build doesn't know about are skipped.
+    pub fn recv(&self) -> Result<RegisteredEventWithData> {
+        loop {
+            let event: registered_events::RegisteredEvent = self
+                .tube
+                .recv_proto()
+                .context("failed to receive registered event")?;
+            match RegisteredEventWithData::from_proto(&event) {
+                Some(event) => return Ok(event),
+                None => warn!("ignoring unknown registered event {:?}", event),
+            }
+        }
+    }
+
+    /// Returns an iterator over the received events. It ends once receiving fails, e.g. when the VM
+    /// exits.
+    pub fn events(&self) -> impl Iterator<Item = RegisteredEventWithData> + '_ {
+        std::iter::from_fn(move || self.recv().ok())
+    }
+}
+
+impl Drop for EventSubscription {
+    fn drop(&mut self) {
+        unregister(&self.control_socket_path, &self.socket_addr);
+    }
+}
+
+fn register(control_socket_path: &Path, socket_addr: &str, event: RegisteredEvent) -> Result<()> {
+    let request = VmRequest::RegisterListener {
+        socket_addr: socket_addr.to_string(),
+        event,
+    };
+    match handle_request(&request, control_socket_path) {
+        Ok(VmResponse::Ok) => Ok(()),
+        Ok(response) => bail!("failed to register for {:?}: {}", event, response),
+        Err(()) => bail!("failed to send request to register for {:?}", event),
+    }
+}
+
+fn unregister(control_socket_path: &Path, socket_addr: &str) {
+    let request = VmRequest::Unregister {
+        socket_addr: socket_addr.to_string(),
+    };
+    match handle_request(&request, control_socket_path) {
+        Ok(VmResponse::Ok) => {}
+        Ok(response) => warn!("failed to unregister {}: {}", socket_addr, response),
+        Err(()) => warn!("failed to send request to unregister {}", socket_addr),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use base::Tube;
+    use base::UnixSeqpacket;
+
+    use super::*;
+    use crate::WSBucket;
+
+    #[test]
+    fn subscription_receives_events_and_unregisters() {
+        let dir = tempfile::tempdir().unwrap();
+        let control_path = dir.path().join("control.sock");
+        let listener_path = dir.path().join("events.sock");
+        let control = UnixSeqpacketListener::bind(&control_path).unwrap();
+
+        let expected_addr = listener_path.to_str().unwrap().to_string();
+        // Fake VM, which emits a single event to its listener.
+        let vm = thread::spawn(move || {
+            let tube = Tube::new_from_unix_seqpacket(control.accept().unwrap()).unwrap();
+            let emitter = match tube.recv().unwrap() {
+                VmRequest::RegisterListener { socket_addr, event } => {
+                    assert_eq!(socket_addr, expected_addr);
+                    assert_eq!(event, RegisteredEvent::VirtioBalloonWsReport);
+                    let sock = UnixSeqpacket::connect(&socket_addr).unwrap();
+                    ProtoTube::new_from_unix_seqpacket(sock).unwrap()
+                }
+                r => panic!("unexpected request: {:?}", r),
+            };
+            tube.send(&VmResponse::Ok).unwrap();
+
+            let event = RegisteredEventWithData::VirtioBalloonWsReport {
+                ws_buckets: vec![WSBucket {
+                    age: 100,
+                    bytes: [4096, 8192],
+                }],
+                balloon_actual: 1 << 20,
+            };
+            emitter.send_proto(&event.into_proto()).unwrap();
+
+            let tube = Tube::new_from_unix_seqpacket(control.accept().unwrap()).unwrap();
+            match tube.recv().unwrap() {
+                VmRequest::Unregister { socket_addr } => assert_eq!(socket_addr, expected_addr),
+                r => panic!("unexpected request: {:?}", r),
+            }
+            tube.send(&VmResponse::Ok).unwrap();
+        });
+
+        let subscription = EventSubscription::new(
+            &control_path,
+            &listener_path,
+            &[RegisteredEvent::VirtioBalloonWsReport],
+        )
+        .unwrap();
+        match subscription.events().next() {
+            Some(RegisteredEventWithData::VirtioBalloonWsReport {
+                ws_buckets,
+                balloon_actual,
+            }) => {
+                assert_eq!(ws_buckets.len(), 1);
+                assert_eq!(ws_buckets[0].age, 100);
+                assert_eq!(ws_buckets[0].bytes, [4096, 8192]);
+                assert_eq!(balloon_actual, 1 << 20);
+            }
+            e => panic!("unexpected event: {:?}", e),
+        }
+
+        drop(subscription);
+        // The fake VM only exits after receiving the `Unregister` request.
+        vm.join().unwrap();
+        assert!(!listener_path.exists());
+    }
+}
@@ -16,9 +16,13 @@ use base::named_pipes::MultiPartMessagePipe;
 use base::named_pipes::OverlappedWrapper;
 use base::Error;
 use base::Event;
+use base::MappedRegion;
 use base::PipeTube;
+use base::Protection;
+use base::SafeDescriptor;
 use hypervisor::MemSlot;
 use hypervisor::Vm;
+use libc::ENOTSUP;
 use resources::Alloc;
 use resources::SystemAllocator;
 
@@ -114,3 +118,16 @@ pub fn prepare_shared_memory_region(
 ) -> std::result::Result<(u64, MemSlot), Error> {
     unimplemented!()
 }
+
+pub fn map_composite(
+    _parts: &[(SafeDescriptor, u64, u64)],
+    _prot: Protection,
+) -> std::result::Result<(Box<dyn MappedRegion>, u64), Error> {
+    // MemoryMappingArena is not implemented on Windows.
+    Err(Error::new(ENOTSUP))
+}
+
+pub fn release_region_pages(_region: &dyn MappedRegion) -> std::result::Result<(), Error> {
+    // The pages backing a view are released when it is unmapped.
+    Ok(())
+}
@@ -4,7 +4,6 @@
 
 use base::linux::MemfdSeals;
 use base::linux::MemoryMappingUnix;
-use base::linux::SharedMemoryLinux;
 use base::SharedMemory;
 use bitflags::bitflags;
 
@@ -26,13 +25,12 @@ pub(crate) fn finalize_shm(shm: &mut SharedMemory) -> Result<()> {
     // Seals are only a concept on Unix systems, so we must add them in conditional
     // compilation. On Windows, SharedMemory allocation cannot be updated after creation
     // regardless, so the same operation is done implicitly.
-    let mut seals = MemfdSeals::new();
-
-    seals.set_shrink_seal();
-    seals.set_grow_seal();
-    seals.set_seal_seal();
-
-    shm.add_seals(seals).map_err(Error::MemoryAddSealsFailed)
+    MemfdSeals::builder()
+        .shrink()
+        .grow()
+        .seal()
+        .apply_to(shm)
+        .map_err(Error::MemoryAddSealsFailed)
 }
 
 impl GuestMemory {
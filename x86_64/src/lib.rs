@@ -181,6 +181,8 @@ pub enum Error {
     ConfigureSystem,
     #[error("unable to create ACPI tables")]
     CreateAcpi,
+    #[error("unable to create ACPI PM resource: {0}")]
+    CreateAcpiPmResource(base::Error),
     #[error("unable to create battery devices: {0}")]
     CreateBatDevices(arch::DeviceRegistrationError),
     #[error("could not create debugcon device: {0}")]
@@ -2012,7 +2014,8 @@ impl X8664arch {
             suspend_evt,
             vm_evt_wrtube,
             acdc,
-        );
+        )
+        .map_err(Error::CreateAcpiPmResource)?;
         pmresource.to_aml_bytes(&mut amls);
         irq_chip
             .register_level_irq_event(